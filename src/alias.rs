@@ -0,0 +1,64 @@
+use rocksdb::{Direction, IteratorMode};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use crate::error::HttpmqError;
+use crate::storage::Storage;
+
+// aliases are stored as #alias#<name> => target queue, next to the topics
+const ALIAS_PREFIX: &str = "#alias#";
+
+fn alias_key(alias: &str) -> String {
+    format!("{}{}", ALIAS_PREFIX, alias)
+}
+
+/// Alternative names for queues. Aliases resolve in one step, they never
+/// point at other aliases.
+pub struct Aliases {
+    inner: RwLock<HashMap<String, String>>,
+}
+
+impl Aliases {
+    pub fn load(db: &dyn Storage) -> Aliases {
+        let mut aliases = HashMap::new();
+        let mode = IteratorMode::From(ALIAS_PREFIX.as_bytes(), Direction::Forward);
+        for (key, value) in db.raw().iterator(mode) {
+            let alias = match key.strip_prefix(ALIAS_PREFIX.as_bytes()) {
+                Some(alias) => String::from_utf8_lossy(alias).to_string(),
+                None => break,
+            };
+            aliases.insert(alias, String::from_utf8_lossy(&value).to_string());
+        }
+        Aliases {
+            inner: RwLock::new(aliases),
+        }
+    }
+
+    /// The queue `name` stands for, if it is an alias.
+    pub fn target(&self, name: &str) -> Option<String> {
+        self.inner.read().unwrap().get(name).cloned()
+    }
+
+    /// Points `alias` at `target`. Fails with false when that would chain
+    /// aliases: the target is an alias itself, or the alias is a target.
+    pub fn add(&self, db: &dyn Storage, alias: &str, target: &str) -> Result<bool, HttpmqError> {
+        let mut inner = self.inner.write().unwrap();
+        if alias == target || inner.contains_key(target) || inner.values().any(|t| t == alias) {
+            return Ok(false);
+        }
+        db.put(alias_key(alias).as_bytes(), target.as_bytes())?;
+        inner.insert(alias.to_string(), target.to_string());
+        Ok(true)
+    }
+
+    /// Drops `alias`, false if there was no such alias.
+    pub fn remove(&self, db: &dyn Storage, alias: &str) -> Result<bool, HttpmqError> {
+        let mut inner = self.inner.write().unwrap();
+        if !inner.contains_key(alias) {
+            return Ok(false);
+        }
+        db.delete(alias_key(alias).as_bytes())?;
+        inner.remove(alias);
+        Ok(true)
+    }
+}
@@ -0,0 +1,17 @@
+// compare without short-circuiting so response timing doesn't leak how
+// much of the token matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Checks a request's `auth` parameter against a configured token.
+/// Requests always pass when no token is configured.
+pub fn token_matches(expected: Option<&str>, given: Option<&str>) -> bool {
+    match expected {
+        None => true,
+        Some(expected) => constant_time_eq(expected.as_bytes(), given.unwrap_or("").as_bytes()),
+    }
+}
@@ -0,0 +1,87 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::stall::WriteStall;
+
+// how often a paused deletion checks whether the stall is over
+const STALL_STEP: Duration = Duration::from_millis(100);
+
+/// Paces bulk deletions, such as opt=remove_prefix and the cleanup of
+/// leftover selftest queues, so they don't take write throughput from
+/// puts. Their writes go in at low priority and within a budget of bytes
+/// per second, and they pause while RocksDB is stalling writes.
+pub struct BackgroundWrites {
+    // bytes per second, zero for no limit
+    rate: u64,
+    // bytes written past the budget as of when, still to be waited off
+    debt: Mutex<(Instant, f64)>,
+    throttled: AtomicBool,
+}
+
+/// The background write budget, for /stats.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct BackgroundStats {
+    // zero for no limit
+    pub budget_bytes_per_sec: u64,
+    // a deletion is waiting for its budget or for a stall to clear
+    pub throttled: bool,
+}
+
+impl BackgroundWrites {
+    pub fn new(rate: u64) -> BackgroundWrites {
+        BackgroundWrites {
+            rate,
+            debt: Mutex::new((Instant::now(), 0.0)),
+            throttled: AtomicBool::new(false),
+        }
+    }
+
+    // what is left of the debt at now
+    fn repaid(&self, (since, debt): (Instant, f64), now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(since).as_secs_f64();
+        (debt - elapsed * self.rate as f64).max(0.0)
+    }
+
+    /// Counts a background write of `bytes` against the budget.
+    pub fn charge(&self, bytes: usize) {
+        if self.rate == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut debt = self.debt.lock().unwrap();
+        *debt = (now, self.repaid(*debt, now) + bytes as f64);
+    }
+
+    /// How long the writes charged so far take to fit the budget.
+    pub fn delay(&self, now: Instant) -> Duration {
+        if self.rate == 0 {
+            return Duration::ZERO;
+        }
+        let debt = self.repaid(*self.debt.lock().unwrap(), now);
+        Duration::from_secs_f64(debt / self.rate as f64)
+    }
+
+    /// Waits until the budget allows another write and the database isn't
+    /// stalled.
+    pub async fn pace(&self, stall: &WriteStall) {
+        loop {
+            let stalled = stall.check();
+            let wait = self.delay(Instant::now());
+            if !stalled && wait.is_zero() {
+                break;
+            }
+            self.throttled.store(true, Ordering::Relaxed);
+            tokio::time::sleep(if stalled { STALL_STEP } else { wait }).await;
+        }
+        self.throttled.store(false, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> BackgroundStats {
+        BackgroundStats {
+            budget_bytes_per_sec: self.rate,
+            throttled: self.throttled.load(Ordering::Relaxed),
+        }
+    }
+}
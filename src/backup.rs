@@ -0,0 +1,114 @@
+use rocksdb::{checkpoint::Checkpoint, DB};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+/// What a backup or a restore wrote.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Backup {
+    pub dir: PathBuf,
+    pub files: u64,
+    // apparent size, hard linked files count in full
+    pub bytes: u64,
+}
+
+/// Why a backup or a restore didn't go through.
+#[derive(Debug)]
+pub enum BackupError {
+    // the sentinel opt=backup answers, e.g. HTTPMQ_BACKUP_NOT_EMPTY
+    Refused(&'static str),
+    // rocksdb's or the filesystem's message
+    Failed(String),
+}
+
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackupError::Refused(result) => write!(f, "refused: {}", result),
+            BackupError::Failed(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for BackupError {}
+
+fn failed(e: impl fmt::Display) -> BackupError {
+    BackupError::Failed(e.to_string())
+}
+
+/// Writes a consistent copy of `db`, which lives in `dbpath`, to `dir`
+/// with a RocksDB checkpoint: SST files are hard linked where `dir` is on
+/// the same filesystem and copied otherwise. `dir` may exist if it is
+/// empty, and may not be in or around `dbpath`. Blocks while the memtables
+/// are flushed and the files linked.
+pub fn create(db: &DB, dbpath: &Path, dir: &Path) -> Result<Backup, BackupError> {
+    let live = absolute(dbpath)
+        .ok_or_else(|| BackupError::Failed(format!("can't resolve {}", dbpath.display())))?;
+    let dir = match absolute(dir) {
+        Some(dir) if !dir.starts_with(&live) && !live.starts_with(&dir) => dir,
+        _ => return Err(BackupError::Refused("HTTPMQ_BACKUP_INVALID")),
+    };
+    if dir.exists() {
+        if std::fs::read_dir(&dir).map_err(failed)?.next().is_some() {
+            return Err(BackupError::Refused("HTTPMQ_BACKUP_NOT_EMPTY"));
+        }
+        // rocksdb creates the directory itself
+        std::fs::remove_dir(&dir).map_err(failed)?;
+    } else if let Some(parent) = dir.parent() {
+        std::fs::create_dir_all(parent).map_err(failed)?;
+    }
+    Checkpoint::new(db)
+        .and_then(|checkpoint| checkpoint.create_checkpoint(&dir))
+        .map_err(|e| BackupError::Failed(e.into_string()))?;
+    measure(dir)
+}
+
+/// Copies the backup in `from` to `dbpath`, for the server to open. A
+/// restore never overwrites a database: `dbpath` must be missing or empty.
+pub fn restore(from: &Path, dbpath: &Path) -> Result<Backup, BackupError> {
+    if !from.join("CURRENT").is_file() {
+        return Err(BackupError::Failed(format!(
+            "{} is not a backup, it has no CURRENT file",
+            from.display()
+        )));
+    }
+    if dbpath.exists() && std::fs::read_dir(dbpath).map_err(failed)?.next().is_some() {
+        return Err(BackupError::Failed(format!(
+            "{} is not empty, restore into a new dbpath",
+            dbpath.display()
+        )));
+    }
+    std::fs::create_dir_all(dbpath).map_err(failed)?;
+    // checkpoints are flat
+    for entry in std::fs::read_dir(from).map_err(failed)? {
+        let entry = entry.map_err(failed)?;
+        if entry.file_type().map_err(failed)?.is_file() {
+            std::fs::copy(entry.path(), dbpath.join(entry.file_name())).map_err(failed)?;
+        }
+    }
+    measure(dbpath.to_path_buf())
+}
+
+fn measure(dir: PathBuf) -> Result<Backup, BackupError> {
+    let (mut files, mut bytes) = (0, 0);
+    for entry in std::fs::read_dir(&dir).map_err(failed)? {
+        let metadata = entry.map_err(failed)?.metadata().map_err(failed)?;
+        if metadata.is_file() {
+            files += 1;
+            bytes += metadata.len();
+        }
+    }
+    Ok(Backup { dir, files, bytes })
+}
+
+// canonical, though the last components may not exist yet. None for a
+// `..` past the part that exists, it could lead anywhere once created.
+fn absolute(path: &Path) -> Option<PathBuf> {
+    let path = std::env::current_dir().ok()?.join(path);
+    let (canonical, rest) = path
+        .ancestors()
+        .find_map(|a| Some((a.canonicalize().ok()?, path.strip_prefix(a).ok()?)))?;
+    if rest.components().any(|c| c == Component::ParentDir) {
+        return None;
+    }
+    Some(canonical.join(rest))
+}
@@ -0,0 +1,146 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// A queue's put rate limit and how much of it is in use, for status.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct PutRate {
+    // puts per second
+    pub limit: u64,
+    // share of the bucket taken, 0 when it is full and 1 when it is empty
+    pub utilization: f64,
+}
+
+struct Bucket {
+    rate: u64,
+    // puts that can go through right now, negative after a batch larger
+    // than the bucket
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn full(rate: u64, now: Instant) -> Bucket {
+        Bucket {
+            rate,
+            tokens: rate as f64,
+            last: now,
+        }
+    }
+
+    fn refill(&mut self, rate: u64, now: Instant) {
+        // the limit changed, start over
+        if self.rate != rate {
+            *self = Bucket::full(rate, now);
+            return;
+        }
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate as f64).min(rate as f64);
+        self.last = now;
+    }
+
+    fn check(&mut self, rate: u64, n: u64, now: Instant) -> Result<(), Duration> {
+        self.refill(rate, now);
+        // a batch larger than the bucket goes through once it is full
+        let needed = n.min(rate) as f64;
+        if self.tokens >= needed {
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64(
+            (needed - self.tokens) / rate as f64,
+        ))
+    }
+
+    fn take(&mut self, rate: u64, n: u64, now: Instant) {
+        self.refill(rate, now);
+        self.tokens -= n as f64;
+    }
+}
+
+/// Token buckets of the queues with a `max_put_rate` setting, so that one
+/// busy producer can't take all of the disk. A bucket holds a second's
+/// worth of puts and refills continuously. Only a queue's first limited
+/// put allocates.
+pub struct PutLimits {
+    buckets: RwLock<HashMap<String, Mutex<Bucket>>>,
+}
+
+impl PutLimits {
+    pub fn new() -> PutLimits {
+        PutLimits {
+            buckets: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn with_bucket<T>(
+        &self,
+        name: &str,
+        rate: u64,
+        now: Instant,
+        f: impl FnOnce(&mut Bucket) -> T,
+    ) -> T {
+        if let Some(bucket) = self.buckets.read().unwrap().get(name) {
+            return f(&mut bucket.lock().unwrap());
+        }
+        f(self
+            .buckets
+            .write()
+            .unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| Mutex::new(Bucket::full(rate, now)))
+            .get_mut()
+            .unwrap())
+    }
+
+    /// Whether the bucket of queue `name`, which allows `rate` a second,
+    /// has room for `n` puts, without taking them. Without it tells how
+    /// long until it does.
+    pub fn check(&self, name: &str, rate: u64, n: u64, now: Instant) -> Result<(), Duration> {
+        self.with_bucket(name, rate, now, |bucket| bucket.check(rate, n, now))
+    }
+
+    /// Takes `n` puts from the bucket of queue `name`, whether or not they
+    /// fit. For puts that passed `check` and went in.
+    pub fn take(&self, name: &str, rate: u64, n: u64, now: Instant) {
+        self.with_bucket(name, rate, now, |bucket| bucket.take(rate, n, now))
+    }
+
+    /// Takes `n` puts from the bucket of queue `name`, which allows `rate`
+    /// a second. Without enough of them it takes none and tells how long
+    /// until there are.
+    pub fn try_take(&self, name: &str, rate: u64, n: u64, now: Instant) -> Result<(), Duration> {
+        self.with_bucket(name, rate, now, |bucket| {
+            bucket.check(rate, n, now)?;
+            bucket.take(rate, n, now);
+            Ok(())
+        })
+    }
+
+    /// How much of the bucket of queue `name` is in use.
+    pub fn status(&self, name: &str, rate: u64, now: Instant) -> PutRate {
+        let utilization = match self.buckets.read().unwrap().get(name) {
+            Some(bucket) => {
+                let mut bucket = bucket.lock().unwrap();
+                bucket.refill(rate, now);
+                (1.0 - bucket.tokens / rate as f64).clamp(0.0, 1.0)
+            }
+            None => 0.0,
+        };
+        PutRate {
+            limit: rate,
+            utilization,
+        }
+    }
+
+    /// Forgets the bucket of a queue that is going away.
+    pub fn remove(&self, name: &str) {
+        self.buckets.write().unwrap().remove(name);
+    }
+}
+
+impl Default for PutLimits {
+    fn default() -> PutLimits {
+        PutLimits::new()
+    }
+}
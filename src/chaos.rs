@@ -0,0 +1,121 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::state::Config;
+
+/// Header a request sends with the value `off` to never get a fault, for
+/// control traffic during a chaos test.
+pub const CHAOS_HEADER: &str = "x-httpmq-chaos";
+
+/// Header naming a request in the chaos log, one is made up when missing.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// What a request gets instead of being handled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    // HTTPMQ_PUT_END, as if the queue were full; puts only
+    Full,
+    // a 500
+    Error,
+    // the connection is cut before the response body
+    Drop,
+}
+
+/// The faults picked for one request: a delay before it is handled, then
+/// maybe a fault instead of handling it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Injection {
+    pub delay: Option<Duration>,
+    pub fault: Option<Fault>,
+}
+
+/// Fault injection for `--chaos`, so clients can be tested against full
+/// queues, errors, slow responses and dropped connections. Each fault
+/// happens with its own configured probability.
+pub struct Chaos {
+    latency: f64,
+    latency_ms: u64,
+    full: f64,
+    error: f64,
+    drop: f64,
+    // splitmix64 state
+    rng: AtomicU64,
+    // numbers requests that came without a request id
+    ids: AtomicU64,
+}
+
+impl Chaos {
+    pub fn new(config: &Config) -> Chaos {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        Chaos {
+            latency: config.chaos_latency,
+            latency_ms: config.chaos_latency_ms,
+            full: config.chaos_full,
+            error: config.chaos_error,
+            drop: config.chaos_drop,
+            rng: AtomicU64::new(seed),
+            ids: AtomicU64::new(0),
+        }
+    }
+
+    /// Checks that the probabilities are probabilities and that together
+    /// they leave room for each other.
+    pub fn validate(config: &Config) -> Result<(), String> {
+        let probabilities = [
+            ("chaos-latency", config.chaos_latency),
+            ("chaos-full", config.chaos_full),
+            ("chaos-error", config.chaos_error),
+            ("chaos-drop", config.chaos_drop),
+        ];
+        for (name, p) in probabilities {
+            if !(0.0..=1.0).contains(&p) {
+                return Err(format!("--{} must be between 0 and 1, not {}", name, p));
+            }
+        }
+        if config.chaos_full + config.chaos_error + config.chaos_drop > 1.0 {
+            return Err(String::from(
+                "--chaos-full, --chaos-error and --chaos-drop add up to more than 1",
+            ));
+        }
+        Ok(())
+    }
+
+    // uniform in [0, 1)
+    fn roll(&self) -> f64 {
+        let mut z = self
+            .rng
+            .fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed)
+            .wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Picks the faults for a request, `put` telling whether it is one.
+    pub fn decide(&self, put: bool) -> Injection {
+        let delay = (self.roll() < self.latency).then(|| Duration::from_millis(self.latency_ms));
+        let roll = self.roll();
+        let fault = if roll < self.drop {
+            Some(Fault::Drop)
+        } else if roll < self.drop + self.error {
+            Some(Fault::Error)
+        } else if put && roll < self.drop + self.error + self.full {
+            Some(Fault::Full)
+        } else {
+            None
+        };
+        Injection { delay, fault }
+    }
+
+    /// The id to log a request under: its `X-Request-Id`, or a made up one.
+    pub fn request_id(&self, given: Option<&str>) -> String {
+        match given {
+            Some(id) if !id.is_empty() => id.to_string(),
+            _ => format!("chaos-{}", self.ids.fetch_add(1, Ordering::Relaxed) + 1),
+        }
+    }
+}
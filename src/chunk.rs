@@ -0,0 +1,171 @@
+use rocksdb::{Snapshot, WriteBatch};
+
+use crate::envelope::{self, Header};
+use crate::error::HttpmqError;
+use crate::storage::Storage;
+
+// A message larger than the chunk size is stored as a manifest under its
+// own key, with the payload split over `<key>#0`, `<key>#1`, ... A stored
+// value starting with MAGIC is always a manifest: messages that happen to
+// start with it are chunked whatever their size, so the two can't be mixed up.
+const MAGIC: &[u8] = b"\0httpmq-chunked\0";
+
+// MAGIC, then the chunk count (u32) and the payload length (u64), big endian
+const MANIFEST_LEN: usize = MAGIC.len() + 4 + 8;
+
+fn chunk_key(key: &str, i: u32) -> String {
+    format!("{}#{}", key, i)
+}
+
+fn is_manifest(value: &[u8]) -> bool {
+    value.starts_with(MAGIC)
+}
+
+/// Where the chunks of a message live and how long it is.
+#[derive(Clone, Debug)]
+pub struct Manifest {
+    key: String,
+    chunks: u32,
+    len: u64,
+}
+
+impl Manifest {
+    fn parse(key: &str, value: &[u8]) -> Result<Manifest, HttpmqError> {
+        if value.len() != MANIFEST_LEN {
+            return Err(HttpmqError::Db(format!("malformed chunk manifest {}", key)));
+        }
+        let rest = &value[MAGIC.len()..];
+        Ok(Manifest {
+            key: key.to_string(),
+            chunks: u32::from_be_bytes(rest[..4].try_into().unwrap()),
+            len: u64::from_be_bytes(rest[4..].try_into().unwrap()),
+        })
+    }
+
+    pub fn chunks(&self) -> u32 {
+        self.chunks
+    }
+
+    /// Payload length in bytes.
+    pub fn size(&self) -> u64 {
+        self.len
+    }
+
+    /// Chunk `i` of the message, None if it is missing.
+    pub fn read_chunk(&self, db: &dyn Storage, i: u32) -> Result<Option<Vec<u8>>, HttpmqError> {
+        db.get(chunk_key(&self.key, i).as_bytes())
+    }
+
+    /// The whole message, None if a chunk is missing.
+    pub fn assemble(&self, db: &dyn Storage) -> Result<Option<Vec<u8>>, HttpmqError> {
+        let keys = (0..self.chunks)
+            .map(|i| chunk_key(&self.key, i).into_bytes())
+            .collect();
+        self.join(db.multi_get(keys))
+    }
+
+    /// The whole message as of `snapshot`, None if a chunk is missing.
+    pub fn assemble_at(&self, snapshot: &Snapshot) -> Result<Option<Vec<u8>>, HttpmqError> {
+        self.join((0..self.chunks).map(|i| Ok(snapshot.get(chunk_key(&self.key, i))?)))
+    }
+
+    // the chunks put back together, in order
+    fn join(
+        &self,
+        chunks: impl IntoIterator<Item = Result<Option<Vec<u8>>, HttpmqError>>,
+    ) -> Result<Option<Vec<u8>>, HttpmqError> {
+        let mut data = Vec::with_capacity(self.len as usize);
+        for x in chunks {
+            match x? {
+                Some(chunk) => data.extend_from_slice(&chunk),
+                None => return Ok(None),
+            }
+        }
+        if data.len() as u64 != self.len {
+            return Err(HttpmqError::Db(format!(
+                "chunk length mismatch {}",
+                self.key
+            )));
+        }
+        Ok(Some(data))
+    }
+
+    /// Adds deletes for all chunks to the batch.
+    pub fn delete(&self, batch: &mut WriteBatch) {
+        for i in 0..self.chunks {
+            batch.delete(chunk_key(&self.key, i));
+        }
+    }
+}
+
+/// A stored value, either the message itself or the manifest of its chunks.
+#[derive(Debug)]
+pub enum Stored {
+    Whole(Vec<u8>),
+    Chunked(Manifest),
+}
+
+/// The header of a stored value and what it holds.
+pub fn open(key: &str, value: Vec<u8>) -> Result<(Header, Stored), HttpmqError> {
+    let (header, value) = envelope::open(key, value)?;
+    if is_manifest(&value) {
+        Ok((header, Stored::Chunked(Manifest::parse(key, &value)?)))
+    } else {
+        Ok((header, Stored::Whole(value)))
+    }
+}
+
+/// Adds `data` under `key` to the batch, split into chunks when it is
+/// larger than `chunk_size` bytes. A chunk size of 0 disables chunking.
+pub fn put(batch: &mut WriteBatch, key: &str, data: &[u8], chunk_size: usize, header: &Header) {
+    let chunk_size = if chunk_size == 0 {
+        usize::MAX
+    } else {
+        chunk_size
+    };
+    if data.len() <= chunk_size && !is_manifest(data) {
+        batch.put(key, envelope::wrap(header, data));
+        return;
+    }
+
+    let mut chunks = 0u32;
+    for chunk in data.chunks(chunk_size) {
+        batch.put(chunk_key(key, chunks), chunk);
+        chunks += 1;
+    }
+    let mut manifest = Vec::with_capacity(MANIFEST_LEN);
+    manifest.extend_from_slice(MAGIC);
+    manifest.extend_from_slice(&chunks.to_be_bytes());
+    manifest.extend_from_slice(&(data.len() as u64).to_be_bytes());
+    batch.put(key, envelope::wrap(header, &manifest));
+}
+
+/// Adds the moves of the chunks behind `value` from `from` to `to` to the
+/// batch, if it is a manifest. The key itself is left to the caller.
+pub fn rename_chunks(
+    db: &dyn Storage,
+    batch: &mut WriteBatch,
+    from: &str,
+    to: &str,
+    value: &[u8],
+) -> Result<(), HttpmqError> {
+    let (_, value) = envelope::open(from, value.to_vec())?;
+    if is_manifest(&value) {
+        for i in 0..Manifest::parse(from, &value)?.chunks {
+            if let Some(chunk) = db.get(chunk_key(from, i).as_bytes())? {
+                batch.put(chunk_key(to, i), chunk);
+                batch.delete(chunk_key(from, i));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adds deletes for the chunks behind `value` to the batch, if it is a
+/// manifest. The key itself is left to the caller.
+pub fn delete_chunks(batch: &mut WriteBatch, key: &str, value: Vec<u8>) -> Result<(), HttpmqError> {
+    if let (_, Stored::Chunked(manifest)) = open(key, value)? {
+        manifest.delete(batch);
+    }
+    Ok(())
+}
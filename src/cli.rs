@@ -0,0 +1,437 @@
+use clap::{App, Arg, ArgMatches};
+use std::fmt;
+
+// arguments whose values aren't logged
+const SECRETS: [&str; 3] = ["admin-auth", "auth", "auth-read"];
+
+/// The command line. Every argument can also be given in an `HTTPMQ_*`
+/// environment variable; a value on the command line wins.
+pub fn app() -> App<'static> {
+    App::new("httpmq-rs")
+        .bin_name("httpmq-rs")
+        .arg(
+            Arg::new("dbpath")
+                .long("dbpath")
+                .env("HTTPMQ_DBPATH")
+                .help("Directory of the RocksDB database, created if missing")
+                .default_value("path"),
+        )
+        .arg(
+            Arg::new("host")
+                .long("host")
+                .env("HTTPMQ_HOST")
+                .help("Address to listen on")
+                .default_value("127.0.0.1"),
+        )
+        .arg(
+            Arg::new("port")
+                .long("port")
+                .env("HTTPMQ_PORT")
+                .help("Port to listen on")
+                .default_value("1218"),
+        )
+        .arg(
+            Arg::new("maxqueue")
+                .long("maxqueue")
+                .env("HTTPMQ_MAXQUEUE")
+                .help("Default number of slots of a queue, at most 1000000000000000")
+                .default_value("100000000"),
+        )
+        .arg(
+            Arg::new("maxqueue-limit")
+                .long("maxqueue-limit")
+                .env("HTTPMQ_MAXQUEUE_LIMIT")
+                .help("Most slots opt=maxqueue may give a queue, at most 1000000000000000")
+                .default_value("1000000000000000"),
+        )
+        .arg(
+            Arg::new("hot-queue-ops")
+                .long("hot-queue-ops")
+                .env("HTTPMQ_HOT_QUEUE_OPS")
+                .help("Ops/s above which a queue is reported as hot, 0 disables")
+                .default_value("10000"),
+        )
+        .arg(
+            Arg::new("hot-queue-share")
+                .long("hot-queue-share")
+                .env("HTTPMQ_HOT_QUEUE_SHARE")
+                .help("Percent of all traffic above which a queue is reported as hot, 0 disables")
+                .default_value("80"),
+        )
+        .arg(
+            Arg::new("admin-auth")
+                .long("admin-auth")
+                .env("HTTPMQ_ADMIN_AUTH")
+                .hide_env_values(true)
+                .takes_value(true)
+                .help("Token required as auth=<token> by admin operations (reset)"),
+        )
+        .arg(
+            Arg::new("auth")
+                .long("auth")
+                .env("HTTPMQ_AUTH")
+                .hide_env_values(true)
+                .takes_value(true)
+                .help("Token required as auth=<token> by operations that write, such as put"),
+        )
+        .arg(
+            Arg::new("auth-read")
+                .long("auth-read")
+                .env("HTTPMQ_AUTH_READ")
+                .hide_env_values(true)
+                .takes_value(true)
+                .help("Token required as auth=<token> by operations that read, such as get"),
+        )
+        .arg(
+            Arg::new("allow-unprotected-reset")
+                .long("allow-unprotected-reset")
+                .env("HTTPMQ_ALLOW_UNPROTECTED_RESET")
+                .help("Don't require confirm=<queue name> on reset"),
+        )
+        .arg(
+            Arg::new("stall-on-missing")
+                .long("stall-on-missing")
+                .env("HTTPMQ_STALL_ON_MISSING")
+                .help("Keep getpos on a slot whose message is missing instead of skipping it"),
+        )
+        .arg(
+            Arg::new("delete-on-get")
+                .long("delete-on-get")
+                .env("HTTPMQ_DELETE_ON_GET")
+                .help("Delete a message once it is consumed instead of when it is overwritten"),
+        )
+        .arg(
+            Arg::new("sync-writes")
+                .long("sync-writes")
+                .env("HTTPMQ_SYNC_WRITES")
+                .help("Sync the WAL on every write before acknowledging it, slower but durable"),
+        )
+        .arg(
+            Arg::new("shutdown-timeout")
+                .long("shutdown-timeout")
+                .env("HTTPMQ_SHUTDOWN_TIMEOUT")
+                .help("Seconds open connections get to finish on SIGTERM or SIGINT")
+                .default_value("30"),
+        )
+        .arg(
+            Arg::new("write-buffer-size")
+                .long("write-buffer-size")
+                .env("HTTPMQ_WRITE_BUFFER_SIZE")
+                .help("Bytes RocksDB buffers in a memtable before writing it out")
+                .default_value("67108864"),
+        )
+        .arg(
+            Arg::new("max-open-files")
+                .long("max-open-files")
+                .env("HTTPMQ_MAX_OPEN_FILES")
+                .help("SST files RocksDB keeps open, -1 for all of them")
+                .allow_hyphen_values(true)
+                .default_value("-1"),
+        )
+        .arg(
+            Arg::new("block-cache-size")
+                .long("block-cache-size")
+                .env("HTTPMQ_BLOCK_CACHE_SIZE")
+                .help("Bytes of RocksDB block cache, for data, index and filter blocks")
+                .default_value("67108864"),
+        )
+        .arg(
+            Arg::new("bloom-bits")
+                .long("bloom-bits")
+                .env("HTTPMQ_BLOOM_BITS")
+                .help("Bloom filter bits per key in new SST files, 0 disables")
+                .default_value("10"),
+        )
+        .arg(
+            Arg::new("fsck")
+                .long("fsck")
+                .env("HTTPMQ_FSCK")
+                .help("Check the metadata of every queue at startup"),
+        )
+        .arg(
+            Arg::new("restore-from")
+                .long("restore-from")
+                .env("HTTPMQ_RESTORE_FROM")
+                .takes_value(true)
+                .help("Copy the opt=backup directory to an empty dbpath before opening it"),
+        )
+        .arg(
+            Arg::new("name-max-len")
+                .long("name-max-len")
+                .env("HTTPMQ_NAME_MAX_LEN")
+                .help("Longest accepted queue name, in bytes")
+                .default_value("256"),
+        )
+        .arg(
+            Arg::new("permissive-names")
+                .long("permissive-names")
+                .env("HTTPMQ_PERMISSIVE_NAMES")
+                .help("Accept any characters in queue names, not just [A-Za-z0-9-_.]"),
+        )
+        .arg(
+            Arg::new("delete-as")
+                .long("delete-as")
+                .env("HTTPMQ_DELETE_AS")
+                .help("Operation a DELETE request performs on the queue")
+                .possible_values(["remove", "reset"])
+                .default_value("remove"),
+        )
+        .arg(
+            Arg::new("chunk-size")
+                .long("chunk-size")
+                .env("HTTPMQ_CHUNK_SIZE")
+                .help("Split messages larger than this many bytes over several keys, 0 disables")
+                .default_value("4194304"),
+        )
+        .arg(
+            Arg::new("topic-skip-full")
+                .long("topic-skip-full")
+                .env("HTTPMQ_TOPIC_SKIP_FULL")
+                .help("Skip full subscriber queues on a topic put instead of failing it"),
+        )
+        .arg(
+            Arg::new("wait-for-lock")
+                .long("wait-for-lock")
+                .env("HTTPMQ_WAIT_FOR_LOCK")
+                .help("Seconds to retry opening the database while another process holds its lock")
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("runtime-metrics")
+                .long("runtime-metrics")
+                .env("HTTPMQ_RUNTIME_METRICS")
+                .help("Sample tokio runtime metrics for /stats, at some cost per request"),
+        )
+        .arg(
+            Arg::new("metrics-max-queues")
+                .long("metrics-max-queues")
+                .env("HTTPMQ_METRICS_MAX_QUEUES")
+                .help("Queues labelled on /metrics, the rest are counted together")
+                .default_value("1000"),
+        )
+        .arg(
+            Arg::new("tcp-keepalive")
+                .long("tcp-keepalive")
+                .env("HTTPMQ_TCP_KEEPALIVE")
+                .help("Seconds a connection idles before TCP keepalive probes start, 0 disables")
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("tcp-keepalive-interval")
+                .long("tcp-keepalive-interval")
+                .env("HTTPMQ_TCP_KEEPALIVE_INTERVAL")
+                .help("Seconds between TCP keepalive probes, 0 keeps the system default")
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("tcp-nodelay")
+                .long("tcp-nodelay")
+                .env("HTTPMQ_TCP_NODELAY")
+                .help("Set TCP_NODELAY on connections"),
+        )
+        .arg(
+            Arg::new("backlog")
+                .long("backlog")
+                .env("HTTPMQ_BACKLOG")
+                .help("Listen backlog of the server socket")
+                .default_value("128"),
+        )
+        .arg(
+            Arg::new("inherit-fd")
+                .long("inherit-fd")
+                .env("HTTPMQ_INHERIT_FD")
+                .takes_value(true)
+                .help("Listen on this already bound socket descriptor instead of binding one"),
+        )
+        .arg(
+            Arg::new("max-connections")
+                .long("max-connections")
+                .env("HTTPMQ_MAX_CONNECTIONS")
+                .help("Refuse new connections while this many are open, 0 disables")
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("request-timeout")
+                .long("request-timeout")
+                .env("HTTPMQ_REQUEST_TIMEOUT")
+                .help("Seconds a request may take unless it asks for another timeout=")
+                .default_value("10"),
+        )
+        .arg(
+            Arg::new("max-request-timeout")
+                .long("max-request-timeout")
+                .env("HTTPMQ_MAX_REQUEST_TIMEOUT")
+                .help("Most seconds a request may ask for with timeout=, and the hard limit")
+                .default_value("60"),
+        )
+        .arg(
+            Arg::new("concurrency-limit")
+                .long("concurrency-limit")
+                .env("HTTPMQ_CONCURRENCY_LIMIT")
+                .help("Requests handled at once, past it new ones get a 503")
+                .default_value("1024"),
+        )
+        .arg(
+            Arg::new("remove-prefix-min")
+                .long("remove-prefix-min")
+                .env("HTTPMQ_REMOVE_PREFIX_MIN")
+                .help("Shortest prefix opt=remove_prefix accepts")
+                .default_value("3"),
+        )
+        .arg(
+            Arg::new("write-stall-ms")
+                .long("write-stall-ms")
+                .env("HTTPMQ_WRITE_STALL_MS")
+                .help("Milliseconds after which a write counts as a stall, 0 disables")
+                .default_value("1000"),
+        )
+        .arg(
+            Arg::new("background-write-rate")
+                .long("background-write-rate")
+                .env("HTTPMQ_BACKGROUND_WRITE_RATE")
+                .help("Bytes per second bulk deletions such as opt=remove_prefix may write, 0 for no limit")
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("reserve-timeout")
+                .long("reserve-timeout")
+                .env("HTTPMQ_RESERVE_TIMEOUT")
+                .help("Seconds a mode=reserve get holds its queue waiting for opt=commit")
+                .default_value("30"),
+        )
+        .arg(
+            Arg::new("preload-metadata")
+                .long("preload-metadata")
+                .env("HTTPMQ_PRELOAD_METADATA")
+                .help("Read every queue's metadata at startup, before accepting requests"),
+        )
+        .arg(
+            Arg::new("preload-timeout")
+                .long("preload-timeout")
+                .env("HTTPMQ_PRELOAD_TIMEOUT")
+                .help("Seconds --preload-metadata may take before startup goes on without it")
+                .default_value("30"),
+        )
+        .arg(
+            Arg::new("max-message-size")
+                .long("max-message-size")
+                .env("HTTPMQ_MAX_MESSAGE_SIZE")
+                .help("Largest message a put may carry, in bytes, 0 for no limit; queues can set their own")
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("max-body-size")
+                .long("max-body-size")
+                .env("HTTPMQ_MAX_BODY_SIZE")
+                .help("Largest request body accepted, in bytes, 0 for no limit; bigger ones get a 413")
+                .default_value("67108864"),
+        )
+        .arg(
+            Arg::new("chaos")
+                .long("chaos")
+                .env("HTTPMQ_CHAOS")
+                .requires("i-know-this-drops-requests")
+                .help("Inject faults into requests, for testing clients; never in production"),
+        )
+        .arg(
+            Arg::new("i-know-this-drops-requests")
+                .long("i-know-this-drops-requests")
+                .env("HTTPMQ_I_KNOW_THIS_DROPS_REQUESTS")
+                .help("Confirm --chaos"),
+        )
+        .arg(
+            Arg::new("chaos-latency")
+                .long("chaos-latency")
+                .env("HTTPMQ_CHAOS_LATENCY")
+                .help("With --chaos, probability of delaying a request by --chaos-latency-ms")
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("chaos-latency-ms")
+                .long("chaos-latency-ms")
+                .env("HTTPMQ_CHAOS_LATENCY_MS")
+                .help("Milliseconds --chaos-latency delays a request by")
+                .default_value("1000"),
+        )
+        .arg(
+            Arg::new("chaos-full")
+                .long("chaos-full")
+                .env("HTTPMQ_CHAOS_FULL")
+                .help("With --chaos, probability of answering a put with HTTPMQ_PUT_END")
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("chaos-error")
+                .long("chaos-error")
+                .env("HTTPMQ_CHAOS_ERROR")
+                .help("With --chaos, probability of answering with a 500")
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("chaos-drop")
+                .long("chaos-drop")
+                .env("HTTPMQ_CHAOS_DROP")
+                .help("With --chaos, probability of dropping the connection")
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("log-format")
+                .long("log-format")
+                .env("HTTPMQ_LOG_FORMAT")
+                .help("Log as plain text lines or as one JSON object per line")
+                .possible_values(["text", "json"])
+                .default_value("text"),
+        )
+        .arg(
+            Arg::new("log-level")
+                .long("log-level")
+                .env("HTTPMQ_LOG_LEVEL")
+                .help("Level of httpmq's own logs when RUST_LOG isn't set, debug logs each request")
+                .possible_values(["error", "warn", "info", "debug", "trace"])
+                .default_value("info"),
+        )
+}
+
+/// Where the value of an argument came from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    CommandLine,
+    Environment,
+    Default,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Source::CommandLine => "command line",
+            Source::Environment => "environment",
+            Source::Default => "default",
+        })
+    }
+}
+
+/// The resolved value of every argument with its source, secrets masked.
+pub fn resolved(app: &App, matches: &ArgMatches) -> Vec<(String, Option<String>, Source)> {
+    app.get_arguments()
+        // all but clap's own help and version
+        .filter_map(|arg| Some((arg, arg.get_env()?)))
+        .map(|(arg, env)| {
+            let name = arg.get_name();
+            let source = if matches.occurrences_of(name) > 0 {
+                Source::CommandLine
+            } else if std::env::var_os(env).is_some() {
+                Source::Environment
+            } else {
+                Source::Default
+            };
+            let value = if !arg.is_set(clap::ArgSettings::TakesValue) {
+                Some(matches.is_present(name).to_string())
+            } else if SECRETS.contains(&name) {
+                matches.value_of(name).map(|_| String::from("****"))
+            } else {
+                matches.value_of(name).map(String::from)
+            };
+            (name.to_string(), value, source)
+        })
+        .collect()
+}
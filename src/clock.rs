@@ -0,0 +1,107 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+
+/// Where the queue logic gets the time from, so tests of expiry and
+/// timeouts can move time along instead of waiting for it.
+pub trait Clock: Send + Sync {
+    /// Wall-clock time, for timestamps that are stored or shown.
+    fn now(&self) -> SystemTime;
+
+    /// Monotonic time, for measuring and deadlines.
+    fn instant(&self) -> Instant;
+
+    /// Waits until `instant()` reaches `deadline`.
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()> + Send + '_>>;
+
+    fn unix_millis(&self) -> u128 {
+        self.now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+    }
+
+    fn unix_secs(&self) -> u64 {
+        (self.unix_millis() / 1000) as u64
+    }
+}
+
+/// The real time.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        Box::pin(tokio::time::sleep_until(deadline.into()))
+    }
+}
+
+/// A clock that only moves when told to, for tests. It starts at the time
+/// it was made.
+pub struct MockClock {
+    system: SystemTime,
+    instant: Instant,
+    // how far it has been moved along
+    offset: Mutex<Duration>,
+    moved: Notify,
+}
+
+impl MockClock {
+    pub fn new() -> MockClock {
+        MockClock {
+            system: SystemTime::now(),
+            instant: Instant::now(),
+            offset: Mutex::new(Duration::ZERO),
+            moved: Notify::new(),
+        }
+    }
+
+    /// Moves the clock along by `by`, waking the sleepers whose deadline
+    /// has come.
+    pub fn advance(&self, by: Duration) {
+        *self.offset.lock().unwrap() += by;
+        self.moved.notify_waiters();
+    }
+
+    fn offset(&self) -> Duration {
+        *self.offset.lock().unwrap()
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> MockClock {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        self.system + self.offset()
+    }
+
+    fn instant(&self) -> Instant {
+        self.instant + self.offset()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        Box::pin(async move {
+            loop {
+                // registered before checking, so an advance in between isn't missed
+                let moved = self.moved.notified();
+                if self.instant() >= deadline {
+                    return;
+                }
+                moved.await;
+            }
+        })
+    }
+}
@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use crate::error::HttpmqError;
+
+// Per-message properties travel in an envelope around the stored value:
+// MAGIC, the header length (u32, big endian), the header as JSON, then the
+// value itself (the message or its chunk manifest). Every put gives its
+// messages a sequence number and put time, so every message is stored in
+// one, at a cost of 55 to 60 bytes each: 17 of MAGIC, 4 of length and a
+// header like {"seq":42,"put_at":1700000000000}. Values without MAGIC are
+// messages stored bare before that, read with an empty header.
+const MAGIC: &[u8] = b"\0httpmq-envelope\0";
+
+/// Properties of a single message.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Header {
+    // unix time in seconds after which the message is discarded unread
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<u64>,
+    // small key/value metadata set by the producer
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub attrs: BTreeMap<String, String>,
+    // the slot's message was taken ahead of the cursor by a filtered get,
+    // the cursor passes over it
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub taken: bool,
+    // the message's number in the queue, counting every put ever accepted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    // unix time in milliseconds the message was put
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub put_at: Option<u64>,
+    // Content-Type of the request body it came in, returned by get
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+}
+
+impl Header {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|deadline| now >= deadline)
+    }
+
+    /// Whether the message was put at least `ttl` seconds before
+    /// `now_millis`. Messages stored without a put time never are.
+    pub fn is_stale(&self, ttl: Option<u64>, now_millis: u64) -> bool {
+        match (ttl, self.put_at) {
+            (Some(ttl), Some(put_at)) => {
+                now_millis >= put_at.saturating_add(ttl.saturating_mul(1000))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The value to store for `stored` with `header`.
+pub fn wrap<'a>(header: &Header, stored: &'a [u8]) -> Cow<'a, [u8]> {
+    if *header == Header::default() && !stored.starts_with(MAGIC) {
+        return Cow::Borrowed(stored);
+    }
+    // a header of plain fields always encodes
+    let json = serde_json::to_vec(header).unwrap();
+    let mut value = Vec::with_capacity(MAGIC.len() + 4 + json.len() + stored.len());
+    value.extend_from_slice(MAGIC);
+    value.extend_from_slice(&(json.len() as u32).to_be_bytes());
+    value.extend_from_slice(&json);
+    value.extend_from_slice(stored);
+    Cow::Owned(value)
+}
+
+/// Splits a stored value into its header and the value inside.
+pub fn open(key: &str, mut value: Vec<u8>) -> Result<(Header, Vec<u8>), HttpmqError> {
+    if !value.starts_with(MAGIC) {
+        return Ok((Header::default(), value));
+    }
+    let malformed = || HttpmqError::Db(format!("malformed message envelope {}", key));
+    let rest = &value[MAGIC.len()..];
+    if rest.len() < 4 {
+        return Err(malformed());
+    }
+    let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+    let json = rest.get(4..4 + len).ok_or_else(malformed)?;
+    let header = serde_json::from_slice(json).map_err(|_| malformed())?;
+    Ok((header, value.split_off(MAGIC.len() + 4 + len)))
+}
@@ -0,0 +1,147 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum HttpmqError {
+    AuthFailed,
+    // the queue name breaks the configured naming policy
+    NameInvalid,
+    // the database failed
+    Db(String),
+    // a queue's metadata holds something that isn't a position
+    QueueCorrupt(String),
+    // RocksDB is stalling writes, puts are refused until it catches up
+    WriteStalled,
+    // the request body is over --max-body-size
+    BodyTooLarge,
+    // the request body couldn't be read
+    BodyUnreadable,
+    // the query string has a value of the wrong type, e.g. a negative num
+    ArgsInvalid(String),
+    // opt= is missing or names no operation
+    OptInvalid,
+}
+
+impl HttpmqError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            HttpmqError::AuthFailed => StatusCode::UNAUTHORIZED,
+            HttpmqError::NameInvalid => StatusCode::BAD_REQUEST,
+            HttpmqError::Db(_) | HttpmqError::QueueCorrupt(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            HttpmqError::WriteStalled => StatusCode::SERVICE_UNAVAILABLE,
+            HttpmqError::BodyTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            HttpmqError::BodyUnreadable | HttpmqError::ArgsInvalid(_) | HttpmqError::OptInvalid => {
+                StatusCode::BAD_REQUEST
+            }
+        }
+    }
+
+    // response body, in the same style as the success sentinels
+    pub fn sentinel(&self) -> &'static str {
+        match self {
+            HttpmqError::AuthFailed => "HTTPMQ_AUTH_FAILED",
+            HttpmqError::NameInvalid => "HTTPMQ_NAME_INVALID",
+            HttpmqError::Db(_) => "HTTPMQ_DB_ERROR",
+            HttpmqError::QueueCorrupt(_) => "HTTPMQ_QUEUE_CORRUPT",
+            HttpmqError::WriteStalled => "HTTPMQ_WRITE_STALLED",
+            HttpmqError::BodyTooLarge => "HTTPMQ_BODY_TOO_LARGE",
+            HttpmqError::BodyUnreadable => "HTTPMQ_BODY_UNREADABLE",
+            HttpmqError::ArgsInvalid(_) => "HTTPMQ_ARGS_INVALID",
+            HttpmqError::OptInvalid => "HTTPMQ_OPT_INVALID",
+        }
+    }
+}
+
+impl fmt::Display for HttpmqError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HttpmqError::AuthFailed => write!(f, "authentication failed"),
+            HttpmqError::NameInvalid => write!(f, "invalid queue name"),
+            HttpmqError::Db(msg) => write!(f, "database error: {}", msg),
+            HttpmqError::QueueCorrupt(name) => write!(f, "queue {} needs fsck", name),
+            HttpmqError::WriteStalled => write!(f, "writes are stalled"),
+            HttpmqError::BodyTooLarge => write!(f, "request body too large"),
+            HttpmqError::BodyUnreadable => write!(f, "can't read request body"),
+            HttpmqError::ArgsInvalid(msg) => write!(f, "invalid arguments: {}", msg),
+            HttpmqError::OptInvalid => write!(f, "invalid opt"),
+        }
+    }
+}
+
+impl std::error::Error for HttpmqError {}
+
+/// Why the database couldn't be opened at startup. Each kind exits with its
+/// own code, so supervisors can tell a restart race from a broken database.
+#[derive(Debug)]
+pub enum OpenError {
+    // another process holds the LOCK file
+    Locked(String),
+    // rocksdb found damaged files
+    Corrupt(String),
+    // the database was written by a newer httpmq-rs
+    SchemaTooNew { found: u32, supported: u32 },
+    Failed(String),
+}
+
+impl OpenError {
+    /// Classifies the message of a failed open of `dbpath`.
+    pub fn from_message(dbpath: &str, msg: String) -> OpenError {
+        // rocksdb only reports these as messages, e.g.
+        // "IO error: While lock file: /data/LOCK: Resource temporarily unavailable"
+        if msg.contains("lock file") || msg.contains("LOCK:") {
+            OpenError::Locked(format!("{}/LOCK", dbpath.trim_end_matches('/')))
+        } else if msg.starts_with("Corruption:") {
+            OpenError::Corrupt(msg)
+        } else {
+            OpenError::Failed(msg)
+        }
+    }
+
+    /// Process exit code: EX_TEMPFAIL, EX_DATAERR, EX_CONFIG or EX_IOERR
+    /// from sysexits.h.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            OpenError::Locked(_) => 75,
+            OpenError::Corrupt(_) => 65,
+            OpenError::SchemaTooNew { .. } => 78,
+            OpenError::Failed(_) => 74,
+        }
+    }
+}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OpenError::Locked(lock) => write!(
+                f,
+                "database lock {} is held, probably by another httpmq-rs on the same dbpath \
+                 (see --wait-for-lock)",
+                lock
+            ),
+            OpenError::Corrupt(msg) => write!(f, "database is corrupt: {}", msg),
+            OpenError::SchemaTooNew { found, supported } => write!(
+                f,
+                "database schema version {} is newer than the supported {}, upgrade httpmq-rs",
+                found, supported
+            ),
+            OpenError::Failed(msg) => write!(f, "can't open database: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for OpenError {}
+
+impl From<HttpmqError> for OpenError {
+    fn from(e: HttpmqError) -> OpenError {
+        OpenError::Failed(e.to_string())
+    }
+}
+
+impl IntoResponse for HttpmqError {
+    fn into_response(self) -> Response {
+        (self.status_code(), self.sentinel()).into_response()
+    }
+}
@@ -0,0 +1,62 @@
+use rocksdb::{FlushOptions, WriteBatch, WriteOptions, DB};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::error::HttpmqError;
+
+/// What a flush did.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Flushed {
+    pub elapsed: Duration,
+    // memtable bytes written out to SST files
+    pub bytes: u64,
+}
+
+/// Makes everything RocksDB has acknowledged durable: memtables are written
+/// to SST files and the WAL is synced, e.g. before the volume is
+/// snapshotted. One flush runs at a time.
+pub struct Flusher {
+    running: AtomicBool,
+}
+
+impl Flusher {
+    pub fn new() -> Flusher {
+        Flusher {
+            running: AtomicBool::new(false),
+        }
+    }
+
+    /// Flushes `db`, None when another flush is running.
+    pub fn run(&self, db: &DB) -> Result<Option<Flushed>, HttpmqError> {
+        if self.running.swap(true, Ordering::AcqRel) {
+            return Ok(None);
+        }
+        let res = flush(db);
+        self.running.store(false, Ordering::Release);
+        res.map(Some)
+    }
+}
+
+impl Default for Flusher {
+    fn default() -> Flusher {
+        Flusher::new()
+    }
+}
+
+fn flush(db: &DB) -> Result<Flushed, HttpmqError> {
+    let start = Instant::now();
+    let bytes = db
+        .property_int_value("rocksdb.cur-size-all-mem-tables")?
+        .unwrap_or(0);
+    let mut options = FlushOptions::default();
+    options.set_wait(true);
+    db.flush_opt(&options)?;
+    // an empty synced write syncs whatever the WAL still holds
+    let mut options = WriteOptions::default();
+    options.set_sync(true);
+    db.write_opt(WriteBatch::default(), &options)?;
+    Ok(Flushed {
+        elapsed: start.elapsed(),
+        bytes,
+    })
+}
@@ -0,0 +1,129 @@
+use axum::{
+    http::{header, header::HeaderName, HeaderMap},
+    response::{Headers, IntoResponse, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Serialize, Serializer};
+use serde_bytes::ByteBuf;
+use serde_json::{Map, Value};
+
+pub const MSGPACK: &str = "application/msgpack";
+pub const JSON: &str = "application/json";
+
+/// Encoding of a response body.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    // the plain text bodies of the original httpmq
+    Text,
+    // the response structs as MessagePack maps
+    Msgpack,
+    // the same as JSON objects
+    Json,
+}
+
+impl Format {
+    /// Picks the response format: an explicit `format=` parameter wins over
+    /// the Accept header, anything unrecognized falls back to text.
+    pub fn negotiate(param: Option<&str>, headers: &HeaderMap) -> Format {
+        match param {
+            Some("msgpack") => Format::Msgpack,
+            Some("json") => Format::Json,
+            Some(_) => Format::Text,
+            None if is_msgpack(headers, header::ACCEPT) => Format::Msgpack,
+            None if is_json(headers) => Format::Json,
+            None => Format::Text,
+        }
+    }
+}
+
+/// Whether a header (Accept or Content-Type) names MessagePack, under
+/// either its registered or its older x- name.
+pub fn is_msgpack(headers: &HeaderMap, name: HeaderName) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.contains(MSGPACK) || v.contains("application/x-msgpack"))
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.contains(JSON))
+}
+
+pub fn msgpack<T: Serialize>(value: &T) -> Response {
+    // our response structs always encode, only a broken writer could fail
+    let body = rmp_serde::to_vec_named(value).unwrap();
+    (Headers(vec![(header::CONTENT_TYPE, MSGPACK)]), body).into_response()
+}
+
+pub fn json<T: Serialize>(value: &T) -> Response {
+    let body = serde_json::to_vec(value).unwrap();
+    (Headers(vec![(header::CONTENT_TYPE, JSON)]), body).into_response()
+}
+
+/// A response struct in `fmt`, MessagePack or JSON.
+pub fn encode<T: Serialize>(fmt: Format, value: &T) -> Response {
+    match fmt {
+        Format::Msgpack => msgpack(value),
+        _ => json(value),
+    }
+}
+
+/// Message data as a string in JSON, base64 encoded when it isn't valid
+/// UTF-8, and as raw bytes in MessagePack.
+pub fn data<S: Serializer>(data: &ByteBuf, s: S) -> Result<S::Ok, S::Error> {
+    if !s.is_human_readable() {
+        return s.serialize_bytes(data);
+    }
+    match std::str::from_utf8(data) {
+        Ok(text) => s.serialize_str(text),
+        Err(_) => s.serialize_str(&STANDARD.encode(data)),
+    }
+}
+
+/// The `encoding` field beside message data in a `fmt` answer: base64 in
+/// JSON for data that isn't valid UTF-8, none where it goes as it is.
+pub fn encoding(fmt: Format, data: &[u8]) -> Option<&'static str> {
+    (fmt == Format::Json && std::str::from_utf8(data).is_err()).then_some("base64")
+}
+
+pub fn opt_data<S: Serializer>(data: &Option<ByteBuf>, s: S) -> Result<S::Ok, S::Error> {
+    match data {
+        Some(data) => self::data(data, s),
+        None => s.serialize_none(),
+    }
+}
+
+/// A plain text answer as a JSON object, for the operations without a
+/// response struct: the first line is the result, and the `key: value`
+/// lines after it become fields, numbers where they parse. Other lines are
+/// kept under `lines`.
+pub fn text_to_json(text: &str) -> Value {
+    let mut lines = text.lines();
+    let mut object = Map::new();
+    object.insert(
+        String::from("result"),
+        Value::from(lines.next().unwrap_or_default()),
+    );
+    let mut rest = Vec::new();
+    for line in lines {
+        match line.split_once(": ") {
+            Some((key, value)) if !key.is_empty() && !key.contains(' ') => {
+                let value = match value.parse::<i64>() {
+                    Ok(n) => Value::from(n),
+                    Err(_) => Value::from(value),
+                };
+                object.insert(key.to_string(), value);
+            }
+            _ => rest.push(Value::from(line)),
+        }
+    }
+    if !rest.is_empty() {
+        object.insert(String::from("lines"), Value::from(rest));
+    }
+    Value::Object(object)
+}
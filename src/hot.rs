@@ -0,0 +1,203 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+// sliding window width, in one-second buckets
+pub const WINDOW_SECS: usize = 10;
+
+// a queue must do at least this many ops/s before the share rule applies,
+// so a quiet server with a single busy-ish queue isn't reported as hot
+const SHARE_MIN_OPS: u64 = 100;
+
+// at most one hot-queue warning per queue per interval
+const WARN_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Default)]
+struct Window {
+    buckets: [u64; WINDOW_SECS],
+    sec: u64,
+}
+
+impl Window {
+    // move the window forward to `sec`, clearing buckets that fell out of it
+    fn advance(&mut self, sec: u64) {
+        if sec <= self.sec {
+            return;
+        }
+        let gap = (sec - self.sec).min(WINDOW_SECS as u64);
+        for i in 1..=gap {
+            self.buckets[((self.sec + i) % WINDOW_SECS as u64) as usize] = 0;
+        }
+        self.sec = sec;
+    }
+
+    fn incr(&mut self, sec: u64) {
+        self.advance(sec);
+        self.buckets[(self.sec % WINDOW_SECS as u64) as usize] += 1;
+    }
+
+    fn sum_at(&self, sec: u64) -> u64 {
+        let mut w = *self;
+        w.advance(sec);
+        w.buckets.iter().sum()
+    }
+}
+
+#[derive(Default)]
+struct QueueRate {
+    window: Window,
+    last_warn: Option<Instant>,
+}
+
+#[derive(Default)]
+struct Inner {
+    total: Window,
+    queues: HashMap<String, QueueRate>,
+    swept: u64,
+}
+
+/// A queue and the number of ops it did during the current window.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QueueOps {
+    pub name: String,
+    pub ops: u64,
+    pub hot: bool,
+}
+
+impl QueueOps {
+    pub fn ops_per_sec(&self) -> f64 {
+        self.ops as f64 / WINDOW_SECS as f64
+    }
+}
+
+/// Tracks recent per-queue op rates and flags queues that exceed
+/// `max_ops` ops/s or take more than `max_share` percent of all traffic.
+/// A threshold of 0 disables that rule.
+pub struct HotQueues {
+    start: Instant,
+    max_ops: u64,
+    max_share: u64,
+    inner: Mutex<Inner>,
+}
+
+impl HotQueues {
+    pub fn new(max_ops: u64, max_share: u64) -> HotQueues {
+        HotQueues {
+            start: Instant::now(),
+            max_ops,
+            max_share,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    fn sec(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.start).as_secs()
+    }
+
+    fn exceeds(&self, ops: u64, total: u64, active: usize) -> bool {
+        let rate = ops / WINDOW_SECS as u64;
+        if self.max_ops > 0 && rate >= self.max_ops {
+            return true;
+        }
+        self.max_share > 0
+            && active > 1
+            && rate >= SHARE_MIN_OPS
+            && ops * 100 >= total * self.max_share
+    }
+
+    pub fn record(&self, name: &str) {
+        self.record_at(name, Instant::now())
+    }
+
+    pub fn record_at(&self, name: &str, now: Instant) {
+        let sec = self.sec(now);
+        let mut guard = self.inner.lock().unwrap();
+        let inner = &mut *guard;
+
+        inner.total.incr(sec);
+        if sec > inner.swept {
+            // once a second, forget queues that went quiet
+            inner.queues.retain(|_, q| q.window.sum_at(sec) > 0);
+            inner.swept = sec;
+        }
+
+        if !inner.queues.contains_key(name) {
+            inner.queues.insert(name.to_string(), QueueRate::default());
+        }
+        let active = inner.queues.len();
+        let total = inner.total.sum_at(sec);
+        let q = inner.queues.get_mut(name).unwrap();
+        q.window.incr(sec);
+        let ops = q.window.sum_at(sec);
+
+        let warn_due = match q.last_warn {
+            Some(t) => now.duration_since(t) >= WARN_INTERVAL,
+            None => true,
+        };
+        if warn_due && self.exceeds(ops, total, active) {
+            q.last_warn = Some(now);
+            warn!(
+                "hot queue {}: {} ops/s, {}% of traffic",
+                name,
+                ops / WINDOW_SECS as u64,
+                ops * 100 / total.max(1)
+            );
+        }
+    }
+
+    pub fn is_hot(&self, name: &str) -> bool {
+        self.is_hot_at(name, Instant::now())
+    }
+
+    pub fn is_hot_at(&self, name: &str, now: Instant) -> bool {
+        let sec = self.sec(now);
+        let inner = self.inner.lock().unwrap();
+        match inner.queues.get(name) {
+            Some(q) => self.exceeds(
+                q.window.sum_at(sec),
+                inner.total.sum_at(sec),
+                inner.queues.len(),
+            ),
+            None => false,
+        }
+    }
+
+    /// Total ops across all queues during the current window.
+    pub fn total_ops(&self) -> u64 {
+        self.inner
+            .lock()
+            .unwrap()
+            .total
+            .sum_at(self.sec(Instant::now()))
+    }
+
+    /// The `n` busiest queues of the current window, busiest first.
+    pub fn top(&self, n: usize) -> Vec<QueueOps> {
+        self.top_at(n, Instant::now())
+    }
+
+    pub fn top_at(&self, n: usize, now: Instant) -> Vec<QueueOps> {
+        let sec = self.sec(now);
+        let inner = self.inner.lock().unwrap();
+        let total = inner.total.sum_at(sec);
+        let active = inner.queues.len();
+        let mut top: Vec<QueueOps> = inner
+            .queues
+            .iter()
+            .map(|(name, q)| {
+                let ops = q.window.sum_at(sec);
+                QueueOps {
+                    name: name.clone(),
+                    ops,
+                    hot: self.exceeds(ops, total, active),
+                }
+            })
+            .filter(|q| q.ops > 0)
+            .collect();
+        top.sort_by(|a, b| b.ops.cmp(&a.ops).then_with(|| a.name.cmp(&b.name)));
+        top.truncate(n);
+        top
+    }
+}
@@ -1 +1,105 @@
+pub mod alias;
+pub mod auth;
+pub mod background;
+pub mod backup;
+pub mod bucket;
+pub mod chaos;
+pub mod chunk;
+pub mod cli;
+pub mod clock;
+pub mod envelope;
+pub mod error;
+pub mod flush;
+pub mod format;
+pub mod hot;
+pub mod listener;
+pub mod load;
+pub mod locks;
+pub mod metrics;
+pub mod mirror;
+pub mod queue;
+pub mod rate;
+pub mod reserve;
+pub mod ring;
+pub mod runtime;
+pub mod schema;
 pub mod service;
+pub mod settings;
+pub mod shutdown;
+pub mod stall;
+pub mod state;
+pub mod storage;
+pub mod topic;
+pub mod totals;
+pub mod waiters;
+
+use axum::{
+    error_handling::HandleErrorLayer, handler::Handler, routing::get, AddExtensionLayer, Router,
+};
+use hyper::{Body, Request};
+use std::time::Duration;
+use tower::ServiceBuilder;
+use tower_http::trace::TraceLayer;
+
+use load::InFlightLayer;
+use service::{
+    handle_error, healthz, method_not_allowed, metrics, process, process_delete, process_post,
+    process_put, readyz, stats,
+};
+use state::SharedState;
+
+pub fn app(state: SharedState) -> Router {
+    routes(state.clone()).merge(probes(state))
+}
+
+fn routes(state: SharedState) -> Router {
+    Router::new()
+        .route(
+            "/",
+            get(process)
+                .post(process_post)
+                .put(process_put)
+                .delete(process_delete)
+                .fallback(method_not_allowed.into_service()),
+        )
+        .route("/stats", get(stats))
+        .route("/metrics", get(metrics))
+        .layer(AddExtensionLayer::new(state))
+}
+
+// /healthz and /readyz for orchestrators. They skip auth and the opt
+// dispatcher, and stay clear of the load shedding of limited_app, so a
+// busy server isn't taken for a dead one.
+fn probes(state: SharedState) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .layer(AddExtensionLayer::new(state))
+}
+
+/// The app behind the middleware that sheds load past the concurrency
+/// limit and cuts off requests at the hard timeout.
+pub fn limited_app(state: SharedState) -> Router {
+    let max_request_timeout = Duration::from_secs(state.config.max_request_timeout.max(1));
+    let concurrency_limit = state.config.concurrency_limit.max(1);
+    let on_error = state.clone();
+    let in_flight = state.clone();
+    routes(state.clone())
+        .layer(
+            ServiceBuilder::new()
+                // Handle errors from middleware
+                .layer(HandleErrorLayer::new(move |error| {
+                    handle_error(on_error.clone(), error)
+                }))
+                .load_shed()
+                .concurrency_limit(concurrency_limit)
+                .layer(InFlightLayer::new(in_flight))
+                .timeout(max_request_timeout)
+                // the path only, the query holds message data and tokens
+                .layer(TraceLayer::new_for_http().make_span_with(|req: &Request<Body>| {
+                    tracing::debug_span!("http", method = %req.method(), path = %req.uri().path())
+                }))
+                .into_inner(),
+        )
+        .merge(probes(state))
+}
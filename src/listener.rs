@@ -0,0 +1,216 @@
+use hyper::server::accept::Accept;
+use socket2::{Domain, SockRef, Socket, TcpKeepalive, Type};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::Sleep;
+use tracing::warn;
+
+use crate::state::Config;
+
+// pause after a failed accept, typically out of file descriptors
+const ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+// the first descriptor systemd passes, SD_LISTEN_FDS_START
+const LISTEN_FDS_START: RawFd = 3;
+
+/// The server's TCP listener: applies the socket options from the config
+/// to every accepted connection and refuses connections past
+/// `max_connections`.
+pub struct Listener {
+    inner: TcpListener,
+    keepalive: Option<TcpKeepalive>,
+    nodelay: bool,
+    max_connections: usize,
+    open: Arc<AtomicUsize>,
+    backoff: Option<Pin<Box<Sleep>>>,
+}
+
+/// Binds `addr` with the listen backlog from the config.
+pub fn bind(addr: SocketAddr, config: &Config) -> io::Result<Listener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(config.backlog)?;
+    listener(socket, config)
+}
+
+/// Takes over the listening socket `fd` passed in by a supervisor, so that
+/// the listener outlives restarts of the server. The socket keeps its
+/// address; listening again only sets the backlog from the config.
+pub fn inherit(fd: RawFd, config: &Config) -> io::Result<Listener> {
+    // Safety: the descriptor is ours from here on, nothing else in the
+    // process uses it
+    let socket = unsafe { Socket::from_raw_fd(fd) };
+    if socket.r#type()? != Type::STREAM {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("fd {} is not a stream socket", fd),
+        ));
+    }
+    socket.listen(config.backlog)?;
+    listener(socket, config)
+}
+
+/// The socket passed by systemd socket activation, if the process was
+/// started that way. The variables are cleared so child processes don't
+/// take them for their own.
+pub fn systemd_fd() -> Option<RawFd> {
+    let pid = std::env::var("LISTEN_PID").ok()?;
+    let fds = std::env::var("LISTEN_FDS").ok()?;
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    if pid.parse::<u32>().ok()? != std::process::id() {
+        return None;
+    }
+    match fds.parse::<i32>().ok()? {
+        0 => None,
+        1 => Some(LISTEN_FDS_START),
+        n => {
+            warn!("systemd passed {} sockets, using the first", n);
+            Some(LISTEN_FDS_START)
+        }
+    }
+}
+
+fn listener(socket: Socket, config: &Config) -> io::Result<Listener> {
+    socket.set_nonblocking(true)?;
+
+    let keepalive = (config.tcp_keepalive > 0).then(|| {
+        let keepalive = TcpKeepalive::new().with_time(Duration::from_secs(config.tcp_keepalive));
+        match config.tcp_keepalive_interval {
+            0 => keepalive,
+            secs => keepalive.with_interval(Duration::from_secs(secs)),
+        }
+    });
+    Ok(Listener {
+        inner: TcpListener::from_std(socket.into())?,
+        keepalive,
+        nodelay: config.tcp_nodelay,
+        max_connections: config.max_connections,
+        open: Arc::new(AtomicUsize::new(0)),
+        backoff: None,
+    })
+}
+
+impl Listener {
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn configure(&self, stream: &TcpStream) -> io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+        if let Some(keepalive) = &self.keepalive {
+            SockRef::from(stream).set_tcp_keepalive(keepalive)?;
+        }
+        Ok(())
+    }
+}
+
+impl Accept for Listener {
+    type Conn = Connection;
+    type Error = io::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<io::Result<Connection>>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(backoff) = &mut this.backoff {
+                if backoff.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                this.backoff = None;
+            }
+            let (stream, addr) = match this.inner.poll_accept(cx) {
+                Poll::Ready(Ok(accepted)) => accepted,
+                Poll::Ready(Err(e)) => {
+                    // an error here must not end the server
+                    warn!("accept failed: {}", e);
+                    this.backoff = Some(Box::pin(tokio::time::sleep(ACCEPT_BACKOFF)));
+                    continue;
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+
+            let open = this.open.load(Ordering::Relaxed);
+            if this.max_connections > 0 && open >= this.max_connections {
+                warn!(
+                    "refused connection from {}: {} connections open",
+                    addr, open
+                );
+                continue;
+            }
+            if let Err(e) = this.configure(&stream) {
+                warn!("can't set socket options for {}: {}", addr, e);
+            }
+            this.open.fetch_add(1, Ordering::Relaxed);
+            return Poll::Ready(Some(Ok(Connection {
+                stream,
+                open: this.open.clone(),
+            })));
+        }
+    }
+}
+
+/// An accepted connection, counted as open until it is dropped.
+pub struct Connection {
+    stream: TcpStream,
+    open: Arc<AtomicUsize>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.open.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
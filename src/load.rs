@@ -0,0 +1,185 @@
+use serde::Serialize;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tower::{Layer, Service};
+use tracing::warn;
+
+use crate::state::SharedState;
+
+// shedding counts as over once no request was shed for this long
+const SHED_QUIET: Duration = Duration::from_secs(1);
+
+/// Counters for requests refused or cut short by the middleware, and for
+/// how close the server came to its concurrency limit.
+pub struct LoadMetrics {
+    start: Instant,
+    limit: usize,
+    shed: AtomicU64,
+    timeouts: AtomicU64,
+    in_flight: AtomicUsize,
+    in_flight_high: AtomicUsize,
+    // an episode of shedding: whether one is going on, when it last shed
+    // (ms since start) and how many it shed so far
+    shedding: AtomicBool,
+    last_shed: AtomicU64,
+    episode: AtomicU64,
+}
+
+/// A snapshot of `LoadMetrics` for /stats.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct LoadStats {
+    pub concurrency_limit: usize,
+    pub in_flight: usize,
+    // the most requests in flight at once since startup
+    pub in_flight_high: usize,
+    pub shed: u64,
+    pub timeouts: u64,
+}
+
+impl LoadMetrics {
+    pub fn new(limit: usize) -> LoadMetrics {
+        LoadMetrics {
+            start: Instant::now(),
+            limit,
+            shed: AtomicU64::new(0),
+            timeouts: AtomicU64::new(0),
+            in_flight: AtomicUsize::new(0),
+            in_flight_high: AtomicUsize::new(0),
+            shedding: AtomicBool::new(false),
+            last_shed: AtomicU64::new(0),
+            episode: AtomicU64::new(0),
+        }
+    }
+
+    fn millis(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.start).as_millis() as u64
+    }
+
+    /// Counts a request refused by load shedding, warning when it starts
+    /// an episode of shedding.
+    pub fn record_shed(&self) {
+        self.record_shed_at(Instant::now())
+    }
+
+    pub fn record_shed_at(&self, now: Instant) {
+        self.shed.fetch_add(1, Ordering::Relaxed);
+        self.last_shed.store(self.millis(now), Ordering::Relaxed);
+        self.episode.fetch_add(1, Ordering::Relaxed);
+        if !self.shedding.swap(true, Ordering::AcqRel) {
+            warn!(
+                "shedding load: {} requests in flight, limit {}",
+                self.in_flight.load(Ordering::Relaxed),
+                self.limit
+            );
+        }
+    }
+
+    /// Counts a request that ran out of time.
+    pub fn record_timeout(&self) {
+        self.timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a request in until the returned guard is dropped, and ends an
+    /// episode of shedding that has gone quiet.
+    pub fn enter(self: &Arc<Self>) -> InFlightGuard {
+        self.enter_at(Instant::now())
+    }
+
+    pub fn enter_at(self: &Arc<Self>, now: Instant) -> InFlightGuard {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+        self.in_flight_high.fetch_max(in_flight, Ordering::Relaxed);
+        if self.shedding.load(Ordering::Acquire)
+            && self.millis(now)
+                >= self.last_shed.load(Ordering::Relaxed) + SHED_QUIET.as_millis() as u64
+            && self.shedding.swap(false, Ordering::AcqRel)
+        {
+            warn!(
+                "stopped shedding load after {} requests",
+                self.episode.swap(0, Ordering::Relaxed)
+            );
+        }
+        InFlightGuard { load: self.clone() }
+    }
+
+    pub fn is_shedding(&self) -> bool {
+        self.shedding.load(Ordering::Acquire)
+    }
+
+    pub fn stats(&self) -> LoadStats {
+        LoadStats {
+            concurrency_limit: self.limit,
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            in_flight_high: self.in_flight_high.load(Ordering::Relaxed),
+            shed: self.shed.load(Ordering::Relaxed),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+        }
+    }
+}
+
+pub struct InFlightGuard {
+    load: Arc<LoadMetrics>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.load.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Counts the requests passing through as in flight until their response
+/// is ready. Goes inside the concurrency limit.
+#[derive(Clone)]
+pub struct InFlightLayer {
+    state: SharedState,
+}
+
+impl InFlightLayer {
+    pub fn new(state: SharedState) -> InFlightLayer {
+        InFlightLayer { state }
+    }
+}
+
+impl<S> Layer<S> for InFlightLayer {
+    type Service = InFlight<S>;
+
+    fn layer(&self, inner: S) -> InFlight<S> {
+        InFlight {
+            inner,
+            state: self.state.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct InFlight<S> {
+    inner: S,
+    state: SharedState,
+}
+
+impl<S, R> Service<R> for InFlight<S>
+where
+    S: Service<R>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: R) -> Self::Future {
+        let guard = self.state.load.enter();
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let res = fut.await;
+            drop(guard);
+            res
+        })
+    }
+}
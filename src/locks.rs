@@ -0,0 +1,72 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+/// Locks held on some queues, released when dropped.
+pub struct QueueGuard {
+    _guards: Vec<OwnedMutexGuard<()>>,
+}
+
+/// One lock per queue, held while a request reads a position from the
+/// queue's metadata and writes the next one back, so two puts can't claim
+/// the same slot and two gets can't take the same message. Requests on
+/// different queues don't wait for each other. Only a queue's first
+/// request allocates.
+pub struct QueueLocks {
+    locks: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
+}
+
+impl QueueLocks {
+    pub fn new() -> QueueLocks {
+        QueueLocks {
+            locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn get(&self, name: &str) -> Arc<AsyncMutex<()>> {
+        self.locks
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Waits for the lock of queue `name`.
+    pub async fn lock(&self, name: &str) -> QueueGuard {
+        self.lock_all([name]).await
+    }
+
+    /// Waits for the locks of all of `names`, e.g. the subscribers of a
+    /// topic. They are taken in name order, so two requests locking
+    /// overlapping sets can't deadlock.
+    pub async fn lock_all<'a>(&self, names: impl IntoIterator<Item = &'a str>) -> QueueGuard {
+        let names: BTreeSet<&str> = names.into_iter().collect();
+        let mut guards = Vec::with_capacity(names.len());
+        for name in names {
+            guards.push(self.get(name).lock_owned().await);
+        }
+        QueueGuard { _guards: guards }
+    }
+
+    /// Forgets the lock of queue `name`, which is going away, then lets go
+    /// of it. `guard` must hold it. A request waiting for the lock keeps
+    /// it, so nobody gets a fresh lock while the old one still excludes.
+    pub fn remove(&self, name: &str, guard: QueueGuard) {
+        let mut locks = self.locks.lock().unwrap();
+        // the map's reference and the guard's
+        if locks
+            .get(name)
+            .is_some_and(|lock| Arc::strong_count(lock) == 2)
+        {
+            locks.remove(name);
+        }
+        drop(guard);
+    }
+}
+
+impl Default for QueueLocks {
+    fn default() -> QueueLocks {
+        QueueLocks::new()
+    }
+}
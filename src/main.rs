@@ -1,50 +1,184 @@
-use axum::{error_handling::HandleErrorLayer, routing::get, Router};
-use clap::{App, Arg};
+use std::{
+    net::ToSocketAddrs,
+    path::Path,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+use tokio::sync::Notify;
 
-use std::{net::SocketAddr, time::Duration};
-use tower::ServiceBuilder;
+use httpmq_rs::{
+    backup, cli,
+    error::OpenError,
+    limited_app, listener,
+    service::{fsck_all, preload_metadata},
+    shutdown, stall,
+    state::{Config, State},
+    totals,
+};
 
-use httpmq_rs::service::{handle_error, init, process};
+// RUST_LOG, when set, wins over --log-level
+fn init_logging(format: &str, level: &str) {
+    use tracing_subscriber::{prelude::*, EnvFilter, Layer, Registry};
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(format!("httpmq_rs={0},tower_http={0}", level)));
+    let fmt: Box<dyn Layer<Registry> + Send + Sync> = match format {
+        "json" => tracing_subscriber::fmt::layer().json().boxed(),
+        _ => tracing_subscriber::fmt::layer().boxed(),
+    };
+    let registry = tracing_subscriber::registry().with(fmt.with_filter(filter));
+    #[cfg(feature = "console")]
+    let registry = registry.with(console_subscriber::spawn());
+    registry.init();
+}
 
 #[tokio::main]
 async fn main() {
-    // Set the RUST_LOG, if it hasn't been explicitly defined
-    if std::env::var_os("RUST_LOG").is_none() {
-        std::env::set_var("RUST_LOG", "httpmq-rs=debug,tower_http=debug")
+    let app = cli::app();
+    let matches = app.clone().get_matches();
+    init_logging(
+        matches.value_of("log-format").unwrap(),
+        matches.value_of("log-level").unwrap(),
+    );
+    for (name, value, source) in cli::resolved(&app, &matches) {
+        tracing::info!(
+            "{} = {} ({})",
+            name,
+            value.as_deref().unwrap_or("unset"),
+            source
+        );
     }
-    tracing_subscriber::fmt::init();
 
-    let matches = App::new("httpmq-rs")
-        .bin_name("httpmq-rs")
-        .arg(
-            Arg::new("maxqueue")
-                .long("maxqueue")
-                .default_value("100000000"),
-        )
-        .get_matches();
+    let config = match Config::from_matches(&matches) {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = config.validate() {
+        tracing::error!("{}", e);
+        std::process::exit(2);
+    }
+    if config.chaos {
+        tracing::warn!("chaos mode: requests will be delayed, failed and dropped on purpose");
+    }
+    if let Some(from) = matches.value_of("restore-from") {
+        match backup::restore(Path::new(from), Path::new(&config.dbpath)) {
+            Ok(restored) => tracing::info!(
+                "restored {} files, {} bytes, from {}",
+                restored.files,
+                restored.bytes,
+                from
+            ),
+            Err(e) => {
+                tracing::error!("can't restore from {}: {}", from, e);
+                std::process::exit(OpenError::Failed(e.to_string()).exit_code());
+            }
+        }
+    }
+    let state = match State::open(config) {
+        Ok(state) => Arc::new(state),
+        Err(e) => {
+            tracing::error!("{}", e);
+            std::process::exit(e.exit_code());
+        }
+    };
+    if matches.is_present("fsck") {
+        let found = fsck_all(&state);
+        tracing::info!("fsck found {} corrupt metadata fields", found);
+    }
+    if state.config.preload_metadata {
+        let start = std::time::Instant::now();
+        let limit = std::time::Duration::from_secs(state.config.preload_timeout);
+        let (warmed, complete) = preload_metadata(&state, limit);
+        if complete {
+            tracing::info!(
+                "preloaded metadata of {} queues in {:.1?}",
+                warmed,
+                start.elapsed()
+            );
+        } else {
+            tracing::warn!(
+                "stopped preloading metadata after {:.1?}, {} queues warmed",
+                start.elapsed(),
+                warmed
+            );
+        }
+    }
+    stall::spawn_poller(state.clone());
+    totals::spawn_flusher(state.clone());
+    // a socket passed in is used as is, never in addition to binding one
+    let listener = match (state.config.inherit_fd, listener::systemd_fd()) {
+        (Some(_), Some(_)) => {
+            tracing::error!("--inherit-fd given, but systemd passed a socket too");
+            std::process::exit(2);
+        }
+        (Some(fd), None) | (None, Some(fd)) => {
+            let listener = listener::inherit(fd, &state.config).unwrap_or_else(|e| {
+                tracing::error!("can't use inherited fd {}: {}", fd, e);
+                std::process::exit(1);
+            });
+            tracing::info!(
+                "listening on {} (inherited fd {})",
+                listener.local_addr().unwrap(),
+                fd
+            );
+            listener
+        }
+        (None, None) => {
+            let config = &state.config;
+            let addr = match (&config.host[..], config.port).to_socket_addrs() {
+                Ok(mut addrs) => addrs.next(),
+                Err(e) => {
+                    tracing::error!("can't resolve --host {}: {}", config.host, e);
+                    std::process::exit(2);
+                }
+            };
+            let addr = addr.unwrap_or_else(|| {
+                tracing::error!("--host {} has no address", config.host);
+                std::process::exit(2);
+            });
+            let listener = listener::bind(addr, &state.config).unwrap_or_else(|e| {
+                tracing::error!("can't bind {}: {}", addr, e);
+                std::process::exit(1);
+            });
+            tracing::info!("listening on {} (bound)", addr);
+            listener
+        }
+    };
 
-    init(matches);
     // Build our application by composing routes
-    let app = Router::new()
-        .route("/", get(process))
-        // Add middleware to all routes
-        .layer(
-            ServiceBuilder::new()
-                // Handle errors from middleware
-                .layer(HandleErrorLayer::new(handle_error))
-                .load_shed()
-                .concurrency_limit(1024)
-                .timeout(Duration::from_secs(10))
-                // .layer(TraceLayer::new_for_http())
-                // .layer(AddExtensionLayer::new(state))
-                .into_inner(),
-        );
+    let app = limited_app(state.clone());
 
-    // Run our app with hyper
-    let addr = SocketAddr::from(([127, 0, 0, 1], 1218));
-    tracing::debug!("listening on {}", addr);
-    axum::Server::bind(&addr)
+    // Run our app with hyper until a signal, then stop accepting and let
+    // open connections finish for up to --shutdown-timeout
+    let drain = Duration::from_secs(state.config.shutdown_timeout);
+    let draining = Arc::new(Notify::new());
+    let server = axum::Server::builder(listener)
         .serve(app.into_make_service())
-        .await
-        .unwrap();
+        .with_graceful_shutdown({
+            let draining = draining.clone();
+            let state = state.clone();
+            async move {
+                shutdown::signal().await;
+                state.shutting_down.store(true, Ordering::Relaxed);
+                tracing::info!("shutting down, draining connections for up to {:?}", drain);
+                draining.notify_one();
+            }
+        });
+    tokio::select! {
+        res = server => {
+            res.unwrap();
+            tracing::info!("connections drained");
+        }
+        _ = async {
+            draining.notified().await;
+            tokio::time::sleep(drain).await;
+        } => tracing::warn!("connections still open after {:?}, closing anyway", drain),
+    }
+    shutdown::close(&state);
+    // background tasks hold the rest of the references, the database is
+    // closed once the runtime drops them
+    drop(state);
+    tracing::info!("stopped");
 }
@@ -0,0 +1,249 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::rate::Event;
+
+/// Label of the queues counted together once `max_queues` have their own.
+pub const OTHER_QUEUE: &str = "_other";
+
+// upper bounds of the latency histogram buckets, in seconds
+const BUCKETS: [f64; 10] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5, 1.0,
+];
+
+// picks one counter out of a queue's
+type Counter = fn(&QueueCounters) -> &AtomicU64;
+
+#[derive(Default)]
+struct QueueCounters {
+    puts: AtomicU64,
+    gets: AtomicU64,
+    // gets that found the queue drained
+    get_end: AtomicU64,
+    // puts refused because the queue was full
+    put_end: AtomicU64,
+    errors: AtomicU64,
+}
+
+struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram {
+            buckets: [(); BUCKETS.len()].map(|_| AtomicU64::new(0)),
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        if let Some(i) = BUCKETS.iter().position(|&le| secs <= le) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Counters for `/metrics`, in the Prometheus text format. Queues are
+/// labelled by name; past `max_queues` of them the rest are counted under
+/// `queue="_other"`, so the number of series stays bounded. A queue's
+/// series go away with the queue. Handler latency is only broken down by
+/// put, get and everything else.
+pub struct Metrics {
+    max_queues: usize,
+    queues: RwLock<HashMap<String, Arc<QueueCounters>>>,
+    other: Arc<QueueCounters>,
+    // indexed by Event::Put, Event::Get and Event::Other
+    latency: [Histogram; 3],
+}
+
+/// A queue's unread messages, worked out when `/metrics` is scraped.
+pub struct Unread {
+    pub queue: String,
+    pub unread: u64,
+}
+
+impl Metrics {
+    pub fn new(max_queues: usize) -> Metrics {
+        Metrics {
+            max_queues,
+            queues: RwLock::new(HashMap::new()),
+            other: Arc::new(QueueCounters::default()),
+            latency: [(); 3].map(|_| Histogram::new()),
+        }
+    }
+
+    fn counters(&self, name: &str) -> Arc<QueueCounters> {
+        if let Some(counters) = self.queues.read().unwrap().get(name) {
+            return counters.clone();
+        }
+        let mut queues = self.queues.write().unwrap();
+        if queues.len() >= self.max_queues && !queues.contains_key(name) {
+            return self.other.clone();
+        }
+        queues.entry(name.to_string()).or_default().clone()
+    }
+
+    /// Counts `messages` put to queue `name`.
+    pub fn record_put(&self, name: &str, messages: u64) {
+        self.counters(name)
+            .puts
+            .fetch_add(messages, Ordering::Relaxed);
+    }
+
+    /// Counts a message taken from queue `name`.
+    pub fn record_get(&self, name: &str) {
+        self.counters(name).gets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts what a request did, see `Event`, and how long it took.
+    pub fn record(&self, name: Option<&str>, events: &[Event], elapsed: Duration) {
+        let kind = match events.first() {
+            Some(Event::Put) => 0,
+            Some(Event::Get) => 1,
+            _ => 2,
+        };
+        self.latency[kind].observe(elapsed);
+        let name = match name {
+            Some(name) => name,
+            None => return,
+        };
+        // requests that only looked at a queue don't give it series
+        for event in events {
+            let counter: Counter = match event {
+                Event::Empty => |c| &c.get_end,
+                Event::Full => |c| &c.put_end,
+                Event::Error => |c| &c.errors,
+                _ => continue,
+            };
+            counter(&self.counters(name)).fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The queues with series of their own.
+    pub fn queues(&self) -> Vec<String> {
+        let mut queues: Vec<_> = self.queues.read().unwrap().keys().cloned().collect();
+        queues.sort();
+        queues
+    }
+
+    /// Drops the series of a queue that is going away.
+    pub fn remove(&self, name: &str) {
+        self.queues.write().unwrap().remove(name);
+    }
+
+    /// The exposition, with the unread gauge of the queues in `unread`.
+    pub fn render(&self, unread: &[Unread]) -> String {
+        let mut queues: Vec<_> = self
+            .queues
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, counters)| (name.clone(), counters.clone()))
+            .collect();
+        queues.sort_by(|a, b| a.0.cmp(&b.0));
+        queues.push((OTHER_QUEUE.to_string(), self.other.clone()));
+
+        let mut buf = String::new();
+        let counters: [(&str, &str, Counter); 5] = [
+            ("httpmq_puts_total", "Messages put.", |c| &c.puts),
+            ("httpmq_gets_total", "Messages taken by get.", |c| &c.gets),
+            (
+                "httpmq_get_end_total",
+                "Gets that found the queue drained.",
+                |c| &c.get_end,
+            ),
+            (
+                "httpmq_put_end_total",
+                "Puts refused because the queue was full.",
+                |c| &c.put_end,
+            ),
+            (
+                "httpmq_errors_total",
+                "Requests that failed with a server error.",
+                |c| &c.errors,
+            ),
+        ];
+        for (metric, help, counter) in counters {
+            let _ = writeln!(buf, "# HELP {} {}\n# TYPE {} counter", metric, help, metric);
+            for (name, c) in &queues {
+                let _ = writeln!(
+                    buf,
+                    "{}{{queue=\"{}\"}} {}",
+                    metric,
+                    escape(name),
+                    counter(c).load(Ordering::Relaxed)
+                );
+            }
+        }
+
+        let _ = writeln!(
+            buf,
+            "# HELP httpmq_unread_messages Messages put and not yet taken.\n\
+             # TYPE httpmq_unread_messages gauge"
+        );
+        for u in unread {
+            let _ = writeln!(
+                buf,
+                "httpmq_unread_messages{{queue=\"{}\"}} {}",
+                escape(&u.queue),
+                u.unread
+            );
+        }
+
+        let _ = writeln!(
+            buf,
+            "# HELP httpmq_request_duration_seconds Time spent handling a request.\n\
+             # TYPE httpmq_request_duration_seconds histogram"
+        );
+        for (opt, h) in ["put", "get", "other"].iter().zip(&self.latency) {
+            let mut cumulative = 0;
+            for (le, bucket) in BUCKETS.iter().zip(&h.buckets) {
+                cumulative += bucket.load(Ordering::Relaxed);
+                let _ = writeln!(
+                    buf,
+                    "httpmq_request_duration_seconds_bucket{{opt=\"{}\",le=\"{}\"}} {}",
+                    opt, le, cumulative
+                );
+            }
+            let count = h.count.load(Ordering::Relaxed);
+            let _ = writeln!(
+                buf,
+                "httpmq_request_duration_seconds_bucket{{opt=\"{}\",le=\"+Inf\"}} {}\n\
+                 httpmq_request_duration_seconds_sum{{opt=\"{}\"}} {}\n\
+                 httpmq_request_duration_seconds_count{{opt=\"{}\"}} {}",
+                opt,
+                count,
+                opt,
+                h.sum_micros.load(Ordering::Relaxed) as f64 / 1e6,
+                opt,
+                count
+            );
+        }
+        buf
+    }
+}
+
+// label values are quoted, with backslash, quote and newline escaped
+fn escape(value: &str) -> Cow<'_, str> {
+    if !value.contains(['\\', '"', '\n']) {
+        return Cow::Borrowed(value);
+    }
+    Cow::Owned(
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n"),
+    )
+}
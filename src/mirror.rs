@@ -0,0 +1,139 @@
+use rocksdb::{Direction, IteratorMode};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use tracing::warn;
+
+use crate::error::HttpmqError;
+use crate::storage::Storage;
+
+// mirrors are stored as #mirror#<source> => JSON, next to the aliases
+const MIRROR_PREFIX: &str = "#mirror#";
+
+fn mirror_key(source: &str) -> String {
+    format!("{}{}", MIRROR_PREFIX, source)
+}
+
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    target: String,
+    strict: bool,
+}
+
+/// The queue a source queue's puts are copied to.
+pub struct Mirror {
+    pub target: String,
+    // a put the target refuses fails on the source as well
+    pub strict: bool,
+    // counted since the mirror was attached or the server started
+    mirrored: AtomicU64,
+    full: AtomicU64,
+}
+
+impl Mirror {
+    fn new(target: &str, strict: bool) -> Mirror {
+        Mirror {
+            target: target.to_string(),
+            strict,
+            mirrored: AtomicU64::new(0),
+            full: AtomicU64::new(0),
+        }
+    }
+
+    /// Counts `messages` copied to the target.
+    pub fn record_mirrored(&self, messages: u64) {
+        self.mirrored.fetch_add(messages, Ordering::Relaxed);
+    }
+
+    /// Counts a put the target was too full to take.
+    pub fn record_full(&self) {
+        self.full.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn status(&self) -> MirrorStatus {
+        MirrorStatus {
+            target: self.target.clone(),
+            strict: self.strict,
+            mirrored: self.mirrored.load(Ordering::Relaxed),
+            full: self.full.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A mirror as shown in the status of its source queue.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MirrorStatus {
+    pub target: String,
+    pub strict: bool,
+    pub mirrored: u64,
+    // puts the target was full for
+    pub full: u64,
+}
+
+/// Queues whose puts are also copied, in the same write, to a mirror
+/// queue, e.g. to look at a queue's traffic without touching its consumer.
+/// Mirrors aren't followed on: a put copied to a target that is mirrored
+/// itself stops there.
+pub struct Mirrors {
+    inner: RwLock<HashMap<String, Arc<Mirror>>>,
+}
+
+impl Mirrors {
+    pub fn load(db: &dyn Storage) -> Mirrors {
+        let mut mirrors = HashMap::new();
+        let mode = IteratorMode::From(MIRROR_PREFIX.as_bytes(), Direction::Forward);
+        for (key, value) in db.raw().iterator(mode) {
+            let source = match key.strip_prefix(MIRROR_PREFIX.as_bytes()) {
+                Some(source) => String::from_utf8_lossy(source).to_string(),
+                None => break,
+            };
+            match serde_json::from_slice::<Stored>(&value) {
+                Ok(stored) => {
+                    mirrors.insert(source, Arc::new(Mirror::new(&stored.target, stored.strict)));
+                }
+                Err(e) => warn!("ignoring unreadable mirror of queue {}: {}", source, e),
+            }
+        }
+        Mirrors {
+            inner: RwLock::new(mirrors),
+        }
+    }
+
+    /// The mirror of queue `source`, if it has one.
+    pub fn get(&self, source: &str) -> Option<Arc<Mirror>> {
+        self.inner.read().unwrap().get(source).cloned()
+    }
+
+    /// Mirrors queue `source` to `target`, replacing any earlier mirror.
+    pub fn attach(
+        &self,
+        db: &dyn Storage,
+        source: &str,
+        target: &str,
+        strict: bool,
+    ) -> Result<(), HttpmqError> {
+        let mut inner = self.inner.write().unwrap();
+        let stored = Stored {
+            target: target.to_string(),
+            strict,
+        };
+        db.put(
+            mirror_key(source).as_bytes(),
+            &serde_json::to_vec(&stored).unwrap(),
+        )?;
+        inner.insert(source.to_string(), Arc::new(Mirror::new(target, strict)));
+        Ok(())
+    }
+
+    /// Stops mirroring queue `source`, false if it wasn't mirrored.
+    pub fn detach(&self, db: &dyn Storage, source: &str) -> Result<bool, HttpmqError> {
+        let mut inner = self.inner.write().unwrap();
+        if !inner.contains_key(source) {
+            return Ok(false);
+        }
+        db.delete(mirror_key(source).as_bytes())?;
+        inner.remove(source);
+        Ok(true)
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::envelope::Header;
+use crate::error::{HttpmqError, OpenError};
+use crate::service::{self, QueueStatus};
+use crate::state::{Config, SharedState, State};
+
+/// Why a queue operation didn't go through.
+#[derive(Debug)]
+pub enum QueueError {
+    // the queue said no, with the sentinel the HTTP API answers, e.g.
+    // HTTPMQ_PUT_END for a full queue
+    Refused(&'static str),
+    Failed(HttpmqError),
+}
+
+impl From<HttpmqError> for QueueError {
+    fn from(e: HttpmqError) -> QueueError {
+        QueueError::Failed(e)
+    }
+}
+
+impl fmt::Display for QueueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueueError::Refused(result) => write!(f, "refused: {}", result),
+            QueueError::Failed(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for QueueError {}
+
+/// A message taken by `QueueStore::get`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub pos: u64,
+    // unless it was put before sequence numbers existed
+    pub seq: Option<u64>,
+    pub data: Vec<u8>,
+    pub attrs: BTreeMap<String, String>,
+    pub content_type: Option<String>,
+}
+
+/// The queues without the HTTP layer, for programs that embed httpmq. It
+/// runs the code the handlers do, on the same keys, so a store opened on a
+/// server's database, or made from its state, sees the same queues. Names
+/// are checked against the naming policy but aliases aren't followed, and
+/// there are no tokens to pass. Defaults such as maxqueue come from the
+/// store's own Config.
+#[derive(Clone)]
+pub struct QueueStore {
+    state: SharedState,
+}
+
+impl QueueStore {
+    /// Opens the database at `config.dbpath`.
+    pub fn open(config: Config) -> Result<QueueStore, OpenError> {
+        config.validate().map_err(OpenError::Failed)?;
+        Ok(QueueStore::from_state(Arc::new(State::open(config)?)))
+    }
+
+    /// A store on the state a server is running with.
+    pub fn from_state(state: SharedState) -> QueueStore {
+        QueueStore { state }
+    }
+
+    pub fn state(&self) -> &SharedState {
+        &self.state
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.state.config
+    }
+
+    fn check_name(&self, name: &str) -> Result<(), QueueError> {
+        if !service::valid_name(&self.state.config, name) {
+            return Err(HttpmqError::NameInvalid.into());
+        }
+        Ok(())
+    }
+
+    /// Puts `data` on queue `name`, or on its subscribers when it is a
+    /// topic. Returns the position it was put at, 0 for a topic.
+    pub async fn put(&self, name: &str, data: &[u8]) -> Result<u64, QueueError> {
+        self.check_name(name)?;
+        let res = service::kv_set(
+            &self.state,
+            name,
+            vec![data.to_vec()],
+            false,
+            &Header::default(),
+        )
+        .await?;
+        match res.result {
+            "HTTPMQ_PUT_OK" => Ok(res.pos.unwrap_or(0)),
+            result => Err(QueueError::Refused(result)),
+        }
+    }
+
+    /// Takes the next message of queue `name`, None when it is drained.
+    pub async fn get(&self, name: &str) -> Result<Option<Message>, QueueError> {
+        self.check_name(name)?;
+        let res = service::httpmq_get(&self.state, name).await?;
+        match res.result {
+            "HTTPMQ_GET_OK" => Ok(Some(Message {
+                pos: res.pos,
+                seq: res.seq,
+                data: res.data.map(|data| data.into_vec()).unwrap_or_default(),
+                attrs: res.attrs,
+                content_type: res.content_type,
+            })),
+            "HTTPMQ_GET_END" => Ok(None),
+            result => Err(QueueError::Refused(result)),
+        }
+    }
+
+    /// The status of queue `name`, as opt=status_json reports it.
+    pub fn status(&self, name: &str) -> Result<QueueStatus, QueueError> {
+        self.check_name(name)?;
+        Ok(service::httpmq_status(&self.state, name)?)
+    }
+
+    /// Drops the messages of queue `name` and puts it back to the default
+    /// maxqueue.
+    pub async fn reset(&self, name: &str) -> Result<(), QueueError> {
+        self.check_name(name)?;
+        match service::httpmq_reset(&self.state, name).await? {
+            "HTTPMQ_RESET_OK" => Ok(()),
+            result => Err(QueueError::Refused(result)),
+        }
+    }
+
+    /// Gives queue `name` `maxqueue` slots, see opt=maxqueue.
+    pub async fn set_maxqueue(&self, name: &str, maxqueue: u64) -> Result<(), QueueError> {
+        self.check_name(name)?;
+        match service::httpmq_set_maxqueue(&self.state, name, maxqueue).await? {
+            "HTTPMQ_MAXQUEUE_OK" => Ok(()),
+            result => Err(QueueError::Refused(result)),
+        }
+    }
+}
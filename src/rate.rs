@@ -0,0 +1,216 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::Instant;
+
+// history kept per counter, in one-second slots
+const SLOTS: usize = 60;
+
+/// Widths, in seconds, of the windows rates are reported over.
+pub const RATE_WINDOWS: [u64; 2] = [10, 60];
+
+// queues with their own counters, the rest share the "other" ones
+const TRACKED_QUEUES: usize = 64;
+
+/// What a request did. Every request counts as one of Put, Get or Other,
+/// Full, Empty and Error come on top of that.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    Put,
+    Get,
+    Other,
+    // a put refused because the queue was full
+    Full,
+    // a request that failed with a server error
+    Error,
+    // a get that found the queue drained
+    Empty,
+}
+
+const EVENTS: usize = 6;
+
+// A ring of per-second counts. Each slot remembers the second it counts
+// for and is zeroed by the first increment of a new second. An increment
+// racing with that reset can get lost, which is fine for a rate.
+struct Window {
+    secs: [AtomicU64; SLOTS],
+    counts: [AtomicU64; SLOTS],
+}
+
+impl Window {
+    fn new() -> Window {
+        Window {
+            secs: [(); SLOTS].map(|_| AtomicU64::new(0)),
+            counts: [(); SLOTS].map(|_| AtomicU64::new(0)),
+        }
+    }
+
+    fn incr(&self, sec: u64) {
+        let i = sec as usize % SLOTS;
+        let seen = self.secs[i].load(Ordering::Acquire);
+        if seen != sec
+            && self.secs[i]
+                .compare_exchange(seen, sec, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        {
+            self.counts[i].store(0, Ordering::Release);
+        }
+        self.counts[i].fetch_add(1, Ordering::Relaxed);
+    }
+
+    // events during the `width` seconds up to and including `sec`
+    fn sum(&self, sec: u64, width: u64) -> u64 {
+        (0..width.min(sec + 1))
+            .map(|back| {
+                let i = (sec - back) as usize % SLOTS;
+                if self.secs[i].load(Ordering::Acquire) == sec - back {
+                    self.counts[i].load(Ordering::Relaxed)
+                } else {
+                    0
+                }
+            })
+            .sum()
+    }
+}
+
+struct Counters {
+    events: [Window; EVENTS],
+}
+
+impl Counters {
+    fn new() -> Counters {
+        Counters {
+            events: [(); EVENTS].map(|_| Window::new()),
+        }
+    }
+
+    fn incr(&self, events: &[Event], sec: u64) {
+        for &event in events {
+            self.events[event as usize].incr(sec);
+        }
+    }
+
+    fn idle(&self, sec: u64) -> bool {
+        self.events.iter().all(|w| w.sum(sec, SLOTS as u64) == 0)
+    }
+
+    fn throughput(&self, sec: u64) -> Vec<Throughput> {
+        RATE_WINDOWS
+            .iter()
+            .map(|&width| {
+                let rate = |event: Event| self.events[event as usize].sum(sec, width);
+                let requests = rate(Event::Put) + rate(Event::Get) + rate(Event::Other);
+                let per_sec = |n: u64| n as f64 / width as f64;
+                Throughput {
+                    window_secs: width,
+                    puts: per_sec(rate(Event::Put)),
+                    gets: per_sec(rate(Event::Get)),
+                    full: per_sec(rate(Event::Full)),
+                    errors: per_sec(rate(Event::Error)),
+                    error_rate: rate(Event::Error) as f64 / requests.max(1) as f64,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Request rates over one window, per second except for `error_rate`,
+/// the share of requests that failed.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Throughput {
+    pub window_secs: u64,
+    pub puts: f64,
+    pub gets: f64,
+    pub full: f64,
+    pub errors: f64,
+    pub error_rate: f64,
+}
+
+/// Sliding-window request rates, overall and per queue. Counting only
+/// touches atomics under a shared lock; the first `TRACKED_QUEUES` active
+/// queues get their own counters and idle ones make room for new ones.
+pub struct Rates {
+    start: Instant,
+    all: Counters,
+    // queues that didn't get counters of their own
+    other: Counters,
+    queues: RwLock<HashMap<String, Counters>>,
+    swept: AtomicU64,
+}
+
+impl Rates {
+    pub fn new() -> Rates {
+        Rates {
+            start: Instant::now(),
+            all: Counters::new(),
+            other: Counters::new(),
+            queues: RwLock::new(HashMap::new()),
+            swept: AtomicU64::new(0),
+        }
+    }
+
+    fn sec(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.start).as_secs()
+    }
+
+    /// Counts a request, for queue `name` unless it was for none.
+    pub fn record(&self, name: Option<&str>, events: &[Event]) {
+        self.record_at(name, events, Instant::now())
+    }
+
+    pub fn record_at(&self, name: Option<&str>, events: &[Event], now: Instant) {
+        let sec = self.sec(now);
+        self.all.incr(events, sec);
+        let name = match name {
+            Some(name) => name,
+            None => return,
+        };
+        if let Some(counters) = self.queues.read().unwrap().get(name) {
+            counters.incr(events, sec);
+            return;
+        }
+
+        let mut queues = self.queues.write().unwrap();
+        // at most once a second, make room by forgetting idle queues
+        if queues.len() >= TRACKED_QUEUES && self.swept.swap(sec, Ordering::Relaxed) != sec {
+            queues.retain(|_, counters| !counters.idle(sec));
+        }
+        if queues.len() < TRACKED_QUEUES {
+            let counters = queues.entry(name.to_string()).or_insert_with(Counters::new);
+            counters.incr(events, sec);
+        } else {
+            self.other.incr(events, sec);
+        }
+    }
+
+    /// Rates across all requests.
+    pub fn total(&self) -> Vec<Throughput> {
+        self.all.throughput(self.sec(Instant::now()))
+    }
+
+    /// Rates of the queues without counters of their own.
+    pub fn other(&self) -> Vec<Throughput> {
+        self.other.throughput(self.sec(Instant::now()))
+    }
+
+    /// Rates of queue `name`, None if it isn't tracked individually.
+    pub fn queue(&self, name: &str) -> Option<Vec<Throughput>> {
+        self.queue_at(name, Instant::now())
+    }
+
+    pub fn queue_at(&self, name: &str, now: Instant) -> Option<Vec<Throughput>> {
+        let sec = self.sec(now);
+        self.queues
+            .read()
+            .unwrap()
+            .get(name)
+            .map(|counters| counters.throughput(sec))
+    }
+}
+
+impl Default for Rates {
+    fn default() -> Rates {
+        Rates::new()
+    }
+}
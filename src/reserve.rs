@@ -0,0 +1,198 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::clock::Clock;
+
+/// Messages handed out and not yet settled. A `mode=reserve` get holds the
+/// whole queue, at most one per queue, until it is committed or times out.
+/// An `ack=manual` get leases just its message's position, under a lease id
+/// to settle it by, so other consumers go on with the messages after it.
+/// All of it lives in memory only, so a restart drops it and the messages
+/// are delivered again. How often each message has gone out unsettled is
+/// counted here too, and just as easily lost.
+pub struct Reservations {
+    timeout: Duration,
+    // queue name to when its mode=reserve reservation times out
+    held: Mutex<HashMap<String, Instant>>,
+    // queue name to its leased positions
+    leases: Mutex<HashMap<String, BTreeMap<u64, Lease>>>,
+    // queue name to the deliveries of its messages by position
+    delivered: Mutex<HashMap<String, HashMap<u64, u64>>>,
+    // started off the clock, so ids from before a restart don't come round
+    // again
+    next_lease: AtomicU64,
+}
+
+struct Lease {
+    until: Instant,
+    // None while a nacked message waits out its delay, nobody can settle it
+    id: Option<u64>,
+}
+
+impl Reservations {
+    /// Lease ids start off `clock`'s wall time. Deadlines are the `now` the
+    /// callers pass, from the same clock.
+    pub fn new(timeout: Duration, clock: &dyn Clock) -> Reservations {
+        let start = (clock.unix_millis() * 1000) as u64;
+        Reservations {
+            timeout,
+            held: Mutex::new(HashMap::new()),
+            leases: Mutex::new(HashMap::new()),
+            delivered: Mutex::new(HashMap::new()),
+            next_lease: AtomicU64::new(start),
+        }
+    }
+
+    /// Whether queue `name` has a reservation that hasn't timed out.
+    pub fn is_held_at(&self, name: &str, now: Instant) -> bool {
+        self.held
+            .lock()
+            .unwrap()
+            .get(name)
+            .is_some_and(|&until| now < until)
+    }
+
+    /// Takes the reservation of queue `name`, false if it is held. One that
+    /// timed out is taken over.
+    pub fn try_reserve_at(&self, name: &str, now: Instant) -> bool {
+        let mut held = self.held.lock().unwrap();
+        if held.get(name).is_some_and(|&until| now < until) {
+            return false;
+        }
+        held.insert(name.to_string(), now + self.timeout);
+        true
+    }
+
+    /// Drops the reservation of queue `name`, the message goes out again.
+    pub fn release(&self, name: &str) {
+        self.held.lock().unwrap().remove(name);
+    }
+
+    /// The leased positions of queue `name`. Leases that timed out are
+    /// reclaimed on the way, their messages go out again.
+    pub fn leased_at(&self, name: &str, now: Instant) -> BTreeSet<u64> {
+        let mut leases = self.leases.lock().unwrap();
+        let Some(queue) = leases.get_mut(name) else {
+            return BTreeSet::new();
+        };
+        queue.retain(|_, lease| now < lease.until);
+        let leased = queue.keys().copied().collect();
+        if queue.is_empty() {
+            leases.remove(name);
+        }
+        leased
+    }
+
+    /// Leases the message at `pos` of queue `name` and returns the lease id.
+    pub fn lease_at(&self, name: &str, pos: u64, now: Instant) -> u64 {
+        let id = self.next_lease.fetch_add(1, Ordering::Relaxed);
+        let until = now + self.timeout;
+        self.leases
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .insert(
+                pos,
+                Lease {
+                    until,
+                    id: Some(id),
+                },
+            );
+        id
+    }
+
+    /// Holds the message at `pos` of queue `name` back until `until`
+    /// without a lease, so a nacked message goes out again no earlier. The
+    /// queue's other messages go out meanwhile.
+    pub fn delay_at(&self, name: &str, pos: u64, until: Instant) {
+        self.leases
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .insert(pos, Lease { until, id: None });
+    }
+
+    /// The position lease `id` of queue `name` is on, None if there is no
+    /// such lease or it timed out.
+    pub fn leased_pos(&self, name: &str, id: u64, now: Instant) -> Option<u64> {
+        self.leases
+            .lock()
+            .unwrap()
+            .get(name)?
+            .iter()
+            .find(|(_, lease)| lease.id == Some(id) && now < lease.until)
+            .map(|(&pos, _)| pos)
+    }
+
+    /// Drops the lease on `pos` of queue `name`, the message goes out again.
+    pub fn unlease(&self, name: &str, pos: u64) {
+        let mut leases = self.leases.lock().unwrap();
+        if let Some(queue) = leases.get_mut(name) {
+            queue.remove(&pos);
+            if queue.is_empty() {
+                leases.remove(name);
+            }
+        }
+    }
+
+    /// How many messages of queue `name` are out, under a reservation or a
+    /// lease, that hasn't timed out. Delayed ones aren't out.
+    pub fn in_flight_at(&self, name: &str, now: Instant) -> u64 {
+        let leased = self.leases.lock().unwrap().get(name).map_or(0, |queue| {
+            queue
+                .values()
+                .filter(|lease| lease.id.is_some() && now < lease.until)
+                .count()
+        });
+        leased as u64 + u64::from(self.is_held_at(name, now))
+    }
+
+    /// Forgets the lease on `pos` of queue `name` and its delivery count,
+    /// once the message is consumed.
+    pub fn settle_pos(&self, name: &str, pos: u64) {
+        self.unlease(name, pos);
+        let mut delivered = self.delivered.lock().unwrap();
+        if let Some(queue) = delivered.get_mut(name) {
+            queue.remove(&pos);
+            if queue.is_empty() {
+                delivered.remove(name);
+            }
+        }
+    }
+
+    /// Drops everything held on queue `name` and its delivery counts, once
+    /// its messages are gone or rewound.
+    pub fn settle(&self, name: &str) {
+        self.release(name);
+        self.leases.lock().unwrap().remove(name);
+        self.delivered.lock().unwrap().remove(name);
+    }
+
+    /// Counts a delivery of the message at `pos` of queue `name` and
+    /// returns how many it has had.
+    pub fn record_delivery(&self, name: &str, pos: u64) -> u64 {
+        let mut delivered = self.delivered.lock().unwrap();
+        let count = delivered
+            .entry(name.to_string())
+            .or_default()
+            .entry(pos)
+            .or_insert(0);
+        *count += 1;
+        *count
+    }
+
+    /// How many times the message at `pos` of queue `name` has gone out.
+    pub fn deliveries(&self, name: &str, pos: u64) -> u64 {
+        self.delivered
+            .lock()
+            .unwrap()
+            .get(name)
+            .and_then(|queue| queue.get(&pos))
+            .copied()
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,60 @@
+// The arithmetic of a queue's ring of `maxqueue` slots. putpos is the slot
+// last written and getpos the slot last read, 0 before the first of either;
+// puts stop one slot short of getpos, so equal positions mean drained.
+
+/// The slot the next put writes, 0 when the queue is full.
+pub fn next_putpos(maxqueue: u64, putpos: u64, getpos: u64) -> u64 {
+    // widened, putpos may be u64::MAX
+    let next = i128::from(putpos) + 1;
+    if next == i128::from(getpos) {
+        0
+    } else if next > i128::from(maxqueue) {
+        // the first slot is free once a get has read past it
+        if getpos <= 1 {
+            0
+        } else {
+            1
+        }
+    } else {
+        next as u64
+    }
+}
+
+/// The slot the next get reads, 0 when the queue is drained.
+// The branches mirror the lap cases of the original httpmq.
+#[allow(clippy::if_same_then_else)]
+pub fn next_getpos(maxqueue: u64, putpos: u64, getpos: u64) -> u64 {
+    if getpos == 0 && putpos > 0 {
+        1 // first get
+    } else if getpos < putpos {
+        getpos + 1 // 1st lap
+    } else if getpos > putpos && getpos < maxqueue {
+        getpos + 1 // 2nd lap
+    } else if getpos > putpos && getpos == maxqueue {
+        1 // wrapping round to the 2nd lap
+    } else {
+        0 // everything put has been read
+    }
+}
+
+/// Messages put and not yet read: how many gets in a row next_getpos lets
+/// through.
+pub fn unread(maxqueue: u64, putpos: u64, getpos: u64) -> u64 {
+    if putpos >= getpos {
+        putpos - getpos
+    } else if getpos <= maxqueue {
+        maxqueue - getpos + putpos
+    } else {
+        // past the end of a shrunk or hand-edited ring, gets stop there
+        0
+    }
+}
+
+/// 1 while putpos is on the lap getpos is on, 2 once it has wrapped ahead.
+pub fn putlap(putpos: u64, getpos: u64) -> i32 {
+    if putpos >= getpos {
+        1
+    } else {
+        2
+    }
+}
@@ -0,0 +1,73 @@
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+
+/// A sample of the tokio runtime, to tell a starved runtime from a slow
+/// database when latency goes up.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RuntimeStats {
+    pub workers: usize,
+    // percent of the time since the previous sample each worker was busy
+    pub busy_percent: Vec<f64>,
+    // tasks waiting in the shared queue for a worker to pick them up
+    pub injection_queue_depth: usize,
+    // tasks waiting for a blocking thread, only known in builds with
+    // RUSTFLAGS="--cfg tokio_unstable"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocking_queue_depth: Option<usize>,
+}
+
+/// Samples runtime metrics on demand. Busy time is reported relative to
+/// the previous sample, so the first one covers the time since startup.
+pub struct RuntimeSampler {
+    last: Mutex<(Instant, Vec<Duration>)>,
+}
+
+impl RuntimeSampler {
+    pub fn new() -> RuntimeSampler {
+        RuntimeSampler {
+            last: Mutex::new((Instant::now(), Vec::new())),
+        }
+    }
+
+    /// Samples the runtime running the caller.
+    pub fn sample(&self) -> RuntimeStats {
+        let metrics = Handle::current().metrics();
+        let workers = metrics.num_workers();
+        let busy: Vec<Duration> = (0..workers)
+            .map(|i| metrics.worker_total_busy_duration(i))
+            .collect();
+
+        let now = Instant::now();
+        let mut last = self.last.lock().unwrap();
+        let elapsed = now.duration_since(last.0).as_secs_f64().max(f64::EPSILON);
+        let busy_percent = busy
+            .iter()
+            .enumerate()
+            .map(|(i, total)| {
+                let before = last.1.get(i).copied().unwrap_or_default();
+                (total.saturating_sub(before).as_secs_f64() / elapsed * 100.0).min(100.0)
+            })
+            .collect();
+        *last = (now, busy);
+
+        #[cfg(tokio_unstable)]
+        let blocking_queue_depth = Some(metrics.blocking_queue_depth());
+        #[cfg(not(tokio_unstable))]
+        let blocking_queue_depth = None;
+
+        RuntimeStats {
+            workers,
+            busy_percent,
+            injection_queue_depth: metrics.global_queue_depth(),
+            blocking_queue_depth,
+        }
+    }
+}
+
+impl Default for RuntimeSampler {
+    fn default() -> RuntimeSampler {
+        RuntimeSampler::new()
+    }
+}
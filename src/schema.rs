@@ -0,0 +1,136 @@
+use rocksdb::{IteratorMode, WriteBatch};
+use std::collections::HashSet;
+use tracing::{info, warn};
+
+use crate::chunk;
+use crate::error::{HttpmqError, OpenError};
+use crate::service::{message_key, METADATA_FIELDS};
+use crate::storage::Storage;
+
+/// Version of the on-disk layout this build reads and writes.
+pub const SCHEMA_VERSION: u32 = 1;
+
+// reserved key holding the layout version of the database. It can't clash
+// with a queue: message keys contain ':' and metadata keys '.'.
+pub const VERSION_KEY: &str = "__schema_version";
+
+// keys a migration writes are flushed in batches of this many
+const MIGRATE_BATCH: usize = 1000;
+
+/// A forward migration from version `to - 1` to `to`. Migrations must be
+/// idempotent: one that was interrupted runs again from the start.
+struct Migration {
+    to: u32,
+    description: &'static str,
+    run: fn(&dyn Storage) -> Result<(), HttpmqError>,
+}
+
+const MIGRATIONS: &[Migration] = &[Migration {
+    to: 1,
+    description: "separate message positions from queue names with ':'",
+    run: migrate_message_keys,
+}];
+
+fn read_version(db: &dyn Storage) -> Result<u32, OpenError> {
+    match db.get(VERSION_KEY.as_bytes())? {
+        Some(raw) => std::str::from_utf8(&raw)
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .ok_or_else(|| OpenError::Corrupt(format!("{} is {:?}", VERSION_KEY, raw))),
+        // a database from before the version key, unless it is empty
+        None if db.raw().iterator(IteratorMode::Start).next().is_some() => Ok(0),
+        None => Ok(SCHEMA_VERSION),
+    }
+}
+
+fn write_version(db: &dyn Storage, version: u32) -> Result<(), OpenError> {
+    Ok(db.put(VERSION_KEY.as_bytes(), version.to_string().as_bytes())?)
+}
+
+/// Brings the database up to `SCHEMA_VERSION`, running the migrations it
+/// is missing in order. Refuses databases written by a newer build.
+pub fn migrate(db: &dyn Storage) -> Result<(), OpenError> {
+    let version = read_version(db)?;
+    if version > SCHEMA_VERSION {
+        return Err(OpenError::SchemaTooNew {
+            found: version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.to > version) {
+        info!(
+            "schema migration {} -> {}: {}",
+            migration.to - 1,
+            migration.to,
+            migration.description
+        );
+        (migration.run)(db)?;
+        write_version(db, migration.to)?;
+    }
+    if db.get(VERSION_KEY.as_bytes())?.is_none() {
+        write_version(db, SCHEMA_VERSION)?;
+    }
+    Ok(())
+}
+
+// The queue and position a version 0 message key `<name><pos>` stands for.
+// Of the queues the key could belong to, the one with the longest name
+// wins: a key both could claim was already shared by the two queues.
+fn split_legacy_key(key: &str, queues: &HashSet<String>) -> Option<(String, u64)> {
+    let mut found = None;
+    let mut candidates = 0;
+    for (i, c) in key.char_indices().skip(1) {
+        if c == '0' || !key[i..].bytes().all(|b| b.is_ascii_digit()) {
+            continue;
+        }
+        if let (true, Ok(pos)) = (queues.contains(&key[..i]), key[i..].parse::<u64>()) {
+            candidates += 1;
+            found = Some((key[..i].to_string(), pos));
+        }
+    }
+    if candidates > 1 {
+        warn!("message key {} was shared by {} queues", key, candidates);
+    }
+    found
+}
+
+// version 0 stored messages under `<name><pos>`, so queue "a" position 11
+// and queue "a1" position 1 were the same key
+fn migrate_message_keys(db: &dyn Storage) -> Result<(), HttpmqError> {
+    let mut queues = HashSet::new();
+    for (key, _) in db.raw().iterator(IteratorMode::Start) {
+        let key = String::from_utf8_lossy(&key);
+        if let Some((name, field)) = key.rsplit_once('.') {
+            if METADATA_FIELDS.contains(&field) {
+                queues.insert(name.to_string());
+            }
+        }
+    }
+
+    let mut batch = WriteBatch::default();
+    let mut moved = 0;
+    for (key, value) in db.raw().iterator(IteratorMode::Start) {
+        let key = match std::str::from_utf8(&key) {
+            // chunks move with their manifest, topics and aliases stay
+            Ok(key) if !key.contains([':', '#']) => key,
+            _ => continue,
+        };
+        // metadata and settings keys never end in a position
+        let (name, pos) = match split_legacy_key(key, &queues) {
+            Some(split) => split,
+            None => continue,
+        };
+        let new_key = message_key(&name, pos);
+        chunk::rename_chunks(db, &mut batch, key, &new_key, &value)?;
+        batch.put(&new_key, &value);
+        batch.delete(key);
+        moved += 1;
+        if batch.len() >= MIGRATE_BATCH {
+            db.write(std::mem::take(&mut batch))?;
+        }
+    }
+    db.write(batch)?;
+    info!("moved {} messages to the new key layout", moved);
+    Ok(())
+}
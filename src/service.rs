@@ -1,246 +1,4090 @@
-use axum::{extract::Query, http::StatusCode, response::IntoResponse};
-use clap::ArgMatches;
-use once_cell::sync::OnceCell;
-use rocksdb::{WriteBatch, DB};
-use serde::Deserialize;
-use std::{borrow::Cow, str};
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::{rejection::QueryRejection, Extension, Query, RawBody},
+    http::{
+        header::{self, HeaderName},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{Headers, IntoResponse, Response},
+};
+use rocksdb::{DBIterator, Direction, IteratorMode, Snapshot, WriteBatch};
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    collections::BTreeSet,
+    collections::HashSet,
+    fmt::{self, Write},
+    path::{Path, PathBuf},
+    str,
+    sync::atomic::{AtomicU64, Ordering},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tower::BoxError;
-use tracing::debug;
+use tracing::{debug, field, info, info_span, trace, warn, Instrument, Span};
 
-pub static DEFAULT_MAX_QUEUE_CELL: OnceCell<i32> = OnceCell::new();
-pub static DATABASE: OnceCell<DB> = OnceCell::new();
+use crate::auth::token_matches;
+use crate::background::BackgroundStats;
+use crate::backup::{self, BackupError};
+use crate::bucket::PutRate;
+use crate::chaos::{Fault, Injection, CHAOS_HEADER, REQUEST_ID_HEADER};
+use crate::chunk::{self, Stored};
+use crate::envelope::{self, Header};
+use crate::error::HttpmqError;
+use crate::format::{self, Format};
+use crate::hot::{QueueOps, WINDOW_SECS};
+use crate::load::LoadStats;
+use crate::metrics::Unread;
+use crate::mirror::{Mirror, MirrorStatus};
+use crate::rate::{Event, Throughput};
+use crate::ring;
+use crate::runtime::RuntimeStats;
+use crate::settings::{Delivery, QueueSettings};
+use crate::stall::StallStats;
+use crate::state::{Config, SharedState, State, MAXQUEUE_LIMIT};
+use crate::storage::View;
+use crate::totals::QueueTotals;
+
+pub(crate) const METADATA_FIELDS: [&str; 4] = ["maxqueue", "putpos", "getpos", "readonly"];
+
+/// Key of the message at `pos` in queue `name`. The separator keeps queue
+/// "a" position 11 apart from queue "a1" position 1.
+pub fn message_key(name: &str, pos: u64) -> String {
+    format!("{}:{}", name, pos)
+}
+
+// the keys of every message (and chunk) of queue name sort between these
+fn message_span(name: &str) -> (String, String) {
+    (format!("{}:", name), format!("{};", name))
+}
+
+// values of the readonly field, 0 (or no field) is a writable queue
+const READONLY_PEEK: u64 = 1;
+const READONLY_ADVANCE: u64 = 2;
+
+// a read-only queue whose gets leave the cursor alone
+fn is_peek_only(readonly: u64) -> bool {
+    readonly != 0 && readonly != READONLY_ADVANCE
+}
+
+fn readonly_mode(readonly: u64) -> Option<&'static str> {
+    match readonly {
+        0 => None,
+        READONLY_ADVANCE => Some("advance"),
+        _ => Some("peek"),
+    }
+}
+
+fn parse_metadata(raw: &[u8]) -> Option<u64> {
+    str::from_utf8(raw).ok()?.parse::<u64>().ok()
+}
 
 // httpmq read metadata api
 // retrieve from leveldb
 // name.maxqueue - maxqueue
 // name.putpos - putpos
 // name.getpos - getpos
-fn httpmq_read_metadata(db: &rocksdb::DB, name: &String) -> Option<Vec<i32>> {
-    let mut result: Vec<_> = db
-        .multi_get(vec![
-            name.to_string() + ".maxqueue",
-            name.to_string() + ".putpos",
-            name.to_string() + ".getpos",
-        ])
+// name.readonly - read-only mode
+fn httpmq_read_metadata(state: &State, name: &str) -> Result<Vec<u64>, HttpmqError> {
+    httpmq_read_metadata_in(state, &View::Live(&*state.db), name)
+}
+
+fn httpmq_read_metadata_in(
+    state: &State,
+    view: &View,
+    name: &str,
+) -> Result<Vec<u64>, HttpmqError> {
+    let mut result = Vec::with_capacity(3);
+    let keys = METADATA_FIELDS
         .iter()
-        .map(|x| match x {
-            Ok(Some(xx)) => str::from_utf8(xx).unwrap().parse::<i32>().unwrap(),
-            _ => 0,
-        })
+        .map(|f| format!("{}.{}", name, f).into_bytes())
         .collect();
+    for (field, x) in METADATA_FIELDS.iter().zip(view.multi_get(keys)) {
+        result.push(match x {
+            Ok(Some(xx)) => match parse_metadata(&xx) {
+                Some(v) => v,
+                None => {
+                    // quarantine the queue until fsck repairs the field
+                    warn!("corrupt metadata {}.{}: {:?}", name, field, xx);
+                    state.corrupt.lock().unwrap().insert(name.to_string());
+                    return Err(HttpmqError::QueueCorrupt(name.to_string()));
+                }
+            },
+            Ok(None) => 0,
+            // a failed read must not look like a new queue, or the next
+            // put would start over at position 1 and overwrite live data
+            Err(e) => return Err(e),
+        });
+    }
 
-    debug!("result {:?}", result);
+    trace!("result {:?}", result);
     if result[0] == 0 {
-        result[0] = *DEFAULT_MAX_QUEUE_CELL.get().unwrap();
+        result[0] = state.config.maxqueue;
     }
-    Some(result)
+    Ok(result)
 }
 
-fn httpmq_now_getpos(db: &rocksdb::DB, name: &String) -> Option<i32> {
-    let metadata = httpmq_read_metadata(db, name);
-    let maxqueue = metadata.as_ref()?[0];
-    let putpos = metadata.as_ref()?[1];
-    let mut getpos = metadata.as_ref()?[2];
+// next position to read, 0 when the queue is drained. The position is not
+// persisted here, kv_get commits it once it knows what the slot holds.
+fn httpmq_next_getpos(metadata: &[u64]) -> u64 {
+    let getpos = ring::next_getpos(metadata[0], metadata[1], metadata[2]);
+    trace!("getpos {} {:?}", getpos, metadata);
+    getpos
+}
 
-    if getpos == 0 && putpos > 0 {
-        getpos = 1 // first get operation, set getpos 1
-    } else if getpos < putpos {
-        getpos += 1 // 1nd lap, increase getpos
-    } else if getpos > putpos && getpos < maxqueue {
-        getpos += 1 // 2nd lap
-    } else if getpos > putpos && getpos == maxqueue {
-        getpos = 1 // 2nd first operation, set getpos 1
-    } else {
-        return Some(0); // all data in queue has been get
+// the readonly field alone, so that a queue with other corrupt fields can
+// still be checked
+fn httpmq_readonly(state: &State, name: &str) -> Result<u64, HttpmqError> {
+    match state.db.get(format!("{}.readonly", name).as_bytes())? {
+        Some(raw) => {
+            parse_metadata(&raw).ok_or_else(|| HttpmqError::QueueCorrupt(name.to_string()))
+        }
+        None => Ok(0),
     }
+}
+
+// a failed write is an error, not an empty queue: the message stays where
+// it is for the next get
+fn httpmq_commit_getpos(state: &State, name: &str, getpos: u64) -> Result<(), HttpmqError> {
+    state.db.put(
+        format!("{}.getpos", name).as_bytes(),
+        getpos.to_string().as_bytes(),
+    )
+}
 
-    debug!("getpos {} {:?}", getpos, metadata);
+// move getpos onto pos once its message is consumed. With --delete-on-get
+// the message goes in the same write, so the slot's space is freed at once
+// rather than when a later lap overwrites it.
+fn httpmq_consume(state: &State, name: &str, pos: u64) -> Result<(), HttpmqError> {
+    state.reservations.settle_pos(name, pos);
+    if !state.config.delete_on_get {
+        return httpmq_commit_getpos(state, name, pos);
+    }
+    let key = message_key(name, pos);
+    let mut batch = WriteBatch::default();
+    if let Some(value) = state.db.get(key.as_bytes())? {
+        chunk::delete_chunks(&mut batch, &key, value)?;
+        batch.delete(&key);
+    }
+    batch.put(format!("{}.getpos", name), pos.to_string());
+    state.db.write(batch)
+}
 
-    db.put(name.to_string() + ".getpos", getpos.to_string())
-        .ok()?;
-    Some(getpos)
+// messages that expired unread over the lifetime of a queue
+fn httpmq_expired_count(view: &View, name: &str) -> Result<u64, HttpmqError> {
+    Ok(view
+        .get(format!("{}.expired", name).as_bytes())?
+        .and_then(|raw| str::from_utf8(&raw).ok()?.parse::<u64>().ok())
+        .unwrap_or(0))
 }
 
-fn httpmq_now_putpos(db: &rocksdb::DB, name: &String) -> Option<i32> {
-    let metadata = httpmq_read_metadata(db, name);
-    let maxqueue = metadata.as_ref()?[0];
-    let mut putpos = metadata.as_ref()?[1];
-    let getpos = metadata.as_ref()?[2];
+// drop the expired or taken message at getpos, which the cursor has moved
+// past. Only expired ones are counted.
+fn httpmq_discard(
+    state: &State,
+    name: &str,
+    pos: u64,
+    header: &Header,
+    stored: Stored,
+) -> Result<(), HttpmqError> {
+    state.reservations.settle_pos(name, pos);
+    let mut batch = WriteBatch::default();
+    if let Stored::Chunked(manifest) = stored {
+        manifest.delete(&mut batch);
+    }
+    batch.delete(message_key(name, pos));
+    if !header.taken {
+        let expired = httpmq_expired_count(&View::Live(&*state.db), name)? + 1;
+        batch.put(format!("{}.expired", name), expired.to_string());
+    }
+    state.db.write(batch)
+}
 
-    let newpos;
+// position the next message goes to, 0 when the queue is full
+fn httpmq_next_putpos(maxqueue: u64, putpos: u64, getpos: u64) -> u64 {
+    let newpos = ring::next_putpos(maxqueue, putpos, getpos);
+    trace!("newpos {} putpos {} getpos {}", newpos, putpos, getpos);
+    newpos
+}
 
-    putpos += 1; // increase put queue pos
-    if putpos == getpos {
-        // queue is full
-        return Some(0); // return 0 to reject put operation
-    } else if getpos <= 1 && putpos > maxqueue {
-        // get operation less than 1
-        return Some(0); // and queue is full, just reject it
-    } else if putpos > maxqueue {
-        //  2nd lap
-        newpos = 1 // reset putpos as 1 and write to leveldb
-    } else {
-        // 1nd lap, convert int to string and write to leveldb
-        newpos = putpos;
+#[derive(Serialize, Debug)]
+pub struct GetResponse {
+    name: String,
+    // HTTPMQ_GET_OK with the message in data, otherwise a sentinel
+    pub(crate) result: &'static str,
+    pub(crate) pos: u64,
+    #[serde(serialize_with = "format::opt_data")]
+    pub(crate) data: Option<ByteBuf>,
+    // base64 when JSON carries data that isn't valid UTF-8
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<&'static str>,
+    // attributes of the message, X-Httpmq-Attr-* headers in text mode
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) attrs: BTreeMap<String, String>,
+    // sequence number of the message, unless it was put before they existed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) seq: Option<u64>,
+    // the Content-Type it was put with, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) content_type: Option<String>,
+    // the reservation of an ack=manual get, X-Httpmq-Lease in text mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lease: Option<u64>,
+    // how often a reserving get has handed this message out, this time
+    // included, X-Httpmq-Deliveries in text mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deliveries: Option<u64>,
+    // a chunked message still in the database, streamed by text mode
+    #[serde(skip)]
+    chunked: Option<chunk::Manifest>,
+}
+
+impl GetResponse {
+    // length of the message taken, 0 without one
+    fn size(&self) -> u64 {
+        match (&self.data, &self.chunked) {
+            (Some(data), _) => data.len() as u64,
+            (None, Some(manifest)) => manifest.size(),
+            (None, None) => 0,
+        }
+    }
+
+    fn new(name: &str, result: &'static str, pos: u64, stored: Option<Stored>) -> GetResponse {
+        let (data, chunked) = match stored {
+            Some(Stored::Whole(data)) => (Some(ByteBuf::from(data)), None),
+            Some(Stored::Chunked(manifest)) => (None, Some(manifest)),
+            None => (None, None),
+        };
+        GetResponse {
+            name: name.to_string(),
+            result,
+            pos,
+            data,
+            encoding: None,
+            attrs: BTreeMap::new(),
+            seq: None,
+            content_type: None,
+            lease: None,
+            deliveries: None,
+            chunked,
+        }
+    }
+
+    // the answer in fmt, MessagePack or JSON
+    fn encode(mut self, fmt: Format) -> Response {
+        self.encoding = self
+            .data
+            .as_deref()
+            .and_then(|data| format::encoding(fmt, data));
+        format::encode(fmt, &self)
+    }
+
+    // the properties of the message it carries
+    fn with_header(self, header: Header) -> GetResponse {
+        GetResponse {
+            attrs: header.attrs,
+            seq: header.seq,
+            content_type: header.content_type,
+            ..self
+        }
+    }
+
+    // text mode answers with the bare message, or the sentinel. The result
+    // header tells the two apart, whatever the message holds.
+    fn into_text(self, state: SharedState) -> Response {
+        let content_type = self
+            .content_type
+            .unwrap_or_else(|| String::from(OCTET_STREAM));
+        let mut res = match (self.data, self.chunked) {
+            (Some(data), _) => message_bytes(content_type, data.into_vec()),
+            (None, Some(manifest)) => stream_chunks(state, manifest, content_type),
+            (None, None) => text_bytes(self.result.as_bytes().to_vec()),
+        };
+        res.headers_mut()
+            .insert(RESULT_HEADER, HeaderValue::from_static(self.result));
+        if self.result == "HTTPMQ_GET_OK" {
+            set_position_headers(&mut res, self.pos, self.seq);
+        }
+        if let Some(lease) = self.lease {
+            res.headers_mut()
+                .insert(LEASE_HEADER, HeaderValue::from(lease));
+        }
+        if let Some(deliveries) = self.deliveries {
+            res.headers_mut()
+                .insert(DELIVERIES_HEADER, HeaderValue::from(deliveries));
+        }
+        set_attr_headers(&mut res, self.attrs);
+        res
+    }
+}
+
+fn set_attr_headers(res: &mut Response, attrs: BTreeMap<String, String>) {
+    // put validated these as header names and values
+    for (key, value) in attrs {
+        let name = HeaderName::from_bytes(format!("{}{}", ATTR_PREFIX, key).as_bytes());
+        if let (Ok(name), Ok(value)) = (name, HeaderValue::from_str(&value)) {
+            res.headers_mut().insert(name, value);
+        }
+    }
+}
+
+// HTTPMQ_GET_OK or the sentinel, on get responses in text mode
+const RESULT_HEADER: &str = "x-httpmq-result";
+// where a message sits in the queue, on put and get responses in text mode
+const POS_HEADER: &str = "x-httpmq-pos";
+const SEQ_HEADER: &str = "x-httpmq-seq";
+const LEASE_HEADER: &str = "x-httpmq-lease";
+const DELIVERIES_HEADER: &str = "x-httpmq-deliveries";
+// when it was put, on opt=tail responses
+const PUT_AT_HEADER: &str = "x-httpmq-put-at";
+// why a name was refused, on HTTPMQ_NAME_INVALID
+const REASON_HEADER: &str = "x-httpmq-reason";
+
+fn set_position_headers(res: &mut Response, pos: u64, seq: Option<u64>) {
+    let headers = res.headers_mut();
+    headers.insert(POS_HEADER, HeaderValue::from(pos));
+    if let Some(seq) = seq {
+        headers.insert(SEQ_HEADER, HeaderValue::from(seq));
+    }
+}
+
+// send a chunked message one chunk at a time, so a get holds about one
+// chunk in memory whatever the message size. The position is committed by
+// then; a chunk that can't be read aborts the response mid-body.
+fn stream_chunks(state: SharedState, manifest: chunk::Manifest, content_type: String) -> Response {
+    let (mut tx, body) = Body::channel();
+    let size = manifest.size();
+    tokio::spawn(async move {
+        for i in 0..manifest.chunks() {
+            match manifest.read_chunk(&*state.db, i) {
+                Ok(Some(data)) => {
+                    // the client went away
+                    if tx.send_data(data.into()).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(None) => {
+                    warn!("chunk {} of {:?} is missing", i, manifest);
+                    return tx.abort();
+                }
+                Err(e) => {
+                    warn!("{}", e);
+                    return tx.abort();
+                }
+            }
+        }
+    });
+    (
+        Headers(vec![
+            (header::CONTENT_TYPE, content_type),
+            (header::CONTENT_LENGTH, size.to_string()),
+        ]),
+        Response::new(body),
+    )
+        .into_response()
+}
+
+// messages are returned exactly as stored, they need not be utf-8. With
+// stream set, chunked messages are left for the response body to read.
+// How the cursor moves depends on the queue's delivery setting. At most once,
+// the default, commits it before the message is returned. At least once,
+// every get is a mode=reserve get: the cursor stays on the message until
+// opt=commit, and the queue's gets wait for that meanwhile.
+async fn kv_get(
+    state: &State,
+    Query(args): Query<KVSet>,
+    stream: bool,
+) -> Result<GetResponse, HttpmqError> {
+    let settings = state.settings.get(&*state.db, &args.name)?;
+    if settings.paused {
+        return Ok(GetResponse::new(&args.name, "HTTPMQ_GET_PAUSED", 0, None));
+    }
+    let manual = match args.ack.as_deref() {
+        None | Some("auto") => false,
+        Some("manual") => true,
+        Some(_) => {
+            return Ok(GetResponse::new(
+                &args.name,
+                "HTTPMQ_GET_MODE_INVALID",
+                0,
+                None,
+            ))
+        }
+    };
+    if manual {
+        // a filtered get takes messages ahead of the cursor, a lease leaves
+        // them in place
+        if args.filter.is_some() {
+            return Ok(GetResponse::new(
+                &args.name,
+                "HTTPMQ_GET_FILTER_INVALID",
+                0,
+                None,
+            ));
+        }
+        return kv_get_leased(state, &args.name, &settings).await;
+    }
+    let reserve = match (args.mode.as_deref(), settings.delivery) {
+        // a reservation only makes sense if the cursor waits for it
+        (Some("reserve"), Delivery::AtMostOnce) => {
+            return Ok(GetResponse::new(
+                &args.name,
+                "HTTPMQ_GET_MODE_INVALID",
+                0,
+                None,
+            ));
+        }
+        (_, Delivery::AtLeastOnce) => true,
+        _ => false,
+    };
+    if !reserve {
+        if state
+            .reservations
+            .is_held_at(&args.name, state.clock.instant())
+        {
+            return Ok(GetResponse::new(&args.name, "HTTPMQ_GET_RESERVED", 0, None));
+        }
+        let _locked = state.queue_locks.lock(&args.name).await;
+        return kv_get_next(state, args, stream, false).await;
+    }
+
+    // a filtered get takes messages ahead of the cursor, which can't wait
+    if args.filter.is_some() {
+        return Ok(GetResponse::new(
+            &args.name,
+            "HTTPMQ_GET_FILTER_INVALID",
+            0,
+            None,
+        ));
+    }
+    let name = args.name.clone();
+    if !state
+        .reservations
+        .try_reserve_at(&name, state.clock.instant())
+    {
+        return Ok(GetResponse::new(&name, "HTTPMQ_GET_RESERVED", 0, None));
+    }
+    let mut locked = state.queue_locks.lock(&name).await;
+    // the cursor waits on the reservation, a leased message would go out
+    // twice
+    if !state
+        .reservations
+        .leased_at(&name, state.clock.instant())
+        .is_empty()
+    {
+        state.reservations.release(&name);
+        return Ok(GetResponse::new(&name, "HTTPMQ_GET_RESERVED", 0, None));
+    }
+    if let Some(max) = settings.max_deliveries {
+        let head = httpmq_next_getpos(&httpmq_read_metadata(state, &name)?);
+        if head != 0 && state.reservations.deliveries(&name, head) >= max {
+            drop(locked);
+            httpmq_dead_letter(state, &name, head, settings.dead_letter.as_deref()).await?;
+            locked = state.queue_locks.lock(&name).await;
+        }
+    }
+    let res = kv_get_next(state, args, stream, true).await;
+    let deliveries = match &res {
+        Ok(r) if r.result == "HTTPMQ_GET_OK" => state.reservations.record_delivery(&name, r.pos),
+        _ => 0,
+    };
+    drop(locked);
+    if deliveries == 0 {
+        state.reservations.release(&name);
+        return res;
+    }
+    res.map(|r| GetResponse {
+        deliveries: Some(deliveries),
+        ..r
+    })
+}
+
+// an ack=manual get leases the first message from the cursor on that isn't
+// leased already and leaves it in place until opt=ack, other gets go on
+// past it. With max_deliveries set, messages that have gone out that many
+// times are dead lettered on the way.
+async fn kv_get_leased(
+    state: &State,
+    name: &str,
+    settings: &QueueSettings,
+) -> Result<GetResponse, HttpmqError> {
+    loop {
+        let locked = state.queue_locks.lock(name).await;
+        let now = state.clock.instant();
+        if state.reservations.is_held_at(name, now) {
+            return Ok(GetResponse::new(name, "HTTPMQ_GET_RESERVED", 0, None));
+        }
+        let leased = state.reservations.leased_at(name, now);
+        let res = httpmq_get_ahead(state, name, |pos, _| !leased.contains(&pos), false)?;
+        if res.result != "HTTPMQ_GET_OK" {
+            return Ok(res);
+        }
+        let lease = state.reservations.lease_at(name, res.pos, now);
+        let dead = settings
+            .max_deliveries
+            .is_some_and(|max| state.reservations.deliveries(name, res.pos) >= max);
+        drop(locked);
+        if dead && httpmq_dead_letter(state, name, res.pos, settings.dead_letter.as_deref()).await?
+        {
+            continue;
+        }
+        let deliveries = state.reservations.record_delivery(name, res.pos);
+        return Ok(GetResponse {
+            lease: Some(lease),
+            deliveries: Some(deliveries),
+            ..res
+        });
+    }
+}
+
+// move the message at pos of queue name, which has gone out max_deliveries
+// times unsettled, to the dead_letter queue, or drop it without one, so
+// gets aren't stuck on it for ever. Returns false if the dead letter queue
+// refuses it, it then stays and goes out again. The caller holds the
+// message, by a reservation or a lease, but not the queue's lock: the put
+// takes the dead letter queue's lock, and that can lead back to this queue.
+async fn httpmq_dead_letter(
+    state: &State,
+    name: &str,
+    pos: u64,
+    dead_letter: Option<&str>,
+) -> Result<bool, HttpmqError> {
+    if httpmq_readonly(state, name)? != 0 {
+        return Ok(false);
     }
+    let key = message_key(name, pos);
+    let message = match state.db.get(key.as_bytes())? {
+        Some(value) => match chunk::open(&key, value)? {
+            (header, Stored::Whole(data)) => Some((header, data)),
+            (header, Stored::Chunked(m)) => m.assemble(&*state.db)?.map(|data| (header, data)),
+        },
+        None => None,
+    };
+    let deliveries = state.reservations.deliveries(name, pos);
+    match (dead_letter, message) {
+        (Some(dead_letter), Some((header, data))) => {
+            let dest = state
+                .aliases
+                .target(dead_letter)
+                .unwrap_or_else(|| dead_letter.to_string());
+            let res = kv_set(state, &dest, vec![data], false, &header).await?;
+            if res.result != "HTTPMQ_PUT_OK" {
+                warn!(
+                    "can't move message {} of queue {} to {}: {}",
+                    pos, name, dest, res.result
+                );
+                return Ok(false);
+            }
+            info!(
+                "moved message {} of queue {} to {} after {} deliveries",
+                pos, name, dest, deliveries
+            );
+        }
+        _ => warn!(
+            "dropping message {} of queue {} after {} deliveries",
+            pos, name, deliveries
+        ),
+    }
+    let _locked = state.queue_locks.lock(name).await;
+    httpmq_take(state, name, pos)?;
+    Ok(true)
+}
 
-    debug!("newpos {} {:?}", newpos, metadata);
+// the longest wait= a get may ask for
+const WAIT_MAX: Duration = Duration::from_secs(30);
+// what a waiting get leaves of the request's timeout to answer in
+const WAIT_MARGIN: Duration = Duration::from_millis(500);
 
-    Some(newpos)
+// a get with wait=<secs> on an empty queue waits for a put rather than
+// answering HTTPMQ_GET_END at once, for at most WAIT_MAX and in any case
+// short of the request's own timeout. A put wakes every get waiting on the
+// queue; those that lose the race for the message wait again.
+async fn kv_get_wait(state: &State, args: KVSet, stream: bool) -> Result<GetResponse, HttpmqError> {
+    let wait = match args.wait {
+        Some(secs) if secs > 0 => Duration::from_secs(secs)
+            .min(WAIT_MAX)
+            .min(request_timeout(&state.config, args.timeout).saturating_sub(WAIT_MARGIN)),
+        _ => return kv_get(state, Query(args), stream).await,
+    };
+    let deadline = state.clock.instant() + wait;
+    let notify = state.waiters.get(&args.name);
+    let res = loop {
+        // listening before the get looks, so a put in between isn't missed
+        let notified = notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        let res = kv_get(state, Query(args.clone()), stream).await;
+        if !matches!(&res, Ok(r) if r.result == "HTTPMQ_GET_END") {
+            break res;
+        }
+        tokio::select! {
+            _ = notified => {}
+            _ = state.clock.sleep_until(deadline) => break res,
+        }
+    };
+    drop(notify);
+    state.waiters.release(&args.name);
+    res
+}
+
+// a get as QueueStore makes it: the whole message, counted like one over HTTP
+pub(crate) async fn httpmq_get(state: &State, name: &str) -> Result<GetResponse, HttpmqError> {
+    let args = KVSet {
+        name: name.to_string(),
+        ..KVSet::default()
+    };
+    let res = kv_get(state, Query(args), false).await?;
+    httpmq_count_get(state, &res, &mut Vec::new());
+    Ok(res)
+}
+
+// most messages a get with num= takes at once
+const GET_BATCH_MAX: u64 = 1000;
+
+#[derive(Serialize, Debug)]
+struct BatchMessage {
+    pos: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seq: Option<u64>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    attrs: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lease: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deliveries: Option<u64>,
+    #[serde(serialize_with = "format::data")]
+    data: ByteBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<&'static str>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct GetBatchResponse {
+    name: String,
+    // HTTPMQ_GET_OK with at least one message, otherwise the sentinel the
+    // first get answered
+    result: &'static str,
+    // oldest first
+    messages: Vec<BatchMessage>,
+    // the gets that went into it, counted once the batch is answered
+    #[serde(skip)]
+    taken: Vec<GetResponse>,
+}
+
+impl GetBatchResponse {
+    // the answer in fmt, MessagePack or JSON
+    fn encode(mut self, fmt: Format) -> Response {
+        for m in &mut self.messages {
+            m.encoding = format::encoding(fmt, &m.data);
+        }
+        format::encode(fmt, &self)
+    }
+
+    // <pos>:<len>:<data> and a newline for each message, the "len" framing
+    // of a batch put with positions, or the bare sentinel
+    fn into_text(self) -> Response {
+        if self.messages.is_empty() {
+            let mut res = text_bytes(self.result.as_bytes().to_vec());
+            res.headers_mut()
+                .insert(RESULT_HEADER, HeaderValue::from_static(self.result));
+            return res;
+        }
+        // an ack=manual get takes one message, whose lease goes in a header
+        let lease = self.messages.first().and_then(|m| m.lease);
+        let deliveries = self.messages.first().and_then(|m| m.deliveries);
+        let mut body = Vec::new();
+        for m in self.messages {
+            body.extend_from_slice(format!("{}:{}:", m.pos, m.data.len()).as_bytes());
+            body.extend_from_slice(&m.data);
+            body.push(b'\n');
+        }
+        let mut res = message_bytes(String::from(OCTET_STREAM), body);
+        res.headers_mut()
+            .insert(RESULT_HEADER, HeaderValue::from_static(self.result));
+        if let Some(lease) = lease {
+            res.headers_mut()
+                .insert(LEASE_HEADER, HeaderValue::from(lease));
+        }
+        if let Some(deliveries) = deliveries {
+            res.headers_mut()
+                .insert(DELIVERIES_HEADER, HeaderValue::from(deliveries));
+        }
+        res
+    }
 }
 
-pub fn init(matches: ArgMatches) {
-    DEFAULT_MAX_QUEUE_CELL
-        .set(
-            matches
-                .value_of("maxqueue")
-                .unwrap()
-                .parse::<i32>()
-                .unwrap(),
-        )
-        .unwrap();
+// a get with num=<n> takes up to n messages (at most GET_BATCH_MAX), one
+// get after the other, stopping early at the end of the queue. Only the
+// first get waits with wait=. Gets that don't move the cursor, on a
+// peek-only queue or with a reservation, take just one.
+async fn kv_get_batch(state: &State, args: KVSet) -> Result<GetBatchResponse, HttpmqError> {
+    let settings = state.settings.get(&*state.db, &args.name)?;
+    let single = is_peek_only(httpmq_readonly(state, &args.name)?)
+        || args.mode.is_some()
+        || args.ack.as_deref() == Some("manual")
+        || settings.delivery == Delivery::AtLeastOnce;
+    let num = match single {
+        true => 1,
+        false => args.num.unwrap_or(1).min(GET_BATCH_MAX),
+    };
 
-    DATABASE.set(DB::open_default("path").unwrap()).unwrap();
+    let mut batch = GetBatchResponse {
+        name: args.name.clone(),
+        result: "HTTPMQ_GET_OK",
+        messages: Vec::new(),
+        taken: Vec::new(),
+    };
+    for i in 0..num {
+        let res = match i {
+            0 => kv_get_wait(state, args.clone(), false).await,
+            _ => kv_get(state, Query(args.clone()), false).await,
+        };
+        // the messages taken so far are gone from the queue, they go out
+        let res = match res {
+            Ok(res) => res,
+            Err(e) if !batch.messages.is_empty() => {
+                warn!("batch get on {} stopped: {}", args.name, e);
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        match res.result {
+            "HTTPMQ_GET_OK" => batch.messages.push(BatchMessage {
+                pos: res.pos,
+                seq: res.seq,
+                attrs: res.attrs.clone(),
+                content_type: res.content_type.clone(),
+                lease: res.lease,
+                deliveries: res.deliveries,
+                data: res.data.clone().unwrap_or_default(),
+                encoding: None,
+            }),
+            // an empty slot the cursor moved past
+            "HTTPMQ_GET_NONE" => {}
+            result => {
+                if batch.messages.is_empty() {
+                    batch.result = result;
+                }
+                break;
+            }
+        }
+        batch.taken.push(res);
+    }
+    if batch.messages.is_empty() && batch.result == "HTTPMQ_GET_OK" {
+        batch.result = "HTTPMQ_GET_NONE";
+    }
+    Ok(batch)
 }
 
-async fn kv_get(Query(args): Query<KVSet>) -> Result<String, StatusCode> {
-    let db = DATABASE.get().unwrap();
-    let getpos = httpmq_now_getpos(&db, &args.name).unwrap_or_default();
+// most expired messages one get discards before giving up
+const EXPIRED_SKIP_MAX: usize = 1000;
+
+// the get itself, with reserve set the message's position isn't committed
+async fn kv_get_next(
+    state: &State,
+    args: KVSet,
+    stream: bool,
+    reserve: bool,
+) -> Result<GetResponse, HttpmqError> {
+    if let Some(filter) = &args.filter {
+        return kv_get_filtered(state, &args.name, filter);
+    }
+    // leased messages stay where they are, the get takes the first one
+    // that isn't
+    let leased = state
+        .reservations
+        .leased_at(&args.name, state.clock.instant());
+    if !leased.is_empty() {
+        return httpmq_get_ahead(state, &args.name, |pos, _| !leased.contains(&pos), true);
+    }
+
+    let now = state.clock.unix_secs();
+    let now_millis = state.clock.unix_millis() as u64;
+    let ttl = state.settings.get(&*state.db, &args.name)?.ttl;
+    // expired messages are passed over until one that is still good, but
+    // not endlessly: past EXPIRED_SKIP_MAX the get answers HTTPMQ_GET_END
+    // and the next one carries on
+    let mut skipped = 0;
+    let (getpos, peek, stored) = loop {
+        if skipped == EXPIRED_SKIP_MAX {
+            return Ok(GetResponse::new(&args.name, "HTTPMQ_GET_END", 0, None));
+        }
+        let metadata = httpmq_read_metadata(state, &args.name)?;
+        let getpos = httpmq_next_getpos(&metadata);
+        let peek = is_peek_only(metadata[3]);
+
+        trace!("getpos {} of queue {}", getpos, args.name);
+
+        if getpos == 0 {
+            return Ok(GetResponse::new(&args.name, "HTTPMQ_GET_END", 0, None));
+        }
+
+        let queue_name = message_key(&args.name, getpos);
+        let stored = state.db.get(queue_name.as_bytes()).and_then(|x| match x {
+            Some(value) => Ok(Some(chunk::open(&queue_name, value)?)),
+            None => Ok(None),
+        });
+        match stored {
+            Ok(Some((header, stored)))
+                if header.taken || header.is_expired(now) || header.is_stale(ttl, now_millis) =>
+            {
+                // a frozen cursor can't move past it
+                if peek {
+                    return Ok(GetResponse::new(
+                        &args.name,
+                        "HTTPMQ_GET_NONE",
+                        getpos,
+                        None,
+                    ));
+                }
+                httpmq_commit_getpos(state, &args.name, getpos)?;
+                debug!("discarding message {} of queue {}", getpos, args.name);
+                httpmq_discard(state, &args.name, getpos, &header, stored)?;
+                skipped += 1;
+            }
+            stored => break (getpos, peek, stored),
+        }
+    };
+    let (header, stored) = match stored {
+        Ok(Some((header, stored))) => (header, Ok(Some(stored))),
+        Ok(None) => (Header::default(), Ok(None)),
+        Err(e) => (Header::default(), Err(e)),
+    };
 
-    debug!("{} {:?}", getpos, args);
+    let stored = stored.and_then(|x| match x {
+        // chunks are written in one batch, if the last one is there the
+        // message is complete. The rest is read while streaming, unless the
+        // get is about to delete them.
+        Some(Stored::Chunked(m)) if stream && !state.config.delete_on_get => Ok(m
+            .read_chunk(&*state.db, m.chunks().saturating_sub(1))?
+            .map(|_| Stored::Chunked(m))),
+        Some(Stored::Chunked(m)) => Ok(m.assemble(&*state.db)?.map(Stored::Whole)),
+        x => Ok(x),
+    });
+    let val = match stored {
+        Ok(Some(obj)) => Some(obj),
+        Ok(None) => {
+            // the slot lost its message. Either skip it (counted, so it doesn't
+            // go unnoticed) or hold the cursor until the data is restored.
+            if !state.config.skip_missing || peek {
+                return Ok(GetResponse::new(
+                    &args.name,
+                    "HTTPMQ_GET_NONE",
+                    getpos,
+                    None,
+                ));
+            }
+            state.missing_skipped.fetch_add(1, Ordering::Relaxed);
+            warn!("skipping missing message {} of queue {}", getpos, args.name);
+            None
+        }
+        // leave the cursor alone so the slot is read again on retry
+        Err(e) => return Err(e),
+    };
 
-    if getpos == 0 {
-        Ok(String::from("HTTPMQ_GET_END"))
+    // a missing message is skipped all the same
+    let reserved = reserve && val.is_some();
+    if !peek && !reserved {
+        httpmq_consume(state, &args.name, getpos)?;
+    }
+    let result = if val.is_some() {
+        "HTTPMQ_GET_OK"
     } else {
-        let queue_name = args.name.to_string() + &getpos.to_string();
-        let val = match db.get(queue_name) {
-            Ok(Some(obj)) => String::from_utf8(obj.clone()).unwrap_or(String::from("")),
-            Ok(None) => String::from("HTTPMQ_GET_NONE"),
-            Err(_) => String::from("HTTPMQ_GET_ERROR"),
+        "HTTPMQ_GET_NONE"
+    };
+    Ok(GetResponse::new(&args.name, result, getpos, val).with_header(header))
+}
+
+// move the cursor onto pos=, the message a mode=reserve get returned. Only
+// the position right after the cursor can be committed, anything else is
+// a conflict: the reservation timed out and someone else got the message.
+async fn kv_commit(state: &State, Query(args): Query<KVSet>) -> Result<Response, HttpmqError> {
+    let _locked = state.queue_locks.lock(&args.name).await;
+    let metadata = httpmq_read_metadata(state, &args.name)?;
+    if metadata[3] != 0 {
+        return Ok("HTTPMQ_QUEUE_READONLY".into_response());
+    }
+    let next = httpmq_next_getpos(&metadata);
+    if next == 0 || args.pos != Some(next) {
+        return Ok((StatusCode::CONFLICT, "HTTPMQ_COMMIT_CONFLICT").into_response());
+    }
+    httpmq_consume(state, &args.name, next)?;
+    state.reservations.release(&args.name);
+    Ok("HTTPMQ_COMMIT_OK".into_response())
+}
+
+// settle the lease id= of an ack=manual get. opt=ack consumes the message,
+// opt=nack gives it back for the next get, or with delay=<secs> for a get
+// that long after. A lease that timed out has been reclaimed, settling it
+// is a conflict.
+async fn kv_ack(state: &State, Query(args): Query<KVSet>) -> Result<Response, HttpmqError> {
+    let (nack, conflict) = match &args.opt[..] {
+        "nack" => (true, "HTTPMQ_NACK_CONFLICT"),
+        _ => (false, "HTTPMQ_ACK_CONFLICT"),
+    };
+    let _locked = state.queue_locks.lock(&args.name).await;
+    let now = state.clock.instant();
+    let pos = match args
+        .id
+        .and_then(|lease| state.reservations.leased_pos(&args.name, lease, now))
+    {
+        Some(pos) => pos,
+        None => return Ok((StatusCode::CONFLICT, conflict).into_response()),
+    };
+    if nack {
+        match args.delay {
+            Some(secs) if secs > 0 => state.reservations.delay_at(
+                &args.name,
+                pos,
+                now + Duration::from_secs(secs).min(NACK_DELAY_MAX),
+            ),
+            _ => state.reservations.unlease(&args.name, pos),
+        }
+        return Ok("HTTPMQ_NACK_OK".into_response());
+    }
+    if httpmq_readonly(state, &args.name)? != 0 {
+        return Ok("HTTPMQ_QUEUE_READONLY".into_response());
+    }
+    httpmq_take(state, &args.name, pos)?;
+    Ok("HTTPMQ_ACK_OK".into_response())
+}
+
+// the longest delay= a nack may ask for
+const NACK_DELAY_MAX: Duration = Duration::from_secs(3600);
+
+// how far past the cursor a filtered get looks for a match
+const FILTER_SCAN_MAX: u64 = 1000;
+
+// A filtered get takes the first message from the cursor on whose attribute
+// matches. At the cursor that is an ordinary get. Further on, the message
+// is replaced by a "taken" marker that gets pass over once the cursor gets
+// there, so messages before it stay where they are for other consumers.
+// Until then the marker still counts as unread.
+fn kv_get_filtered(state: &State, name: &str, filter: &str) -> Result<GetResponse, HttpmqError> {
+    let (attr, want) = match filter.split_once('=') {
+        Some((attr, want)) if !attr.is_empty() => (attr.to_ascii_lowercase(), want),
+        _ => return Ok(GetResponse::new(name, "HTTPMQ_GET_FILTER_INVALID", 0, None)),
+    };
+    let leased = state.reservations.leased_at(name, state.clock.instant());
+    httpmq_get_ahead(
+        state,
+        name,
+        |pos, header| {
+            !leased.contains(&pos) && header.attrs.get(&attr).map(String::as_str) == Some(want)
+        },
+        true,
+    )
+}
+
+// the first message from the cursor on, looking FILTER_SCAN_MAX positions
+// ahead at most, that is still good and that wanted accepts. With take set
+// it is consumed (unless the queue is peek-only), a message ahead of the
+// cursor by marking it taken.
+fn httpmq_get_ahead(
+    state: &State,
+    name: &str,
+    wanted: impl Fn(u64, &Header) -> bool,
+    take: bool,
+) -> Result<GetResponse, HttpmqError> {
+    let now = state.clock.unix_secs();
+    let now_millis = state.clock.unix_millis() as u64;
+    let ttl = state.settings.get(&*state.db, name)?.ttl;
+    let mut metadata = httpmq_read_metadata(state, name)?;
+    let peek = is_peek_only(metadata[3]);
+
+    let mut pos = httpmq_next_getpos(&metadata);
+    for _ in 0..FILTER_SCAN_MAX {
+        if pos == 0 {
+            return Ok(GetResponse::new(name, "HTTPMQ_GET_END", 0, None));
+        }
+        let key = message_key(name, pos);
+        if let Some(value) = state.db.get(key.as_bytes())? {
+            let (header, stored) = chunk::open(&key, value)?;
+            if !header.taken
+                && !header.is_expired(now)
+                && !header.is_stale(ttl, now_millis)
+                && wanted(pos, &header)
+            {
+                let data = match stored {
+                    Stored::Chunked(m) => m.assemble(&*state.db)?,
+                    Stored::Whole(data) => Some(data),
+                };
+                let data = match data {
+                    Some(data) => data,
+                    None => return Ok(GetResponse::new(name, "HTTPMQ_GET_NONE", pos, None)),
+                };
+                if take && !peek {
+                    httpmq_take(state, name, pos)?;
+                }
+                return Ok(
+                    GetResponse::new(name, "HTTPMQ_GET_OK", pos, Some(Stored::Whole(data)))
+                        .with_header(header),
+                );
+            }
+        }
+        // the position after pos, as if the cursor were there
+        metadata[2] = pos;
+        pos = httpmq_next_getpos(&metadata);
+    }
+    Ok(GetResponse::new(name, "HTTPMQ_GET_SCAN_LIMIT", 0, None))
+}
+
+// consume the message at pos of queue name: the next one is simply
+// consumed, one further ahead is marked taken for the cursor to pass over.
+// Called with the queue locked.
+fn httpmq_take(state: &State, name: &str, pos: u64) -> Result<(), HttpmqError> {
+    if pos == httpmq_next_getpos(&httpmq_read_metadata(state, name)?) {
+        return httpmq_consume(state, name, pos);
+    }
+    state.reservations.settle_pos(name, pos);
+    let key = message_key(name, pos);
+    let mut batch = WriteBatch::default();
+    if let Some(value) = state.db.get(key.as_bytes())? {
+        if let (_, Stored::Chunked(m)) = chunk::open(&key, value)? {
+            m.delete(&mut batch);
+        }
+    }
+    let taken = Header {
+        taken: true,
+        ..Header::default()
+    };
+    batch.put(&key, envelope::wrap(&taken, b""));
+    state.db.write(batch)
+}
+
+// most messages opt=tail returns at once
+const TAIL_MAX: u64 = 100;
+
+#[derive(Serialize, Debug)]
+struct TailMessage {
+    pos: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seq: Option<u64>,
+    // unix time in milliseconds it was put, unless that was before it was
+    // recorded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    put_at: Option<u64>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    attrs: BTreeMap<String, String>,
+    #[serde(serialize_with = "format::data")]
+    data: ByteBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<&'static str>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TailResponse {
+    name: String,
+    // HTTPMQ_TAIL_OK, or HTTPMQ_TAIL_NONE when nothing was ever put
+    result: &'static str,
+    // newest first
+    messages: Vec<TailMessage>,
+}
+
+// the last num messages put on queue name, newest first, read back from
+// putpos around the ring until a slot is empty. Consumed messages still
+// count, their slots keep them until they are overwritten.
+fn httpmq_tail(state: &State, name: &str, num: u64) -> Result<Vec<TailMessage>, HttpmqError> {
+    let metadata = httpmq_read_metadata(state, name)?;
+    let maxqueue = metadata[0];
+    let mut pos = metadata[1];
+    let mut messages = Vec::new();
+    if pos == 0 {
+        return Ok(messages);
+    }
+    for _ in 0..num.clamp(1, TAIL_MAX).min(maxqueue) {
+        let key = message_key(name, pos);
+        let (header, stored) = match state.db.get(key.as_bytes())? {
+            Some(value) => chunk::open(&key, value)?,
+            None => break,
+        };
+        let data = match stored {
+            Stored::Whole(data) => data,
+            Stored::Chunked(m) => match m.assemble(&*state.db)? {
+                Some(data) => data,
+                None => break,
+            },
         };
+        messages.push(TailMessage {
+            pos,
+            seq: header.seq,
+            put_at: header.put_at,
+            attrs: header.attrs,
+            data: ByteBuf::from(data),
+            encoding: None,
+        });
+        pos = if pos == 1 { maxqueue } else { pos - 1 };
+    }
+    Ok(messages)
+}
+
+// opt=tail shows what was put last without moving any cursor. In text mode
+// a single message comes back bare, with its position, sequence number and
+// put time as headers; num=<k> lists the last k, one per line.
+fn kv_tail(state: &State, args: &KVSet, fmt: Format) -> Result<Response, HttpmqError> {
+    let num = args.num.unwrap_or(1);
+    let messages = httpmq_tail(state, &args.name, num)?;
+    let mut res = TailResponse {
+        name: args.name.clone(),
+        result: if messages.is_empty() {
+            "HTTPMQ_TAIL_NONE"
+        } else {
+            "HTTPMQ_TAIL_OK"
+        },
+        messages,
+    };
+    if fmt != Format::Text {
+        for m in &mut res.messages {
+            m.encoding = format::encoding(fmt, &m.data);
+        }
+        return Ok(format::encode(fmt, &res));
+    }
+    if res.messages.is_empty() {
+        return Ok(res.result.into_response());
+    }
+    if num <= 1 {
+        let m = res.messages.into_iter().next().unwrap();
+        let mut out = text_bytes(m.data.into_vec());
+        set_position_headers(&mut out, m.pos, m.seq);
+        if let Some(put_at) = m.put_at {
+            out.headers_mut()
+                .insert(PUT_AT_HEADER, HeaderValue::from(put_at));
+        }
+        set_attr_headers(&mut out, m.attrs);
+        return Ok(out);
+    }
+
+    let mut buf = format!("{}\n", res.result);
+    let or_dash = |x: Option<u64>| x.map_or(String::from("-"), |x| x.to_string());
+    for m in &res.messages {
+        let _ = writeln!(
+            buf,
+            "{} {} {} {}",
+            m.pos,
+            or_dash(m.seq),
+            or_dash(m.put_at),
+            String::from_utf8_lossy(&m.data).escape_debug()
+        );
+    }
+    Ok(buf.into_response())
+}
 
-        Ok(val)
+// opt=view&pos=<n> looks at the slot at position n whatever the cursors
+// say, e.g. to see what a stuck consumer is stuck on. Neither getpos nor
+// putpos is read for it or moved. Chunked messages stream as in kv_get.
+fn kv_view(state: &State, args: &KVSet, stream: bool) -> Result<GetResponse, HttpmqError> {
+    let name = &args.name;
+    let maxqueue = httpmq_read_metadata(state, name)?[0];
+    let pos = match args.pos {
+        Some(pos) if (1..=maxqueue).contains(&pos) => pos,
+        _ => return Ok(GetResponse::new(name, "HTTPMQ_VIEW_INVALID", 0, None)),
+    };
+    let key = message_key(name, pos);
+    let (header, stored) = match state.db.get(key.as_bytes())? {
+        Some(value) => chunk::open(&key, value)?,
+        None => return Ok(GetResponse::new(name, "HTTPMQ_GET_NONE", pos, None)),
+    };
+    // a filtered get took the message from this slot already
+    if header.taken {
+        return Ok(GetResponse::new(name, "HTTPMQ_GET_NONE", pos, None));
+    }
+    let stored = match stored {
+        Stored::Chunked(m) if stream => m
+            .read_chunk(&*state.db, m.chunks().saturating_sub(1))?
+            .map(|_| Stored::Chunked(m)),
+        Stored::Chunked(m) => m.assemble(&*state.db)?.map(Stored::Whole),
+        stored => Some(stored),
+    };
+    if stored.is_none() {
+        return Ok(GetResponse::new(name, "HTTPMQ_GET_NONE", pos, None));
     }
+    Ok(GetResponse::new(name, "HTTPMQ_GET_OK", pos, stored).with_header(header))
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Default, Clone)]
 pub struct KVSet {
+    // PUT and DELETE requests imply the opt
+    #[serde(default)]
     opt: String,
+    // not needed by UNNAMED_OPTS
+    #[serde(default)]
     name: String,
     data: Option<String>,
-    // pos: Option<i32>,
-    num: Option<i32>,
+    // the position opt=commit moves the cursor onto, or opt=view looks at
+    pos: Option<u64>,
+    num: Option<u64>,
+    auth: Option<String>,
+    confirm: Option<String>,
+    field: Option<String>,
+    format: Option<String>,
+    // topic=1 on a put: fail unless name is a topic
+    topic: Option<i32>,
+    // the subscriber queue of opt=subscribe and opt=unsubscribe, the
+    // target of opt=alias
+    queue: Option<String>,
+    // peek or advance, for opt=readonly; reserve, for opt=get
+    mode: Option<String>,
+    // manual on a get: a reservation settled by opt=ack or opt=nack with
+    // the lease id it answers with; auto, the default, for a plain get
+    ack: Option<String>,
+    // the lease opt=ack and opt=nack settle
+    id: Option<u64>,
+    // seconds the request may take, see request_timeout
+    timeout: Option<u64>,
+    // seconds a get on an empty queue waits for a put, see kv_get_wait
+    wait: Option<u64>,
+    // seconds a put message stays readable, 0 or none for ever
+    expires: Option<u64>,
+    // <attr>=<value>, a get only takes messages with that attribute
+    filter: Option<String>,
+    // the queues opt=remove_prefix removes, or opt=list lists, start with it
+    prefix: Option<String>,
+    // opt=list: at most limit queues, the first named after after=
+    limit: Option<usize>,
+    after: Option<String>,
+    // how a put's body or data= holds several messages: "lines" or "len"
+    batch: Option<String>,
+    // dry_run=1: only report what would be done
+    dry_run: Option<i32>,
+    // the positions opt=replay copies, from..=to wrapping past maxqueue,
+    // and the queue it copies them to
+    from: Option<u64>,
+    to: Option<u64>,
+    dest: Option<String>,
+    // where opt=backup writes the checkpoint
+    dir: Option<String>,
+    // seconds before a nacked message goes out again
+    delay: Option<u64>,
+    // strict=1: opt=mirror fails puts the mirror refuses
+    strict: Option<i32>,
+    // force=1: opt=replayall voids a reservation that is out
+    force: Option<i32>,
+    // set by dispatch when name was an alias and has been resolved
+    #[serde(skip)]
+    alias: Option<String>,
 }
 
-async fn kv_maxqueue(Query(args): Query<KVSet>) -> Result<String, StatusCode> {
-    let num = args.num.unwrap_or(0);
-    if num > 0 && num <= *DEFAULT_MAX_QUEUE_CELL.get().unwrap() {
-        let db = DATABASE.get().unwrap();
-        db.put(args.name.to_string() + ".maxqueue", num.to_string())
-            .unwrap();
-        Ok(String::from("HTTPMQ_MAXQUEUE_OK"))
-    } else {
-        Ok(String::from("HTTPMQ_MAXQUEUE_CANCLE"))
+// hand-written so the auth token and the message never end up in the logs
+impl fmt::Debug for KVSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KVSet")
+            .field("opt", &self.opt)
+            .field("name", &self.name)
+            .field(
+                "data",
+                &self.data.as_ref().map(|d| format!("<{} bytes>", d.len())),
+            )
+            .field("pos", &self.pos)
+            .field("num", &self.num)
+            .field("auth", &self.auth.as_ref().map(|_| "<redacted>"))
+            .field("confirm", &self.confirm)
+            .field("field", &self.field)
+            .field("format", &self.format)
+            .field("topic", &self.topic)
+            .field("queue", &self.queue)
+            .field("mode", &self.mode)
+            .field("ack", &self.ack)
+            .field("id", &self.id)
+            .field("timeout", &self.timeout)
+            .field("wait", &self.wait)
+            .field("expires", &self.expires)
+            .field("filter", &self.filter)
+            .field("prefix", &self.prefix)
+            .field("limit", &self.limit)
+            .field("after", &self.after)
+            .field("batch", &self.batch)
+            .field("dry_run", &self.dry_run)
+            .field("from", &self.from)
+            .field("to", &self.to)
+            .field("dest", &self.dest)
+            .field("dir", &self.dir)
+            .field("delay", &self.delay)
+            .field("strict", &self.strict)
+            .field("force", &self.force)
+            .field("alias", &self.alias)
+            .finish()
     }
 }
 
-async fn kv_set(Query(args): Query<KVSet>) -> Result<String, StatusCode> {
-    let db = DATABASE.get().unwrap();
+// operations that destroy or rewrite queue contents
+const ADMIN_OPTS: &[&str] = &[
+    "reset",
+    "remove",
+    "remove_prefix",
+    "fsck",
+    "selftest",
+    "subscribe",
+    "unsubscribe",
+    "alias",
+    "unalias",
+    "replayall",
+    "replay",
+    "readonly",
+    "unlock",
+    "flush",
+    "backup",
+    "mirror",
+    "unmirror",
+];
 
-    let putpos = httpmq_now_putpos(&db, &args.name).unwrap_or_default();
+// operations that only read, or consume, messages and positions
+const READ_OPTS: &[&str] = &[
+    "get",
+    "commit",
+    "ack",
+    "nack",
+    "view",
+    "tail",
+    "status",
+    "status_json",
+    "count",
+    "status_prefix",
+    "status_prefix_json",
+    "list",
+];
+
+// the token opt needs as auth=, if any: --admin-auth for ADMIN_OPTS when it
+// is set, otherwise --auth-read for READ_OPTS and --auth for the rest
+fn required_token<'a>(config: &'a Config, opt: &str) -> Option<&'a str> {
+    if ADMIN_OPTS.contains(&opt) && config.admin_auth.is_some() {
+        config.admin_auth.as_deref()
+    } else if READ_OPTS.contains(&opt) {
+        config.auth_read.as_deref()
+    } else {
+        config.auth.as_deref()
+    }
+}
 
-    debug!("{} {:?}", putpos, args);
+// operations on the alias itself, name is not resolved for them
+const ALIAS_OPTS: &[&str] = &["alias", "unalias"];
 
-    if putpos > 0 {
-        let queue_name = args.name.to_string() + &putpos.to_string();
+// operations that don't act on the queue given by name=
+const UNNAMED_OPTS: &[&str] = &[
+    "selftest",
+    "remove_prefix",
+    "status_prefix",
+    "status_prefix_json",
+    "list",
+    "flush",
+    "backup",
+];
 
-        let data = args.data.unwrap_or("".to_string());
-        if data.len() > 0 {
-            let mut batch = WriteBatch::default();
-            batch.put(args.name.to_string() + ".putpos", putpos.to_string());
-            batch.put(queue_name, data);
-            db.write(batch).unwrap();
-            return Ok(String::from("HTTPMQ_PUT_OK"));
-        }
-        Ok(String::from("HTTPMQ_PUT_NO_DATA"))
-    } else {
-        Ok(String::from("HTTPMQ_PUT_END"))
+// opt=maxqueue sizes a queue's ring, past --maxqueue if need be but not
+// past --maxqueue-limit. The ring arithmetic breaks if a cursor ends up past
+// the new end, or if the size changes while putpos has wrapped around and
+// getpos hasn't yet.
+async fn kv_maxqueue(state: &State, Query(args): Query<KVSet>) -> Result<String, HttpmqError> {
+    let num = args.num.unwrap_or(0);
+    match httpmq_set_maxqueue(state, &args.name, num).await? {
+        "HTTPMQ_MAXQUEUE_OK" => Ok(format!("HTTPMQ_MAXQUEUE_OK\nmaxqueue: {}\n", num)),
+        result => Ok(String::from(result)),
     }
 }
 
-async fn kv_status(Query(args): Query<KVSet>) -> Result<String, StatusCode> {
-    let db = DATABASE.get().unwrap();
-    let metadata = httpmq_read_metadata(db, &args.name).unwrap_or(vec![0, 0, 0]);
-    let maxqueue = metadata[0];
-    let putpos = metadata[1];
-    let getpos = metadata[2];
+// give queue name num slots, as long as its messages stay where they are
+pub(crate) async fn httpmq_set_maxqueue(
+    state: &State,
+    name: &str,
+    num: u64,
+) -> Result<&'static str, HttpmqError> {
+    if !(1..=state.config.maxqueue_limit).contains(&num) {
+        return Ok("HTTPMQ_MAXQUEUE_CANCLE");
+    }
+    let _locked = state.queue_locks.lock(name).await;
+    let metadata = httpmq_read_metadata(state, name)?;
+    if metadata[3] != 0 {
+        return Ok("HTTPMQ_QUEUE_READONLY");
+    }
+    let (maxqueue, putpos, getpos) = (metadata[0], metadata[1], metadata[2]);
+    if num < putpos || num < getpos || (num != maxqueue && putpos < getpos) {
+        return Ok("HTTPMQ_MAXQUEUE_RESET_FIRST");
+    }
+    state.db.put(
+        format!("{}.maxqueue", name).as_bytes(),
+        num.to_string().as_bytes(),
+    )?;
+    Ok("HTTPMQ_MAXQUEUE_OK")
+}
 
-    let mut ungetnum = 0;
-    let mut put_times = "";
-    let mut get_times = "";
-    if putpos >= getpos {
-        ungetnum = (putpos - getpos).abs();
-        put_times = "1st lap";
-        get_times = "1st lap";
-    } else if putpos < getpos {
-        ungetnum = (maxqueue + putpos - getpos).abs();
-        put_times = "2st lap";
-        get_times = "1st lap";
+#[derive(Serialize, Debug)]
+pub struct PutResponse {
+    name: String,
+    pub(crate) result: &'static str,
+    // messages written, all of a batch or none of it
+    count: usize,
+    // position and sequence number of the last message written, not
+    // reported for topics
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) pos: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seq: Option<u64>,
+    // full subscriber queues a topic put went past, see --topic-skip-full
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    skipped: Vec<String>,
+    // seconds to wait before putting again, with HTTPMQ_PUT_RATELIMITED
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after: Option<u64>,
+}
+
+impl PutResponse {
+    fn new(name: &str, result: &'static str, count: usize) -> PutResponse {
+        PutResponse {
+            name: name.to_string(),
+            result,
+            count,
+            pos: None,
+            seq: None,
+            skipped: Vec::new(),
+            retry_after: None,
+        }
     }
 
-    let buf = format!(
-        "HTTP Simple Queue Service
-------------------------------
-Queue Name: {}
-Maximum number of queues: {}
-Put position of queue ({}): {}
-Get position of queue ({}): {}
-Number of unread queue: {}
-",
-        args.name.to_string(),
-        maxqueue,
-        put_times,
-        putpos,
-        get_times,
-        getpos,
-        ungetnum
-    );
+    fn ratelimited(name: &str, wait: Duration) -> PutResponse {
+        PutResponse {
+            retry_after: Some(wait.as_secs_f64().ceil().max(1.0) as u64),
+            ..PutResponse::new(name, "HTTPMQ_PUT_RATELIMITED", 0)
+        }
+    }
 
-    Ok(buf)
+    fn into_text(self) -> Response {
+        let mut res = if self.result == "HTTPMQ_PUT_PARTIAL" {
+            format!("{}\naccepted: {}\n", self.result, self.count).into_response()
+        } else if self.skipped.is_empty() {
+            self.result.into_response()
+        } else {
+            format!("{}\nskipped: {}\n", self.result, self.skipped.join(" ")).into_response()
+        };
+        if let Some(pos) = self.pos {
+            set_position_headers(&mut res, pos, self.seq);
+        }
+        if let Some(secs) = self.retry_after {
+            res.headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        res
+    }
 }
 
-async fn kv_reset(Query(args): Query<KVSet>) -> Result<String, StatusCode> {
-    let db = DATABASE.get().unwrap();
-    db.put(
-        args.name.to_string() + ".maxqueue",
-        DEFAULT_MAX_QUEUE_CELL.get().unwrap().to_string(),
-    )
-    .unwrap();
-    db.put(args.name.to_string() + ".putpos", "0").unwrap();
-    db.put(args.name.to_string() + ".getpos", "0").unwrap();
+// message attributes are sent and returned as X-Httpmq-Attr-<key> headers
+const ATTR_PREFIX: &str = "x-httpmq-attr-";
+
+// limits on the attributes of one message, keys and values together
+const ATTRS_MAX: usize = 16;
+const ATTRS_MAX_BYTES: usize = 1024;
+
+// the per-message properties a put asks for, None when its attributes
+// break the limits
+fn put_header(args: &KVSet, headers: &HeaderMap, body: Option<&Bytes>, now: u64) -> Option<Header> {
+    let mut attrs = BTreeMap::new();
+    let mut size = 0;
+    for name in headers.keys() {
+        let key = match name.as_str().strip_prefix(ATTR_PREFIX) {
+            Some(key) => key,
+            None => continue,
+        };
+        let mut values = headers.get_all(name).iter();
+        let value = values.next()?.to_str().ok()?;
+        if key.is_empty() || values.next().is_some() {
+            return None;
+        }
+        size += key.len() + value.len();
+        attrs.insert(key.to_string(), value.to_string());
+    }
+    if attrs.len() > ATTRS_MAX || size > ATTRS_MAX_BYTES {
+        return None;
+    }
+
+    Some(Header {
+        expires: args
+            .expires
+            .filter(|&secs| secs > 0)
+            .map(|secs| now.saturating_add(secs)),
+        attrs,
+        content_type: body_content_type(headers, body),
+        ..Header::default()
+    })
+}
 
-    Ok(String::from("HTTPMQ_RESET_OK"))
+// the Content-Type a message body was sent with, kept to return on get.
+// A MessagePack batch is unpacked, and a form is what curl -d sends when
+// nothing is said.
+fn body_content_type(headers: &HeaderMap, body: Option<&Bytes>) -> Option<String> {
+    if body.is_none_or(|body| body.is_empty()) || format::is_msgpack(headers, header::CONTENT_TYPE)
+    {
+        return None;
+    }
+    let content_type = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+    if content_type.starts_with("application/x-www-form-urlencoded") {
+        return None;
+    }
+    Some(content_type.to_string())
 }
 
-pub async fn process(Query(args): Query<KVSet>) -> Result<String, StatusCode> {
-    let res = match &args.opt[..] {
-        "get" => kv_get(Query(args)).await,
-        "put" => kv_set(Query(args)).await,
-        "status" => kv_status(Query(args)).await,
-        "reset" => kv_reset(Query(args)).await,
-        "maxqueue" => kv_maxqueue(Query(args)).await,
-        _ => Ok(String::from("invalid opt")),
+// the messages of a put: a non-empty request body takes precedence over the
+// data parameter, and a MessagePack body is an array of messages. With
+// batch= the body or data holds several, see unframe. None when the body
+// doesn't decode.
+fn put_messages(args: &KVSet, headers: &HeaderMap, body: Option<Bytes>) -> Option<Vec<Vec<u8>>> {
+    let raw = match body {
+        Some(body) if !body.is_empty() => {
+            if format::is_msgpack(headers, header::CONTENT_TYPE) {
+                let messages: Vec<ByteBuf> = rmp_serde::from_slice(&body).ok()?;
+                return Some(messages.into_iter().map(ByteBuf::into_vec).collect());
+            }
+            body.to_vec()
+        }
+        _ => args.data.clone().unwrap_or_default().into_bytes(),
     };
+    match args.batch.as_deref() {
+        None => Some(vec![raw]),
+        Some(framing) => unframe(framing, &raw),
+    }
+}
+
+// the messages of a batch put. "lines" has one per line, a last newline
+// optional. "len" has <len>:<bytes> for each, each optionally followed by a
+// newline, for messages that hold newlines themselves; get num= answers in
+// the same framing with the position in front.
+fn unframe(framing: &str, mut raw: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut messages = Vec::new();
+    match framing {
+        "lines" => {
+            let raw = raw.strip_suffix(b"\n").unwrap_or(raw);
+            messages.extend(raw.split(|&b| b == b'\n').map(<[u8]>::to_vec));
+        }
+        "len" => {
+            while !raw.is_empty() {
+                let colon = raw.iter().position(|&b| b == b':')?;
+                let len: usize = str::from_utf8(&raw[..colon]).ok()?.parse().ok()?;
+                let data = raw.get(colon + 1..colon + 1 + len)?;
+                messages.push(data.to_vec());
+                raw = &raw[colon + 1 + len..];
+                raw = raw.strip_prefix(b"\n").unwrap_or(raw);
+            }
+        }
+        _ => return None,
+    }
+    Some(messages)
+}
+
+// what httpmq_stage_put did: HTTPMQ_PUT_OK with the position and sequence
+// number of the last message, or the sentinel saying why nothing was added
+struct Staged {
+    result: &'static str,
+    pos: u64,
+    seq: u64,
+}
+
+impl Staged {
+    fn refused(result: &'static str) -> Result<Staged, HttpmqError> {
+        Ok(Staged {
+            result,
+            pos: 0,
+            seq: 0,
+        })
+    }
+}
+
+// last sequence number given out by queue name, 0 before its first put
+fn httpmq_read_seq(view: &View, name: &str) -> Result<u64, HttpmqError> {
+    match view.get(format!("{}.seq", name).as_bytes())? {
+        Some(raw) => String::from_utf8_lossy(&raw)
+            .parse()
+            .map_err(|_| HttpmqError::QueueCorrupt(name.to_string())),
+        None => Ok(0),
+    }
+}
+
+// add the writes putting messages on queue name to the batch. The sequence
+// number goes into the same batch, so it can't get ahead of the messages
+// or fall behind them.
+fn httpmq_stage_put(
+    state: &State,
+    batch: &mut WriteBatch,
+    name: &str,
+    messages: &[Vec<u8>],
+    header: &Header,
+) -> Result<Staged, HttpmqError> {
+    let metadata = httpmq_read_metadata(state, name)?;
+    let maxqueue = metadata[0];
+    let getpos = metadata[2];
+
+    if metadata[3] != 0 {
+        return Staged::refused("HTTPMQ_QUEUE_READONLY");
+    }
+    if messages.is_empty() {
+        return Staged::refused("HTTPMQ_PUT_NO_DATA");
+    }
+    let limit = state
+        .settings
+        .get(&*state.db, name)?
+        .max_message_size(state.config.max_message_size);
+    if limit > 0 && messages.iter().any(|data| data.len() as u64 > limit) {
+        return Staged::refused("HTTPMQ_PUT_TOO_LARGE");
+    }
+
+    let mut putpos = metadata[1];
+    let mut keys = Vec::with_capacity(messages.len());
+    for data in messages {
+        putpos = httpmq_next_putpos(maxqueue, putpos, getpos);
+        trace!("{} {} {}", name, putpos, data.len());
+        if putpos == 0 {
+            return Staged::refused("HTTPMQ_PUT_END");
+        }
+        if data.is_empty() {
+            return Staged::refused("HTTPMQ_PUT_NO_DATA");
+        }
+        keys.push(message_key(name, putpos));
+    }
+
+    // a slot reused on a later lap may still hold the chunks of an older
+    // large message, drop them along with the overwrite
+    let old = state
+        .db
+        .multi_get(keys.iter().map(|k| k.clone().into_bytes()).collect());
+    for (key, x) in keys.iter().zip(old) {
+        if let Some(value) = x? {
+            chunk::delete_chunks(batch, key, value)?;
+        }
+    }
+    let mut seq = httpmq_read_seq(&View::Live(&*state.db), name)?;
+    let put_at = state.clock.unix_millis() as u64;
+    for (key, data) in keys.iter().zip(messages) {
+        seq += 1;
+        let header = Header {
+            seq: Some(seq),
+            put_at: Some(put_at),
+            ..header.clone()
+        };
+        chunk::put(batch, key, data, state.config.chunk_size, &header);
+    }
+    batch.put(format!("{}.putpos", name), putpos.to_string());
+    batch.put(format!("{}.seq", name), seq.to_string());
+    Ok(Staged {
+        result: "HTTPMQ_PUT_OK",
+        pos: putpos,
+        seq,
+    })
+}
+
+// the queues a put on name moves putpos on: name or its subscribers, and
+// the mirrors of those
+fn httpmq_put_queues(state: &State, name: &str) -> Vec<String> {
+    let mut queues = state
+        .topics
+        .subscribers(name)
+        .unwrap_or_else(|| vec![name.to_string()]);
+    let targets: Vec<String> = queues
+        .iter()
+        .filter_map(|queue| state.mirrors.get(queue))
+        .map(|mirror| mirror.target.clone())
+        .collect();
+    queues.extend(targets);
+    queues
+}
+
+fn httpmq_count_get(state: &State, res: &GetResponse, events: &mut Vec<Event>) {
+    if res.result == "HTTPMQ_GET_END" {
+        events.push(Event::Empty);
+    }
+    if res.result == "HTTPMQ_GET_OK" {
+        state.totals.record_get(&*state.db, &res.name, res.size());
+        state.metrics.record_get(&res.name);
+    }
+}
+
+fn httpmq_count_put(state: &State, name: &str, messages: &[Vec<u8>]) {
+    let bytes = messages.iter().map(|m| m.len() as u64).sum();
+    state
+        .totals
+        .record_put(&*state.db, name, messages.len() as u64, bytes);
+    state.metrics.record_put(name, messages.len() as u64);
+    // the write is done, gets waiting on the queue can have the messages
+    state.waiters.notify(name);
+}
+
+// write batch, telling the stall detector how long it took
+fn httpmq_write_timed(state: &State, batch: WriteBatch) -> Result<(), HttpmqError> {
+    let start = state.clock.instant();
+    let res = state.db.write(batch);
+    let now = state.clock.instant();
+    state.stall.observe_write_at(now - start, now);
+    res
+}
+
+// whether the rate limit of queue name, if it has one, lets messages puts
+// through. Some tells how long until it would. Nothing is taken yet, a put
+// refused further on shouldn't count against the limit.
+fn httpmq_put_limited(
+    state: &State,
+    name: &str,
+    messages: usize,
+) -> Result<Option<Duration>, HttpmqError> {
+    let rate = match state.settings.get(&*state.db, name)?.max_put_rate {
+        Some(rate) => rate,
+        None => return Ok(None),
+    };
+    let now = state.clock.instant();
+    Ok(state
+        .put_limits
+        .check(name, rate, messages as u64, now)
+        .err())
+}
+
+// take messages puts that went in from the rate limit of queue name, if it
+// has one. The queue lock, held since httpmq_put_limited, keeps other puts
+// from taking them in between.
+fn httpmq_put_spend(state: &State, name: &str, messages: usize) -> Result<(), HttpmqError> {
+    if let Some(rate) = state.settings.get(&*state.db, name)?.max_put_rate {
+        let now = state.clock.instant();
+        state.put_limits.take(name, rate, messages as u64, now);
+    }
+    Ok(())
+}
+
+// what became of the mirror copy of a put
+enum MirrorCopy {
+    // the queue isn't mirrored, or its target can't take the copy
+    None,
+    Staged(Arc<Mirror>),
+    // a strict mirror refused the copy, the put fails with this
+    Refused(&'static str),
+}
+
+// add the copy of a put on queue to its mirror, if it has one, to the
+// batch. staged tells the queues the batch already puts to; a mirror
+// among them is left out, staging a queue twice would put both copies in
+// the same slot.
+fn httpmq_stage_mirror(
+    state: &State,
+    batch: &mut WriteBatch,
+    queue: &str,
+    staged: impl Fn(&str) -> bool,
+    messages: &[Vec<u8>],
+    header: &Header,
+) -> Result<MirrorCopy, HttpmqError> {
+    let mirror = match state.mirrors.get(queue) {
+        Some(mirror) if !staged(&mirror.target) => mirror,
+        _ => return Ok(MirrorCopy::None),
+    };
+    let result = httpmq_stage_put(state, batch, &mirror.target, messages, header)?.result;
+    if result == "HTTPMQ_PUT_OK" {
+        return Ok(MirrorCopy::Staged(mirror));
+    }
+    if result == "HTTPMQ_PUT_END" {
+        mirror.record_full();
+    }
+    if mirror.strict {
+        return Ok(MirrorCopy::Refused(if result == "HTTPMQ_PUT_END" {
+            "HTTPMQ_MIRROR_FULL"
+        } else {
+            "HTTPMQ_MIRROR_REFUSED"
+        }));
+    }
+    Ok(MirrorCopy::None)
+}
+
+fn httpmq_count_mirrored(state: &State, mirrors: &[Arc<Mirror>], messages: &[Vec<u8>]) {
+    for mirror in mirrors {
+        mirror.record_mirrored(messages.len() as u64);
+        httpmq_count_put(state, &mirror.target, messages);
+    }
+}
+
+// put messages on a queue, or on every subscriber queue when name is a
+// topic. With topic set the caller insists on name being a topic.
+pub(crate) async fn kv_set(
+    state: &State,
+    name: &str,
+    messages: Vec<Vec<u8>>,
+    topic: bool,
+    header: &Header,
+) -> Result<PutResponse, HttpmqError> {
+    // keep puts from piling up behind a stalled database
+    if !state.stall.wait().await {
+        return Err(HttpmqError::WriteStalled);
+    }
+    let queues = httpmq_put_queues(state, name);
+    let _locked = state
+        .queue_locks
+        .lock_all(queues.iter().map(String::as_str))
+        .await;
+    let mut batch = WriteBatch::default();
+    let subscribers = match state.topics.subscribers(name) {
+        Some(subscribers) => subscribers,
+        None if topic => return Ok(PutResponse::new(name, "HTTPMQ_TOPIC_NOT_FOUND", 0)),
+        None => {
+            if let Some(wait) = httpmq_put_limited(state, name, messages.len())? {
+                return Ok(PutResponse::ratelimited(name, wait));
+            }
+            let staged = httpmq_stage_put(state, &mut batch, name, &messages, header)?;
+            if staged.result == "HTTPMQ_PUT_OK" {
+                let mirror = match httpmq_stage_mirror(
+                    state,
+                    &mut batch,
+                    name,
+                    |q| q == name,
+                    &messages,
+                    header,
+                )? {
+                    MirrorCopy::Refused(result) => return Ok(PutResponse::new(name, result, 0)),
+                    MirrorCopy::Staged(mirror) => Some(mirror),
+                    MirrorCopy::None => None,
+                };
+                httpmq_write_timed(state, batch)?;
+                httpmq_put_spend(state, name, messages.len())?;
+                httpmq_count_put(state, name, &messages);
+                httpmq_count_mirrored(state, mirror.as_slice(), &messages);
+                return Ok(PutResponse {
+                    pos: Some(staged.pos),
+                    seq: Some(staged.seq),
+                    ..PutResponse::new(name, staged.result, messages.len())
+                });
+            }
+            return Ok(PutResponse::new(name, staged.result, 0));
+        }
+    };
+
+    // all subscribers go into one batch, so they get the message together
+    let mut skipped = Vec::new();
+    let mut mirrors: Vec<Arc<Mirror>> = Vec::new();
+    for queue in &subscribers {
+        if let Some(wait) = httpmq_put_limited(state, queue, messages.len())? {
+            return Ok(PutResponse::ratelimited(name, wait));
+        }
+        let result = httpmq_stage_put(state, &mut batch, queue, &messages, header)?.result;
+        if result == "HTTPMQ_PUT_OK" {
+            let staged = |q: &str| {
+                subscribers.iter().any(|s| s == q) || mirrors.iter().any(|m| m.target == q)
+            };
+            match httpmq_stage_mirror(state, &mut batch, queue, staged, &messages, header)? {
+                MirrorCopy::Refused(result) => return Ok(PutResponse::new(name, result, 0)),
+                MirrorCopy::Staged(mirror) => mirrors.push(mirror),
+                MirrorCopy::None => {}
+            }
+            continue;
+        }
+        if result == "HTTPMQ_PUT_END" && state.config.topic_skip_full {
+            skipped.push(queue.clone());
+            continue;
+        }
+        return Ok(PutResponse::new(name, result, 0));
+    }
+    if skipped.len() == subscribers.len() {
+        return Ok(PutResponse::new(name, "HTTPMQ_PUT_END", 0));
+    }
+    httpmq_write_timed(state, batch)?;
+    for queue in subscribers.iter().filter(|q| !skipped.contains(q)) {
+        httpmq_put_spend(state, queue, messages.len())?;
+        httpmq_count_put(state, queue, &messages);
+    }
+    httpmq_count_mirrored(state, &mirrors, &messages);
+    if !skipped.is_empty() {
+        warn!("topic {}: skipped full queues {:?}", name, skipped);
+    }
+    Ok(PutResponse {
+        skipped,
+        ..PutResponse::new(name, "HTTPMQ_PUT_OK", messages.len())
+    })
+}
+
+// how many of n more messages queue metadata has room for
+fn httpmq_room(metadata: &[u64], n: usize) -> usize {
+    let mut putpos = metadata[1];
+    for fit in 0..n {
+        putpos = httpmq_next_putpos(metadata[0], putpos, metadata[2]);
+        if putpos == 0 {
+            return fit;
+        }
+    }
+    n
+}
+
+// a batch= put that doesn't fit goes in as far as it does: the messages
+// that fit, in order, in one write at consecutive positions, answered with
+// HTTPMQ_PUT_PARTIAL and how many were accepted. X-Httpmq-Pos is the
+// position of the last of them.
+async fn kv_set_partial(
+    state: &State,
+    name: &str,
+    mut messages: Vec<Vec<u8>>,
+    header: &Header,
+) -> Result<PutResponse, HttpmqError> {
+    let mut partial = false;
+    loop {
+        let res = kv_set(state, name, messages.clone(), false, header).await?;
+        if res.result == "HTTPMQ_PUT_OK" && partial {
+            return Ok(PutResponse {
+                result: "HTTPMQ_PUT_PARTIAL",
+                ..res
+            });
+        }
+        if res.result != "HTTPMQ_PUT_END" || state.topics.is_topic(name) {
+            return Ok(res);
+        }
+        // another put may take the room first, then this goes round again
+        let room = httpmq_room(&httpmq_read_metadata(state, name)?, messages.len());
+        if room == 0 {
+            return Ok(res);
+        }
+        messages.truncate(room);
+        partial = true;
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct QueueStatus {
+    pub name: String,
+    pub maxqueue: u64,
+    pub putpos: u64,
+    pub putlap: i32,
+    pub getpos: u64,
+    pub getlap: i32,
+    pub unread: u64,
+    // messages handed out by a reserving get and not yet settled
+    pub in_flight: u64,
+    hot: bool,
+    // "peek" or "advance" when the queue is read-only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    readonly: Option<&'static str>,
+    // the alias the status was asked for by, name is the queue behind it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alias: Option<String>,
+    // messages discarded unread because they expired
+    pub expired: u64,
+    // recent request rates, unless the queue is only counted under "other"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rates: Option<Vec<Throughput>>,
+    // sequence number of the last message put
+    pub seq: u64,
+    delivery: Delivery,
+    // the largest message a put may carry, 0 for no limit
+    max_message_size: u64,
+    // seconds messages stay readable, see opt=ttl
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl: Option<u64>,
+    // with a max_put_rate setting
+    #[serde(skip_serializing_if = "Option::is_none")]
+    put_rate: Option<PutRate>,
+    // the queue puts are copied to, see opt=mirror
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mirror: Option<MirrorStatus>,
+    // puts and gets since the process started, and over the queue's lifetime
+    since_start: QueueTotals,
+    lifetime: QueueTotals,
+}
+
+// unread messages and the lap putpos is on, shared by status, count,
+// list and /metrics
+fn httpmq_unread(metadata: &[u64]) -> (u64, i32) {
+    let (maxqueue, putpos, getpos) = (metadata[0], metadata[1], metadata[2]);
+    (
+        ring::unread(maxqueue, putpos, getpos),
+        ring::putlap(putpos, getpos),
+    )
+}
+
+pub(crate) fn httpmq_status(state: &State, name: &str) -> Result<QueueStatus, HttpmqError> {
+    httpmq_status_in(state, &View::Live(&*state.db), name)
+}
+
+fn httpmq_status_in(state: &State, view: &View, name: &str) -> Result<QueueStatus, HttpmqError> {
+    let metadata = httpmq_read_metadata_in(state, view, name)?;
+    let maxqueue = metadata[0];
+    let putpos = metadata[1];
+    let getpos = metadata[2];
+    let (unread, putlap) = httpmq_unread(&metadata);
+    let (since_start, lifetime) = state.totals.get(&*state.db, name);
+    let settings = state.settings.get(&*state.db, name)?;
+
+    Ok(QueueStatus {
+        name: name.to_string(),
+        maxqueue,
+        putpos,
+        putlap,
+        getpos,
+        getlap: 1,
+        unread,
+        in_flight: state.reservations.in_flight_at(name, state.clock.instant()),
+        hot: state.hot.is_hot(name),
+        readonly: readonly_mode(metadata[3]),
+        alias: None,
+        expired: httpmq_expired_count(view, name)?,
+        rates: state.rates.queue(name),
+        seq: httpmq_read_seq(view, name)?,
+        delivery: settings.delivery,
+        max_message_size: settings.max_message_size(state.config.max_message_size),
+        ttl: settings.ttl,
+        put_rate: settings
+            .max_put_rate
+            .map(|rate| state.put_limits.status(name, rate, state.clock.instant())),
+        mirror: state.mirrors.get(name).map(|mirror| mirror.status()),
+        since_start,
+        lifetime,
+    })
+}
+
+// status of the queue a request is for, telling whether it came through an alias
+fn httpmq_args_status(state: &State, args: &KVSet) -> Result<QueueStatus, HttpmqError> {
+    Ok(QueueStatus {
+        alias: args.alias.clone(),
+        ..httpmq_status(state, &args.name)?
+    })
+}
+
+// the body of opt=count in MessagePack
+#[derive(Serialize)]
+struct Count {
+    count: u64,
+}
+
+fn lap_name(lap: i32) -> &'static str {
+    if lap == 1 {
+        "1st lap"
+    } else {
+        "2st lap"
+    }
+}
+
+async fn kv_status(state: &State, Query(args): Query<KVSet>) -> Result<String, HttpmqError> {
+    let status = httpmq_args_status(state, &args)?;
+
+    let mut buf = format!(
+        "HTTP Simple Queue Service
+------------------------------
+Queue Name: {}
+Maximum number of queues: {}
+Put position of queue ({}): {}
+Get position of queue ({}): {}
+Number of unread queue: {}
+",
+        status.name,
+        status.maxqueue,
+        lap_name(status.putlap),
+        status.putpos,
+        lap_name(status.getlap),
+        status.getpos,
+        status.unread
+    );
+    if let Some(mode) = status.readonly {
+        let _ = writeln!(buf, "Read-only: {}", mode);
+    }
+    if let Some(alias) = &status.alias {
+        let _ = writeln!(buf, "Alias: {} -> {}", alias, status.name);
+    }
+    if status.expired > 0 {
+        let _ = writeln!(buf, "Expired unread: {}", status.expired);
+    }
+    if status.in_flight > 0 {
+        let _ = writeln!(buf, "In flight: {}", status.in_flight);
+    }
+    let _ = writeln!(buf, "Last sequence number: {}", status.seq);
+    let _ = writeln!(buf, "Delivery: {}", status.delivery.as_str());
+    if status.max_message_size > 0 {
+        let _ = writeln!(buf, "Max message size: {}", status.max_message_size);
+    }
+    if let Some(ttl) = status.ttl {
+        let _ = writeln!(buf, "Message TTL: {}s", ttl);
+    }
+    if let Some(rate) = &status.put_rate {
+        let _ = writeln!(
+            buf,
+            "Put rate limit: {}/s, {:.1}% used",
+            rate.limit,
+            rate.utilization * 100.0
+        );
+    }
+    if let Some(mirror) = &status.mirror {
+        let _ = writeln!(
+            buf,
+            "Mirror: {}{}, {} mirrored, {} full",
+            mirror.target,
+            if mirror.strict { " (strict)" } else { "" },
+            mirror.mirrored,
+            mirror.full
+        );
+    }
+    let _ = writeln!(
+        buf,
+        "Lifetime: {} puts, {} gets, {} bytes in, {} bytes out",
+        status.lifetime.total_put,
+        status.lifetime.total_get,
+        status.lifetime.total_bytes_in,
+        status.lifetime.total_bytes_out
+    );
+
+    Ok(buf)
+}
+
+async fn kv_status_json(state: &State, Query(args): Query<KVSet>) -> Result<String, HttpmqError> {
+    let status = httpmq_args_status(state, &args)?;
+    Ok(serde_json::to_string(&status).unwrap())
+}
+
+#[derive(Serialize, Debug)]
+pub struct TopicStatus {
+    name: String,
+    // unread messages summed over the subscriber queues
+    unread: u64,
+    // unix time in milliseconds the subscriber queues were read as of
+    snapshot_ms: u64,
+    subscribers: Vec<QueueStatus>,
+}
+
+// a snapshot to read several queues from, so that sums over them add up
+// even while they are busy, and the unix time in milliseconds it was taken
+fn httpmq_snapshot(state: &State) -> (View<'_>, u64) {
+    (
+        View::Snapshot(state.db.raw().snapshot()),
+        state.clock.unix_millis() as u64,
+    )
+}
+
+fn httpmq_statuses<'a>(
+    state: &State,
+    view: &View,
+    queues: impl IntoIterator<Item = &'a String>,
+) -> Result<Vec<QueueStatus>, HttpmqError> {
+    queues
+        .into_iter()
+        .map(|queue| httpmq_status_in(state, view, queue))
+        .collect()
+}
+
+fn httpmq_topic_status(
+    state: &State,
+    name: &str,
+    subscribers: &[String],
+) -> Result<TopicStatus, HttpmqError> {
+    let (view, snapshot_ms) = httpmq_snapshot(state);
+    let subscribers = httpmq_statuses(state, &view, subscribers)?;
+    Ok(TopicStatus {
+        name: name.to_string(),
+        unread: subscribers.iter().map(|s| s.unread).sum(),
+        snapshot_ms,
+        subscribers,
+    })
+}
+
+#[derive(Serialize, Debug)]
+pub struct PrefixStatus {
+    prefix: String,
+    // unread messages summed over the queues
+    unread: u64,
+    // unix time in milliseconds the queues were read as of
+    snapshot_ms: u64,
+    queues: Vec<QueueStatus>,
+}
+
+// opt=status_prefix and opt=status_prefix_json: the status of every queue
+// whose name starts with prefix=, all read as of the same instant
+fn kv_status_prefix(state: &State, args: &KVSet, fmt: Format) -> Result<Response, HttpmqError> {
+    let prefix = args.prefix.clone().unwrap_or_default();
+    let (view, snapshot_ms) = httpmq_snapshot(state);
+    let queues = httpmq_statuses(state, &view, &httpmq_queues_with_prefix(&view, &prefix))?;
+    let status = PrefixStatus {
+        prefix,
+        unread: queues.iter().map(|s| s.unread).sum(),
+        snapshot_ms,
+        queues,
+    };
+    if args.opt == "status_prefix_json" {
+        return Ok(serde_json::to_string(&status).unwrap().into_response());
+    }
+    if fmt != Format::Text {
+        return Ok(format::encode(fmt, &status));
+    }
+
+    let mut buf = format!(
+        "HTTP Simple Queue Service
+------------------------------
+Prefix: {}
+Number of queues: {}
+Number of unread queue: {}
+Snapshot time: {}
+",
+        status.prefix,
+        status.queues.len(),
+        status.unread,
+        status.snapshot_ms
+    );
+    for s in &status.queues {
+        let _ = writeln!(buf, "Queue {}: {} unread", s.name, s.unread);
+    }
+    Ok(buf.into_response())
+}
+
+// most queues opt=list returns at once
+const LIST_MAX: usize = 1000;
+
+#[derive(Serialize, Debug)]
+struct ListedQueue {
+    name: String,
+    putpos: u64,
+    getpos: u64,
+    unread: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ListResponse {
+    result: &'static str,
+    queues: Vec<ListedQueue>,
+    // queues whose metadata needs opt=fsck
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    corrupt: Vec<String>,
+    // the last queue listed when there are more, to pass as after=
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next: Option<String>,
+}
+
+// opt=list names the queues in the database, in order, with their cursors.
+// Queues are found by their metadata keys only. prefix= narrows the list;
+// limit= (at most LIST_MAX) and after= page through it.
+fn kv_list(state: &State, args: &KVSet, fmt: Format) -> Result<Response, HttpmqError> {
+    let limit = args.limit.unwrap_or(LIST_MAX).clamp(1, LIST_MAX);
+    let (view, _) = httpmq_snapshot(state);
+    let prefix = args.prefix.as_deref().unwrap_or("");
+    let mut names = httpmq_queue_page(&view, prefix, args.after.as_deref(), limit)?;
+    let next = (names.len() > limit).then(|| names[limit - 1].clone());
+    names.truncate(limit);
+
+    let mut res = ListResponse {
+        result: "HTTPMQ_LIST_OK",
+        queues: Vec::with_capacity(names.len()),
+        corrupt: Vec::new(),
+        next,
+    };
+    for name in names {
+        match httpmq_read_metadata_in(state, &view, &name) {
+            Ok(metadata) => res.queues.push(ListedQueue {
+                name,
+                putpos: metadata[1],
+                getpos: metadata[2],
+                unread: httpmq_unread(&metadata).0,
+            }),
+            Err(HttpmqError::QueueCorrupt(_)) => res.corrupt.push(name),
+            Err(e) => return Err(e),
+        }
+    }
+    if fmt != Format::Text {
+        return Ok(format::encode(fmt, &res));
+    }
+
+    let mut buf = format!("{}\nqueues: {}\n", res.result, res.queues.len());
+    if let Some(next) = &res.next {
+        let _ = writeln!(buf, "next: {}", next);
+    }
+    for q in &res.queues {
+        let _ = writeln!(
+            buf,
+            "Queue {}: putpos {}, getpos {}, unread {}",
+            q.name, q.putpos, q.getpos, q.unread
+        );
+    }
+    for name in &res.corrupt {
+        let _ = writeln!(buf, "Corrupt queue {}", name);
+    }
+    Ok(buf.into_response())
+}
+
+fn httpmq_queue_exists(state: &State, name: &str) -> Result<bool, HttpmqError> {
+    httpmq_queue_exists_in(&View::Live(&*state.db), name)
+}
+
+fn httpmq_queue_exists_in(view: &View, name: &str) -> Result<bool, HttpmqError> {
+    let keys = METADATA_FIELDS
+        .iter()
+        .map(|f| format!("{}.{}", name, f).into_bytes())
+        .collect();
+    for x in view.multi_get(keys) {
+        if x?.is_some() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+async fn kv_alias(state: &State, Query(args): Query<KVSet>) -> Result<String, HttpmqError> {
+    if args.opt == "unalias" {
+        if state.aliases.remove(&*state.db, &args.name)? {
+            return Ok(String::from("HTTPMQ_UNALIAS_OK"));
+        }
+        return Ok(String::from("HTTPMQ_UNALIAS_NONE"));
+    }
+
+    let target = match &args.queue {
+        Some(target) if valid_name(&state.config, target) => target,
+        _ => return Ok(String::from("HTTPMQ_ALIAS_INVALID")),
+    };
+    // an alias must not hide a queue or topic living under its name
+    if state.topics.is_topic(&args.name) || httpmq_queue_exists(state, &args.name)? {
+        return Ok(String::from("HTTPMQ_ALIAS_EXISTS"));
+    }
+    if !state.aliases.add(&*state.db, &args.name, target)? {
+        return Ok(String::from("HTTPMQ_ALIAS_INVALID"));
+    }
+    Ok(String::from("HTTPMQ_ALIAS_OK"))
+}
+
+// opt=mirror (an admin operation) copies every put on queue name to the
+// queue dest=, in the same write, until opt=unmirror. A put the mirror is
+// full for still goes through, unless strict=1.
+async fn kv_mirror(state: &State, Query(args): Query<KVSet>) -> Result<String, HttpmqError> {
+    if args.opt == "unmirror" {
+        if state.mirrors.detach(&*state.db, &args.name)? {
+            return Ok(String::from("HTTPMQ_UNMIRROR_OK"));
+        }
+        return Ok(String::from("HTTPMQ_UNMIRROR_NONE"));
+    }
+
+    let target = match &args.dest {
+        Some(target)
+            if valid_name(&state.config, target)
+                && *target != args.name
+                && !state.topics.is_topic(target) =>
+        {
+            state
+                .aliases
+                .target(target)
+                .unwrap_or_else(|| target.clone())
+        }
+        _ => return Ok(String::from("HTTPMQ_MIRROR_INVALID")),
+    };
+    if target == args.name {
+        return Ok(String::from("HTTPMQ_MIRROR_INVALID"));
+    }
+    let strict = args.strict.unwrap_or(0) != 0;
+    state
+        .mirrors
+        .attach(&*state.db, &args.name, &target, strict)?;
+    info!("mirroring queue {} to {}", args.name, target);
+    Ok(String::from("HTTPMQ_MIRROR_OK"))
+}
+
+// opt=status and opt=status_json on a topic, in the format asked for
+fn kv_topic_status(
+    state: &State,
+    opt: &str,
+    fmt: Format,
+    name: &str,
+) -> Result<Response, HttpmqError> {
+    let subscribers = state.topics.subscribers(name).unwrap_or_default();
+    let status = httpmq_topic_status(state, name, &subscribers)?;
+    if opt == "status_json" {
+        return Ok(serde_json::to_string(&status).unwrap().into_response());
+    }
+    if fmt != Format::Text {
+        return Ok(format::encode(fmt, &status));
+    }
+
+    let mut buf = format!(
+        "HTTP Simple Queue Service
+------------------------------
+Topic Name: {}
+Number of subscribers: {}
+Number of unread queue: {}
+Snapshot time: {}
+",
+        status.name,
+        status.subscribers.len(),
+        status.unread,
+        status.snapshot_ms
+    );
+    for s in &status.subscribers {
+        let _ = writeln!(buf, "Subscriber {}: {} unread", s.name, s.unread);
+    }
+    Ok(buf.into_response())
+}
+
+async fn kv_subscribe(state: &State, Query(args): Query<KVSet>) -> Result<String, HttpmqError> {
+    // subscribe the queue behind an alias, fanout doesn't resolve names
+    let queue = match &args.queue {
+        Some(queue) => state.aliases.target(queue).unwrap_or_else(|| queue.clone()),
+        None => return Ok(String::from("HTTPMQ_TOPIC_INVALID")),
+    };
+    // fanout is one level deep: a topic can't subscribe itself or another topic
+    if !valid_name(&state.config, &queue) || queue == args.name || state.topics.is_topic(&queue) {
+        return Ok(String::from("HTTPMQ_TOPIC_INVALID"));
+    }
+
+    if args.opt == "subscribe" {
+        state.topics.subscribe(&*state.db, &args.name, &queue)?;
+        return Ok(String::from("HTTPMQ_SUBSCRIBE_OK"));
+    }
+    if state.topics.unsubscribe(&*state.db, &args.name, &queue)? {
+        Ok(String::from("HTTPMQ_UNSUBSCRIBE_OK"))
+    } else {
+        Ok(String::from("HTTPMQ_UNSUBSCRIBE_NONE"))
+    }
+}
+
+async fn kv_reset(state: &State, Query(args): Query<KVSet>) -> Result<String, HttpmqError> {
+    // reset is irreversible, make the caller spell out the queue name
+    if !state.config.allow_unprotected_reset && args.confirm.as_deref() != Some(&args.name[..]) {
+        return Ok(String::from("HTTPMQ_CONFIRM_REQUIRED"));
+    }
+    httpmq_reset(state, &args.name).await.map(String::from)
+}
+
+// empty queue name and put it back to the default maxqueue
+pub(crate) async fn httpmq_reset(state: &State, name: &str) -> Result<&'static str, HttpmqError> {
+    let _locked = state.queue_locks.lock(name).await;
+    if httpmq_readonly(state, name)? != 0 {
+        return Ok("HTTPMQ_QUEUE_READONLY");
+    }
+
+    // the old messages go too, or replayall would bring them back
+    let mut batch = WriteBatch::default();
+    httpmq_delete_span(&mut batch, name);
+    batch.put(
+        format!("{}.maxqueue", name),
+        state.config.maxqueue.to_string(),
+    );
+    batch.put(format!("{}.putpos", name), b"0");
+    batch.put(format!("{}.getpos", name), b"0");
+    state.db.write(batch)?;
+    state.reservations.settle(name);
+
+    Ok("HTTPMQ_RESET_OK")
+}
+
+// queue the deletion of all of name's messages as a single range delete.
+// No name contains ':', so nothing but name's messages sits in the span.
+fn httpmq_delete_span(batch: &mut WriteBatch, name: &str) {
+    let (from, to) = message_span(name);
+    batch.delete_range(from, to);
+}
+
+// how a deletion's writes go in: as soon as possible, or at low priority
+// and paced by state.background
+#[derive(Clone, Copy, PartialEq)]
+enum Priority {
+    Foreground,
+    Background,
+}
+
+fn httpmq_write(state: &State, batch: WriteBatch, priority: Priority) -> Result<(), HttpmqError> {
+    match priority {
+        Priority::Foreground => state.db.write(batch),
+        Priority::Background => {
+            state.background.charge(batch.size_in_bytes());
+            state.db.write_low_pri(batch)
+        }
+    }
+}
+
+// messages are probed for this many positions at a time
+const REMOVE_CHUNK: u64 = 1000;
+
+// delete every message of a queue, then its metadata. Metadata goes last,
+// so an interrupted remove can simply be retried. A read-only queue is
+// left alone, and one whose flag can't be read is an error rather than
+// taken for writable.
+async fn httpmq_remove(
+    state: &State,
+    name: &str,
+    priority: Priority,
+) -> Result<&'static str, HttpmqError> {
+    // held throughout, so a put or get can't write metadata back behind it
+    let locked = state.queue_locks.lock(name).await;
+    if httpmq_readonly(state, name)? != 0 {
+        return Ok("HTTPMQ_QUEUE_READONLY");
+    }
+    let mut batch = WriteBatch::default();
+    httpmq_delete_span(&mut batch, name);
+
+    for field in METADATA_FIELDS {
+        batch.delete(format!("{}.{}", name, field));
+    }
+    batch.delete(format!("{}.expired", name));
+    batch.delete(format!("{}.seq", name));
+    httpmq_write(state, batch, priority)?;
+    state.settings.remove(&*state.db, name)?;
+    state.totals.remove(&*state.db, name)?;
+    state.put_limits.remove(name);
+    state.metrics.remove(name);
+    state.mirrors.detach(&*state.db, name)?;
+    state.reservations.settle(name);
+    state.corrupt.lock().unwrap().remove(name);
+    state.queue_locks.remove(name, locked);
+    Ok("HTTPMQ_REMOVE_OK")
+}
+
+async fn kv_remove(state: &State, Query(args): Query<KVSet>) -> Result<String, HttpmqError> {
+    // same rule as reset, a removed queue can't be brought back
+    if !state.config.allow_unprotected_reset && args.confirm.as_deref() != Some(&args.name[..]) {
+        return Ok(String::from("HTTPMQ_CONFIRM_REQUIRED"));
+    }
+    // read-only is checked under the queue lock, so an opt=readonly that
+    // lands first is honored
+    httpmq_remove(state, &args.name, Priority::Foreground)
+        .await
+        .map(String::from)
+}
+
+// the metadata keys, as (name, field), of the queues starting with prefix,
+// in key order from `from`. No name contains ':', so a key with one is a
+// message of the queue before it and the rest of that queue's messages are
+// skipped with a seek: this reads a few keys per queue, not per message.
+// A name can come up again after others, its fields sort around those of
+// the names that extend it ("q.getpos", "q.n.putpos", "q.putpos").
+struct QueueKeys<'a> {
+    view: &'a View<'a>,
+    prefix: &'a str,
+    iter: DBIterator<'a>,
+}
+
+impl<'a> QueueKeys<'a> {
+    fn new(view: &'a View<'a>, prefix: &'a str, from: &str) -> QueueKeys<'a> {
+        let from = from.max(prefix);
+        let iter = view.iterator(IteratorMode::From(from.as_bytes(), Direction::Forward));
+        QueueKeys { view, prefix, iter }
+    }
+}
+
+impl Iterator for QueueKeys<'_> {
+    type Item = (String, &'static str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, _) = self.iter.next()?;
+            if !key.starts_with(self.prefix.as_bytes()) {
+                return None;
+            }
+            if let Some(colon) = key.iter().position(|&b| b == b':') {
+                let mut past = key[..colon].to_vec();
+                past.push(b';');
+                self.iter = self
+                    .view
+                    .iterator(IteratorMode::From(&past, Direction::Forward));
+                continue;
+            }
+            let key = String::from_utf8_lossy(&key);
+            if let Some((name, field)) = key.rsplit_once('.') {
+                if let Some(field) = METADATA_FIELDS.iter().find(|&&f| f == field) {
+                    return Some((name.to_string(), *field));
+                }
+            }
+        }
+    }
+}
+
+// up to limit + 1 names, in order, of the queues starting with prefix that
+// sort after `after`. Reading stops once limit + 1 names sort before the
+// current key: any name still to come sorts after that key or is a prefix
+// of it, and those prefixes are looked up directly.
+fn httpmq_queue_page(
+    view: &View,
+    prefix: &str,
+    after: Option<&str>,
+    limit: usize,
+) -> Result<Vec<String>, HttpmqError> {
+    let mut names = BTreeSet::new();
+    for (name, field) in QueueKeys::new(view, prefix, after.unwrap_or("")) {
+        if after.is_some_and(|after| name.as_str() <= after) {
+            continue;
+        }
+        let key = format!("{}.{}", name, field);
+        names.insert(name);
+        if names.len() > limit + 1 {
+            names.pop_last();
+        }
+        if names.len() > limit && names.last().unwrap() < &key {
+            // a prefix's own fields only sort after key where key goes on
+            // with a byte up to '.'
+            for (i, _) in key.bytes().enumerate().skip(1).filter(|&(_, b)| b <= b'.') {
+                let shorter = &key[..i];
+                if shorter.starts_with(prefix)
+                    && after.is_none_or(|after| shorter > after)
+                    && httpmq_queue_exists_in(view, shorter)?
+                {
+                    names.insert(shorter.to_string());
+                }
+            }
+            break;
+        }
+    }
+    Ok(names.into_iter().take(limit + 1).collect())
+}
+
+// names of the queues starting with prefix, found through their metadata
+fn httpmq_queues_with_prefix(view: &View, prefix: &str) -> BTreeSet<String> {
+    QueueKeys::new(view, prefix, prefix)
+        .map(|(name, _)| name)
+        .collect()
+}
+
+// remove every queue whose name starts with prefix= (an admin operation).
+// The prefix has to be at least --remove-prefix-min bytes so a typo can't
+// take everything. Read-only queues are left alone, and so are those whose
+// read-only flag doesn't parse.
+async fn kv_remove_prefix(state: &State, Query(args): Query<KVSet>) -> Result<String, HttpmqError> {
+    let prefix = args.prefix.unwrap_or_default();
+    if prefix.is_empty() || prefix.len() < state.config.remove_prefix_min {
+        return Ok(String::from("HTTPMQ_PREFIX_TOO_SHORT"));
+    }
+    let dry_run = args.dry_run.unwrap_or(0) != 0;
+
+    let mut removed = Vec::new();
+    let mut readonly = Vec::new();
+    let mut corrupt = Vec::new();
+    for name in httpmq_queues_with_prefix(&View::Live(&*state.db), &prefix) {
+        let res = if dry_run {
+            httpmq_readonly(state, &name).map(|flag| match flag {
+                0 => "HTTPMQ_REMOVE_OK",
+                _ => "HTTPMQ_QUEUE_READONLY",
+            })
+        } else {
+            // a bulk deletion, so it gives way to puts
+            state.background.pace(&state.stall).await;
+            httpmq_remove(state, &name, Priority::Background).await
+        };
+        match res {
+            Ok("HTTPMQ_REMOVE_OK") => removed.push(name),
+            Ok(_) => readonly.push(name),
+            Err(HttpmqError::QueueCorrupt(_)) => corrupt.push(name),
+            Err(e) => return Err(e),
+        }
+    }
+    if !dry_run {
+        warn!("removed {} queues with prefix {}", removed.len(), prefix);
+    }
+
+    let mut buf = String::from(if dry_run {
+        "HTTPMQ_REMOVE_PREFIX_DRY_RUN\n"
+    } else {
+        "HTTPMQ_REMOVE_PREFIX_OK\n"
+    });
+    let _ = writeln!(buf, "removed: {}", removed.len());
+    for name in &removed {
+        let _ = writeln!(buf, "{}", name);
+    }
+    for name in &readonly {
+        let _ = writeln!(buf, "readonly, kept: {}", name);
+    }
+    for name in &corrupt {
+        let _ = writeln!(buf, "corrupt, kept: {}", name);
+    }
+    Ok(buf)
+}
+
+// opt=flush (an admin operation) writes the memtables out and syncs the
+// WAL, so that a snapshot of the volume holds everything acknowledged. The
+// flush blocks, so it runs on a blocking thread like a backup.
+async fn kv_flush(state: &SharedState) -> Result<String, HttpmqError> {
+    let state = state.clone();
+    let res = tokio::task::spawn_blocking(move || state.flusher.run(state.db.raw()))
+        .await
+        .map_err(|e| HttpmqError::Db(e.to_string()))?;
+    match res? {
+        Some(flushed) => {
+            info!("flushed {} bytes in {:.1?}", flushed.bytes, flushed.elapsed);
+            Ok(format!(
+                "HTTPMQ_FLUSH_OK\nelapsed_ms: {}\nbytes: {}\n",
+                flushed.elapsed.as_millis(),
+                flushed.bytes
+            ))
+        }
+        None => Ok(String::from("HTTPMQ_FLUSH_BUSY")),
+    }
+}
+
+// opt=backup&dir=<path> writes a checkpoint of the whole database, with the
+// queue totals written out and the database flushed first as on shutdown.
+// The filesystem work runs on a blocking thread, other requests go on
+// meanwhile.
+async fn kv_backup(state: &SharedState, args: &KVSet) -> Result<Response, HttpmqError> {
+    let dir = match &args.dir {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => return Ok(httpmq_text_answer(String::from("HTTPMQ_BACKUP_INVALID"))),
+    };
+    let start = Instant::now();
+    let state = state.clone();
+    let res = tokio::task::spawn_blocking(move || {
+        if let Err(e) = state.totals.flush(&*state.db) {
+            warn!("can't flush queue totals before the backup: {}", e);
+        }
+        match state.flusher.run(state.db.raw()) {
+            Ok(Some(_)) => {}
+            Ok(None) => return Err(BackupError::Refused("HTTPMQ_BACKUP_BUSY")),
+            Err(e) => return Err(BackupError::Failed(e.to_string())),
+        }
+        backup::create(state.db.raw(), Path::new(&state.config.dbpath), &dir)
+    })
+    .await
+    .map_err(|e| HttpmqError::Db(e.to_string()))?;
+    match res {
+        Ok(backup) => {
+            info!(
+                "backed up {} files, {} bytes, to {} in {:.1?}",
+                backup.files,
+                backup.bytes,
+                backup.dir.display(),
+                start.elapsed()
+            );
+            Ok(httpmq_text_answer(format!(
+                "HTTPMQ_BACKUP_OK\ndir: {}\nfiles: {}\nbytes: {}\nelapsed_ms: {}\n",
+                backup.dir.display(),
+                backup.files,
+                backup.bytes,
+                start.elapsed().as_millis()
+            )))
+        }
+        Err(BackupError::Refused(result)) => Ok(httpmq_text_answer(String::from(result))),
+        Err(BackupError::Failed(msg)) => {
+            warn!("backup failed: {}", msg);
+            httpmq_record("HTTPMQ_BACKUP_FAILED", None);
+            Ok((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("HTTPMQ_BACKUP_FAILED\n{}", msg),
+            )
+                .into_response())
+        }
+    }
+}
+
+// first position of the ranges, taken in order, that still holds a message
+fn httpmq_first_stored(
+    state: &State,
+    name: &str,
+    ranges: &[(u64, u64)],
+) -> Result<Option<u64>, HttpmqError> {
+    for &(first, last) in ranges {
+        let mut pos = first;
+        while pos <= last {
+            let end = pos.saturating_add(REMOVE_CHUNK - 1).min(last);
+            let keys = (pos..=end)
+                .map(|p| message_key(name, p).into_bytes())
+                .collect();
+            for (p, x) in (pos..=end).zip(state.db.multi_get(keys)) {
+                if x?.is_some() {
+                    return Ok(Some(p));
+                }
+            }
+            if end == last {
+                break;
+            }
+            pos = end + 1;
+        }
+    }
+    Ok(None)
+}
+
+// rewind getpos to the oldest message still stored. Once the queue has
+// lapped, slots past putpos hold the previous lap, but the ring can only
+// offer maxqueue - 1 of them: the slot right after putpos stays unread.
+// Returns how many messages became readable again.
+fn httpmq_replayall(state: &State, name: &str) -> Result<i64, HttpmqError> {
+    let before = httpmq_status(state, name)?;
+    let maxqueue = before.maxqueue;
+    let putpos = before.putpos;
+
+    let lapped = putpos < maxqueue - 1
+        && state
+            .db
+            .get(message_key(name, putpos + 1).as_bytes())?
+            .is_some();
+    let ranges = if lapped {
+        vec![(putpos + 2, maxqueue), (1, putpos)]
+    } else {
+        vec![(1, putpos)]
+    };
+
+    // getpos is the position before the next one read
+    let getpos = match httpmq_first_stored(state, name, &ranges)? {
+        Some(1) if lapped => maxqueue,
+        Some(pos) => pos - 1,
+        None => putpos,
+    };
+    state.db.put(
+        format!("{}.getpos", name).as_bytes(),
+        getpos.to_string().as_bytes(),
+    )?;
+
+    let after = httpmq_status(state, name)?;
+    Ok(after.unread as i64 - before.unread as i64)
+}
+
+// a reserved message would be delivered twice once the cursor rewinds, so
+// while one is out this is a conflict, unless force=1 voids its lease
+async fn kv_replayall(state: &State, Query(args): Query<KVSet>) -> Result<Response, HttpmqError> {
+    // a peek-only queue keeps its cursor where it was frozen
+    if is_peek_only(httpmq_readonly(state, &args.name)?) {
+        return Ok(httpmq_text_answer(String::from("HTTPMQ_QUEUE_READONLY")));
+    }
+    let _locked = state.queue_locks.lock(&args.name).await;
+    if state
+        .reservations
+        .in_flight_at(&args.name, state.clock.instant())
+        > 0
+    {
+        if args.force != Some(1) {
+            return Ok((StatusCode::CONFLICT, "HTTPMQ_REPLAYALL_IN_FLIGHT").into_response());
+        }
+        state.reservations.settle(&args.name);
+    }
+    let replayed = httpmq_replayall(state, &args.name)?;
+    Ok(httpmq_text_answer(format!(
+        "HTTPMQ_REPLAYALL_OK\nreplayed: {}\n",
+        replayed
+    )))
+}
+
+// messages opt=replay puts with one write
+const REPLAY_BATCH: usize = 100;
+
+// positions from..=to of a ring of maxqueue slots, wrapping past the end
+fn ring_range(maxqueue: u64, from: u64, to: u64) -> impl Iterator<Item = u64> + Clone {
+    let (end, wrapped) = if from <= to { (to, 0) } else { (maxqueue, to) };
+    (from..=end).chain(1..=wrapped)
+}
+
+// the message at pos of queue name as of snapshot, with its header. None
+// when the slot is empty, its message expired or was taken, or a chunk is
+// missing.
+fn httpmq_snapshot_message(
+    snapshot: &Snapshot,
+    name: &str,
+    pos: u64,
+    now: u64,
+) -> Result<Option<(Header, Vec<u8>)>, HttpmqError> {
+    let key = message_key(name, pos);
+    let (header, stored) = match snapshot.get(key.as_bytes())? {
+        Some(value) => chunk::open(&key, value)?,
+        None => return Ok(None),
+    };
+    if header.taken || header.is_expired(now) {
+        return Ok(None);
+    }
+    let data = match stored {
+        Stored::Whole(data) => Some(data),
+        Stored::Chunked(m) => m.assemble_at(snapshot)?,
+    };
+    Ok(data.map(|data| (header, data)))
+}
+
+// put messages sharing a header on dest through the normal put path, in one
+// write if they all fit and one by one up to the first refused otherwise.
+// Returns how many went in and, unless all did, the sentinel refusing the
+// rest.
+async fn httpmq_replay_put(
+    state: &State,
+    dest: &str,
+    messages: Vec<Vec<u8>>,
+    header: &Header,
+) -> Result<(usize, Option<&'static str>), HttpmqError> {
+    let res = kv_set(state, dest, messages.clone(), false, header).await?;
+    if res.result == "HTTPMQ_PUT_OK" {
+        return Ok((messages.len(), None));
+    }
+    if res.result != "HTTPMQ_PUT_END" || messages.len() == 1 {
+        return Ok((0, Some(res.result)));
+    }
+    for (copied, data) in messages.into_iter().enumerate() {
+        let res = kv_set(state, dest, vec![data], false, header).await?;
+        if res.result != "HTTPMQ_PUT_OK" {
+            return Ok((copied, Some(res.result)));
+        }
+    }
+    unreachable!("the whole batch didn't fit")
+}
+
+// a message opt=replay is about to copy: where it was, how many slots
+// before it were missing, and the message
+struct ReplayEntry {
+    pos: u64,
+    missing: usize,
+    data: Vec<u8>,
+}
+
+// opt=replay copies the messages at positions from..=to of a queue onto
+// dest, leaving the queue itself alone. The range is read from a snapshot,
+// so puts racing the replay aren't copied. Empty, expired or taken slots
+// count as missing. The ring is written in order, so where the sequence
+// numbers drop the slots before were written over by a later lap; those are
+// skipped. When dest refuses a put the replay stops and reports the
+// position to resume from.
+async fn kv_replay(state: &State, Query(args): Query<KVSet>) -> Result<String, HttpmqError> {
+    let dest = match args.dest.as_deref() {
+        Some(dest) if dest != args.name => state
+            .aliases
+            .target(dest)
+            .unwrap_or_else(|| dest.to_string()),
+        _ => return Ok(String::from("HTTPMQ_REPLAY_INVALID")),
+    };
+    if !valid_name(&state.config, &dest) {
+        return Err(HttpmqError::NameInvalid);
+    }
+    let maxqueue = httpmq_read_metadata(state, &args.name)?[0];
+    let in_ring = |pos: u64| (1..=maxqueue).contains(&pos);
+    let positions = match (args.from, args.to) {
+        (Some(from), Some(to)) if in_ring(from) && in_ring(to) => ring_range(maxqueue, from, to),
+        _ => return Ok(String::from("HTTPMQ_REPLAY_INVALID")),
+    };
+    let snapshot = state.db.raw().snapshot();
+
+    // find the last drop in sequence numbers, counting the slots before it
+    let (mut start, mut overwritten, mut stored, mut last) = (0, 0, 0, 0);
+    for (i, pos) in positions.clone().enumerate() {
+        let key = message_key(&args.name, pos);
+        let seq = match snapshot.get(key.as_bytes())? {
+            Some(value) => chunk::open(&key, value)?.0.seq,
+            None => continue,
+        };
+        // messages from before sequence numbers can't tell
+        if let Some(seq) = seq {
+            if seq < last {
+                start = i;
+                overwritten = stored;
+            }
+            last = seq;
+        }
+        stored += 1;
+    }
+
+    let now = state.clock.unix_secs();
+    let (mut copied, mut missing) = (0, 0);
+    let mut stopped = None;
+    // consecutive messages with the same header, put together
+    let mut run: Vec<ReplayEntry> = Vec::new();
+    let mut run_header = Header::default();
+    let mut positions = positions.skip(start);
+    loop {
+        let next = match positions.next() {
+            Some(pos) => match httpmq_snapshot_message(&snapshot, &args.name, pos, now)? {
+                Some((header, data)) => Some((pos, header, data)),
+                None => {
+                    missing += 1;
+                    continue;
+                }
+            },
+            None => None,
+        };
+        let flush = match &next {
+            Some((_, header, _)) => {
+                !run.is_empty()
+                    && (header.attrs != run_header.attrs
+                        || header.expires != run_header.expires
+                        || run.len() == REPLAY_BATCH)
+            }
+            None => !run.is_empty(),
+        };
+        if flush {
+            let messages = run
+                .iter_mut()
+                .map(|e| std::mem::take(&mut e.data))
+                .collect();
+            let (n, refused) = httpmq_replay_put(state, &dest, messages, &run_header).await?;
+            copied += n;
+            if let Some(result) = refused {
+                // the counts only cover the slots before the one to resume at
+                missing = run[n].missing;
+                stopped = Some((result, run[n].pos));
+                break;
+            }
+            run.clear();
+        }
+        match next {
+            Some((pos, header, data)) => {
+                run_header = Header {
+                    expires: header.expires,
+                    attrs: header.attrs,
+                    ..Header::default()
+                };
+                run.push(ReplayEntry { pos, missing, data });
+            }
+            None => break,
+        }
+    }
+
+    let mut buf = format!(
+        "{}\ncopied: {}\nmissing: {}\noverwritten: {}\n",
+        if stopped.is_some() {
+            "HTTPMQ_REPLAY_STOPPED"
+        } else {
+            "HTTPMQ_REPLAY_OK"
+        },
+        copied,
+        missing,
+        overwritten,
+    );
+    if let Some((result, pos)) = stopped {
+        let _ = write!(buf, "reason: {}\nnext: {}\n", result, pos);
+    }
+    warn!(
+        "replayed {} messages of queue {} onto {}",
+        copied, args.name, dest
+    );
+    Ok(buf)
+}
+
+// opt=readonly freezes a queue's contents, mode=peek (the default) also
+// freezes its cursor while mode=advance lets gets move on. opt=unlock
+// makes it writable again.
+async fn kv_readonly(state: &State, Query(args): Query<KVSet>) -> Result<String, HttpmqError> {
+    let key = format!("{}.readonly", args.name);
+    if args.opt == "unlock" {
+        if httpmq_readonly(state, &args.name)? == 0 {
+            return Ok(String::from("HTTPMQ_UNLOCK_NONE"));
+        }
+        state.db.delete(key.as_bytes())?;
+        return Ok(String::from("HTTPMQ_UNLOCK_OK"));
+    }
+
+    let value = match args.mode.as_deref() {
+        None | Some("peek") => READONLY_PEEK,
+        Some("advance") => READONLY_ADVANCE,
+        Some(_) => return Ok(String::from("HTTPMQ_READONLY_INVALID_MODE")),
+    };
+    state.db.put(key.as_bytes(), value.to_string().as_bytes())?;
+    warn!("queue {} is now read-only ({:?})", args.name, args.mode);
+    Ok(String::from("HTTPMQ_READONLY_OK"))
+}
+
+// opt=config lists a queue's settings as JSON. Given a JSON object, in the
+// request body or in data=, it sets the fields it names (an admin operation)
+// and logs each change.
+async fn kv_config(
+    state: &State,
+    Query(args): Query<KVSet>,
+    body: Option<Bytes>,
+) -> Result<String, HttpmqError> {
+    let current = state.settings.get(&*state.db, &args.name)?;
+    let update = match body {
+        Some(body) if !body.is_empty() => body.to_vec(),
+        _ => match &args.data {
+            Some(data) => data.clone().into_bytes(),
+            None => return Ok(serde_json::to_string(&current).unwrap()),
+        },
+    };
+    if !token_matches(state.config.admin_auth.as_deref(), args.auth.as_deref()) {
+        return Err(HttpmqError::AuthFailed);
+    }
+
+    // merge the given fields over the current ones
+    let mut merged = serde_json::to_value(&current).unwrap();
+    let fields = match serde_json::from_slice(&update) {
+        Ok(serde_json::Value::Object(fields)) => fields,
+        _ => return Ok(String::from("HTTPMQ_CONFIG_INVALID\nnot a JSON object\n")),
+    };
+    for (field, value) in fields {
+        merged[field] = value;
+    }
+    let settings: QueueSettings = match serde_json::from_value(merged) {
+        Ok(settings) => settings,
+        Err(e) => return Ok(format!("HTTPMQ_CONFIG_INVALID\n{}\n", e)),
+    };
+    if let Err(e) = settings.validate() {
+        return Ok(format!("HTTPMQ_CONFIG_INVALID\n{}\n", e));
+    }
+    if let Some(dead_letter) = &settings.dead_letter {
+        if !valid_name(&state.config, dead_letter) || *dead_letter == args.name {
+            return Ok(String::from(
+                "HTTPMQ_CONFIG_INVALID\ndead_letter: not a valid name of another queue\n",
+            ));
+        }
+        // through an alias, a topic or a mirror
+        let dest = state
+            .aliases
+            .target(dead_letter)
+            .unwrap_or_else(|| dead_letter.clone());
+        if httpmq_put_queues(state, &dest).contains(&args.name) {
+            return Ok(String::from(
+                "HTTPMQ_CONFIG_INVALID\ndead_letter: leads back to the queue\n",
+            ));
+        }
+    }
+
+    let (old, new) = (
+        serde_json::to_value(&current).unwrap(),
+        serde_json::to_value(&settings).unwrap(),
+    );
+    for (field, value) in new.as_object().unwrap() {
+        if old[field] != *value {
+            warn!(
+                "config {}.{}: {} -> {}",
+                args.name, field, old[field], value
+            );
+        }
+    }
+    state.settings.set(&*state.db, &args.name, &settings)?;
+    Ok(String::from("HTTPMQ_CONFIG_OK"))
+}
+
+// opt=ttl&num=<secs> sets the ttl setting of a queue (an admin operation
+// like any change to its settings), num=0 drops it. Without num it answers
+// the ttl in effect, 0 for none.
+async fn kv_ttl(state: &State, Query(args): Query<KVSet>) -> Result<String, HttpmqError> {
+    let mut settings = state.settings.get(&*state.db, &args.name)?;
+    let num = match args.num {
+        Some(num) => num,
+        None => return Ok(settings.ttl.unwrap_or(0).to_string()),
+    };
+    if !token_matches(state.config.admin_auth.as_deref(), args.auth.as_deref()) {
+        return Err(HttpmqError::AuthFailed);
+    }
+    let ttl = (num > 0).then_some(num);
+    if settings.ttl != ttl {
+        warn!(
+            "config {}.ttl: {} -> {}",
+            args.name,
+            serde_json::json!(settings.ttl),
+            serde_json::json!(ttl)
+        );
+    }
+    settings.ttl = ttl;
+    state.settings.set(&*state.db, &args.name, &settings)?;
+    Ok(String::from("HTTPMQ_TTL_OK"))
+}
+
+// metadata fields of a queue that don't parse, with their raw contents
+fn httpmq_check_metadata(
+    state: &State,
+    name: &str,
+) -> Result<Vec<(&'static str, Vec<u8>)>, HttpmqError> {
+    let mut bad = Vec::new();
+    for field in METADATA_FIELDS {
+        if let Some(raw) = state.db.get(format!("{}.{}", name, field).as_bytes())? {
+            if parse_metadata(&raw).is_none() {
+                bad.push((field, raw));
+            }
+        }
+    }
+    Ok(bad)
+}
+
+// check a queue's metadata. With field=<name>, that field is overwritten
+// first: with num=<n> when given, otherwise with its reset value.
+async fn kv_fsck(state: &State, Query(args): Query<KVSet>) -> Result<String, HttpmqError> {
+    if let Some(field) = &args.field {
+        let value = match &field[..] {
+            "maxqueue" => args.num.unwrap_or(state.config.maxqueue),
+            "putpos" | "getpos" | "readonly" => args.num.unwrap_or(0),
+            _ => return Ok(String::from("HTTPMQ_FSCK_INVALID_FIELD")),
+        };
+        if !(0..=MAXQUEUE_LIMIT).contains(&value) || (field == "maxqueue" && value == 0) {
+            return Ok(String::from("HTTPMQ_FSCK_INVALID_FIELD"));
+        }
+        state.db.put(
+            format!("{}.{}", args.name, field).as_bytes(),
+            value.to_string().as_bytes(),
+        )?;
+        warn!("fsck set {}.{} to {}", args.name, field, value);
+    }
+
+    let bad = httpmq_check_metadata(state, &args.name)?;
+    if bad.is_empty() {
+        state.corrupt.lock().unwrap().remove(&args.name);
+        return Ok(String::from("HTTPMQ_FSCK_OK"));
+    }
+
+    state.corrupt.lock().unwrap().insert(args.name.to_string());
+    let mut buf = String::from("HTTPMQ_FSCK_CORRUPT\n");
+    for (field, raw) in bad {
+        let _ = writeln!(buf, "{}: {:?}", field, String::from_utf8_lossy(&raw));
+    }
+    Ok(buf)
+}
+
+/// Reads the metadata of every queue once, so the first requests after a
+/// restart don't all pay for cold reads at once. Gives up after `limit`.
+/// Returns how many queues were warmed and whether that was all of them.
+pub fn preload_metadata(state: &State, limit: Duration) -> (usize, bool) {
+    let deadline = state.clock.instant() + limit;
+    let view = View::Live(&*state.db);
+    let mut warmed = HashSet::new();
+    for (name, _) in QueueKeys::new(&view, "", "") {
+        if state.clock.instant() >= deadline {
+            return (warmed.len(), false);
+        }
+        if !warmed.contains(&name) {
+            // a corrupt queue is quarantined on the way
+            let _ = httpmq_read_metadata(state, &name);
+            warmed.insert(name);
+        }
+    }
+    (warmed.len(), true)
+}
+
+/// Scans every metadata key in the database and quarantines queues whose
+/// metadata doesn't parse. Returns the number of corrupt fields found.
+/// This walks the whole keyspace, so it only runs when asked for at startup.
+pub fn fsck_all(state: &State) -> usize {
+    let mut found = 0;
+    for (key, value) in state.db.raw().iterator(IteratorMode::Start) {
+        let key = String::from_utf8_lossy(&key);
+        let (name, field) = match key.rsplit_once('.') {
+            Some(split) => split,
+            None => continue,
+        };
+        if METADATA_FIELDS.contains(&field) && parse_metadata(&value).is_none() {
+            warn!("corrupt metadata {}.{}: {:?}", name, field, value);
+            state.corrupt.lock().unwrap().insert(name.to_string());
+            found += 1;
+        }
+    }
+    found
+}
+
+// temporary queues of opt=selftest are named <prefix><unix ms>-<seq>
+const SELFTEST_PREFIX: &str = "httpmq-selftest-";
+
+// a selftest queue this old was left behind by a run that never finished
+const SELFTEST_STALE: Duration = Duration::from_secs(300);
+
+const SELFTEST_MESSAGES: usize = 10;
+
+static SELFTEST_SEQ: AtomicU64 = AtomicU64::new(0);
+
+// remove selftest queues left behind by crashed runs, found through their
+// metadata keys. Returns how many were removed.
+async fn selftest_gc(state: &State) -> Result<usize, HttpmqError> {
+    let now = state.clock.unix_millis();
+    let mut stale = BTreeSet::new();
+    for name in httpmq_queues_with_prefix(&View::Live(&*state.db), SELFTEST_PREFIX) {
+        let started = name[SELFTEST_PREFIX.len()..]
+            .split('-')
+            .next()
+            .and_then(|ms| ms.parse::<u128>().ok());
+        if let Some(started) = started {
+            if now.saturating_sub(started) > SELFTEST_STALE.as_millis() {
+                stale.insert(name);
+            }
+        }
+    }
+    for name in &stale {
+        warn!("removing leftover selftest queue {}", name);
+        httpmq_remove(state, name, Priority::Background).await?;
+    }
+    Ok(stale.len())
+}
+
+fn expect(what: &str, got: &str, want: &str) -> Result<(), String> {
+    if got == want {
+        Ok(())
+    } else {
+        Err(format!("{}: expected {}, got {}", what, want, got))
+    }
+}
+
+async fn selftest_get(state: &State, name: &str, want: &[u8]) -> Result<(), String> {
+    let args = KVSet {
+        name: name.to_string(),
+        ..Default::default()
+    };
+    let res = kv_get(state, Query(args), false)
+        .await
+        .map_err(|e| e.to_string())?;
+    expect("get", res.result, "HTTPMQ_GET_OK")?;
+    let got = res.data.map(ByteBuf::into_vec).unwrap_or_default();
+    expect(
+        "get",
+        &String::from_utf8_lossy(&got),
+        &String::from_utf8_lossy(want),
+    )
+}
+
+async fn selftest_get_end(state: &State, name: &str) -> Result<(), String> {
+    let args = KVSet {
+        name: name.to_string(),
+        ..Default::default()
+    };
+    let res = kv_get(state, Query(args), false)
+        .await
+        .map_err(|e| e.to_string())?;
+    expect("get", res.result, "HTTPMQ_GET_END")
+}
+
+async fn selftest_put(state: &State, name: &str, data: &[u8], want: &str) -> Result<(), String> {
+    let res = kv_set(state, name, vec![data.to_vec()], false, &Header::default())
+        .await
+        .map_err(|e| e.to_string())?;
+    expect("put", res.result, want)
+}
+
+// one step of the selftest script, run against the temporary queue
+async fn selftest_step(state: &State, name: &str, step: &str) -> Result<(), String> {
+    let args = |num| KVSet {
+        name: name.to_string(),
+        confirm: Some(name.to_string()),
+        num,
+        ..Default::default()
+    };
+    let message = |i: usize| format!("selftest message {}", i).into_bytes();
+
+    match step {
+        "put" => {
+            for i in 0..SELFTEST_MESSAGES {
+                selftest_put(state, name, &message(i), "HTTPMQ_PUT_OK").await?;
+            }
+        }
+        "get" => {
+            for i in 0..SELFTEST_MESSAGES {
+                selftest_get(state, name, &message(i)).await?;
+            }
+            selftest_get_end(state, name).await?;
+        }
+        "ring" => {
+            let res = kv_reset(state, Query(args(None))).await;
+            expect("reset", &res.map_err(|e| e.to_string())?, "HTTPMQ_RESET_OK")?;
+            let res = kv_maxqueue(state, Query(args(Some(3)))).await;
+            expect(
+                "maxqueue",
+                &res.map_err(|e| e.to_string())?,
+                "HTTPMQ_MAXQUEUE_OK\nmaxqueue: 3\n",
+            )?;
+            for i in 0..3 {
+                selftest_put(state, name, &message(i), "HTTPMQ_PUT_OK").await?;
+            }
+            selftest_put(state, name, &message(3), "HTTPMQ_PUT_END").await?;
+            selftest_get(state, name, &message(0)).await?;
+            selftest_get(state, name, &message(1)).await?;
+            // wraps around to position 1
+            selftest_put(state, name, &message(3), "HTTPMQ_PUT_OK").await?;
+            for i in 2..4 {
+                selftest_get(state, name, &message(i)).await?;
+            }
+            selftest_get_end(state, name).await?;
+        }
+        "reset" => {
+            let res = kv_reset(state, Query(args(None))).await;
+            expect("reset", &res.map_err(|e| e.to_string())?, "HTTPMQ_RESET_OK")?;
+            let status = httpmq_status(state, name).map_err(|e| e.to_string())?;
+            expect(
+                "status",
+                &format!("{} {}", status.putpos, status.getpos),
+                "0 0",
+            )?;
+        }
+        "remove" => {
+            let res = kv_remove(state, Query(args(None))).await;
+            expect(
+                "remove",
+                &res.map_err(|e| e.to_string())?,
+                "HTTPMQ_REMOVE_OK",
+            )?;
+            for key in [format!("{}.putpos", name), message_key(name, 1)] {
+                if state
+                    .db
+                    .get(key.as_bytes())
+                    .map_err(|e| e.to_string())?
+                    .is_some()
+                {
+                    return Err(format!("remove: {} still exists", key));
+                }
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+// run a scripted put/get/wrap/reset/remove sequence on a temporary queue
+// and report each step with its timing. The first line is
+// HTTPMQ_SELFTEST_OK or HTTPMQ_SELFTEST_FAILED.
+async fn kv_selftest(state: &State) -> Result<Response, HttpmqError> {
+    let gc = selftest_gc(state).await?;
+    let name = format!(
+        "{}{}-{}",
+        SELFTEST_PREFIX,
+        state.clock.unix_millis(),
+        SELFTEST_SEQ.fetch_add(1, Ordering::Relaxed)
+    );
+
+    let start = state.clock.instant();
+    let mut report = String::new();
+    let mut failed = false;
+    for step in ["put", "get", "ring", "reset", "remove"] {
+        let t = state.clock.instant();
+        match selftest_step(state, &name, step).await {
+            Ok(()) => {
+                let _ = writeln!(report, "{}: ok {:.1?}", step, state.clock.instant() - t);
+            }
+            Err(e) => {
+                let _ = writeln!(report, "{}: FAILED {}", step, e);
+                failed = true;
+                break;
+            }
+        }
+    }
+    if failed {
+        // best effort, a later run's gc picks up whatever is left
+        let _ = httpmq_remove(state, &name, Priority::Foreground).await;
+    }
+
+    let buf = format!(
+        "{}\nqueue: {}\nleftover queues removed: {}\n{}total: {:.1?}\n",
+        if failed {
+            "HTTPMQ_SELFTEST_FAILED"
+        } else {
+            "HTTPMQ_SELFTEST_OK"
+        },
+        name,
+        gc,
+        report,
+        state.clock.instant() - start
+    );
+    if failed {
+        warn!("selftest failed: {}", report);
+        return Ok((StatusCode::INTERNAL_SERVER_ERROR, buf).into_response());
+    }
+    Ok(buf.into_response())
+}
+
+// queue names end up in keys, log lines and metrics labels, so keep them
+// short and, unless configured otherwise, boring
+pub(crate) fn valid_name(config: &Config, name: &str) -> bool {
+    name_problem(config, name).is_none()
+}
+
+// why name breaks the naming policy, as X-Httpmq-Reason says it
+fn name_problem(config: &Config, name: &str) -> Option<&'static str> {
+    if name.is_empty() {
+        Some("name_missing")
+    } else if name.len() > config.name_max_len {
+        Some("name_too_long")
+    } else if !config.permissive_names
+        && !name
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_' || c == b'.')
+    {
+        Some("name_characters")
+    } else if name.contains(':') {
+        // ':' starts the message positions, see message_span
+        Some("name_characters")
+    } else {
+        None
+    }
+}
+
+// a query string that doesn't fit KVSet, e.g. num=-1, is the client's
+// mistake and answered as such
+fn query_args(args: Result<Query<KVSet>, QueryRejection>) -> Result<KVSet, HttpmqError> {
+    args.map(|Query(args)| args)
+        .map_err(|e| HttpmqError::ArgsInvalid(e.to_string()))
+}
+
+pub async fn process(
+    args: Result<Query<KVSet>, QueryRejection>,
+    Extension(state): Extension<SharedState>,
+    headers: HeaderMap,
+) -> Result<Response, HttpmqError> {
+    dispatch(state, query_args(args)?, headers, None).await
+}
+
+// read a request body of at most limit bytes, 0 for no limit. A declared
+// length over the limit is refused before anything is read.
+async fn read_body(headers: &HeaderMap, mut body: Body, limit: u64) -> Result<Bytes, HttpmqError> {
+    let declared = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok()?.parse::<u64>().ok());
+    if limit > 0 && declared.is_some_and(|len| len > limit) {
+        return Err(HttpmqError::BodyTooLarge);
+    }
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|_| HttpmqError::BodyUnreadable)?;
+        if limit > 0 && (buf.len() + chunk.len()) as u64 > limit {
+            return Err(HttpmqError::BodyTooLarge);
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(Bytes::from(buf))
+}
+
+// PUT /?name=<queue> with the message as the body, same as opt=put
+pub async fn process_put(
+    args: Result<Query<KVSet>, QueryRejection>,
+    Extension(state): Extension<SharedState>,
+    headers: HeaderMap,
+    RawBody(body): RawBody,
+) -> Result<Response, HttpmqError> {
+    let mut args = query_args(args)?;
+    // a PUT also carries the body of opt=config
+    if args.opt != "config" {
+        args.opt = String::from("put");
+    }
+    let body = read_body(&headers, body, state.config.max_body_size).await?;
+    dispatch(state, args, headers, Some(body)).await
+}
+
+// POST /?opt=put&name=<queue> with the message as the body, as in the
+// original httpmq. The opt stays in the query, put when it is missing.
+pub async fn process_post(
+    args: Result<Query<KVSet>, QueryRejection>,
+    Extension(state): Extension<SharedState>,
+    headers: HeaderMap,
+    RawBody(body): RawBody,
+) -> Result<Response, HttpmqError> {
+    let mut args = query_args(args)?;
+    if args.opt.is_empty() {
+        args.opt = String::from("put");
+    }
+    let body = read_body(&headers, body, state.config.max_body_size).await?;
+    dispatch(state, args, headers, Some(body)).await
+}
+
+// DELETE /?name=<queue>, opt=remove or opt=reset depending on --delete-as
+pub async fn process_delete(
+    args: Result<Query<KVSet>, QueryRejection>,
+    Extension(state): Extension<SharedState>,
+    headers: HeaderMap,
+) -> Result<Response, HttpmqError> {
+    let mut args = query_args(args)?;
+    args.opt = state.config.delete_opt.clone();
+    dispatch(state, args, headers, None).await
+}
+
+pub async fn method_not_allowed() -> impl IntoResponse {
+    (
+        StatusCode::METHOD_NOT_ALLOWED,
+        Headers(vec![(header::ALLOW, "GET, HEAD, POST, PUT, DELETE")]),
+    )
+}
+
+/// How long a request may take: `timeout=<secs>` when given, otherwise
+/// the server default, clamped to between 1s and the server maximum.
+pub fn request_timeout(config: &Config, asked: Option<u64>) -> Duration {
+    let secs = asked.unwrap_or(config.request_timeout);
+    Duration::from_secs(secs.clamp(1, config.max_request_timeout.max(1)))
+}
+
+// the handler bounds its own work by the request's timeout, the tower
+// timeout around it only catches what overruns max_request_timeout
+async fn dispatch(
+    state: SharedState,
+    args: KVSet,
+    headers: HeaderMap,
+    body: Option<Bytes>,
+) -> Result<Response, HttpmqError> {
+    let limit = request_timeout(&state.config, args.timeout);
+    let load = state.load.clone();
+    // pos and result are filled in by dispatch_opt, see httpmq_record
+    let span = info_span!(
+        "request",
+        opt = %args.opt,
+        name = %args.name,
+        pos = field::Empty,
+        result = field::Empty,
+    );
+    let start = Instant::now();
+    let handled = async move {
+        if let Some(res) = httpmq_chaos(&state, &args, &headers).await {
+            return Ok(res);
+        }
+        let json = Format::negotiate(args.format.as_deref(), &headers) == Format::Json;
+        let res = dispatch_opt(state, args, headers, body).await;
+        if json {
+            return Ok(httpmq_json_answer(res).await);
+        }
+        res
+    };
+    let res = match tokio::time::timeout(limit, handled.instrument(span.clone())).await {
+        Ok(res) => res,
+        Err(_) => {
+            load.record_timeout();
+            Ok((StatusCode::REQUEST_TIMEOUT, "request timed out").into_response())
+        }
+    };
+    httpmq_log_request(&span, &res, start.elapsed());
+    res
+}
+
+// one line per request at debug, carrying the fields of its span. Only
+// names, positions and sentinels go in, never message data or tokens.
+fn httpmq_log_request(span: &Span, res: &Result<Response, HttpmqError>, elapsed: Duration) {
+    let status = match res {
+        Ok(r) => r.status(),
+        Err(e) => {
+            span.record("result", field::display(e));
+            e.status_code()
+        }
+    };
+    debug!(
+        parent: span,
+        status = status.as_u16(),
+        latency_ms = elapsed.as_secs_f64() * 1000.0,
+        "handled"
+    );
+}
+
+// fills in the result and position of the request span
+fn httpmq_record(result: &str, pos: Option<u64>) {
+    let span = Span::current();
+    span.record("result", result);
+    if let Some(pos) = pos {
+        span.record("pos", pos);
+    }
+}
+
+// a plain text answer, its sentinel recorded on the request span
+fn httpmq_text_answer(text: String) -> Response {
+    if let Some(result) = text.lines().next().filter(|l| l.starts_with("HTTPMQ_")) {
+        httpmq_record(result, None);
+    }
+    text.into_response()
+}
+
+// with format=json, the operations that answer in plain text, and errors,
+// answer with the text as a JSON object, see format::text_to_json
+async fn httpmq_json_answer(res: Result<Response, HttpmqError>) -> Response {
+    let res = res.unwrap_or_else(IntoResponse::into_response);
+    let is_json = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(format::JSON.as_bytes()));
+    if is_json {
+        return res;
+    }
+    let (parts, body) = res.into_parts();
+    // only streamed gets can fail mid-body, and those are never text here
+    let text = hyper::body::to_bytes(body).await.unwrap_or_default();
+    // opt=status_json and opt=config already answer in JSON
+    let value = serde_json::from_slice(&text)
+        .unwrap_or_else(|_| format::text_to_json(&String::from_utf8_lossy(&text)));
+    let mut out = format::json(&value);
+    *out.status_mut() = parts.status;
+    for (name, value) in &parts.headers {
+        if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+            out.headers_mut().insert(name, value.clone());
+        }
+    }
+    out
+}
+
+// with --chaos, delay the request and maybe answer it with a fault instead
+// of handling it, unless it asks for no chaos. Each injection is logged
+// with the request id.
+async fn httpmq_chaos(state: &State, args: &KVSet, headers: &HeaderMap) -> Option<Response> {
+    let chaos = state.chaos.as_ref()?;
+    if headers
+        .get(CHAOS_HEADER)
+        .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"off"))
+    {
+        return None;
+    }
+    let injection = chaos.decide(args.opt == "put");
+    if injection == Injection::default() {
+        return None;
+    }
+    let id = chaos.request_id(headers.get(REQUEST_ID_HEADER).and_then(|v| v.to_str().ok()));
+    if let Some(delay) = injection.delay {
+        warn!("chaos: delaying request {} by {:?}", id, delay);
+        state.clock.sleep_until(state.clock.instant() + delay).await;
+    }
+    let fault = injection.fault?;
+    warn!(
+        "chaos: {:?} for request {} ({} {})",
+        fault, id, args.opt, args.name
+    );
+    let mut res = match fault {
+        Fault::Full => {
+            let res = PutResponse::new(&args.name, "HTTPMQ_PUT_END", 0);
+            match Format::negotiate(args.format.as_deref(), headers) {
+                Format::Text => res.into_text(),
+                fmt => format::encode(fmt, &res),
+            }
+        }
+        Fault::Error => (StatusCode::INTERNAL_SERVER_ERROR, "HTTPMQ_CHAOS_ERROR").into_response(),
+        Fault::Drop => {
+            // promise a body, then cut it off
+            let (tx, body) = Body::channel();
+            tx.abort();
+            (
+                Headers(vec![(header::CONTENT_LENGTH, String::from("1"))]),
+                Response::new(body),
+            )
+                .into_response()
+        }
+    };
+    if let Ok(id) = HeaderValue::from_str(&id) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, id);
+    }
+    Some(res)
+}
+
+async fn dispatch_opt(
+    state: SharedState,
+    mut args: KVSet,
+    headers: HeaderMap,
+    body: Option<Bytes>,
+) -> Result<Response, HttpmqError> {
+    let unnamed = UNNAMED_OPTS.contains(&&args.opt[..]);
+    if !unnamed {
+        if let Some(reason) = name_problem(&state.config, &args.name) {
+            let mut res = HttpmqError::NameInvalid.into_response();
+            res.headers_mut()
+                .insert(REASON_HEADER, HeaderValue::from_static(reason));
+            return Ok(res);
+        }
+        if !ALIAS_OPTS.contains(&&args.opt[..]) {
+            if let Some(target) = state.aliases.target(&args.name) {
+                args.alias = Some(std::mem::replace(&mut args.name, target));
+            }
+        }
+    }
+
+    if !token_matches(
+        required_token(&state.config, &args.opt),
+        args.auth.as_deref(),
+    ) {
+        return Err(HttpmqError::AuthFailed);
+    }
+    // only requests that got in count towards the hot queues
+    if !unnamed {
+        state.hot.record(&args.name);
+    }
+
+    let start = Instant::now();
+    let queue = (!unnamed).then(|| args.name.clone());
+    let mut events = vec![match &args.opt[..] {
+        "put" => Event::Put,
+        "get" => Event::Get,
+        _ => Event::Other,
+    }];
+
+    let fmt = Format::negotiate(args.format.as_deref(), &headers);
+    let res = match (&args.opt[..], fmt) {
+        ("get", fmt) if args.num.is_some_and(|num| num > 1) => kv_get_batch(&state, args)
+            .await
+            .inspect(|r| {
+                httpmq_record(r.result, None);
+                for taken in &r.taken {
+                    httpmq_count_get(&state, taken, &mut events);
+                }
+                if r.messages.is_empty() && r.result == "HTTPMQ_GET_END" {
+                    events.push(Event::Empty);
+                }
+            })
+            .map(|r| match fmt {
+                Format::Text => r.into_text(),
+                fmt => r.encode(fmt),
+            }),
+        ("get", Format::Text) => kv_get_wait(&state, args, true)
+            .await
+            .inspect(|r| {
+                httpmq_record(r.result, Some(r.pos));
+                httpmq_count_get(&state, r, &mut events)
+            })
+            .map(|r| r.into_text(state.clone())),
+        ("get", fmt) => kv_get_wait(&state, args, false)
+            .await
+            .inspect(|r| {
+                httpmq_record(r.result, Some(r.pos));
+                httpmq_count_get(&state, r, &mut events)
+            })
+            .map(|r| r.encode(fmt)),
+        ("status" | "status_json", _) if state.topics.is_topic(&args.name) => {
+            kv_topic_status(&state, &args.opt, fmt, &args.name)
+        }
+        ("put", _) => match (
+            put_header(&args, &headers, body.as_ref(), state.clock.unix_secs()),
+            put_messages(&args, &headers, body),
+        ) {
+            (_, None) => Ok("HTTPMQ_PUT_INVALID_BODY".into_response()),
+            (None, _) => Ok("HTTPMQ_PUT_ATTR_INVALID".into_response()),
+            (Some(header), Some(messages)) => {
+                let topic = args.topic == Some(1);
+                match args.batch {
+                    Some(_) if !topic => {
+                        kv_set_partial(&state, &args.name, messages, &header).await
+                    }
+                    _ => kv_set(&state, &args.name, messages, topic, &header).await,
+                }
+                .inspect(|r| {
+                    httpmq_record(r.result, r.pos);
+                    if r.result == "HTTPMQ_PUT_END" {
+                        events.push(Event::Full);
+                    }
+                })
+                .map(|r| match fmt {
+                    Format::Text => r.into_text(),
+                    fmt => format::encode(fmt, &r),
+                })
+            }
+        },
+        ("status", Format::Text) => kv_status(&state, Query(args)).await.map(httpmq_text_answer),
+        ("status", fmt) => httpmq_args_status(&state, &args).map(|r| format::encode(fmt, &r)),
+        ("count", fmt) => {
+            let (count, _) = httpmq_unread(&httpmq_read_metadata(&state, &args.name)?);
+            Ok(match fmt {
+                Format::Text => count.to_string().into_response(),
+                fmt => format::encode(fmt, &Count { count }),
+            })
+        }
+        ("status_json", _) => kv_status_json(&state, Query(args))
+            .await
+            .map(httpmq_text_answer),
+        ("reset", _) => kv_reset(&state, Query(args)).await.map(httpmq_text_answer),
+        ("remove", _) => kv_remove(&state, Query(args)).await.map(httpmq_text_answer),
+        ("config", _) => kv_config(&state, Query(args), body)
+            .await
+            .map(httpmq_text_answer),
+        ("ttl", _) => kv_ttl(&state, Query(args)).await.map(httpmq_text_answer),
+        ("readonly" | "unlock", _) => kv_readonly(&state, Query(args))
+            .await
+            .map(httpmq_text_answer),
+        ("replayall", _) => kv_replayall(&state, Query(args)).await,
+        ("replay", _) => kv_replay(&state, Query(args)).await.map(httpmq_text_answer),
+        ("maxqueue", _) => kv_maxqueue(&state, Query(args))
+            .await
+            .map(httpmq_text_answer),
+        ("selftest", _) => kv_selftest(&state).await,
+        ("commit", _) => kv_commit(&state, Query(args)).await,
+        ("ack" | "nack", _) => kv_ack(&state, Query(args)).await,
+        ("tail", fmt) => kv_tail(&state, &args, fmt),
+        ("view", Format::Text) => kv_view(&state, &args, true).map(|r| r.into_text(state.clone())),
+        ("view", fmt) => kv_view(&state, &args, false).map(|r| format::encode(fmt, &r)),
+        ("status_prefix" | "status_prefix_json", fmt) => kv_status_prefix(&state, &args, fmt),
+        ("list", fmt) => kv_list(&state, &args, fmt),
+        ("remove_prefix", _) => kv_remove_prefix(&state, Query(args))
+            .await
+            .map(httpmq_text_answer),
+        ("flush", _) => kv_flush(&state).await.map(httpmq_text_answer),
+        ("backup", _) => kv_backup(&state, &args).await,
+        ("alias" | "unalias", _) => kv_alias(&state, Query(args)).await.map(httpmq_text_answer),
+        ("mirror" | "unmirror", _) => kv_mirror(&state, Query(args)).await.map(httpmq_text_answer),
+        ("subscribe" | "unsubscribe", _) => kv_subscribe(&state, Query(args))
+            .await
+            .map(httpmq_text_answer),
+        ("fsck", _) => kv_fsck(&state, Query(args)).await.map(httpmq_text_answer),
+        _ => Err(HttpmqError::OptInvalid),
+    };
+
+    match &res {
+        Err(e) => {
+            warn!("{}", e);
+            if e.status_code().is_server_error() {
+                events.push(Event::Error);
+            }
+        }
+        Ok(r) if r.status().is_server_error() => events.push(Event::Error),
+        Ok(_) => {}
+    }
+    state.rates.record(queue.as_deref(), &events);
+    state
+        .metrics
+        .record(queue.as_deref(), &events, start.elapsed());
+    res
+}
+
+// a stored message as the response body, passed through byte for byte
+fn text_bytes(body: Vec<u8>) -> Response {
+    (Headers(vec![(header::CONTENT_TYPE, "text/plain")]), body).into_response()
+}
+
+// messages put without a Content-Type are returned as this
+const OCTET_STREAM: &str = "application/octet-stream";
+
+fn message_bytes(content_type: String, body: Vec<u8>) -> Response {
+    (Headers(vec![(header::CONTENT_TYPE, content_type)]), body).into_response()
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StatsArgs {
+    top: Option<usize>,
+    format: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Stats {
+    window_secs: usize,
+    // ops across all queues during the window
+    total_ops: u64,
+    missing_skipped: u64,
+    // queues waiting for opt=fsck
+    corrupt: Vec<String>,
+    top: Vec<QueueOps>,
+    // request rates over each of RATE_WINDOWS
+    throughput: Vec<Throughput>,
+    // the part of throughput from queues not tracked individually
+    other_throughput: Vec<Throughput>,
+    // requests shed, timed out and in flight
+    load: LoadStats,
+    write_stalls: StallStats,
+    background_writes: BackgroundStats,
+    // with --runtime-metrics
+    #[serde(skip_serializing_if = "Option::is_none")]
+    runtime: Option<RuntimeStats>,
+}
+
+pub async fn stats(
+    Query(args): Query<StatsArgs>,
+    Extension(state): Extension<SharedState>,
+    headers: HeaderMap,
+) -> Response {
+    let mut corrupt: Vec<_> = state.corrupt.lock().unwrap().iter().cloned().collect();
+    corrupt.sort();
+    let stats = Stats {
+        window_secs: WINDOW_SECS,
+        total_ops: state.hot.total_ops(),
+        missing_skipped: state.missing_skipped.load(Ordering::Relaxed),
+        corrupt,
+        top: state.hot.top(args.top.unwrap_or(10)),
+        throughput: state.rates.total(),
+        other_throughput: state.rates.other(),
+        load: state.load.stats(),
+        write_stalls: state.stall.stats(),
+        background_writes: state.background.stats(),
+        runtime: state.config.runtime_metrics.then(|| state.runtime.sample()),
+    };
+
+    match Format::negotiate(args.format.as_deref(), &headers) {
+        Format::Text => stats_text(&stats).into_response(),
+        fmt => format::encode(fmt, &stats),
+    }
+}
+
+// read by /readyz, whether or not it holds anything
+const READY_KEY: &[u8] = b"#ready";
+
+/// GET /healthz, answers as long as the process serves requests.
+pub async fn healthz() -> &'static str {
+    "HTTPMQ_OK"
+}
+
+/// GET /readyz, a 503 once shutdown has begun or when the database can't
+/// be read.
+pub async fn readyz(Extension(state): Extension<SharedState>) -> Response {
+    if state.shutting_down.load(Ordering::Relaxed) {
+        return (StatusCode::SERVICE_UNAVAILABLE, "HTTPMQ_SHUTTING_DOWN").into_response();
+    }
+    match state.db.get(READY_KEY) {
+        Ok(_) => "HTTPMQ_READY".into_response(),
+        Err(e) => {
+            warn!("not ready: {}", e);
+            (StatusCode::SERVICE_UNAVAILABLE, e.sentinel()).into_response()
+        }
+    }
+}
+
+/// GET /metrics, the Prometheus exposition of `state.metrics`. The unread
+/// gauge is read from each labelled queue's metadata at scrape time.
+pub async fn metrics(Extension(state): Extension<SharedState>) -> Response {
+    let unread: Vec<Unread> = state
+        .metrics
+        .queues()
+        .into_iter()
+        .filter_map(|queue| {
+            let metadata = httpmq_read_metadata(&state, &queue).ok()?;
+            Some(Unread {
+                unread: httpmq_unread(&metadata).0,
+                queue,
+            })
+        })
+        .collect();
+    (
+        Headers(vec![(
+            header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )]),
+        state.metrics.render(&unread),
+    )
+        .into_response()
+}
+
+fn stats_text(stats: &Stats) -> String {
+    let total = stats.total_ops;
+    let mut buf = format!(
+        "HTTP Simple Queue Service
+------------------------------
+Total ops/s (last {}s): {:.1}
+Missing messages skipped: {}
+Queues needing fsck: {}
+",
+        stats.window_secs,
+        total as f64 / stats.window_secs as f64,
+        stats.missing_skipped,
+        stats.corrupt.join(" ")
+    );
+    for t in &stats.throughput {
+        let _ = writeln!(
+            buf,
+            "Throughput (last {}s): {:.1} puts/s, {:.1} gets/s, {:.1} full/s, {:.1}% errors",
+            t.window_secs,
+            t.puts,
+            t.gets,
+            t.full,
+            t.error_rate * 100.0
+        );
+    }
+    let _ = writeln!(
+        buf,
+        "Load: {} in flight, most {} of limit {}, {} shed, {} timed out",
+        stats.load.in_flight,
+        stats.load.in_flight_high,
+        stats.load.concurrency_limit,
+        stats.load.shed,
+        stats.load.timeouts
+    );
+    let _ = writeln!(
+        buf,
+        "Write stalls: {}, {:.1}s stalled{}",
+        stats.write_stalls.episodes,
+        stats.write_stalls.stalled_ms as f64 / 1000.0,
+        if stats.write_stalls.stalled {
+            ", stalled now"
+        } else {
+            ""
+        }
+    );
+    let _ = writeln!(
+        buf,
+        "Background writes: {}{}",
+        match stats.background_writes.budget_bytes_per_sec {
+            0 => String::from("no limit"),
+            rate => format!("{} bytes/s", rate),
+        },
+        if stats.background_writes.throttled {
+            ", throttled now"
+        } else {
+            ""
+        }
+    );
+    if let Some(rt) = &stats.runtime {
+        let busy: Vec<_> = rt
+            .busy_percent
+            .iter()
+            .map(|b| format!("{:.1}%", b))
+            .collect();
+        let _ = writeln!(
+            buf,
+            "Runtime: {} workers busy {}, injection queue {}, blocking queue {}",
+            rt.workers,
+            busy.join(" "),
+            rt.injection_queue_depth,
+            rt.blocking_queue_depth
+                .map_or(String::from("n/a"), |d| d.to_string())
+        );
+    }
+    buf.push_str("Top queues by recent ops:\n");
+    for (i, q) in stats.top.iter().enumerate() {
+        let _ = writeln!(
+            buf,
+            "{}. {} {:.1} ops/s {}%{}",
+            i + 1,
+            q.name,
+            q.ops_per_sec(),
+            q.ops * 100 / total.max(1),
+            if q.hot { " hot" } else { "" }
+        );
+    }
 
-    return res;
+    buf
 }
 
-pub async fn handle_error(error: BoxError) -> impl IntoResponse {
+pub async fn handle_error(state: SharedState, error: BoxError) -> impl IntoResponse {
     if error.is::<tower::timeout::error::Elapsed>() {
+        state.load.record_timeout();
         return (StatusCode::REQUEST_TIMEOUT, Cow::from("request timed out"));
     }
 
     if error.is::<tower::load_shed::error::Overloaded>() {
+        state.load.record_shed();
         return (
             StatusCode::SERVICE_UNAVAILABLE,
             Cow::from("service is overloaded, try again later"),
@@ -0,0 +1,151 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use crate::error::HttpmqError;
+use crate::storage::Storage;
+
+// cached settings are dropped wholesale past this many queues
+const CACHE_MAX: usize = 10000;
+
+/// Per-queue options, stored as JSON under `<name>.settings`. A queue
+/// without a record gets the defaults. New options go here as fields.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueueSettings {
+    // free-form note for operators, such as the owning team
+    pub description: Option<String>,
+    // gets answer HTTPMQ_GET_PAUSED and leave the cursor alone
+    pub paused: bool,
+    pub delivery: Delivery,
+    // largest message a put may carry, in bytes, 0 for no limit; unset
+    // follows --max-message-size
+    pub max_message_size: Option<u64>,
+    // puts per second across all producers, unset for no limit
+    pub max_put_rate: Option<u64>,
+    // seconds a message stays readable after its put, unset for ever
+    pub ttl: Option<u64>,
+    // deliveries of a message that go unsettled, by nack or lease expiry,
+    // before it is moved to dead_letter (or dropped); unset for no limit
+    pub max_deliveries: Option<u64>,
+    pub dead_letter: Option<String>,
+}
+
+/// When a get moves the cursor past the message it returns.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Delivery {
+    // the cursor is committed before the message is returned, a consumer
+    // that dies with it loses it
+    #[default]
+    AtMostOnce,
+    // every get is a reservation that opt=commit completes, a consumer that
+    // dies before committing gets the message again
+    AtLeastOnce,
+}
+
+impl Delivery {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Delivery::AtMostOnce => "at-most-once",
+            Delivery::AtLeastOnce => "at-least-once",
+        }
+    }
+}
+
+impl QueueSettings {
+    /// The message size limit in effect given the server-wide one.
+    pub fn max_message_size(&self, global: u64) -> u64 {
+        self.max_message_size.unwrap_or(global)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(description) = &self.description {
+            if description.len() > 256 {
+                return Err(String::from("description: longer than 256 bytes"));
+            }
+        }
+        if self.max_put_rate == Some(0) {
+            return Err(String::from(
+                "max_put_rate: must be positive, null for no limit",
+            ));
+        }
+        if self.ttl == Some(0) {
+            return Err(String::from("ttl: must be positive, null for no limit"));
+        }
+        if self.max_deliveries == Some(0) {
+            return Err(String::from(
+                "max_deliveries: must be positive, null for no limit",
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn settings_key(name: &str) -> String {
+    format!("{}.settings", name)
+}
+
+/// Read-through cache of queue settings.
+pub struct Settings {
+    cache: RwLock<HashMap<String, QueueSettings>>,
+}
+
+impl Settings {
+    pub fn new() -> Settings {
+        Settings {
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, db: &dyn Storage, name: &str) -> Result<QueueSettings, HttpmqError> {
+        if let Some(settings) = self.cache.read().unwrap().get(name) {
+            return Ok(settings.clone());
+        }
+
+        let settings = match db.get(settings_key(name).as_bytes())? {
+            Some(raw) => serde_json::from_slice(&raw)
+                .map_err(|_| HttpmqError::QueueCorrupt(name.to_string()))?,
+            None => QueueSettings::default(),
+        };
+        let mut cache = self.cache.write().unwrap();
+        if cache.len() >= CACHE_MAX {
+            cache.clear();
+        }
+        cache.insert(name.to_string(), settings.clone());
+        Ok(settings)
+    }
+
+    pub fn set(
+        &self,
+        db: &dyn Storage,
+        name: &str,
+        settings: &QueueSettings,
+    ) -> Result<(), HttpmqError> {
+        let mut cache = self.cache.write().unwrap();
+        if *settings == QueueSettings::default() {
+            db.delete(settings_key(name).as_bytes())?;
+        } else {
+            db.put(
+                settings_key(name).as_bytes(),
+                serde_json::to_string(settings).unwrap().as_bytes(),
+            )?;
+        }
+        cache.insert(name.to_string(), settings.clone());
+        Ok(())
+    }
+
+    /// Deletes the record of a queue that is going away.
+    pub fn remove(&self, db: &dyn Storage, name: &str) -> Result<(), HttpmqError> {
+        let mut cache = self.cache.write().unwrap();
+        db.delete(settings_key(name).as_bytes())?;
+        cache.remove(name);
+        Ok(())
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings::new()
+    }
+}
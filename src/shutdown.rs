@@ -0,0 +1,38 @@
+use tracing::{info, warn};
+
+use crate::state::State;
+
+/// Resolves on SIGINT or SIGTERM, or on ctrl-c where there are no signals.
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut interrupt = signal(SignalKind::interrupt()).expect("can't handle SIGINT");
+        let mut terminate = signal(SignalKind::terminate()).expect("can't handle SIGTERM");
+        tokio::select! {
+            _ = interrupt.recv() => info!("got SIGINT"),
+            _ = terminate.recv() => info!("got SIGTERM"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.expect("can't handle ctrl-c");
+        info!("got ctrl-c");
+    }
+}
+
+/// Makes everything the server acknowledged durable once it has stopped
+/// serving: the lifetime totals are written out, then the memtables and
+/// the WAL are flushed. Failures are logged, the database is closed anyway
+/// when the last reference to the state goes.
+pub fn close(state: &State) {
+    match state.totals.flush(&*state.db) {
+        Ok(queues) => info!("wrote the totals of {} queues", queues),
+        Err(e) => warn!("can't flush queue totals: {}", e),
+    }
+    match state.flusher.run(state.db.raw()) {
+        Ok(Some(flushed)) => info!("flushed {} bytes in {:.1?}", flushed.bytes, flushed.elapsed),
+        Ok(None) => warn!("a flush was still running"),
+        Err(e) => warn!("can't flush the database: {}", e),
+    }
+}
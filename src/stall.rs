@@ -0,0 +1,143 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::state::SharedState;
+
+/// How often the background task asks RocksDB whether writes are stopped.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// a slow write counts as a stall for this long after it returned
+const SLOW_HOLD: Duration = Duration::from_secs(1);
+
+// how long a put waits for a stall to clear before giving up
+const PUT_WAIT: Duration = Duration::from_millis(100);
+const PUT_WAIT_STEP: Duration = Duration::from_millis(10);
+
+/// Tracks RocksDB write stalls, seen either as writes that take longer than
+/// the configured threshold or as the `rocksdb.is-write-stopped` and
+/// `rocksdb.actual-delayed-write-rate` properties. While stalled, puts fail
+/// fast instead of piling up behind the stalled writes.
+pub struct WriteStall {
+    start: Instant,
+    // writes slower than this start a stall, zero disables
+    threshold: Duration,
+    // set by the poller from the database properties
+    stopped: AtomicBool,
+    // a slow write keeps the stall going until then (ms since start)
+    slow_until: AtomicU64,
+    // when the current episode started
+    episode: Mutex<Option<Instant>>,
+    episodes: AtomicU64,
+    stalled_ms: AtomicU64,
+}
+
+/// Stall episodes since startup, for /stats.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct StallStats {
+    pub stalled: bool,
+    pub episodes: u64,
+    // total time spent stalled in finished episodes
+    pub stalled_ms: u64,
+}
+
+impl WriteStall {
+    pub fn new(threshold: Duration) -> WriteStall {
+        WriteStall {
+            start: Instant::now(),
+            threshold,
+            stopped: AtomicBool::new(false),
+            slow_until: AtomicU64::new(0),
+            episode: Mutex::new(None),
+            episodes: AtomicU64::new(0),
+            stalled_ms: AtomicU64::new(0),
+        }
+    }
+
+    fn millis(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.start).as_millis() as u64
+    }
+
+    /// Notes how long a write took.
+    pub fn observe_write(&self, elapsed: Duration) {
+        self.observe_write_at(elapsed, Instant::now())
+    }
+
+    pub fn observe_write_at(&self, elapsed: Duration, now: Instant) {
+        if !self.threshold.is_zero() && elapsed >= self.threshold {
+            self.slow_until
+                .fetch_max(self.millis(now + SLOW_HOLD), Ordering::Relaxed);
+        }
+        self.check_at(now);
+    }
+
+    /// Sets what the database properties say.
+    pub fn set_stopped(&self, stopped: bool) {
+        self.stopped.store(stopped, Ordering::Relaxed);
+        self.check_at(Instant::now());
+    }
+
+    /// Whether writes are stalled now, starting or ending an episode.
+    pub fn check(&self) -> bool {
+        self.check_at(Instant::now())
+    }
+
+    pub fn check_at(&self, now: Instant) -> bool {
+        let stalled = self.stopped.load(Ordering::Relaxed)
+            || self.millis(now) < self.slow_until.load(Ordering::Relaxed);
+        let mut episode = self.episode.lock().unwrap();
+        match (*episode, stalled) {
+            (None, true) => {
+                warn!("write stall started, failing puts until it ends");
+                self.episodes.fetch_add(1, Ordering::Relaxed);
+                *episode = Some(now);
+            }
+            (Some(since), false) => {
+                let lasted = now.saturating_duration_since(since);
+                warn!("write stall ended after {:.1?}", lasted);
+                self.stalled_ms
+                    .fetch_add(lasted.as_millis() as u64, Ordering::Relaxed);
+                *episode = None;
+            }
+            _ => {}
+        }
+        stalled
+    }
+
+    /// Waits a little for a stall to clear. False if it didn't.
+    pub async fn wait(&self) -> bool {
+        let deadline = Instant::now() + PUT_WAIT;
+        while self.check() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(PUT_WAIT_STEP).await;
+        }
+        true
+    }
+
+    pub fn stats(&self) -> StallStats {
+        StallStats {
+            stalled: self.episode.lock().unwrap().is_some(),
+            episodes: self.episodes.load(Ordering::Relaxed),
+            stalled_ms: self.stalled_ms.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Polls the database for stopped or delayed writes until the process ends.
+pub fn spawn_poller(state: SharedState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            let db = state.db.raw();
+            let property = |name| db.property_int_value(name).ok().flatten().unwrap_or(0);
+            let stopped = property("rocksdb.is-write-stopped") != 0
+                || property("rocksdb.actual-delayed-write-rate") != 0;
+            state.stall.set_stopped(stopped);
+        }
+    });
+}
@@ -0,0 +1,415 @@
+use clap::ArgMatches;
+use rocksdb::{BlockBasedOptions, Options, DB};
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64},
+    Arc, Mutex,
+};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::alias::Aliases;
+use crate::background::BackgroundWrites;
+use crate::bucket::PutLimits;
+use crate::chaos::Chaos;
+use crate::clock::{Clock, SystemClock};
+use crate::error::OpenError;
+use crate::flush::Flusher;
+use crate::hot::HotQueues;
+use crate::load::LoadMetrics;
+use crate::locks::QueueLocks;
+use crate::metrics::Metrics;
+use crate::mirror::Mirrors;
+use crate::rate::Rates;
+use crate::reserve::Reservations;
+use crate::runtime::RuntimeSampler;
+use crate::schema;
+use crate::settings::Settings;
+use crate::stall::WriteStall;
+use crate::storage::{Storage, SyncedDb};
+use crate::topic::Topics;
+use crate::totals::Totals;
+use crate::waiters::Waiters;
+
+/// The largest maxqueue accepted. Positions stay below 2^53, so JSON
+/// clients that read numbers as doubles still see them exactly.
+pub const MAXQUEUE_LIMIT: u64 = 1_000_000_000_000_000;
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub dbpath: String,
+    // address and port the server binds, unless it inherits a socket
+    pub host: String,
+    pub port: u16,
+    pub maxqueue: u64,
+    // the most slots opt=maxqueue may give a queue, at most MAXQUEUE_LIMIT
+    pub maxqueue_limit: u64,
+    // ops/s above which a queue is reported as hot, 0 disables
+    pub hot_queue_ops: u64,
+    // percent of all traffic above which a queue is reported as hot, 0 disables
+    pub hot_queue_share: u64,
+    // token required by admin operations such as reset
+    pub admin_auth: Option<String>,
+    // token required by the other operations that write, such as put
+    pub auth: Option<String>,
+    // token required by the operations that read, such as get and status
+    pub auth_read: Option<String>,
+    // let reset go through without confirm=<name>
+    pub allow_unprotected_reset: bool,
+    // advance getpos past slots whose message is missing instead of
+    // returning HTTPMQ_GET_NONE for the same slot until it is restored
+    pub skip_missing: bool,
+    // delete a message in the write that moves getpos past it
+    pub delete_on_get: bool,
+    // sync the WAL on every write before it is acknowledged
+    pub sync_writes: bool,
+    // seconds open connections get to finish after SIGTERM or SIGINT
+    pub shutdown_timeout: u64,
+    // RocksDB tuning, see db_options: memtable size in bytes, SST files kept
+    // open (-1 for all), block cache in bytes and bloom filter bits per key
+    // (0 disables)
+    pub write_buffer_size: usize,
+    pub max_open_files: i32,
+    pub block_cache_size: usize,
+    pub bloom_bits: i32,
+    // longest accepted queue name, in bytes
+    pub name_max_len: usize,
+    // accept any characters in queue names, not just [A-Za-z0-9-_.]
+    pub permissive_names: bool,
+    // opt that a DELETE request maps to, "remove" or "reset"
+    pub delete_opt: String,
+    // messages larger than this many bytes are split over several keys,
+    // 0 disables chunking
+    pub chunk_size: usize,
+    // a put to a topic skips subscriber queues that are full instead of
+    // failing as a whole
+    pub topic_skip_full: bool,
+    // seconds to keep retrying the database open while another process
+    // holds its lock
+    pub wait_for_lock: u64,
+    // sample tokio runtime metrics for /stats
+    pub runtime_metrics: bool,
+    // queues labelled on /metrics, the rest are counted together
+    pub metrics_max_queues: usize,
+    // seconds a connection idles before keepalive probes start, 0 disables
+    pub tcp_keepalive: u64,
+    // seconds between keepalive probes, 0 keeps the system default
+    pub tcp_keepalive_interval: u64,
+    pub tcp_nodelay: bool,
+    // listen backlog of the server socket
+    pub backlog: i32,
+    // listen on this descriptor, passed in by a supervisor, instead of
+    // binding a socket
+    pub inherit_fd: Option<i32>,
+    // open connections past which new ones are refused, 0 disables
+    pub max_connections: usize,
+    // seconds a request may take unless it asks for another timeout=
+    pub request_timeout: u64,
+    // the most a request may ask for, also the hard limit on any request
+    pub max_request_timeout: u64,
+    // requests handled at once, past it new ones are shed with a 503
+    pub concurrency_limit: usize,
+    // shortest prefix opt=remove_prefix accepts
+    pub remove_prefix_min: usize,
+    // milliseconds after which a write counts as stalled, 0 only goes by
+    // the database's own stall properties
+    pub write_stall_ms: u64,
+    // bytes per second bulk deletions may write, 0 for no limit
+    pub background_write_rate: u64,
+    // seconds a mode=reserve get holds a queue waiting for opt=commit
+    pub reserve_timeout: u64,
+    // read every queue's metadata at startup, for at most preload_timeout
+    // seconds
+    pub preload_metadata: bool,
+    pub preload_timeout: u64,
+    // largest message a put may carry, in bytes, 0 for no limit; queues
+    // can set their own
+    pub max_message_size: u64,
+    // largest request body read, in bytes, 0 for no limit
+    pub max_body_size: u64,
+    // inject faults into requests, see Chaos
+    pub chaos: bool,
+    // probability of delaying a request by chaos_latency_ms
+    pub chaos_latency: f64,
+    pub chaos_latency_ms: u64,
+    // probabilities of answering a put as full, of answering with a 500 and
+    // of dropping the connection
+    pub chaos_full: f64,
+    pub chaos_error: f64,
+    pub chaos_drop: f64,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            dbpath: String::from("path"),
+            host: String::from("127.0.0.1"),
+            port: 1218,
+            maxqueue: 100000000,
+            maxqueue_limit: MAXQUEUE_LIMIT,
+            hot_queue_ops: 10000,
+            hot_queue_share: 80,
+            admin_auth: None,
+            auth: None,
+            auth_read: None,
+            allow_unprotected_reset: false,
+            skip_missing: true,
+            delete_on_get: false,
+            sync_writes: false,
+            shutdown_timeout: 30,
+            write_buffer_size: 64 << 20,
+            max_open_files: -1,
+            block_cache_size: 64 << 20,
+            bloom_bits: 10,
+            name_max_len: 256,
+            permissive_names: false,
+            delete_opt: String::from("remove"),
+            chunk_size: 4 << 20,
+            topic_skip_full: false,
+            wait_for_lock: 0,
+            runtime_metrics: false,
+            metrics_max_queues: 1000,
+            tcp_keepalive: 0,
+            tcp_keepalive_interval: 0,
+            tcp_nodelay: false,
+            backlog: 128,
+            inherit_fd: None,
+            max_connections: 0,
+            request_timeout: 10,
+            max_request_timeout: 60,
+            concurrency_limit: 1024,
+            remove_prefix_min: 3,
+            write_stall_ms: 1000,
+            background_write_rate: 0,
+            reserve_timeout: 30,
+            preload_metadata: false,
+            preload_timeout: 30,
+            max_message_size: 0,
+            max_body_size: 64 << 20,
+            chaos: false,
+            chaos_latency: 0.0,
+            chaos_latency_ms: 1000,
+            chaos_full: 0.0,
+            chaos_error: 0.0,
+            chaos_drop: 0.0,
+        }
+    }
+}
+
+impl Config {
+    /// Checks the values that parse but make no sense.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=MAXQUEUE_LIMIT).contains(&self.maxqueue) {
+            return Err(format!(
+                "--maxqueue must be between 1 and {}, not {}",
+                MAXQUEUE_LIMIT, self.maxqueue
+            ));
+        }
+        if !(self.maxqueue..=MAXQUEUE_LIMIT).contains(&self.maxqueue_limit) {
+            return Err(format!(
+                "--maxqueue-limit must be between --maxqueue ({}) and {}, not {}",
+                self.maxqueue, MAXQUEUE_LIMIT, self.maxqueue_limit
+            ));
+        }
+        if self.chaos {
+            Chaos::validate(self)?;
+        }
+        Ok(())
+    }
+
+    /// The configuration the command line asks for. Numbers that don't
+    /// parse are an error, like the values `validate` refuses.
+    pub fn from_matches(matches: &ArgMatches) -> Result<Config, String> {
+        Ok(Config {
+            dbpath: matches.value_of("dbpath").unwrap().to_string(),
+            host: matches.value_of("host").unwrap().to_string(),
+            port: number(matches, "port")?,
+            maxqueue: number(matches, "maxqueue")?,
+            maxqueue_limit: number(matches, "maxqueue-limit")?,
+            hot_queue_ops: number(matches, "hot-queue-ops")?,
+            hot_queue_share: number(matches, "hot-queue-share")?,
+            admin_auth: matches.value_of("admin-auth").map(String::from),
+            auth: matches.value_of("auth").map(String::from),
+            auth_read: matches.value_of("auth-read").map(String::from),
+            allow_unprotected_reset: matches.is_present("allow-unprotected-reset"),
+            skip_missing: !matches.is_present("stall-on-missing"),
+            delete_on_get: matches.is_present("delete-on-get"),
+            sync_writes: matches.is_present("sync-writes"),
+            shutdown_timeout: number(matches, "shutdown-timeout")?,
+            write_buffer_size: number(matches, "write-buffer-size")?,
+            max_open_files: number(matches, "max-open-files")?,
+            block_cache_size: number(matches, "block-cache-size")?,
+            bloom_bits: number(matches, "bloom-bits")?,
+            name_max_len: number(matches, "name-max-len")?,
+            permissive_names: matches.is_present("permissive-names"),
+            delete_opt: matches.value_of("delete-as").unwrap().to_string(),
+            chunk_size: number(matches, "chunk-size")?,
+            topic_skip_full: matches.is_present("topic-skip-full"),
+            runtime_metrics: matches.is_present("runtime-metrics"),
+            metrics_max_queues: number(matches, "metrics-max-queues")?,
+            tcp_keepalive: number(matches, "tcp-keepalive")?,
+            tcp_keepalive_interval: number(matches, "tcp-keepalive-interval")?,
+            tcp_nodelay: matches.is_present("tcp-nodelay"),
+            backlog: number(matches, "backlog")?,
+            inherit_fd: matches
+                .is_present("inherit-fd")
+                .then(|| number(matches, "inherit-fd"))
+                .transpose()?,
+            max_connections: number(matches, "max-connections")?,
+            request_timeout: number(matches, "request-timeout")?,
+            max_request_timeout: number(matches, "max-request-timeout")?,
+            concurrency_limit: number(matches, "concurrency-limit")?,
+            remove_prefix_min: number(matches, "remove-prefix-min")?,
+            write_stall_ms: number(matches, "write-stall-ms")?,
+            background_write_rate: number(matches, "background-write-rate")?,
+            reserve_timeout: number(matches, "reserve-timeout")?,
+            preload_metadata: matches.is_present("preload-metadata"),
+            preload_timeout: number(matches, "preload-timeout")?,
+            max_message_size: number(matches, "max-message-size")?,
+            max_body_size: number(matches, "max-body-size")?,
+            chaos: matches.is_present("chaos"),
+            chaos_latency: number(matches, "chaos-latency")?,
+            chaos_latency_ms: number(matches, "chaos-latency-ms")?,
+            chaos_full: number(matches, "chaos-full")?,
+            chaos_error: number(matches, "chaos-error")?,
+            chaos_drop: number(matches, "chaos-drop")?,
+            wait_for_lock: number(matches, "wait-for-lock")?,
+        })
+    }
+}
+
+// the value of numeric argument name, which is present or has a default
+fn number<T>(matches: &ArgMatches, name: &str) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let value = matches.value_of(name).unwrap();
+    value
+        .parse()
+        .map_err(|e| format!("--{} must be a number, not {:?}: {}", name, value, e))
+}
+
+pub struct State {
+    pub db: Box<dyn Storage>,
+    pub config: Config,
+    pub hot: HotQueues,
+    pub rates: Rates,
+    pub metrics: Metrics,
+    pub runtime: RuntimeSampler,
+    pub load: Arc<LoadMetrics>,
+    pub stall: WriteStall,
+    pub background: BackgroundWrites,
+    pub totals: Totals,
+    pub reservations: Reservations,
+    // gets waiting for a put, see wait= on opt=get
+    pub waiters: Waiters,
+    // messages found missing and skipped by get
+    pub missing_skipped: AtomicU64,
+    // set once a shutdown signal came in, /readyz fails from then on
+    pub shutting_down: AtomicBool,
+    // queues with unparseable metadata, waiting for opt=fsck
+    pub corrupt: Mutex<HashSet<String>>,
+    pub topics: Topics,
+    pub aliases: Aliases,
+    pub mirrors: Mirrors,
+    pub settings: Settings,
+    pub put_limits: PutLimits,
+    // held while a queue's putpos or getpos is moved
+    pub queue_locks: QueueLocks,
+    pub flusher: Flusher,
+    // with --chaos
+    pub chaos: Option<Chaos>,
+    // every time read of the queue logic goes through it
+    pub clock: Arc<dyn Clock>,
+}
+
+pub type SharedState = Arc<State>;
+
+// How the database is opened. Every key a request reads is known up front,
+// metadata or message, so bloom filters spare most lookups of keys that
+// aren't there a disk read, and the index and filter blocks are kept in the
+// block cache with the data. A prefix extractor doesn't fit: queue names
+// have no fixed length. The options are those of any default column family
+// database, so one created by open_default opens as it is.
+fn db_options(config: &Config) -> Options {
+    let mut table = BlockBasedOptions::default();
+    if config.bloom_bits > 0 {
+        table.set_bloom_filter(config.bloom_bits, false);
+    }
+    table.set_cache_index_and_filter_blocks(true);
+    table.set_lru_cache(config.block_cache_size);
+
+    let mut options = Options::default();
+    options.create_if_missing(true);
+    options.set_write_buffer_size(config.write_buffer_size);
+    options.set_max_open_files(config.max_open_files);
+    options.set_block_based_table_factory(&table);
+    options
+}
+
+impl State {
+    pub fn new(config: Config) -> State {
+        State::open(config).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Opens the database at `config.dbpath`, retrying for up to
+    /// `config.wait_for_lock` seconds while its lock is held elsewhere.
+    pub fn open(config: Config) -> Result<State, OpenError> {
+        let deadline = Instant::now() + Duration::from_secs(config.wait_for_lock);
+        // rocksdb only creates the last directory of the path
+        std::fs::create_dir_all(&config.dbpath)
+            .map_err(|e| OpenError::Failed(format!("can't create {}: {}", config.dbpath, e)))?;
+        loop {
+            match DB::open(&db_options(&config), &config.dbpath) {
+                Ok(db) => {
+                    schema::migrate(&db)?;
+                    let db: Box<dyn Storage> = match config.sync_writes {
+                        true => Box::new(SyncedDb(db)),
+                        false => Box::new(db),
+                    };
+                    return Ok(State::with_storage(config, db));
+                }
+                Err(e) => match OpenError::from_message(&config.dbpath, e.into_string()) {
+                    OpenError::Locked(lock) if Instant::now() < deadline => {
+                        warn!("database lock {} is held, retrying", lock);
+                        std::thread::sleep(Duration::from_millis(500));
+                    }
+                    e => return Err(e),
+                },
+            }
+        }
+    }
+
+    pub fn with_storage(config: Config, db: Box<dyn Storage>) -> State {
+        let clock = Arc::new(SystemClock);
+        State {
+            topics: Topics::load(&*db),
+            aliases: Aliases::load(&*db),
+            mirrors: Mirrors::load(&*db),
+            settings: Settings::new(),
+            put_limits: PutLimits::new(),
+            queue_locks: QueueLocks::new(),
+            flusher: Flusher::new(),
+            chaos: config.chaos.then(|| Chaos::new(&config)),
+            db,
+            hot: HotQueues::new(config.hot_queue_ops, config.hot_queue_share),
+            rates: Rates::new(),
+            metrics: Metrics::new(config.metrics_max_queues),
+            runtime: RuntimeSampler::new(),
+            load: Arc::new(LoadMetrics::new(config.concurrency_limit)),
+            totals: Totals::new(),
+            waiters: Waiters::new(),
+            reservations: Reservations::new(Duration::from_secs(config.reserve_timeout), &*clock),
+            stall: WriteStall::new(Duration::from_millis(config.write_stall_ms)),
+            background: BackgroundWrites::new(config.background_write_rate),
+            missing_skipped: AtomicU64::new(0),
+            shutting_down: AtomicBool::new(false),
+            corrupt: Mutex::new(HashSet::new()),
+            clock,
+            config,
+        }
+    }
+}
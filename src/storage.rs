@@ -0,0 +1,133 @@
+use rocksdb::{DBIterator, IteratorMode, Snapshot, WriteBatch, WriteOptions, DB};
+
+use crate::error::HttpmqError;
+
+/// The key/value operations the queue logic performs on the database.
+/// Keeping them behind a trait lets tests wrap the database and inject
+/// faults; anything RocksDB specific goes through `raw()`.
+pub trait Storage: Send + Sync {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, HttpmqError>;
+    fn multi_get(&self, keys: Vec<Vec<u8>>) -> Vec<Result<Option<Vec<u8>>, HttpmqError>>;
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<(), HttpmqError>;
+    fn delete(&self, key: &[u8]) -> Result<(), HttpmqError>;
+    fn write(&self, batch: WriteBatch) -> Result<(), HttpmqError>;
+    /// Writes a batch that may wait behind other writes, for bulk deletions.
+    fn write_low_pri(&self, batch: WriteBatch) -> Result<(), HttpmqError> {
+        self.write(batch)
+    }
+    fn raw(&self) -> &DB;
+}
+
+/// What status reads see: the database as it is, or a snapshot of it so
+/// that several queues are read as of the same instant.
+pub enum View<'a> {
+    Live(&'a dyn Storage),
+    Snapshot(Snapshot<'a>),
+}
+
+impl View<'_> {
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, HttpmqError> {
+        match self {
+            View::Live(db) => db.get(key),
+            View::Snapshot(snapshot) => Ok(snapshot.get(key)?),
+        }
+    }
+
+    pub fn multi_get(&self, keys: Vec<Vec<u8>>) -> Vec<Result<Option<Vec<u8>>, HttpmqError>> {
+        match self {
+            View::Live(db) => db.multi_get(keys),
+            View::Snapshot(_) => keys.iter().map(|key| self.get(key)).collect(),
+        }
+    }
+
+    pub fn iterator(&self, mode: IteratorMode) -> DBIterator<'_> {
+        match self {
+            View::Live(db) => db.raw().iterator(mode),
+            View::Snapshot(snapshot) => snapshot.iterator(mode),
+        }
+    }
+}
+
+impl From<rocksdb::Error> for HttpmqError {
+    fn from(e: rocksdb::Error) -> HttpmqError {
+        HttpmqError::Db(e.into_string())
+    }
+}
+
+impl Storage for DB {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, HttpmqError> {
+        Ok(DB::get(self, key)?)
+    }
+
+    fn multi_get(&self, keys: Vec<Vec<u8>>) -> Vec<Result<Option<Vec<u8>>, HttpmqError>> {
+        DB::multi_get(self, keys)
+            .into_iter()
+            .map(|x| x.map_err(HttpmqError::from))
+            .collect()
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<(), HttpmqError> {
+        Ok(DB::put(self, key, value)?)
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<(), HttpmqError> {
+        Ok(DB::delete(self, key)?)
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<(), HttpmqError> {
+        Ok(DB::write(self, batch)?)
+    }
+
+    fn write_low_pri(&self, batch: WriteBatch) -> Result<(), HttpmqError> {
+        let mut options = WriteOptions::default();
+        options.set_low_pri(true);
+        Ok(DB::write_opt(self, batch, &options)?)
+    }
+
+    fn raw(&self) -> &DB {
+        self
+    }
+}
+
+/// The database with every write synced to the WAL before it returns, for
+/// `--sync-writes`. A put acknowledged then survives a crash of the
+/// machine, not just of the process. Bulk deletions aren't synced.
+pub struct SyncedDb(pub DB);
+
+impl SyncedDb {
+    fn write_options() -> WriteOptions {
+        let mut options = WriteOptions::default();
+        options.set_sync(true);
+        options
+    }
+}
+
+impl Storage for SyncedDb {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, HttpmqError> {
+        Storage::get(&self.0, key)
+    }
+
+    fn multi_get(&self, keys: Vec<Vec<u8>>) -> Vec<Result<Option<Vec<u8>>, HttpmqError>> {
+        Storage::multi_get(&self.0, keys)
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<(), HttpmqError> {
+        Ok(self.0.put_opt(key, value, &SyncedDb::write_options())?)
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<(), HttpmqError> {
+        Ok(self.0.delete_opt(key, &SyncedDb::write_options())?)
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<(), HttpmqError> {
+        Ok(self.0.write_opt(batch, &SyncedDb::write_options())?)
+    }
+
+    fn write_low_pri(&self, batch: WriteBatch) -> Result<(), HttpmqError> {
+        Storage::write_low_pri(&self.0, batch)
+    }
+
+    fn raw(&self) -> &DB {
+        &self.0
+    }
+}
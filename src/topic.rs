@@ -0,0 +1,105 @@
+use rocksdb::{Direction, IteratorMode};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use crate::error::HttpmqError;
+use crate::storage::Storage;
+
+// subscriber lists are stored as #topic#<name> => newline separated queue
+// names. '#' can't appear in a queue name under the default naming policy.
+const TOPIC_PREFIX: &str = "#topic#";
+
+fn topic_key(topic: &str) -> String {
+    format!("{}{}", TOPIC_PREFIX, topic)
+}
+
+/// Topics and their subscriber queues. A put to a topic goes to every
+/// subscriber queue instead. The lists are persisted and cached here, so
+/// a put doesn't pay an extra read to find out whether it hit a topic.
+pub struct Topics {
+    inner: RwLock<HashMap<String, Vec<String>>>,
+}
+
+impl Topics {
+    pub fn load(db: &dyn Storage) -> Topics {
+        let mut topics = HashMap::new();
+        let mode = IteratorMode::From(TOPIC_PREFIX.as_bytes(), Direction::Forward);
+        for (key, value) in db.raw().iterator(mode) {
+            let topic = match key.strip_prefix(TOPIC_PREFIX.as_bytes()) {
+                Some(topic) => String::from_utf8_lossy(topic).to_string(),
+                None => break,
+            };
+            let subscribers = String::from_utf8_lossy(&value)
+                .lines()
+                .map(String::from)
+                .collect();
+            topics.insert(topic, subscribers);
+        }
+        Topics {
+            inner: RwLock::new(topics),
+        }
+    }
+
+    pub fn is_topic(&self, name: &str) -> bool {
+        self.inner.read().unwrap().contains_key(name)
+    }
+
+    pub fn subscribers(&self, topic: &str) -> Option<Vec<String>> {
+        self.inner.read().unwrap().get(topic).cloned()
+    }
+
+    // the list is written while holding the lock so the cache and the
+    // database can't disagree
+    fn update<F>(&self, db: &dyn Storage, topic: &str, f: F) -> Result<bool, HttpmqError>
+    where
+        F: FnOnce(&mut Vec<String>) -> bool,
+    {
+        let mut inner = self.inner.write().unwrap();
+        let mut subscribers = inner.get(topic).cloned().unwrap_or_default();
+        if !f(&mut subscribers) {
+            return Ok(false);
+        }
+        if subscribers.is_empty() {
+            db.delete(topic_key(topic).as_bytes())?;
+            inner.remove(topic);
+        } else {
+            db.put(
+                topic_key(topic).as_bytes(),
+                subscribers.join("\n").as_bytes(),
+            )?;
+            inner.insert(topic.to_string(), subscribers);
+        }
+        Ok(true)
+    }
+
+    /// Adds `queue` to the subscribers of `topic`, false if it already was.
+    pub fn subscribe(
+        &self,
+        db: &dyn Storage,
+        topic: &str,
+        queue: &str,
+    ) -> Result<bool, HttpmqError> {
+        self.update(db, topic, |subscribers| {
+            if subscribers.iter().any(|s| s == queue) {
+                return false;
+            }
+            subscribers.push(queue.to_string());
+            true
+        })
+    }
+
+    /// Drops `queue` from the subscribers of `topic`, false if it wasn't
+    /// one. The topic goes away with its last subscriber.
+    pub fn unsubscribe(
+        &self,
+        db: &dyn Storage,
+        topic: &str,
+        queue: &str,
+    ) -> Result<bool, HttpmqError> {
+        self.update(db, topic, |subscribers| {
+            let len = subscribers.len();
+            subscribers.retain(|s| s != queue);
+            subscribers.len() != len
+        })
+    }
+}
@@ -0,0 +1,192 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::warn;
+
+use crate::error::HttpmqError;
+use crate::state::SharedState;
+use crate::storage::Storage;
+
+/// How often the lifetime counters are written to the database.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Messages and bytes through a queue.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct QueueTotals {
+    pub total_put: u64,
+    pub total_get: u64,
+    pub total_bytes_in: u64,
+    pub total_bytes_out: u64,
+}
+
+impl QueueTotals {
+    fn add(&self, other: &QueueTotals) -> QueueTotals {
+        QueueTotals {
+            total_put: self.total_put + other.total_put,
+            total_get: self.total_get + other.total_get,
+            total_bytes_in: self.total_bytes_in + other.total_bytes_in,
+            total_bytes_out: self.total_bytes_out + other.total_bytes_out,
+        }
+    }
+}
+
+// the key a queue's lifetime totals are kept under
+fn totals_key(name: &str) -> String {
+    format!("{}.totals", name)
+}
+
+// the lifetime totals stored for queue name
+fn load(db: &dyn Storage, name: &str) -> QueueTotals {
+    match db.get(totals_key(name).as_bytes()) {
+        Ok(Some(raw)) => serde_json::from_slice(&raw).unwrap_or_else(|e| {
+            warn!("ignoring unreadable totals of queue {}: {}", name, e);
+            QueueTotals::default()
+        }),
+        Ok(None) => QueueTotals::default(),
+        Err(e) => {
+            warn!("can't load totals of queue {}: {}", name, e);
+            QueueTotals::default()
+        }
+    }
+}
+
+struct Counters {
+    // lifetime totals as of process start
+    base: QueueTotals,
+    put: AtomicU64,
+    get: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    // counted since the last flush
+    dirty: AtomicBool,
+}
+
+impl Counters {
+    fn session(&self) -> QueueTotals {
+        QueueTotals {
+            total_put: self.put.load(Ordering::Relaxed),
+            total_get: self.get.load(Ordering::Relaxed),
+            total_bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            total_bytes_out: self.bytes_out.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Per-queue put and get totals, both since process start and over the
+/// queue's lifetime. The lifetime figures are loaded from `<name>.totals`
+/// the first time a queue is touched and written back every
+/// `FLUSH_INTERVAL` rather than on each request.
+pub struct Totals {
+    queues: RwLock<HashMap<String, Arc<Counters>>>,
+}
+
+impl Totals {
+    pub fn new() -> Totals {
+        Totals {
+            queues: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn counters(&self, db: &dyn Storage, name: &str) -> Arc<Counters> {
+        if let Some(counters) = self.queues.read().unwrap().get(name) {
+            return counters.clone();
+        }
+        let base = load(db, name);
+        self.queues
+            .write()
+            .unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| {
+                Arc::new(Counters {
+                    base,
+                    put: AtomicU64::new(0),
+                    get: AtomicU64::new(0),
+                    bytes_in: AtomicU64::new(0),
+                    bytes_out: AtomicU64::new(0),
+                    dirty: AtomicBool::new(false),
+                })
+            })
+            .clone()
+    }
+
+    /// Counts `messages` put to queue `name`, `bytes` in all.
+    pub fn record_put(&self, db: &dyn Storage, name: &str, messages: u64, bytes: u64) {
+        let counters = self.counters(db, name);
+        counters.put.fetch_add(messages, Ordering::Relaxed);
+        counters.bytes_in.fetch_add(bytes, Ordering::Relaxed);
+        counters.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Counts a message of `bytes` taken from queue `name`.
+    pub fn record_get(&self, db: &dyn Storage, name: &str, bytes: u64) {
+        let counters = self.counters(db, name);
+        counters.get.fetch_add(1, Ordering::Relaxed);
+        counters.bytes_out.fetch_add(bytes, Ordering::Relaxed);
+        counters.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Totals of queue `name` since process start and over its lifetime.
+    pub fn get(&self, db: &dyn Storage, name: &str) -> (QueueTotals, QueueTotals) {
+        match self.queues.read().unwrap().get(name) {
+            Some(counters) => {
+                let session = counters.session();
+                (session, counters.base.add(&session))
+            }
+            None => (QueueTotals::default(), load(db, name)),
+        }
+    }
+
+    /// Writes the lifetime totals of the queues counted since the last
+    /// flush. Returns how many were written.
+    pub fn flush(&self, db: &dyn Storage) -> Result<usize, HttpmqError> {
+        let queues = self.queues.read().unwrap();
+        let mut batch = rocksdb::WriteBatch::default();
+        let mut flushed = Vec::new();
+        for (name, counters) in queues.iter() {
+            if counters.dirty.swap(false, Ordering::Relaxed) {
+                let lifetime = counters.base.add(&counters.session());
+                batch.put(totals_key(name), serde_json::to_vec(&lifetime).unwrap());
+                flushed.push(counters);
+            }
+        }
+        if let Err(e) = db.write(batch) {
+            // try again next time
+            for counters in flushed {
+                counters.dirty.store(true, Ordering::Relaxed);
+            }
+            return Err(e);
+        }
+        Ok(flushed.len())
+    }
+
+    /// Forgets queue `name` and deletes its lifetime totals.
+    pub fn remove(&self, db: &dyn Storage, name: &str) -> Result<(), HttpmqError> {
+        // under the write lock, so a flush in progress can't write them back
+        let mut queues = self.queues.write().unwrap();
+        queues.remove(name);
+        db.delete(totals_key(name).as_bytes())
+    }
+}
+
+impl Default for Totals {
+    fn default() -> Totals {
+        Totals::new()
+    }
+}
+
+/// Flushes the lifetime totals every `FLUSH_INTERVAL` until the process
+/// ends.
+pub fn spawn_flusher(state: SharedState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = state.totals.flush(&*state.db) {
+                warn!("can't flush queue totals: {}", e);
+            }
+        }
+    });
+}
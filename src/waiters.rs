@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tokio::sync::Notify;
+
+/// Gets waiting for a put on an empty queue, see `wait=` on opt=get. Only
+/// queues with a get waiting have an entry, so puts elsewhere don't
+/// allocate.
+pub struct Waiters {
+    inner: RwLock<HashMap<String, Arc<Notify>>>,
+}
+
+impl Waiters {
+    pub fn new() -> Waiters {
+        Waiters {
+            inner: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// What a get waiting on queue `name` waits for.
+    pub fn get(&self, name: &str) -> Arc<Notify> {
+        if let Some(notify) = self.inner.read().unwrap().get(name) {
+            return notify.clone();
+        }
+        self.inner
+            .write()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Wakes the gets waiting on queue `name`, after a put to it.
+    pub fn notify(&self, name: &str) {
+        if let Some(notify) = self.inner.read().unwrap().get(name) {
+            notify.notify_waiters();
+        }
+    }
+
+    /// Drops the entry of queue `name` once nothing waits on it any more.
+    pub fn release(&self, name: &str) {
+        let mut inner = self.inner.write().unwrap();
+        if inner
+            .get(name)
+            .is_some_and(|notify| Arc::strong_count(notify) == 1)
+        {
+            inner.remove(name);
+        }
+    }
+}
+
+impl Default for Waiters {
+    fn default() -> Waiters {
+        Waiters::new()
+    }
+}
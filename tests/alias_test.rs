@@ -0,0 +1,58 @@
+mod common;
+
+#[tokio::test]
+async fn test_alias() {
+    let server = common::server();
+    server.get("/?name=orders_v2&opt=put&data=new").await;
+
+    let (_, body) = server.get("/?name=orders&opt=alias&queue=orders_v2").await;
+    assert_eq!(body, "HTTPMQ_ALIAS_OK");
+
+    // producers on either name share the physical queue
+    let (_, body) = server.get("/?name=orders&opt=put&data=old").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+    let (_, body) = server.get("/?name=orders_v2&opt=get").await;
+    assert_eq!(body, "new");
+    let (_, body) = server.get("/?name=orders_v2&opt=get").await;
+    assert_eq!(body, "old");
+    assert_eq!(server.state.db.raw().get("orders.putpos").unwrap(), None);
+
+    let (_, body) = server.get("/?name=orders&opt=status").await;
+    assert!(body.contains("Queue Name: orders_v2"), "{}", body);
+    assert!(body.contains("Alias: orders -> orders_v2"), "{}", body);
+    let (_, body) = server.get("/?name=orders&opt=status_json").await;
+    assert!(body.contains(r#""alias":"orders""#), "{}", body);
+    let (_, body) = server.get("/?name=orders_v2&opt=status_json").await;
+    assert!(!body.contains("alias"), "{}", body);
+
+    let (_, body) = server.get("/?name=orders&opt=unalias").await;
+    assert_eq!(body, "HTTPMQ_UNALIAS_OK");
+    let (_, body) = server.get("/?name=orders&opt=unalias").await;
+    assert_eq!(body, "HTTPMQ_UNALIAS_NONE");
+    let (_, body) = server.get("/?name=orders&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+}
+
+#[tokio::test]
+async fn test_alias_rules() {
+    let server = common::server();
+    server.get("/?name=real&opt=put&data=a").await;
+    server.get("/?name=a&opt=alias&queue=b").await;
+
+    // shadowing a real queue
+    let (_, body) = server.get("/?name=real&opt=alias&queue=b").await;
+    assert_eq!(body, "HTTPMQ_ALIAS_EXISTS");
+    // chains in either direction
+    let (_, body) = server.get("/?name=c&opt=alias&queue=a").await;
+    assert_eq!(body, "HTTPMQ_ALIAS_INVALID");
+    let (_, body) = server.get("/?name=b&opt=alias&queue=d").await;
+    assert_eq!(body, "HTTPMQ_ALIAS_INVALID");
+    let (_, body) = server.get("/?name=e&opt=alias&queue=e").await;
+    assert_eq!(body, "HTTPMQ_ALIAS_INVALID");
+    let (_, body) = server.get("/?name=e&opt=alias").await;
+    assert_eq!(body, "HTTPMQ_ALIAS_INVALID");
+
+    // aliases survive a restart
+    let aliases = httpmq_rs::alias::Aliases::load(&*server.state.db);
+    assert_eq!(aliases.target("a"), Some(String::from("b")));
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use axum::{body::Body, http::Request};
+use httpmq_rs::{envelope, state::Config};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+#[derive(Deserialize, Debug)]
+struct Get {
+    result: String,
+    #[serde(default)]
+    attrs: BTreeMap<String, String>,
+}
+
+fn put(uri: &str, attrs: &[(&str, &str)], body: &'static str) -> Request<Body> {
+    let mut req = Request::put(uri);
+    for (name, value) in attrs {
+        req = req.header(*name, *value);
+    }
+    req.body(Body::from(body)).unwrap()
+}
+
+#[tokio::test]
+async fn test_attrs_round_trip() {
+    let server = common::server_with(Config {
+        chunk_size: 4,
+        ..Default::default()
+    });
+    let attrs = [
+        ("X-Httpmq-Attr-Event", "signup"),
+        ("X-Httpmq-Attr-Tenant", "42"),
+    ];
+    let (_, _, body) = server.request(put("/?name=xoyo", &attrs, "a")).await;
+    assert_eq!(body, b"HTTPMQ_PUT_OK");
+    // chunked, the attributes sit with the manifest
+    let (_, _, body) = server
+        .request(put("/?name=xoyo", &attrs, "0123456789"))
+        .await;
+    assert_eq!(body, b"HTTPMQ_PUT_OK");
+    server.get("/?name=xoyo&opt=put&data=abc").await;
+
+    let req = Request::get("/?name=xoyo&opt=get")
+        .body(Body::empty())
+        .unwrap();
+    let (_, headers, body) = server.request(req).await;
+    assert_eq!(body, b"a");
+    assert_eq!(headers["x-httpmq-attr-event"], "signup");
+    assert_eq!(headers["x-httpmq-attr-tenant"], "42");
+
+    let req = Request::get("/?name=xoyo&opt=get&format=msgpack")
+        .body(Body::empty())
+        .unwrap();
+    let (_, _, body) = server.request(req).await;
+    let get: Get = rmp_serde::from_slice(&body).unwrap();
+    assert_eq!(get.result, "HTTPMQ_GET_OK");
+    assert_eq!(get.attrs["event"], "signup");
+    assert_eq!(get.attrs.len(), 2);
+
+    // without attributes none are stored or sent
+    let raw = server.state.db.raw().get("xoyo:3").unwrap().unwrap();
+    let (header, value) = envelope::open("xoyo:3", raw).unwrap();
+    assert!(header.attrs.is_empty());
+    assert_eq!(value, b"abc");
+    let req = Request::get("/?name=xoyo&opt=get")
+        .body(Body::empty())
+        .unwrap();
+    let (_, headers, body) = server.request(req).await;
+    assert_eq!(body, b"abc");
+    assert!(!headers
+        .keys()
+        .any(|k| k.as_str().starts_with("x-httpmq-attr-")));
+}
+
+#[tokio::test]
+async fn test_attrs_limits() {
+    let server = common::server();
+    let long = "x".repeat(1100);
+    let (_, _, body) = server
+        .request(put("/?name=xoyo", &[("X-Httpmq-Attr-Big", &long)], "a"))
+        .await;
+    assert_eq!(body, b"HTTPMQ_PUT_ATTR_INVALID");
+
+    let names: Vec<String> = (0..17).map(|i| format!("X-Httpmq-Attr-K{}", i)).collect();
+    let many: Vec<(&str, &str)> = names.iter().map(|n| (&n[..], "v")).collect();
+    let (_, _, body) = server.request(put("/?name=xoyo", &many, "a")).await;
+    assert_eq!(body, b"HTTPMQ_PUT_ATTR_INVALID");
+
+    let twice = [("X-Httpmq-Attr-K", "1"), ("X-Httpmq-Attr-K", "2")];
+    let (_, _, body) = server.request(put("/?name=xoyo", &twice, "a")).await;
+    assert_eq!(body, b"HTTPMQ_PUT_ATTR_INVALID");
+
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""putpos":0"#), "{}", body);
+}
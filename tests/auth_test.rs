@@ -0,0 +1,71 @@
+mod common;
+
+use axum::http::StatusCode;
+
+use httpmq_rs::state::Config;
+
+#[tokio::test]
+async fn test_auth_protects_writes() {
+    let server = common::server_with(Config {
+        auth: Some(String::from("sesame")),
+        ..Default::default()
+    });
+    for uri in [
+        "/?name=xoyo&opt=put&data=a",
+        "/?name=xoyo&opt=put&data=a&auth=wrong",
+        "/?name=xoyo&opt=maxqueue&num=10",
+        "/?name=xoyo&opt=reset&confirm=xoyo",
+    ] {
+        let (code, body) = server.get(uri).await;
+        assert_eq!(code, StatusCode::UNAUTHORIZED, "{}", uri);
+        assert_eq!(body, "HTTPMQ_AUTH_FAILED", "{}", uri);
+    }
+    let (_, body) = server.get("/?name=xoyo&opt=put&data=a&auth=sesame").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+    let (_, body) = server
+        .get("/?name=xoyo&opt=maxqueue&num=10&auth=sesame")
+        .await;
+    assert!(body.starts_with("HTTPMQ_MAXQUEUE_OK"), "{}", body);
+
+    // reads stay open without --auth-read
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "a");
+}
+
+#[tokio::test]
+async fn test_auth_read() {
+    let server = common::server_with(Config {
+        auth: Some(String::from("sesame")),
+        auth_read: Some(String::from("peek")),
+        ..Default::default()
+    });
+    server.get("/?name=xoyo&opt=put&data=a&auth=sesame").await;
+    for opt in ["get", "status", "view&pos=1"] {
+        let (code, _) = server.get(&format!("/?name=xoyo&opt={}", opt)).await;
+        assert_eq!(code, StatusCode::UNAUTHORIZED, "{}", opt);
+        // the write token doesn't read
+        let (code, _) = server
+            .get(&format!("/?name=xoyo&opt={}&auth=sesame", opt))
+            .await;
+        assert_eq!(code, StatusCode::UNAUTHORIZED, "{}", opt);
+    }
+    let (_, body) = server.get("/?name=xoyo&opt=get&auth=peek").await;
+    assert_eq!(body, "a");
+}
+
+#[tokio::test]
+async fn test_admin_auth_wins_for_admin_operations() {
+    let server = common::server_with(Config {
+        auth: Some(String::from("sesame")),
+        admin_auth: Some(String::from("root")),
+        ..Default::default()
+    });
+    let (code, _) = server
+        .get("/?name=xoyo&opt=reset&confirm=xoyo&auth=sesame")
+        .await;
+    assert_eq!(code, StatusCode::UNAUTHORIZED);
+    let (_, body) = server
+        .get("/?name=xoyo&opt=reset&confirm=xoyo&auth=root")
+        .await;
+    assert_eq!(body, "HTTPMQ_RESET_OK");
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use httpmq_rs::background::BackgroundWrites;
+use httpmq_rs::state::Config;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_background_budget() {
+    let background = BackgroundWrites::new(1000);
+    let now = Instant::now();
+    background.charge(500);
+    assert!(background.delay(now) > Duration::from_millis(400));
+    assert_eq!(
+        background.delay(now + Duration::from_secs(1)),
+        Duration::ZERO
+    );
+    assert_eq!(background.stats().budget_bytes_per_sec, 1000);
+
+    // no limit
+    let background = BackgroundWrites::new(0);
+    background.charge(1 << 30);
+    assert_eq!(background.delay(Instant::now()), Duration::ZERO);
+}
+
+#[tokio::test]
+async fn test_remove_prefix_waits_for_stall() {
+    let server = common::server_with(Config {
+        background_write_rate: 1 << 20,
+        ..Default::default()
+    });
+    for name in ["bulk-1", "bulk-2", "keep"] {
+        server.get(&format!("/?name={}&opt=put&data=a", name)).await;
+    }
+    let (_, body) = server.get("/stats").await;
+    assert!(
+        body.contains("Background writes: 1048576 bytes/s\n"),
+        "{}",
+        body
+    );
+
+    server.state.stall.set_stopped(true);
+    let ((_, removed), _) = tokio::join!(server.get("/?opt=remove_prefix&prefix=bulk-"), async {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let (_, body) = server.get("/stats").await;
+        assert!(body.contains("1048576 bytes/s, throttled now"), "{}", body);
+        let (_, body) = server.get("/?name=bulk-1&opt=status").await;
+        assert!(body.contains("Number of unread queue: 1\n"), "{}", body);
+        server.state.stall.set_stopped(false);
+    });
+    assert!(
+        removed.starts_with("HTTPMQ_REMOVE_PREFIX_OK\nremoved: 2\n"),
+        "{}",
+        removed
+    );
+    let (_, body) = server.get("/stats").await;
+    assert!(
+        body.contains("Background writes: 1048576 bytes/s\n"),
+        "{}",
+        body
+    );
+}
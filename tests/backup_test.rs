@@ -0,0 +1,105 @@
+mod common;
+
+use std::sync::Arc;
+
+use httpmq_rs::backup::{self, BackupError};
+use httpmq_rs::state::{Config, State};
+
+#[tokio::test]
+async fn test_backup_and_restore() {
+    let server = common::server_with(Config {
+        admin_auth: Some(String::from("sesame")),
+        ..Default::default()
+    });
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    server.get("/?name=xoyo&opt=put&data=b").await;
+    server.get("/?name=xoyo&opt=get").await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("nightly");
+    let uri = format!("/?opt=backup&dir={}", target.display());
+    let (_, body) = server.get(&uri).await;
+    assert_eq!(body, "HTTPMQ_AUTH_FAILED");
+
+    let (status, body) = server.get(&format!("{}&auth=sesame", uri)).await;
+    assert_eq!(status, 200);
+    assert!(
+        body.starts_with(&format!(
+            "HTTPMQ_BACKUP_OK\ndir: {}\nfiles: ",
+            target.canonicalize().unwrap().display()
+        )),
+        "{}",
+        body
+    );
+    assert!(body.contains("\nbytes: "), "{}", body);
+
+    // the server goes on while the copy is restored elsewhere
+    server.get("/?name=xoyo&opt=put&data=c").await;
+    let restored = dir.path().join("restored");
+    let copied = backup::restore(&target, &restored).unwrap();
+    assert!(copied.files > 0);
+    let state = Arc::new(State::new(Config {
+        dbpath: restored.to_str().unwrap().to_string(),
+        ..Default::default()
+    }));
+    let restored = common::TestServer::new(httpmq_rs::app(state.clone()), state);
+    let (_, body) = restored.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "b");
+    let (_, body) = restored.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+}
+
+#[tokio::test]
+async fn test_backup_refusals() {
+    let server = common::server();
+    let (_, body) = server.get("/?opt=backup").await;
+    assert_eq!(body, "HTTPMQ_BACKUP_INVALID");
+
+    // into the live database, or a directory holding it
+    let dbpath = server.state.config.dbpath.clone();
+    for dir in [format!("{}/backup", dbpath), dbpath.clone()] {
+        let (_, body) = server.get(&format!("/?opt=backup&dir={}", dir)).await;
+        assert_eq!(body, "HTTPMQ_BACKUP_INVALID", "{}", dir);
+    }
+    let (_, body) = server
+        .get(&format!("/?opt=backup&dir={}/new/../../x", dbpath))
+        .await;
+    assert_eq!(body, "HTTPMQ_BACKUP_INVALID");
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("keep"), b"").unwrap();
+    let (_, body) = server
+        .get(&format!("/?opt=backup&dir={}", dir.path().display()))
+        .await;
+    assert_eq!(body, "HTTPMQ_BACKUP_NOT_EMPTY");
+
+    // an empty directory is fine
+    let empty = tempfile::tempdir().unwrap();
+    let (_, body) = server
+        .get(&format!(
+            "/?opt=backup&dir={}&format=json",
+            empty.path().display()
+        ))
+        .await;
+    assert!(body.contains(r#""result":"HTTPMQ_BACKUP_OK""#), "{}", body);
+}
+
+#[test]
+fn test_restore_refusals() {
+    let dir = tempfile::tempdir().unwrap();
+    assert!(matches!(
+        backup::restore(dir.path(), &dir.path().join("db")),
+        Err(BackupError::Failed(_))
+    ));
+
+    let from = dir.path().join("backup");
+    std::fs::create_dir(&from).unwrap();
+    std::fs::write(from.join("CURRENT"), b"MANIFEST-000001\n").unwrap();
+    let into = dir.path().join("db");
+    std::fs::create_dir(&into).unwrap();
+    std::fs::write(into.join("LOCK"), b"").unwrap();
+    match backup::restore(&from, &into) {
+        Err(BackupError::Failed(msg)) => assert!(msg.contains("not empty"), "{}", msg),
+        res => panic!("{:?}", res),
+    }
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use axum::{body::Body, http::Request};
+use serde_json::{json, Value};
+
+use httpmq_rs::state::Config;
+
+async fn post(server: &common::TestServer, uri: &str, body: &'static str) -> (String, String) {
+    let req = Request::post(uri).body(Body::from(body)).unwrap();
+    let (_, headers, body) = server.request(req).await;
+    let pos = headers
+        .get("x-httpmq-pos")
+        .map(|pos| pos.to_str().unwrap().to_string())
+        .unwrap_or_default();
+    (String::from_utf8(body).unwrap(), pos)
+}
+
+#[tokio::test]
+async fn test_batch_put_and_get() {
+    let server = common::server();
+    let (body, pos) = post(&server, "/?name=xoyo&batch=lines", "a\nb\nc\n").await;
+    assert_eq!((&body[..], &pos[..]), ("HTTPMQ_PUT_OK", "3"));
+    // messages holding newlines need the length framing
+    let (body, pos) = post(&server, "/?name=xoyo&batch=len", "3:d\ne\n1:f").await;
+    assert_eq!((&body[..], &pos[..]), ("HTTPMQ_PUT_OK", "5"));
+
+    let (_, body) = server.get("/?name=xoyo&opt=get&num=2").await;
+    assert_eq!(body, "1:1:a\n2:1:b\n");
+    let (_, body) = server.get("/?name=xoyo&opt=get&num=10&format=json").await;
+    let res: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(
+        res,
+        json!({
+            "name": "xoyo",
+            "result": "HTTPMQ_GET_OK",
+            "messages": [
+                {"pos": 3, "seq": 3, "data": "c"},
+                {"pos": 4, "seq": 4, "data": "d\ne"},
+                {"pos": 5, "seq": 5, "data": "f"},
+            ],
+        })
+    );
+    let (_, body) = server.get("/?name=xoyo&opt=get&num=10").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+    let (_, status) = server.get("/?name=xoyo&opt=status").await;
+    assert!(status.contains("Number of unread queue: 0\n"), "{}", status);
+}
+
+#[tokio::test]
+async fn test_batch_put_partial() {
+    let server = common::server_with(Config {
+        maxqueue: 5,
+        ..Default::default()
+    });
+    post(&server, "/?name=xoyo&batch=lines", "a\nb").await;
+    // three slots are left: the first three go in, the rest don't
+    let (body, pos) = post(&server, "/?name=xoyo&batch=lines", "c\nd\ne\nf\ng").await;
+    assert_eq!(body, "HTTPMQ_PUT_PARTIAL\naccepted: 3\n");
+    assert_eq!(pos, "5");
+    let (body, _) = post(&server, "/?name=xoyo&batch=lines", "h\ni").await;
+    assert_eq!(body, "HTTPMQ_PUT_END");
+
+    let (_, body) = server.get("/?name=xoyo&opt=get&num=10").await;
+    assert_eq!(body, "1:1:a\n2:1:b\n3:1:c\n4:1:d\n5:1:e\n");
+}
+
+#[tokio::test]
+async fn test_batch_put_invalid() {
+    let server = common::server();
+    for (batch, body) in [("len", "3:ab"), ("len", "x:a"), ("csv", "a,b")] {
+        let (res, _) = post(&server, &format!("/?name=xoyo&batch={}", batch), body).await;
+        assert_eq!(res, "HTTPMQ_PUT_INVALID_BODY", "{} {}", batch, body);
+    }
+    // an empty line is an empty message
+    let (res, _) = post(&server, "/?name=xoyo&batch=lines", "a\n\nb").await;
+    assert_eq!(res, "HTTPMQ_PUT_NO_DATA");
+    let (_, status) = server.get("/?name=xoyo&opt=status").await;
+    assert!(status.contains("Number of unread queue: 0\n"), "{}", status);
+}
+
+#[tokio::test]
+async fn test_batch_get_on_peek_only_queue() {
+    let server = common::server();
+    post(&server, "/?name=xoyo&batch=lines", "a\nb").await;
+    server.get("/?name=xoyo&opt=readonly").await;
+    let (_, body) = server.get("/?name=xoyo&opt=get&num=2").await;
+    assert_eq!(body, "1:1:a\n");
+}
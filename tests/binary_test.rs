@@ -0,0 +1,92 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+
+#[tokio::test]
+async fn test_get_returns_binary_message_verbatim() {
+    let server = common::server();
+    let junk = vec![0xff, 0x00, 0xfe, 0x80, b'a'];
+    server.state.db.raw().put("xoyo:1", &junk).unwrap();
+    server.state.db.raw().put("xoyo.putpos", "1").unwrap();
+
+    let (code, body) = server.get_bytes("/?name=xoyo&opt=get").await;
+    assert_eq!(code, StatusCode::OK);
+    assert_eq!(body, junk);
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+}
+
+#[tokio::test]
+async fn test_non_utf8_metadata_is_reported_corrupt() {
+    let server = common::server();
+    server
+        .state
+        .db
+        .raw()
+        .put("xoyo.putpos", [0xff, 0xfe])
+        .unwrap();
+
+    for opt in ["get", "put&data=a", "status", "status_json"] {
+        let (code, body) = server.get(&format!("/?name=xoyo&opt={}", opt)).await;
+        assert_eq!(code, StatusCode::INTERNAL_SERVER_ERROR, "{}", opt);
+        assert_eq!(body, "HTTPMQ_QUEUE_CORRUPT", "{}", opt);
+    }
+
+    // other queues keep working
+    let (_, body) = server.get("/?name=other&opt=put&data=a").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+}
+
+#[tokio::test]
+async fn test_get_returns_put_content_type() {
+    let server = common::server();
+    let message = vec![0x08, 0x96, 0x01, 0xff];
+    let req = Request::put("/?name=xoyo")
+        .header(header::CONTENT_TYPE, "application/x-protobuf")
+        .body(Body::from(message.clone()))
+        .unwrap();
+    server.request(req).await;
+    // what curl -d sends unless told otherwise
+    let req = Request::post("/?name=xoyo")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::from("a=b"))
+        .unwrap();
+    server.request(req).await;
+    server.get("/?name=xoyo&opt=put&data=c").await;
+
+    let get = || {
+        Request::get("/?name=xoyo&opt=get")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let (_, headers, body) = server.request(get()).await;
+    assert_eq!(body, message);
+    assert_eq!(headers[header::CONTENT_TYPE], "application/x-protobuf");
+    for data in ["a=b", "c"] {
+        let (_, headers, body) = server.request(get()).await;
+        assert_eq!(body, data.as_bytes());
+        assert_eq!(headers[header::CONTENT_TYPE], "application/octet-stream");
+    }
+}
+
+#[tokio::test]
+async fn test_result_header_tells_message_from_sentinel() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=put&data=HTTPMQ_GET_END").await;
+
+    let get = || {
+        Request::get("/?name=xoyo&opt=get")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let (_, headers, body) = server.request(get()).await;
+    assert_eq!(body, b"HTTPMQ_GET_END");
+    assert_eq!(headers["x-httpmq-result"], "HTTPMQ_GET_OK");
+    let (_, headers, body) = server.request(get()).await;
+    assert_eq!(body, b"HTTPMQ_GET_END");
+    assert_eq!(headers["x-httpmq-result"], "HTTPMQ_GET_END");
+    assert_eq!(headers[header::CONTENT_TYPE], "text/plain");
+}
@@ -0,0 +1,155 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use std::time::{Duration, Instant};
+use tower::ServiceExt;
+
+use httpmq_rs::{
+    chaos::{Chaos, Fault, Injection},
+    cli,
+    state::Config,
+};
+
+fn chaos_config(config: Config) -> Config {
+    Config {
+        chaos: true,
+        ..config
+    }
+}
+
+#[test]
+fn test_decide() {
+    let none = Chaos::new(&chaos_config(Config::default()));
+    for put in [false, true] {
+        assert_eq!(none.decide(put), Injection::default());
+    }
+
+    let full = Chaos::new(&chaos_config(Config {
+        chaos_full: 1.0,
+        chaos_latency: 1.0,
+        chaos_latency_ms: 5,
+        ..Default::default()
+    }));
+    assert_eq!(
+        full.decide(true),
+        Injection {
+            delay: Some(Duration::from_millis(5)),
+            fault: Some(Fault::Full),
+        }
+    );
+    // only puts can be full
+    assert_eq!(full.decide(false).fault, None);
+
+    let drop = Chaos::new(&chaos_config(Config {
+        chaos_drop: 1.0,
+        ..Default::default()
+    }));
+    assert_eq!(drop.decide(false).fault, Some(Fault::Drop));
+}
+
+#[test]
+fn test_validate() {
+    assert!(Chaos::validate(&Config::default()).is_ok());
+    for config in [
+        Config {
+            chaos_error: 1.5,
+            ..Default::default()
+        },
+        Config {
+            chaos_latency: -0.1,
+            ..Default::default()
+        },
+        Config {
+            chaos_error: 0.6,
+            chaos_drop: 0.6,
+            ..Default::default()
+        },
+    ] {
+        assert!(Chaos::validate(&config).is_err(), "{:?}", config);
+    }
+}
+
+#[test]
+fn test_chaos_needs_guard() {
+    assert!(cli::app()
+        .try_get_matches_from(["httpmq-rs", "--chaos"])
+        .is_err());
+    let matches = cli::app()
+        .try_get_matches_from(["httpmq-rs", "--chaos", "--i-know-this-drops-requests"])
+        .unwrap();
+    assert!(Config::from_matches(&matches).unwrap().chaos);
+}
+
+#[tokio::test]
+async fn test_chaos_error_and_escape_header() {
+    let server = common::server_with(chaos_config(Config {
+        chaos_error: 1.0,
+        ..Default::default()
+    }));
+    let (code, headers, body) = server
+        .request(
+            Request::get("/?name=xoyo&opt=put&data=a")
+                .header("x-request-id", "test-1")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(
+        (code, &body[..]),
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &b"HTTPMQ_CHAOS_ERROR"[..]
+        )
+    );
+    assert_eq!(headers["x-request-id"], "test-1");
+
+    let (code, _, body) = server
+        .request(
+            Request::get("/?name=xoyo&opt=put&data=a")
+                .header("x-httpmq-chaos", "off")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!((code, &body[..]), (StatusCode::OK, &b"HTTPMQ_PUT_OK"[..]));
+}
+
+#[tokio::test]
+async fn test_chaos_full_and_latency() {
+    let server = common::server_with(chaos_config(Config {
+        chaos_full: 1.0,
+        chaos_latency: 1.0,
+        chaos_latency_ms: 50,
+        ..Default::default()
+    }));
+    let start = Instant::now();
+    let (_, body) = server.get("/?name=xoyo&opt=put&data=a").await;
+    assert_eq!(body, "HTTPMQ_PUT_END");
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    // nothing was put
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+}
+
+#[tokio::test]
+async fn test_chaos_drop() {
+    let server = common::server_with(chaos_config(Config {
+        chaos_drop: 1.0,
+        ..Default::default()
+    }));
+    let res = server
+        .app
+        .clone()
+        .oneshot(
+            Request::get("/?name=xoyo&opt=get")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(res.headers().contains_key("x-request-id"));
+    assert!(hyper::body::to_bytes(res.into_body()).await.is_err());
+}
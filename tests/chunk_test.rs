@@ -0,0 +1,132 @@
+mod common;
+
+use axum::{
+    body::{Body, HttpBody},
+    http::{header, Request},
+};
+use httpmq_rs::{envelope, state::Config};
+use tower::ServiceExt;
+
+fn server() -> common::TestServer {
+    common::server_with(Config {
+        chunk_size: 4,
+        ..Default::default()
+    })
+}
+
+#[tokio::test]
+async fn test_chunked_roundtrip() {
+    let server = server();
+    let (_, body) = server.get("/?name=xoyo&opt=put&data=0123456789").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+    server.get("/?name=xoyo&opt=put&data=abcd").await;
+
+    let db = server.state.db.raw();
+    assert_eq!(db.get("xoyo:1#0").unwrap().unwrap(), b"0123");
+    assert_eq!(db.get("xoyo:1#2").unwrap().unwrap(), b"89");
+    assert_eq!(db.get("xoyo:1#3").unwrap(), None);
+    // at the threshold the message is stored whole
+    let (_, value) = envelope::open("xoyo:2", db.get("xoyo:2").unwrap().unwrap()).unwrap();
+    assert_eq!(value, b"abcd");
+
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "0123456789");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "abcd");
+}
+
+#[tokio::test]
+async fn test_message_looking_like_a_manifest() {
+    let server = common::server();
+    let (_, body) = server
+        .get("/?name=xoyo&opt=put&data=%00httpmq-chunked%00xyz")
+        .await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "\0httpmq-chunked\0xyz");
+}
+
+#[tokio::test]
+async fn test_overwrite_drops_old_chunks() {
+    let server = server();
+    server.get("/?name=xoyo&opt=maxqueue&num=2").await;
+    server.get("/?name=xoyo&opt=put&data=0123456789").await;
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    server.get("/?name=xoyo&opt=get").await;
+    server.get("/?name=xoyo&opt=get").await;
+
+    // second lap, slot 1 is reused for a small message
+    let (_, body) = server.get("/?name=xoyo&opt=put&data=b").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+    let db = server.state.db.raw();
+    let (_, value) = envelope::open("xoyo:1", db.get("xoyo:1").unwrap().unwrap()).unwrap();
+    assert_eq!(value, b"b");
+    for i in 0..3 {
+        assert_eq!(db.get(format!("xoyo:1#{}", i)).unwrap(), None);
+    }
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "b");
+}
+
+#[tokio::test]
+async fn test_remove_and_missing_chunks() {
+    let server = server();
+    server.get("/?name=xoyo&opt=put&data=0123456789").await;
+    server.get("/?name=xoyo&opt=put&data=abcdefgh").await;
+
+    // a lost chunk makes the message missing
+    let db = server.state.db.raw();
+    db.delete("xoyo:1#2").unwrap();
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_NONE");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "abcdefgh");
+
+    let (_, body) = server.get("/?name=xoyo&opt=remove&confirm=xoyo").await;
+    assert_eq!(body, "HTTPMQ_REMOVE_OK");
+    for key in ["xoyo:1#0", "xoyo:1#1", "xoyo:2#0", "xoyo:2#1", "xoyo:2"] {
+        assert_eq!(db.get(key).unwrap(), None, "{}", key);
+    }
+}
+
+#[tokio::test]
+async fn test_chunked_get_streams() {
+    let server = common::server_with(Config {
+        chunk_size: 64 << 10,
+        ..Default::default()
+    });
+    let message: Vec<u8> = (0..1 << 20).map(|i| (i % 251) as u8).collect();
+    let n = 8;
+    for _ in 0..n {
+        let req = Request::put("/?name=xoyo")
+            .body(Body::from(message.clone()))
+            .unwrap();
+        server.request(req).await;
+    }
+
+    let gets = (0..n).map(|_| {
+        let app = server.app.clone();
+        tokio::spawn(async move {
+            let req = Request::get("/?name=xoyo&opt=get")
+                .body(Body::empty())
+                .unwrap();
+            let mut res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.headers()[header::CONTENT_LENGTH], "1048576");
+            let mut frames = 0;
+            let mut body = Vec::new();
+            while let Some(frame) = res.body_mut().data().await {
+                // one chunk per frame, never the whole message at once
+                let frame = frame.unwrap();
+                assert!(frame.len() <= 64 << 10);
+                body.extend_from_slice(&frame);
+                frames += 1;
+            }
+            (frames, body)
+        })
+    });
+    for get in gets.collect::<Vec<_>>() {
+        let (frames, body) = get.await.unwrap();
+        assert_eq!(frames, 16);
+        assert!(body == message);
+    }
+}
@@ -0,0 +1,135 @@
+use httpmq_rs::{
+    cli::{self, Source},
+    state::Config,
+};
+
+fn parse(args: &[&str]) -> (Config, Vec<(String, Option<String>, Source)>) {
+    let app = cli::app();
+    let matches = app
+        .clone()
+        .try_get_matches_from(["httpmq-rs"].iter().chain(args))
+        .unwrap();
+    (
+        Config::from_matches(&matches).unwrap(),
+        cli::resolved(&app, &matches),
+    )
+}
+
+fn source_of(
+    resolved: &[(String, Option<String>, Source)],
+    name: &str,
+) -> (Option<String>, Source) {
+    let (_, value, source) = resolved.iter().find(|(n, _, _)| n == name).unwrap();
+    (value.clone(), *source)
+}
+
+// the environment is shared by the whole test binary, so everything that
+// sets variables lives in this one test
+#[test]
+fn test_env_precedence() {
+    let (config, resolved) = parse(&[]);
+    assert_eq!(config.maxqueue, 100000000);
+    assert_eq!(
+        source_of(&resolved, "maxqueue"),
+        (Some(String::from("100000000")), Source::Default)
+    );
+
+    std::env::set_var("HTTPMQ_MAXQUEUE", "500");
+    std::env::set_var("HTTPMQ_CHUNK_SIZE", "64");
+    std::env::set_var("HTTPMQ_TCP_NODELAY", "true");
+    std::env::set_var("HTTPMQ_FSCK", "false");
+    std::env::set_var("HTTPMQ_ADMIN_AUTH", "sesame");
+
+    let (config, resolved) = parse(&["--chunk-size", "128"]);
+    // env beats the default
+    assert_eq!(config.maxqueue, 500);
+    assert_eq!(
+        source_of(&resolved, "maxqueue"),
+        (Some(String::from("500")), Source::Environment)
+    );
+    // the command line beats env
+    assert_eq!(config.chunk_size, 128);
+    assert_eq!(
+        source_of(&resolved, "chunk-size"),
+        (Some(String::from("128")), Source::CommandLine)
+    );
+    // flags take true/false
+    assert!(config.tcp_nodelay);
+    assert_eq!(
+        source_of(&resolved, "fsck"),
+        (Some(String::from("false")), Source::Environment)
+    );
+    // secrets are used but never shown
+    assert_eq!(config.admin_auth.as_deref(), Some("sesame"));
+    assert_eq!(
+        source_of(&resolved, "admin-auth"),
+        (Some(String::from("****")), Source::Environment)
+    );
+
+    let (config, _) = parse(&["--maxqueue", "7"]);
+    assert_eq!(config.maxqueue, 7);
+
+    let (config, _) = parse(&[]);
+    assert_eq!(
+        (&config.dbpath[..], &config.host[..], config.port),
+        ("path", "127.0.0.1", 1218)
+    );
+    std::env::set_var("HTTPMQ_DBPATH", "/var/lib/httpmq");
+    std::env::set_var("HTTPMQ_PORT", "1219");
+    let (config, _) = parse(&["--host", "0.0.0.0"]);
+    assert_eq!(
+        (&config.dbpath[..], &config.host[..], config.port),
+        ("/var/lib/httpmq", "0.0.0.0", 1219)
+    );
+
+    for var in [
+        "HTTPMQ_MAXQUEUE",
+        "HTTPMQ_CHUNK_SIZE",
+        "HTTPMQ_TCP_NODELAY",
+        "HTTPMQ_FSCK",
+        "HTTPMQ_ADMIN_AUTH",
+        "HTTPMQ_DBPATH",
+        "HTTPMQ_PORT",
+    ] {
+        std::env::remove_var(var);
+    }
+}
+
+#[test]
+fn test_log_flags() {
+    let app = cli::app();
+    let matches = app.clone().try_get_matches_from(["httpmq-rs"]).unwrap();
+    assert_eq!(matches.value_of("log-format"), Some("text"));
+    assert_eq!(matches.value_of("log-level"), Some("info"));
+
+    let matches = app
+        .clone()
+        .try_get_matches_from(["httpmq-rs", "--log-format", "json"])
+        .unwrap();
+    assert_eq!(matches.value_of("log-format"), Some("json"));
+    assert!(app
+        .clone()
+        .try_get_matches_from(["httpmq-rs", "--log-format", "xml"])
+        .is_err());
+    assert!(app
+        .try_get_matches_from(["httpmq-rs", "--log-level", "loud"])
+        .is_err());
+}
+
+#[test]
+fn test_bad_numbers() {
+    let matches = cli::app()
+        .try_get_matches_from(["httpmq-rs", "--port", "abc"])
+        .unwrap();
+    let err = Config::from_matches(&matches).err().unwrap();
+    assert!(
+        err.starts_with("--port must be a number, not \"abc\""),
+        "{}",
+        err
+    );
+
+    let matches = cli::app()
+        .try_get_matches_from(["httpmq-rs", "--inherit-fd", "-"])
+        .unwrap();
+    assert!(Config::from_matches(&matches).is_err());
+}
@@ -0,0 +1,40 @@
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use httpmq_rs::clock::{Clock, MockClock};
+
+#[test]
+fn test_mock_clock_moves_when_told() {
+    let clock = MockClock::new();
+    let (now, instant) = (clock.now(), clock.instant());
+    assert_eq!(clock.now(), now);
+    clock.advance(Duration::from_secs(5));
+    assert_eq!(clock.now(), now + Duration::from_secs(5));
+    assert_eq!(clock.instant(), instant + Duration::from_secs(5));
+    assert_eq!(
+        clock.unix_secs(),
+        (now + Duration::from_secs(5))
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    );
+}
+
+#[tokio::test]
+async fn test_mock_clock_sleep_until() {
+    let clock = Arc::new(MockClock::new());
+    let deadline = clock.instant() + Duration::from_secs(60);
+    let sleeper = {
+        let clock = clock.clone();
+        tokio::spawn(async move { clock.sleep_until(deadline).await })
+    };
+
+    clock.advance(Duration::from_secs(30));
+    tokio::task::yield_now().await;
+    assert!(!sleeper.is_finished());
+    clock.advance(Duration::from_secs(30));
+    tokio::time::timeout(Duration::from_secs(1), sleeper)
+        .await
+        .unwrap()
+        .unwrap();
+}
@@ -0,0 +1,77 @@
+#![allow(dead_code)]
+
+use axum::{
+    body::Body,
+    http::{HeaderMap, Request, StatusCode},
+    Router,
+};
+use std::sync::Arc;
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+use httpmq_rs::{
+    app,
+    clock::{Clock, SystemClock},
+    state::{Config, SharedState, State},
+};
+
+pub struct TestServer {
+    pub state: SharedState,
+    pub app: Router,
+    // keep the database directory alive for the lifetime of the test
+    _dir: Option<TempDir>,
+}
+
+pub fn server() -> TestServer {
+    server_with(Config::default())
+}
+
+pub fn server_with(config: Config) -> TestServer {
+    server_with_clock(config, Arc::new(SystemClock))
+}
+
+pub fn server_with_clock(config: Config, clock: Arc<dyn Clock>) -> TestServer {
+    let dir = tempfile::tempdir().unwrap();
+    let state = Arc::new(State {
+        clock,
+        ..State::new(Config {
+            dbpath: dir.path().to_str().unwrap().to_string(),
+            ..config
+        })
+    });
+    TestServer {
+        app: app(state.clone()),
+        state,
+        _dir: Some(dir),
+    }
+}
+
+impl TestServer {
+    pub fn new(app: Router, state: SharedState) -> TestServer {
+        TestServer {
+            app,
+            state,
+            _dir: None,
+        }
+    }
+
+    pub async fn get(&self, uri: &str) -> (StatusCode, String) {
+        let (status, body) = self.get_bytes(uri).await;
+        (status, String::from_utf8(body).unwrap())
+    }
+
+    pub async fn get_bytes(&self, uri: &str) -> (StatusCode, Vec<u8>) {
+        let (status, _, body) = self
+            .request(Request::get(uri).body(Body::empty()).unwrap())
+            .await;
+        (status, body)
+    }
+
+    pub async fn request(&self, req: Request<Body>) -> (StatusCode, HeaderMap, Vec<u8>) {
+        let res = self.app.clone().oneshot(req).await.unwrap();
+        let status = res.status();
+        let headers = res.headers().clone();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, headers, body.to_vec())
+    }
+}
@@ -0,0 +1,201 @@
+mod common;
+
+use axum::{body::Body, http::Request, Router};
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+use tower::ServiceExt;
+
+const N: usize = 1000;
+
+async fn get(app: Router, uri: String) -> String {
+    let req = Request::get(uri).body(Body::empty()).unwrap();
+    let res = app.oneshot(req).await.unwrap();
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn test_parallel_puts_and_gets() {
+    let server = common::server();
+
+    let puts: Vec<_> = (0..N)
+        .map(|i| {
+            let uri = format!("/?name=xoyo&opt=put&data=m{}", i);
+            tokio::spawn(get(server.app.clone(), uri))
+        })
+        .collect();
+    for put in puts {
+        assert_eq!(put.await.unwrap(), "HTTPMQ_PUT_OK");
+    }
+
+    // every put got a slot of its own
+    let (_, body) = server.get("/?name=xoyo&opt=status").await;
+    assert!(
+        body.contains(&format!("Put position of queue (1st lap): {}", N)),
+        "{}",
+        body
+    );
+    let mut seen = HashSet::new();
+    for pos in 1..=N {
+        let (_, data) = server
+            .get(&format!("/?name=xoyo&opt=view&pos={}", pos))
+            .await;
+        assert!(data.starts_with('m'), "slot {}: {}", pos, data);
+        assert!(seen.insert(data), "slot {} written twice", pos);
+    }
+
+    // and every message is taken exactly once
+    let gets: Vec<_> = (0..N)
+        .map(|_| {
+            let uri = String::from("/?name=xoyo&opt=get");
+            tokio::spawn(get(server.app.clone(), uri))
+        })
+        .collect();
+    let mut taken = HashSet::new();
+    for get in gets {
+        let data = get.await.unwrap();
+        assert!(seen.contains(&data), "{}", data);
+        assert!(taken.insert(data.clone()), "{} taken twice", data);
+    }
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_queues_lock_separately() {
+    let server = common::server();
+    // a put waiting on one queue doesn't hold up another
+    let locked = server.state.queue_locks.lock("xoyo").await;
+    let (_, body) = server.get("/?name=other&opt=put&data=a").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+    drop(locked);
+    let (_, body) = server.get("/?name=xoyo&opt=put&data=a").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+}
+
+const QUEUES: usize = 16;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_queue_locks_scale() {
+    let server = common::server();
+    // each holds its queue's lock as long as a slow write would; serialized
+    // they would take QUEUES times as long
+    let hold = Duration::from_millis(100);
+    let start = Instant::now();
+    let tasks: Vec<_> = (0..QUEUES)
+        .map(|i| {
+            let state = server.state.clone();
+            tokio::spawn(async move {
+                let _locked = state.queue_locks.lock(&format!("q{}", i)).await;
+                tokio::time::sleep(hold).await;
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+    let elapsed = start.elapsed();
+    assert!(elapsed < hold * 4, "{:?}", elapsed);
+
+    // while the same queue's requests still take turns
+    let start = Instant::now();
+    let tasks: Vec<_> = (0..4)
+        .map(|_| {
+            let state = server.state.clone();
+            tokio::spawn(async move {
+                let _locked = state.queue_locks.lock("q0").await;
+                tokio::time::sleep(hold).await;
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+    assert!(start.elapsed() >= hold * 4, "{:?}", start.elapsed());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn test_parallel_queues_keep_order() {
+    let server = common::server();
+    let producers: Vec<_> = (0..QUEUES)
+        .map(|q| {
+            let app = server.app.clone();
+            tokio::spawn(async move {
+                for i in 0..50 {
+                    let uri = format!("/?name=q{}&opt=put&data=m{}", q, i);
+                    assert_eq!(get(app.clone(), uri).await, "HTTPMQ_PUT_OK");
+                }
+            })
+        })
+        .collect();
+    for producer in producers {
+        producer.await.unwrap();
+    }
+    for q in 0..QUEUES {
+        for i in 0..50 {
+            let (_, data) = server.get(&format!("/?name=q{}&opt=get", q)).await;
+            assert_eq!(data, format!("m{}", i), "queue q{}", q);
+        }
+    }
+}
+
+// a remove waits for the request holding the queue, and nothing it left
+// behind survives
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_remove_takes_the_queue_lock() {
+    let server = common::server_with(httpmq_rs::state::Config {
+        allow_unprotected_reset: true,
+        ..Default::default()
+    });
+    get(
+        server.app.clone(),
+        String::from("/?name=xoyo&opt=put&data=a"),
+    )
+    .await;
+
+    let held = server.state.queue_locks.lock("xoyo").await;
+    let remove = tokio::spawn(get(
+        server.app.clone(),
+        String::from("/?name=xoyo&opt=remove"),
+    ));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!remove.is_finished());
+    // what a put in flight writes before the remove goes ahead
+    server.state.db.put(b"xoyo.putpos", b"1").unwrap();
+    drop(held);
+    assert_eq!(remove.await.unwrap(), "HTTPMQ_REMOVE_OK");
+    assert_eq!(server.state.db.get(b"xoyo.putpos").unwrap(), None);
+
+    // puts and gets go on with a lock of their own
+    let (_, body) = server.get("/?name=xoyo&opt=put&data=b").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "b");
+}
+
+// an opt=readonly that gets the lock first is seen by the waiting remove
+#[tokio::test]
+async fn test_remove_sees_readonly_set_while_waiting() {
+    let server = common::server_with(httpmq_rs::state::Config {
+        allow_unprotected_reset: true,
+        ..Default::default()
+    });
+    get(
+        server.app.clone(),
+        String::from("/?name=xoyo&opt=put&data=a"),
+    )
+    .await;
+
+    let held = server.state.queue_locks.lock("xoyo").await;
+    let remove = tokio::spawn(get(
+        server.app.clone(),
+        String::from("/?name=xoyo&opt=remove"),
+    ));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    server.state.db.put(b"xoyo.readonly", b"1").unwrap();
+    drop(held);
+    assert_eq!(remove.await.unwrap(), "HTTPMQ_QUEUE_READONLY");
+    assert!(server.state.db.get(b"xoyo:1").unwrap().is_some());
+}
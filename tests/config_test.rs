@@ -0,0 +1,143 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use httpmq_rs::state::Config;
+
+#[tokio::test]
+async fn test_config_set_and_list() {
+    let server = common::server();
+    let (_, body) = server.get("/?name=xoyo&opt=config").await;
+    assert_eq!(
+        body,
+        r#"{"description":null,"paused":false,"delivery":"at-most-once","max_message_size":null,"max_put_rate":null,"ttl":null,"max_deliveries":null,"dead_letter":null}"#
+    );
+
+    let (_, body) = server
+        .get("/?name=xoyo&opt=config&data=%7B%22description%22%3A%22billing%22%7D")
+        .await;
+    assert_eq!(body, "HTTPMQ_CONFIG_OK");
+
+    let req = Request::put("/?name=xoyo&opt=config")
+        .body(Body::from(r#"{"paused":true}"#))
+        .unwrap();
+    let (_, _, body) = server.request(req).await;
+    assert_eq!(body, b"HTTPMQ_CONFIG_OK");
+
+    // fields not given keep their value
+    let (_, body) = server.get("/?name=xoyo&opt=config").await;
+    assert_eq!(
+        body,
+        r#"{"description":"billing","paused":true,"delivery":"at-most-once","max_message_size":null,"max_put_rate":null,"ttl":null,"max_deliveries":null,"dead_letter":null}"#
+    );
+    assert!(server
+        .state
+        .db
+        .raw()
+        .get("xoyo.settings")
+        .unwrap()
+        .is_some());
+}
+
+#[tokio::test]
+async fn test_config_invalid() {
+    let server = common::server();
+    for data in [
+        "nope",
+        "%7B%22colour%22%3A1%7D",
+        "%7B%22paused%22%3A%22yes%22%7D",
+    ] {
+        let uri = format!("/?name=xoyo&opt=config&data={}", data);
+        let (_, body) = server.get(&uri).await;
+        assert!(body.starts_with("HTTPMQ_CONFIG_INVALID\n"), "{}", body);
+    }
+
+    let long = "x".repeat(300);
+    let uri = format!(
+        "/?name=xoyo&opt=config&data=%7B%22description%22%3A%22{}%22%7D",
+        long
+    );
+    let (_, body) = server.get(&uri).await;
+    assert_eq!(
+        body,
+        "HTTPMQ_CONFIG_INVALID\ndescription: longer than 256 bytes\n"
+    );
+    let (_, body) = server.get("/?name=xoyo&opt=config").await;
+    assert_eq!(
+        body,
+        r#"{"description":null,"paused":false,"delivery":"at-most-once","max_message_size":null,"max_put_rate":null,"ttl":null,"max_deliveries":null,"dead_letter":null}"#
+    );
+}
+
+#[tokio::test]
+async fn test_config_paused_get() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    server
+        .get("/?name=xoyo&opt=config&data=%7B%22paused%22%3Atrue%7D")
+        .await;
+
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_PAUSED");
+    let (_, body) = server.get("/?name=xoyo&opt=put&data=b").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+
+    // back to the defaults, the record goes away
+    server
+        .get("/?name=xoyo&opt=config&data=%7B%22paused%22%3Afalse%7D")
+        .await;
+    assert!(server
+        .state
+        .db
+        .raw()
+        .get("xoyo.settings")
+        .unwrap()
+        .is_none());
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "a");
+}
+
+#[tokio::test]
+async fn test_config_set_requires_admin_auth() {
+    let server = common::server_with(Config {
+        admin_auth: Some(String::from("secret")),
+        ..Default::default()
+    });
+
+    let (code, _) = server.get("/?name=xoyo&opt=config").await;
+    assert_eq!(code, StatusCode::OK);
+    let (code, _) = server
+        .get("/?name=xoyo&opt=config&data=%7B%22paused%22%3Atrue%7D")
+        .await;
+    assert_eq!(code, StatusCode::UNAUTHORIZED);
+    let (_, body) = server
+        .get("/?name=xoyo&opt=config&data=%7B%22paused%22%3Atrue%7D&auth=secret")
+        .await;
+    assert_eq!(body, "HTTPMQ_CONFIG_OK");
+}
+
+#[tokio::test]
+async fn test_remove_drops_config() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    server
+        .get("/?name=xoyo&opt=config&data=%7B%22paused%22%3Atrue%7D")
+        .await;
+    let (_, body) = server.get("/?name=xoyo&opt=remove&confirm=xoyo").await;
+    assert_eq!(body, "HTTPMQ_REMOVE_OK");
+
+    assert!(server
+        .state
+        .db
+        .raw()
+        .get("xoyo.settings")
+        .unwrap()
+        .is_none());
+    let (_, body) = server.get("/?name=xoyo&opt=config").await;
+    assert_eq!(
+        body,
+        r#"{"description":null,"paused":false,"delivery":"at-most-once","max_message_size":null,"max_put_rate":null,"ttl":null,"max_deliveries":null,"dead_letter":null}"#
+    );
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use axum::{body::Body, http::Request};
+use httpmq_rs::state::Config;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct Count {
+    count: u64,
+}
+
+#[tokio::test]
+async fn test_count() {
+    let server = common::server_with(Config {
+        maxqueue: 3,
+        ..Default::default()
+    });
+    let (_, body) = server.get("/?name=nobody&opt=count").await;
+    assert_eq!(body, "0");
+
+    for data in ["a", "b", "c"] {
+        server
+            .get(&format!("/?name=xoyo&opt=put&data={}", data))
+            .await;
+    }
+    server.get("/?name=xoyo&opt=get").await;
+    server.get("/?name=xoyo&opt=get").await;
+    // putpos wraps around behind getpos
+    server.get("/?name=xoyo&opt=put&data=d").await;
+    let (_, body) = server.get("/?name=xoyo&opt=count").await;
+    assert_eq!(body, "2");
+    let (_, status) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(status.contains(r#""unread":2"#), "{}", status);
+
+    let req = Request::get("/?name=xoyo&opt=count&format=msgpack")
+        .body(Body::empty())
+        .unwrap();
+    let (_, _, body) = server.request(req).await;
+    let count: Count = rmp_serde::from_slice(&body).unwrap();
+    assert_eq!(count.count, 2);
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use rocksdb::{Direction, IteratorMode};
+
+use httpmq_rs::state::Config;
+
+// keys of queue name's messages and chunks still stored
+fn stored_keys(server: &common::TestServer, name: &str) -> Vec<String> {
+    let prefix = format!("{}:", name);
+    let mode = IteratorMode::From(prefix.as_bytes(), Direction::Forward);
+    server
+        .state
+        .db
+        .raw()
+        .iterator(mode)
+        .map(|(key, _)| String::from_utf8_lossy(&key).to_string())
+        .take_while(|key| key.starts_with(&prefix))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_delete_on_get() {
+    let server = common::server_with(Config {
+        delete_on_get: true,
+        chunk_size: 4,
+        ..Default::default()
+    });
+    for data in ["a", "b", "chunked-message"] {
+        server
+            .get(&format!("/?name=xoyo&opt=put&data={}", data))
+            .await;
+    }
+
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "a");
+    let (_, body) = server.get("/?name=xoyo&opt=view&pos=1").await;
+    assert_eq!(body, "HTTPMQ_GET_NONE");
+    // unconsumed messages are left alone
+    let (_, body) = server.get("/?name=xoyo&opt=view&pos=2").await;
+    assert_eq!(body, "b");
+
+    for data in ["b", "chunked-message"] {
+        let (_, body) = server.get("/?name=xoyo&opt=get").await;
+        assert_eq!(body, data);
+    }
+    assert_eq!(stored_keys(&server, "xoyo"), Vec::<String>::new());
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+}
+
+#[tokio::test]
+async fn test_delete_on_commit() {
+    let server = common::server_with(Config {
+        delete_on_get: true,
+        ..Default::default()
+    });
+    server
+        .get("/?name=xoyo&opt=config&data=%7B%22delivery%22%3A%22at-least-once%22%7D")
+        .await;
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    let (_, body) = server.get("/?name=xoyo&opt=get&mode=reserve").await;
+    assert_eq!(body, "a");
+    // reserved, not consumed yet
+    assert_eq!(stored_keys(&server, "xoyo"), ["xoyo:1"]);
+    let (_, body) = server.get("/?name=xoyo&opt=commit&pos=1").await;
+    assert_eq!(body, "HTTPMQ_COMMIT_OK");
+    assert_eq!(stored_keys(&server, "xoyo"), Vec::<String>::new());
+}
+
+#[tokio::test]
+async fn test_messages_kept_by_default() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(stored_keys(&server, "xoyo"), ["xoyo:1"]);
+}
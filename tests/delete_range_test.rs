@@ -0,0 +1,121 @@
+mod common;
+
+use axum::http::StatusCode;
+use httpmq_rs::{service::message_key, state::Config};
+use rocksdb::WriteBatch;
+use std::time::Instant;
+
+const MESSAGES: u64 = 1_000_000;
+
+fn fill(server: &common::TestServer, name: &str, n: u64) {
+    for start in (1..=n).step_by(10_000) {
+        let mut batch = WriteBatch::default();
+        for pos in start..(start + 10_000).min(n + 1) {
+            batch.put(message_key(name, pos), b"m");
+        }
+        server.state.db.write(batch).unwrap();
+    }
+    server
+        .state
+        .db
+        .put(
+            format!("{}.putpos", name).as_bytes(),
+            n.to_string().as_bytes(),
+        )
+        .unwrap();
+    server
+        .state
+        .db
+        .put(format!("{}.getpos", name).as_bytes(), b"0")
+        .unwrap();
+}
+
+fn stored(server: &common::TestServer, name: &str) -> usize {
+    let prefix = format!("{}:", name);
+    server
+        .state
+        .db
+        .raw()
+        .prefix_iterator(prefix.as_bytes())
+        .take_while(|(key, _)| key.starts_with(prefix.as_bytes()))
+        .count()
+}
+
+// what remove used to do: look up every position and delete it on its own
+fn delete_point_by_point(server: &common::TestServer, name: &str, n: u64) {
+    for start in (1..=n).step_by(1000) {
+        let positions: Vec<u64> = (start..(start + 1000).min(n + 1)).collect();
+        let keys = positions
+            .iter()
+            .map(|&pos| message_key(name, pos).into_bytes())
+            .collect();
+        let mut batch = WriteBatch::default();
+        for (&pos, x) in positions.iter().zip(server.state.db.multi_get(keys)) {
+            if x.unwrap().is_some() {
+                batch.delete(message_key(name, pos));
+            }
+        }
+        server.state.db.write(batch).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_remove_large_queue() {
+    let server = common::server();
+    fill(&server, "xoyo", MESSAGES);
+    server.get("/?name=xoyo1&opt=put&data=other").await;
+
+    let start = Instant::now();
+    let (_, body) = server.get("/?name=xoyo&opt=remove&confirm=xoyo").await;
+    assert_eq!(body, "HTTPMQ_REMOVE_OK");
+    let ranged = start.elapsed();
+    assert_eq!(stored(&server, "xoyo"), 0);
+
+    fill(&server, "pointwise", MESSAGES);
+    let start = Instant::now();
+    delete_point_by_point(&server, "pointwise", MESSAGES);
+    let pointwise = start.elapsed();
+    assert_eq!(stored(&server, "pointwise"), 0);
+    // a single range delete, not a million lookups and point deletes
+    assert!(ranged * 4 < pointwise, "{:?} vs {:?}", ranged, pointwise);
+
+    // a new queue of the same name starts clean
+    server.get("/?name=xoyo&opt=put&data=fresh").await;
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "fresh");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+    let (_, body) = server.get("/?name=xoyo1&opt=get").await;
+    assert_eq!(body, "other");
+}
+
+#[tokio::test]
+async fn test_reset_deletes_messages() {
+    let server = common::server();
+    fill(&server, "xoyo", 1000);
+    let (_, body) = server.get("/?name=xoyo&opt=reset&confirm=xoyo").await;
+    assert_eq!(body, "HTTPMQ_RESET_OK");
+    assert_eq!(stored(&server, "xoyo"), 0);
+    let (_, body) = server.get("/?name=xoyo&opt=replayall").await;
+    assert!(body.starts_with("HTTPMQ_REPLAYALL_OK"), "{}", body);
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+}
+
+#[tokio::test]
+async fn test_permissive_names_refuse_colon() {
+    let server = common::server_with(Config {
+        permissive_names: true,
+        ..Default::default()
+    });
+    server.get("/?name=a&opt=put&data=1").await;
+
+    // "a:b" would sit inside a's message span
+    let (code, body) = server.get("/?name=a:b&opt=put&data=2").await;
+    assert_eq!(code, StatusCode::BAD_REQUEST);
+    assert_eq!(body, "HTTPMQ_NAME_INVALID");
+
+    let (_, body) = server.get("/?name=a&opt=remove&confirm=a").await;
+    assert_eq!(body, "HTTPMQ_REMOVE_OK");
+    assert!(server.state.db.get(b"a:1").unwrap().is_none());
+}
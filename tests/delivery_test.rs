@@ -0,0 +1,54 @@
+mod common;
+
+#[tokio::test]
+async fn test_at_most_once_by_default() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    server.get("/?name=xoyo&opt=put&data=b").await;
+
+    let (_, body) = server.get("/?name=xoyo&opt=status").await;
+    assert!(body.contains("Delivery: at-most-once\n"), "{}", body);
+    // the cursor moved before the message came back, nothing to commit
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "a");
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""getpos":1"#), "{}", body);
+    assert!(body.contains(r#""delivery":"at-most-once""#), "{}", body);
+    let (_, body) = server.get("/?name=xoyo&opt=get&mode=reserve").await;
+    assert_eq!(body, "HTTPMQ_GET_MODE_INVALID");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "b");
+}
+
+#[tokio::test]
+async fn test_at_least_once() {
+    let server = common::server();
+    let (_, body) = server
+        .get("/?name=xoyo&opt=config&data=%7B%22delivery%22%3A%22at-least-once%22%7D")
+        .await;
+    assert_eq!(body, "HTTPMQ_CONFIG_OK");
+    server.get("/?name=xoyo&opt=put&data=a").await;
+
+    let (_, body) = server.get("/?name=xoyo&opt=status").await;
+    assert!(body.contains("Delivery: at-least-once\n"), "{}", body);
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "a");
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""getpos":0"#), "{}", body);
+    // filtered gets skip the cursor, they can't wait for a commit
+    let (_, body) = server.get("/?name=xoyo&opt=get&filter=type%3Dorder").await;
+    assert_eq!(body, "HTTPMQ_GET_FILTER_INVALID");
+    let (_, body) = server.get("/?name=xoyo&opt=commit&pos=1").await;
+    assert_eq!(body, "HTTPMQ_COMMIT_OK");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+}
+
+#[tokio::test]
+async fn test_unknown_delivery_mode() {
+    let server = common::server();
+    let (_, body) = server
+        .get("/?name=xoyo&opt=config&data=%7B%22delivery%22%3A%22exactly-once%22%7D")
+        .await;
+    assert!(body.starts_with("HTTPMQ_CONFIG_INVALID\n"), "{}", body);
+}
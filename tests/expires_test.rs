@@ -0,0 +1,180 @@
+mod common;
+
+use httpmq_rs::{
+    chunk,
+    clock::MockClock,
+    envelope::{self, Header},
+    state::Config,
+};
+use rocksdb::WriteBatch;
+use std::sync::Arc;
+use std::time::Duration;
+
+// overwrite a message with one that expired long ago
+fn expire(server: &common::TestServer, key: &str, data: &[u8], chunk_size: usize) {
+    let mut batch = WriteBatch::default();
+    let header = Header {
+        expires: Some(1),
+        ..Default::default()
+    };
+    chunk::put(&mut batch, key, data, chunk_size, &header);
+    server.state.db.raw().write(batch).unwrap();
+}
+
+#[tokio::test]
+async fn test_expired_messages_skipped() {
+    let server = common::server();
+    for data in ["a", "b", "c", "d"] {
+        let uri = format!("/?name=xoyo&opt=put&data={}&expires=3600", data);
+        let (_, body) = server.get(&uri).await;
+        assert_eq!(body, "HTTPMQ_PUT_OK");
+    }
+    expire(&server, "xoyo:1", b"a", 0);
+    expire(&server, "xoyo:2", b"0123456789", 4);
+
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "c");
+    let db = server.state.db.raw();
+    for key in ["xoyo:1", "xoyo:2", "xoyo:2#0", "xoyo:2#2"] {
+        assert_eq!(db.get(key).unwrap(), None, "{}", key);
+    }
+
+    let (_, body) = server.get("/?name=xoyo&opt=status").await;
+    assert!(body.contains("Expired unread: 2\n"), "{}", body);
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""expired":2"#), "{}", body);
+
+    expire(&server, "xoyo:4", b"d", 0);
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""expired":3"#), "{}", body);
+
+    server.get("/?name=xoyo&opt=remove&confirm=xoyo").await;
+    assert_eq!(db.get("xoyo.expired").unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_expired_head_of_peek_queue() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    expire(&server, "xoyo:1", b"a", 0);
+    server.get("/?name=xoyo&opt=readonly").await;
+
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_NONE");
+    assert!(server.state.db.raw().get("xoyo:1").unwrap().is_some());
+}
+
+#[tokio::test]
+async fn test_plain_messages_only_carry_seq_and_time() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    server.get("/?name=xoyo&opt=put&data=b&expires=0").await;
+    let db = server.state.db.raw();
+    for (pos, data) in [(1, b"a"), (2, b"b")] {
+        let key = format!("xoyo:{}", pos);
+        let (header, value) = envelope::open(&key, db.get(&key).unwrap().unwrap()).unwrap();
+        assert!(header.put_at.is_some());
+        let seq = Header {
+            seq: Some(pos),
+            put_at: header.put_at,
+            ..Header::default()
+        };
+        assert_eq!((header, &value[..]), (seq, &data[..]));
+    }
+
+    // a message that looks like an envelope still comes back as sent
+    let (_, body) = server
+        .get("/?name=xoyo&opt=put&data=%00httpmq-envelope%00xyz")
+        .await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+    server.get("/?name=xoyo&opt=get").await;
+    server.get("/?name=xoyo&opt=get").await;
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "\0httpmq-envelope\0xyz");
+}
+
+#[tokio::test]
+async fn test_messages_expire_on_clock() {
+    let clock = Arc::new(MockClock::new());
+    let server = common::server_with_clock(Config::default(), clock.clone());
+    server.get("/?name=xoyo&opt=put&data=a&expires=60").await;
+    server.get("/?name=xoyo&opt=put&data=b&expires=120").await;
+
+    clock.advance(Duration::from_secs(90));
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "b");
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""expired":1"#), "{}", body);
+}
+
+#[tokio::test]
+async fn test_queue_ttl() {
+    let clock = Arc::new(MockClock::new());
+    let server = common::server_with_clock(Config::default(), clock.clone());
+    let (_, body) = server.get("/?name=xoyo&opt=ttl").await;
+    assert_eq!(body, "0");
+    let (_, body) = server.get("/?name=xoyo&opt=ttl&num=60").await;
+    assert_eq!(body, "HTTPMQ_TTL_OK");
+    let (_, body) = server.get("/?name=xoyo&opt=ttl").await;
+    assert_eq!(body, "60");
+
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    clock.advance(Duration::from_secs(30));
+    server.get("/?name=xoyo&opt=put&data=b").await;
+    server.get("/?name=xoyo&opt=put&data=c").await;
+    clock.advance(Duration::from_secs(40));
+
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "b");
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""ttl":60"#), "{}", body);
+    assert!(body.contains(r#""expired":1"#), "{}", body);
+
+    // dropping the ttl keeps what is left
+    server.get("/?name=xoyo&opt=ttl&num=0").await;
+    clock.advance(Duration::from_secs(3600));
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "c");
+    let (_, body) = server.get("/?name=xoyo&opt=config").await;
+    assert!(body.contains(r#""ttl":null"#), "{}", body);
+}
+
+#[tokio::test]
+async fn test_queue_ttl_requires_admin_auth() {
+    let server = common::server_with(Config {
+        admin_auth: Some(String::from("secret")),
+        ..Config::default()
+    });
+    let (code, _) = server.get("/?name=xoyo&opt=ttl&num=60").await;
+    assert_eq!(code, axum::http::StatusCode::UNAUTHORIZED);
+    let (_, body) = server.get("/?name=xoyo&opt=ttl&num=60&auth=secret").await;
+    assert_eq!(body, "HTTPMQ_TTL_OK");
+    let (_, body) = server
+        .get("/?name=xoyo&opt=config&data=%7B%22ttl%22%3A0%7D&auth=secret")
+        .await;
+    assert!(body.starts_with("HTTPMQ_CONFIG_INVALID"), "{}", body);
+}
+
+// one get gives up after EXPIRED_SKIP_MAX expired messages, the next goes on
+#[tokio::test]
+async fn test_expired_skip_is_bounded() {
+    let clock = Arc::new(MockClock::new());
+    let server = common::server_with_clock(Config::default(), clock.clone());
+    server.get("/?name=xoyo&opt=ttl&num=1").await;
+    let body = "x\n".repeat(1001);
+    let req = axum::http::Request::post("/?name=xoyo&batch=lines")
+        .body(axum::body::Body::from(body))
+        .unwrap();
+    server.request(req).await;
+    clock.advance(Duration::from_secs(2));
+    server.get("/?name=xoyo&opt=put&data=fresh").await;
+
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "fresh");
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""expired":1001"#), "{}", body);
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use axum::{body::Body, http::Request};
+use httpmq_rs::state::Config;
+
+async fn put(server: &common::TestServer, kind: &str, body: &'static str) {
+    let req = Request::put("/?name=xoyo")
+        .header("X-Httpmq-Attr-Type", kind)
+        .body(Body::from(body))
+        .unwrap();
+    let (_, _, body) = server.request(req).await;
+    assert_eq!(body, b"HTTPMQ_PUT_OK");
+}
+
+#[tokio::test]
+async fn test_filtered_get() {
+    let server = common::server_with(Config {
+        chunk_size: 4,
+        ..Default::default()
+    });
+    put(&server, "click", "a").await;
+    put(&server, "order", "b").await;
+    put(&server, "click", "c").await;
+    put(&server, "order", "0123456789").await;
+
+    let (_, body) = server.get("/?name=xoyo&opt=get&filter=type%3Dorder").await;
+    assert_eq!(body, "b");
+    let (_, body) = server.get("/?name=xoyo&opt=get&filter=Type%3Dorder").await;
+    assert_eq!(body, "0123456789");
+    assert_eq!(server.state.db.raw().get("xoyo:4#0").unwrap(), None);
+    let (_, body) = server.get("/?name=xoyo&opt=get&filter=type%3Dorder").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+
+    // the rest is still there in order, the taken ones are passed over
+    for want in ["a", "c", "HTTPMQ_GET_END"] {
+        let (_, body) = server.get("/?name=xoyo&opt=get").await;
+        assert_eq!(body, want);
+    }
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""expired":0"#), "{}", body);
+    assert!(body.contains(r#""getpos":4"#), "{}", body);
+}
+
+#[tokio::test]
+async fn test_filtered_get_at_cursor() {
+    let server = common::server();
+    put(&server, "order", "a").await;
+    put(&server, "click", "b").await;
+    let (_, body) = server.get("/?name=xoyo&opt=get&filter=type%3Dorder").await;
+    assert_eq!(body, "a");
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""getpos":1"#), "{}", body);
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "b");
+}
+
+#[tokio::test]
+async fn test_filtered_get_scan_limit() {
+    let server = common::server();
+    for _ in 0..1001 {
+        server.get("/?name=xoyo&opt=put&data=x").await;
+    }
+    put(&server, "order", "b").await;
+    let (_, body) = server.get("/?name=xoyo&opt=get&filter=type%3Dorder").await;
+    assert_eq!(body, "HTTPMQ_GET_SCAN_LIMIT");
+    let (_, body) = server.get("/?name=xoyo&opt=get&filter=nope").await;
+    assert_eq!(body, "HTTPMQ_GET_FILTER_INVALID");
+}
@@ -0,0 +1,28 @@
+mod common;
+
+use httpmq_rs::state::Config;
+
+#[tokio::test]
+async fn test_flush() {
+    let server = common::server_with(Config {
+        admin_auth: Some(String::from("sesame")),
+        ..Default::default()
+    });
+    server.get("/?name=xoyo&opt=put&data=a").await;
+
+    let (_, body) = server.get("/?opt=flush").await;
+    assert_eq!(body, "HTTPMQ_AUTH_FAILED");
+    let (_, body) = server.get("/?opt=flush&auth=sesame").await;
+    assert!(
+        body.starts_with("HTTPMQ_FLUSH_OK\nelapsed_ms: "),
+        "{}",
+        body
+    );
+    assert!(body.contains("\nbytes: "), "{}", body);
+
+    // and again once it is done
+    let (_, body) = server.get("/?opt=flush&auth=sesame").await;
+    assert!(body.starts_with("HTTPMQ_FLUSH_OK\n"), "{}", body);
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "a");
+}
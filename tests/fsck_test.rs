@@ -0,0 +1,76 @@
+mod common;
+
+use axum::http::StatusCode;
+use httpmq_rs::{service::fsck_all, state::Config};
+
+#[tokio::test]
+async fn test_corrupt_metadata_quarantines_queue() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    server.state.db.raw().put("xoyo.getpos", "oops").unwrap();
+
+    let (code, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(code, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body, "HTTPMQ_QUEUE_CORRUPT");
+    let (_, body) = server.get("/stats").await;
+    assert!(body.contains("Queues needing fsck: xoyo\n"), "{}", body);
+
+    let (_, body) = server.get("/?name=xoyo&opt=fsck").await;
+    assert_eq!(body, "HTTPMQ_FSCK_CORRUPT\ngetpos: \"oops\"\n");
+
+    let (_, body) = server.get("/?name=xoyo&opt=fsck&field=getpos").await;
+    assert_eq!(body, "HTTPMQ_FSCK_OK");
+    let (_, body) = server.get("/stats").await;
+    assert!(body.contains("Queues needing fsck: \n"), "{}", body);
+
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "a");
+}
+
+#[tokio::test]
+async fn test_fsck_sets_explicit_value() {
+    let server = common::server();
+    for data in ["a", "b"] {
+        server
+            .get(&format!("/?name=xoyo&opt=put&data={}", data))
+            .await;
+    }
+    server.state.db.raw().put("xoyo.putpos", "2x").unwrap();
+
+    let (_, body) = server.get("/?name=xoyo&opt=fsck&field=putpos&num=2").await;
+    assert_eq!(body, "HTTPMQ_FSCK_OK");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "a");
+
+    let (_, body) = server.get("/?name=xoyo&opt=fsck&field=bogus").await;
+    assert_eq!(body, "HTTPMQ_FSCK_INVALID_FIELD");
+    let (_, body) = server
+        .get("/?name=xoyo&opt=fsck&field=maxqueue&num=0")
+        .await;
+    assert_eq!(body, "HTTPMQ_FSCK_INVALID_FIELD");
+}
+
+#[tokio::test]
+async fn test_fsck_requires_admin_auth() {
+    let server = common::server_with(Config {
+        admin_auth: Some(String::from("secret")),
+        ..Default::default()
+    });
+
+    let (code, _) = server.get("/?name=xoyo&opt=fsck").await;
+    assert_eq!(code, StatusCode::UNAUTHORIZED);
+    let (_, body) = server.get("/?name=xoyo&opt=fsck&auth=secret").await;
+    assert_eq!(body, "HTTPMQ_FSCK_OK");
+}
+
+#[tokio::test]
+async fn test_fsck_all() {
+    let server = common::server();
+    server.get("/?name=good&opt=put&data=a").await;
+    server.state.db.raw().put("bad.maxqueue", "-").unwrap();
+    server.state.db.raw().put("bad.putpos", [0xff]).unwrap();
+
+    assert_eq!(fsck_all(&server.state), 2);
+    let (_, body) = server.get("/stats").await;
+    assert!(body.contains("Queues needing fsck: bad\n"), "{}", body);
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use httpmq_rs::state::Config;
+use std::sync::atomic::Ordering;
+
+#[tokio::test]
+async fn test_get_skips_missing_message() {
+    let server = common::server();
+    for data in ["a", "b", "c"] {
+        server
+            .get(&format!("/?name=xoyo&opt=put&data={}", data))
+            .await;
+    }
+    server.state.db.raw().delete("xoyo:2").unwrap();
+
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "a");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_NONE");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "c");
+    assert_eq!(server.state.missing_skipped.load(Ordering::Relaxed), 1);
+
+    let (_, body) = server.get("/stats").await;
+    assert!(body.contains("Missing messages skipped: 1\n"), "{}", body);
+}
+
+#[tokio::test]
+async fn test_get_stalls_on_missing_message() {
+    let server = common::server_with(Config {
+        skip_missing: false,
+        ..Default::default()
+    });
+    for data in ["a", "b", "c"] {
+        server
+            .get(&format!("/?name=xoyo&opt=put&data={}", data))
+            .await;
+    }
+    server.state.db.raw().delete("xoyo:2").unwrap();
+
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "a");
+    for _ in 0..2 {
+        let (_, body) = server.get("/?name=xoyo&opt=get").await;
+        assert_eq!(body, "HTTPMQ_GET_NONE");
+    }
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""getpos":1"#), "{}", body);
+
+    // once the message is recovered it is delivered in order
+    server.state.db.raw().put("xoyo:2", "b").unwrap();
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "b");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "c");
+    assert_eq!(server.state.missing_skipped.load(Ordering::Relaxed), 0);
+}
+
+#[tokio::test]
+async fn test_get_missing_message_after_wrap() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=maxqueue&num=3").await;
+    for data in ["a", "b", "c"] {
+        server
+            .get(&format!("/?name=xoyo&opt=put&data={}", data))
+            .await;
+    }
+    for _ in 0..2 {
+        server.get("/?name=xoyo&opt=get").await;
+    }
+    // wrap the producer around to slot 1
+    server.get("/?name=xoyo&opt=put&data=d").await;
+    server.state.db.raw().delete("xoyo:3").unwrap();
+
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_NONE");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "d");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+}
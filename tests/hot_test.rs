@@ -0,0 +1,89 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use httpmq_rs::hot::HotQueues;
+
+#[test]
+fn test_hot_by_rate() {
+    let hot = HotQueues::new(10, 0);
+    let now = Instant::now();
+    for _ in 0..99 {
+        hot.record_at("busy", now);
+    }
+    assert!(!hot.is_hot_at("busy", now));
+    hot.record_at("busy", now);
+    assert!(hot.is_hot_at("busy", now));
+
+    // the window slides past the burst
+    let later = now + Duration::from_secs(11);
+    assert!(!hot.is_hot_at("busy", later));
+    assert!(hot.top_at(10, later).is_empty());
+}
+
+#[test]
+fn test_hot_by_share() {
+    let hot = HotQueues::new(0, 50);
+    let now = Instant::now();
+    for _ in 0..2000 {
+        hot.record_at("busy", now);
+    }
+    // a lone queue doesn't dominate anything
+    assert!(!hot.is_hot_at("busy", now));
+
+    for _ in 0..100 {
+        hot.record_at("quiet", now);
+    }
+    assert!(hot.is_hot_at("busy", now));
+    assert!(!hot.is_hot_at("quiet", now));
+}
+
+#[test]
+fn test_top_queues() {
+    let hot = HotQueues::new(0, 0);
+    let now = Instant::now();
+    for (name, n) in [("a", 3), ("b", 5), ("c", 1)] {
+        for _ in 0..n {
+            hot.record_at(name, now);
+        }
+    }
+    let top: Vec<_> = hot
+        .top_at(2, now)
+        .into_iter()
+        .map(|q| (q.name, q.ops))
+        .collect();
+    assert_eq!(top, vec![("b".to_string(), 5), ("a".to_string(), 3)]);
+}
+
+#[tokio::test]
+async fn test_hot_queue_in_status_json_and_stats() {
+    let server = common::server_with(httpmq_rs::state::Config {
+        hot_queue_ops: 1,
+        ..Default::default()
+    });
+    for _ in 0..10 {
+        server.get("/?name=xoyo&opt=put&data=a").await;
+    }
+
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""hot":true"#), "{}", body);
+
+    let (_, body) = server.get("/stats?top=1").await;
+    assert!(body.contains("1. xoyo "), "{}", body);
+    assert!(body.ends_with(" hot\n"), "{}", body);
+}
+
+#[tokio::test]
+async fn test_refused_requests_arent_hot() {
+    let server = common::server_with(httpmq_rs::state::Config {
+        hot_queue_ops: 1,
+        auth: Some("sesame".to_string()),
+        ..Default::default()
+    });
+    for _ in 0..10 {
+        server.get("/?name=xoyo&opt=put&data=a").await;
+    }
+
+    let (_, body) = server.get("/?name=xoyo&opt=status_json&auth=sesame").await;
+    assert!(body.contains(r#""hot":false"#), "{}", body);
+}
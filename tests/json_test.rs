@@ -0,0 +1,89 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+use serde_json::{json, Value};
+
+async fn get_json(server: &common::TestServer, uri: &str) -> (StatusCode, Value) {
+    let (code, headers, body) = server
+        .request(Request::get(uri).body(Body::empty()).unwrap())
+        .await;
+    assert_eq!(headers[header::CONTENT_TYPE], "application/json", "{}", uri);
+    (code, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn test_json_put_get_status() {
+    let server = common::server();
+    let (_, put) = get_json(&server, "/?name=xoyo&opt=put&data=hello&format=json").await;
+    assert_eq!(put["result"], "HTTPMQ_PUT_OK");
+    assert_eq!(put["pos"], 1);
+
+    let (_, status) = get_json(&server, "/?name=xoyo&opt=status&format=json").await;
+    assert_eq!(status["name"], "xoyo");
+    assert_eq!(status["putpos"], 1);
+    assert_eq!(status["getpos"], 0);
+    assert_eq!(status["unread"], 1);
+
+    let (_, get) = get_json(&server, "/?name=xoyo&opt=get&format=json").await;
+    assert_eq!(get["result"], "HTTPMQ_GET_OK");
+    assert_eq!(get["pos"], 1);
+    assert_eq!(get["data"], "hello");
+    let (_, get) = get_json(&server, "/?name=xoyo&opt=get&format=json").await;
+    assert_eq!(get["result"], "HTTPMQ_GET_END");
+
+    // plain text stays the default
+    let (_, body) = server.get("/?name=xoyo&opt=put&data=a").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+}
+
+#[tokio::test]
+async fn test_json_accept_header() {
+    let server = common::server();
+    let (_, headers, body) = server
+        .request(
+            Request::get("/?name=xoyo&opt=put&data=a")
+                .header(header::ACCEPT, "application/json")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(headers[header::CONTENT_TYPE], "application/json");
+    let put: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(put["result"], "HTTPMQ_PUT_OK");
+}
+
+#[tokio::test]
+async fn test_json_binary_data() {
+    let server = common::server();
+    server
+        .request(
+            Request::put("/?name=xoyo")
+                .body(Body::from(vec![0xff, 0x00]))
+                .unwrap(),
+        )
+        .await;
+    let (_, get) = get_json(&server, "/?name=xoyo&opt=get&format=json").await;
+    assert_eq!(get["data"], "/wA=");
+    assert_eq!(get["encoding"], "base64");
+
+    server.get("/?name=xoyo&opt=put&data=text").await;
+    let (_, get) = get_json(&server, "/?name=xoyo&opt=get&format=json").await;
+    assert_eq!(get["data"], "text");
+    assert!(get.get("encoding").is_none(), "{}", get);
+}
+
+#[tokio::test]
+async fn test_json_text_answers() {
+    let server = common::server();
+    let (_, res) = get_json(&server, "/?name=xoyo&opt=maxqueue&num=10&format=json").await;
+    assert_eq!(res, json!({"result": "HTTPMQ_MAXQUEUE_OK", "maxqueue": 10}));
+    let (code, res) = get_json(&server, "/?name=bad%20name&opt=get&format=json").await;
+    assert_eq!(code, StatusCode::BAD_REQUEST);
+    assert_eq!(res, json!({"result": "HTTPMQ_NAME_INVALID"}));
+    // already JSON, passed through
+    let (_, res) = get_json(&server, "/?name=xoyo&opt=status_json&format=json").await;
+    assert_eq!(res["maxqueue"], 10);
+}
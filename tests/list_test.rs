@@ -0,0 +1,115 @@
+mod common;
+
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn test_list() {
+    let server = common::server();
+    for name in ["orders", "orders-eu", "audit"] {
+        server.get(&format!("/?name={}&opt=put&data=a", name)).await;
+    }
+    server.get("/?name=orders&opt=put&data=b").await;
+    server.get("/?name=orders&opt=get").await;
+    // neither a message key nor any other key makes a queue
+    let db = &server.state.db;
+    db.put(b"ordersqueue17", b"x").unwrap();
+    db.put(b"ghost.expired", b"1").unwrap();
+
+    let (_, body) = server.get("/?opt=list").await;
+    assert_eq!(
+        body,
+        "HTTPMQ_LIST_OK\n\
+         queues: 3\n\
+         Queue audit: putpos 1, getpos 0, unread 1\n\
+         Queue orders: putpos 2, getpos 1, unread 1\n\
+         Queue orders-eu: putpos 1, getpos 0, unread 1\n"
+    );
+
+    let (_, body) = server.get("/?opt=list&prefix=orders&format=json").await;
+    let res: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(
+        res,
+        json!({
+            "result": "HTTPMQ_LIST_OK",
+            "queues": [
+                {"name": "orders", "putpos": 2, "getpos": 1, "unread": 1},
+                {"name": "orders-eu", "putpos": 1, "getpos": 0, "unread": 1},
+            ],
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_list_pages() {
+    let server = common::server();
+    for i in 0..5 {
+        server.get(&format!("/?name=q{}&opt=put&data=a", i)).await;
+    }
+    let mut seen = Vec::new();
+    let mut after = String::new();
+    loop {
+        let (_, body) = server
+            .get(&format!("/?opt=list&limit=2&format=json{}", after))
+            .await;
+        let res: Value = serde_json::from_str(&body).unwrap();
+        for q in res["queues"].as_array().unwrap() {
+            seen.push(q["name"].as_str().unwrap().to_string());
+        }
+        match res["next"].as_str() {
+            Some(next) => after = format!("&after={}", next),
+            None => break,
+        }
+    }
+    assert_eq!(seen, ["q0", "q1", "q2", "q3", "q4"]);
+
+    let (_, body) = server.get("/?opt=list&limit=2").await;
+    assert!(
+        body.starts_with("HTTPMQ_LIST_OK\nqueues: 2\nnext: q1\n"),
+        "{}",
+        body
+    );
+}
+
+#[tokio::test]
+async fn test_list_pages_in_name_order() {
+    let server = common::server();
+    // "q-x.putpos" < "q.a.putpos" < "q.putpos", but "q" < "q-x" < "q.a"
+    for name in ["q", "q-x", "q.a", "r"] {
+        for _ in 0..3 {
+            server.get(&format!("/?name={}&opt=put&data=a", name)).await;
+        }
+    }
+
+    let mut seen = Vec::new();
+    let mut after = String::new();
+    loop {
+        let (_, body) = server
+            .get(&format!("/?opt=list&limit=1&format=json{}", after))
+            .await;
+        let res: Value = serde_json::from_str(&body).unwrap();
+        for q in res["queues"].as_array().unwrap() {
+            seen.push(q["name"].as_str().unwrap().to_string());
+        }
+        match res["next"].as_str() {
+            Some(next) => after = format!("&after={}", next),
+            None => break,
+        }
+    }
+    assert_eq!(seen, ["q", "q-x", "q.a", "r"]);
+}
+
+#[tokio::test]
+async fn test_list_corrupt() {
+    let server = common::server();
+    server.get("/?name=good&opt=put&data=a").await;
+    server.state.db.put(b"bad.putpos", b"x").unwrap();
+
+    let (_, body) = server.get("/?opt=list").await;
+    assert_eq!(
+        body,
+        "HTTPMQ_LIST_OK\n\
+         queues: 1\n\
+         Queue good: putpos 1, getpos 0, unread 1\n\
+         Corrupt queue bad\n"
+    );
+}
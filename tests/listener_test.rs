@@ -0,0 +1,86 @@
+mod common;
+
+use httpmq_rs::{app, listener, state::Config};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+async fn request(stream: &mut TcpStream) -> String {
+    stream
+        .write_all(b"GET /?name=xoyo&opt=put&data=a HTTP/1.1\r\nHost: test\r\n\r\n")
+        .await
+        .unwrap();
+    let mut buf = vec![0; 1024];
+    let n = stream.read(&mut buf).await.unwrap();
+    String::from_utf8_lossy(&buf[..n]).to_string()
+}
+
+#[tokio::test]
+async fn test_max_connections() {
+    let server = common::server();
+    let config = Config {
+        max_connections: 1,
+        tcp_nodelay: true,
+        tcp_keepalive: 60,
+        tcp_keepalive_interval: 10,
+        ..Default::default()
+    };
+    let listener = listener::bind("127.0.0.1:0".parse().unwrap(), &config).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = app(server.state.clone());
+    tokio::spawn(axum::Server::builder(listener).serve(app.into_make_service()));
+
+    let mut first = TcpStream::connect(addr).await.unwrap();
+    assert!(request(&mut first).await.ends_with("HTTPMQ_PUT_OK"));
+
+    // the second connection is closed right away
+    let mut second = TcpStream::connect(addr).await.unwrap();
+    let mut buf = [0; 16];
+    let n = tokio::time::timeout(Duration::from_secs(5), second.read(&mut buf))
+        .await
+        .unwrap()
+        .unwrap_or(0);
+    assert_eq!(n, 0);
+
+    // and once the first is gone there is room again
+    drop(first);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut third = TcpStream::connect(addr).await.unwrap();
+    assert!(request(&mut third).await.ends_with("HTTPMQ_PUT_OK"));
+}
+
+#[tokio::test]
+async fn test_inherit_fd() {
+    use std::os::unix::io::IntoRawFd;
+
+    let server = common::server();
+    let std_listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = std_listener.local_addr().unwrap();
+    let listener = listener::inherit(std_listener.into_raw_fd(), &Config::default()).unwrap();
+    assert_eq!(listener.local_addr().unwrap(), addr);
+    let app = app(server.state.clone());
+    tokio::spawn(axum::Server::builder(listener).serve(app.into_make_service()));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    assert!(request(&mut stream).await.ends_with("HTTPMQ_PUT_OK"));
+
+    // not a socket
+    let dir = tempfile::tempdir().unwrap();
+    let file = std::fs::File::create(dir.path().join("f")).unwrap();
+    assert!(listener::inherit(file.into_raw_fd(), &Config::default()).is_err());
+}
+
+#[test]
+fn test_systemd_fd() {
+    std::env::set_var("LISTEN_PID", std::process::id().to_string());
+    std::env::set_var("LISTEN_FDS", "1");
+    assert_eq!(listener::systemd_fd(), Some(3));
+    // cleared once read
+    assert!(std::env::var_os("LISTEN_FDS").is_none());
+    assert_eq!(listener::systemd_fd(), None);
+
+    // meant for another process
+    std::env::set_var("LISTEN_PID", "1");
+    std::env::set_var("LISTEN_FDS", "1");
+    assert_eq!(listener::systemd_fd(), None);
+}
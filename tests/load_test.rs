@@ -0,0 +1,66 @@
+mod common;
+
+use axum::{body::Body, http::Request};
+use httpmq_rs::{
+    limited_app,
+    load::LoadMetrics,
+    state::{Config, State},
+};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tower::ServiceExt;
+
+#[test]
+fn test_in_flight_high_water_mark() {
+    let load = Arc::new(LoadMetrics::new(8));
+    let a = load.enter();
+    let b = load.enter();
+    drop(a);
+    let _c = load.enter();
+    drop(b);
+    let stats = load.stats();
+    assert_eq!(stats.in_flight, 1);
+    assert_eq!(stats.in_flight_high, 2);
+    assert_eq!(stats.concurrency_limit, 8);
+}
+
+#[test]
+fn test_shedding_episode() {
+    let load = Arc::new(LoadMetrics::new(1));
+    let now = Instant::now();
+    load.record_shed_at(now);
+    load.record_shed_at(now);
+    assert!(load.is_shedding());
+    // still shedding a moment later
+    drop(load.enter_at(now + Duration::from_millis(100)));
+    assert!(load.is_shedding());
+    drop(load.enter_at(now + Duration::from_secs(2)));
+    assert!(!load.is_shedding());
+    load.record_timeout();
+    let stats = load.stats();
+    assert_eq!(stats.shed, 2);
+    assert_eq!(stats.timeouts, 1);
+}
+
+#[tokio::test]
+async fn test_load_in_stats() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = Arc::new(State::new(Config {
+        dbpath: dir.path().to_str().unwrap().to_string(),
+        concurrency_limit: 16,
+        ..Default::default()
+    }));
+    let app = limited_app(state.clone());
+    let res = app
+        .oneshot(Request::get("/stats").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(
+        body.contains("Load: 1 in flight, most 1 of limit 16, 0 shed, 0 timed out"),
+        "{}",
+        body
+    );
+    assert_eq!(state.load.stats().in_flight, 0);
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use axum::{body::Body, http::Request};
+use httpmq_rs::state::Config;
+
+fn put(name: &str, size: usize) -> Request<Body> {
+    Request::put(format!("/?name={}", name))
+        .body(Body::from(vec![b'x'; size]))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_global_and_queue_limits() {
+    let server = common::server_with(Config {
+        max_message_size: 16,
+        ..Default::default()
+    });
+    let (_, _, body) = server.request(put("small", 16)).await;
+    assert_eq!(body, b"HTTPMQ_PUT_OK");
+    let (_, _, body) = server.request(put("small", 17)).await;
+    assert_eq!(body, b"HTTPMQ_PUT_TOO_LARGE");
+
+    let (_, body) = server
+        .get("/?name=blobs&opt=config&data=%7B%22max_message_size%22%3A1024%7D")
+        .await;
+    assert_eq!(body, "HTTPMQ_CONFIG_OK");
+    let (_, _, body) = server.request(put("blobs", 1024)).await;
+    assert_eq!(body, b"HTTPMQ_PUT_OK");
+    let (_, _, body) = server.request(put("blobs", 1025)).await;
+    assert_eq!(body, b"HTTPMQ_PUT_TOO_LARGE");
+
+    let (_, body) = server.get("/?name=blobs&opt=status_json").await;
+    assert!(body.contains(r#""max_message_size":1024"#), "{}", body);
+    let (_, body) = server.get("/?name=small&opt=status_json").await;
+    assert!(body.contains(r#""max_message_size":16"#), "{}", body);
+    let (_, body) = server.get("/?name=small&opt=status").await;
+    assert!(body.contains("Max message size: 16\n"), "{}", body);
+}
+
+#[tokio::test]
+async fn test_lowered_limit_keeps_stored_messages() {
+    let server = common::server();
+    server.request(put("xoyo", 100)).await;
+    server
+        .get("/?name=xoyo&opt=config&data=%7B%22max_message_size%22%3A10%7D")
+        .await;
+
+    let (_, _, body) = server.request(put("xoyo", 100)).await;
+    assert_eq!(body, b"HTTPMQ_PUT_TOO_LARGE");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body.len(), 100);
+
+    // 0 lifts the limit for the queue
+    server
+        .get("/?name=xoyo&opt=config&data=%7B%22max_message_size%22%3A0%7D")
+        .await;
+    let (_, _, body) = server.request(put("xoyo", 100)).await;
+    assert_eq!(body, b"HTTPMQ_PUT_OK");
+}
@@ -0,0 +1,222 @@
+mod common;
+
+use axum::http::StatusCode;
+use httpmq_rs::state::{Config, MAXQUEUE_LIMIT};
+
+#[test]
+fn test_maxqueue_bounds() {
+    let config = |maxqueue| Config {
+        maxqueue,
+        ..Default::default()
+    };
+    assert!(config(MAXQUEUE_LIMIT).validate().is_ok());
+    assert!(config(0).validate().is_err());
+    assert!(config(MAXQUEUE_LIMIT + 1).validate().is_err());
+    assert!(config(u64::MAX).validate().is_err());
+    // past what a 32-bit position could hold
+    assert!(config(1 << 33).validate().is_ok());
+}
+
+#[tokio::test]
+async fn test_maxqueue_at_limit() {
+    let server = common::server_with(Config {
+        maxqueue: MAXQUEUE_LIMIT,
+        ..Default::default()
+    });
+    let (_, body) = server
+        .get(&format!(
+            "/?name=big&opt=maxqueue&num={}",
+            MAXQUEUE_LIMIT + 1
+        ))
+        .await;
+    assert_eq!(body, "HTTPMQ_MAXQUEUE_CANCLE");
+    let (_, body) = server
+        .get(&format!(
+            "/?name=big&opt=fsck&field=maxqueue&num={}",
+            MAXQUEUE_LIMIT + 1
+        ))
+        .await;
+    assert_eq!(body, "HTTPMQ_FSCK_INVALID_FIELD");
+
+    // putpos at the end of the ring, the next put wraps
+    server
+        .get(&format!(
+            "/?name=big&opt=fsck&field=putpos&num={}",
+            MAXQUEUE_LIMIT
+        ))
+        .await;
+    server
+        .get(&format!(
+            "/?name=big&opt=fsck&field=getpos&num={}",
+            MAXQUEUE_LIMIT - 1
+        ))
+        .await;
+    let (_, body) = server.get("/?name=big&opt=put&data=a").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+    let (_, body) = server.get("/?name=big&opt=status").await;
+    assert!(
+        body.contains("Put position of queue (2st lap): 1\n"),
+        "{}",
+        body
+    );
+    assert!(body.contains("Number of unread queue: 2\n"), "{}", body);
+}
+
+#[tokio::test]
+async fn test_stored_maxqueue_past_limit() {
+    // not something opt=maxqueue gives out, but the ring arithmetic copes
+    let server = common::server();
+    let db = &server.state.db;
+    db.put(b"huge.maxqueue", u64::MAX.to_string().as_bytes())
+        .unwrap();
+    db.put(b"huge.putpos", u64::MAX.to_string().as_bytes())
+        .unwrap();
+    db.put(b"huge.getpos", b"5").unwrap();
+
+    let (_, body) = server.get("/?name=huge&opt=status").await;
+    assert!(
+        body.contains(&format!("Number of unread queue: {}\n", u64::MAX - 5)),
+        "{}",
+        body
+    );
+    let (_, body) = server.get("/?name=huge&opt=put&data=a").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+
+    // wrapped cursors
+    db.put(b"huge.putpos", b"1").unwrap();
+    db.put(b"huge.getpos", u64::MAX.to_string().as_bytes())
+        .unwrap();
+    let (_, body) = server.get("/?name=huge&opt=status").await;
+    assert!(body.contains("Number of unread queue: 1\n"), "{}", body);
+    let (_, body) = server.get("/?name=huge&opt=get").await;
+    assert_eq!(body, "a");
+    let (_, body) = server.get("/?name=huge&opt=status").await;
+    assert!(body.contains("Number of unread queue: 0\n"), "{}", body);
+}
+
+#[test]
+fn test_maxqueue_limit_bounds() {
+    let config = |maxqueue, maxqueue_limit| Config {
+        maxqueue,
+        maxqueue_limit,
+        ..Default::default()
+    };
+    assert!(config(10, 10).validate().is_ok());
+    assert!(config(10, 9).validate().is_err());
+    assert!(config(10, MAXQUEUE_LIMIT + 1).validate().is_err());
+}
+
+#[tokio::test]
+async fn test_maxqueue_past_default() {
+    let server = common::server_with(Config {
+        maxqueue: 10,
+        maxqueue_limit: 100,
+        ..Default::default()
+    });
+    let (_, body) = server.get("/?name=xoyo&opt=maxqueue&num=50").await;
+    assert_eq!(body, "HTTPMQ_MAXQUEUE_OK\nmaxqueue: 50\n");
+    let (_, body) = server.get("/?name=xoyo&opt=status").await;
+    assert!(body.contains("Maximum number of queues: 50\n"), "{}", body);
+    for num in ["0", "101"] {
+        let (_, body) = server
+            .get(&format!("/?name=xoyo&opt=maxqueue&num={}", num))
+            .await;
+        assert_eq!(body, "HTTPMQ_MAXQUEUE_CANCLE", "{}", num);
+    }
+}
+
+#[tokio::test]
+async fn test_maxqueue_num_not_a_count() {
+    let server = common::server();
+    for num in ["-1", "abc", "1.5", "18446744073709551616"] {
+        let (code, body) = server
+            .get(&format!("/?name=xoyo&opt=maxqueue&num={}", num))
+            .await;
+        assert_eq!(code, StatusCode::BAD_REQUEST, "{}", num);
+        assert_eq!(body, "HTTPMQ_ARGS_INVALID", "{}", num);
+    }
+    let (_, body) = server.get("/?name=xoyo&opt=status").await;
+    assert!(
+        body.contains("Maximum number of queues: 100000000\n"),
+        "{}",
+        body
+    );
+}
+
+#[tokio::test]
+async fn test_maxqueue_past_32_bits() {
+    let maxqueue: u64 = (1 << 32) + 2;
+    let server = common::server_with(Config {
+        maxqueue,
+        ..Default::default()
+    });
+    // the last two slots of the ring, then round to the first
+    for (field, pos) in [("putpos", maxqueue - 1), ("getpos", maxqueue - 1)] {
+        server
+            .get(&format!("/?name=big&opt=fsck&field={}&num={}", field, pos))
+            .await;
+    }
+    for data in ["a", "b"] {
+        let (_, body) = server
+            .get(&format!("/?name=big&opt=put&data={}", data))
+            .await;
+        assert_eq!(body, "HTTPMQ_PUT_OK");
+    }
+    let (_, body) = server.get("/?name=big&opt=status").await;
+    assert!(
+        body.contains(&format!("Maximum number of queues: {}\n", maxqueue)),
+        "{}",
+        body
+    );
+    assert!(
+        body.contains("Put position of queue (2st lap): 1\n"),
+        "{}",
+        body
+    );
+    assert!(body.contains("Number of unread queue: 2\n"), "{}", body);
+
+    let (_, body) = server.get("/?name=big&opt=view&pos=4294967298").await;
+    assert_eq!(body, "a");
+    for data in ["a", "b"] {
+        let (_, body) = server.get("/?name=big&opt=get").await;
+        assert_eq!(body, data);
+    }
+    let (_, body) = server.get("/?name=big&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+    let (_, body) = server.get("/?name=big&opt=status").await;
+    assert!(body.contains("Number of unread queue: 0\n"), "{}", body);
+}
+
+#[tokio::test]
+async fn test_maxqueue_shrink() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=maxqueue&num=5").await;
+    for data in ["a", "b", "c"] {
+        server
+            .get(&format!("/?name=xoyo&opt=put&data={}", data))
+            .await;
+    }
+    let (_, body) = server.get("/?name=xoyo&opt=maxqueue&num=2").await;
+    assert_eq!(body, "HTTPMQ_MAXQUEUE_RESET_FIRST");
+    let (_, body) = server.get("/?name=xoyo&opt=maxqueue&num=3").await;
+    assert_eq!(body, "HTTPMQ_MAXQUEUE_OK\nmaxqueue: 3\n");
+
+    // unread messages wrap around the end, the size is stuck until they
+    // are read
+    for data in ["a", "b"] {
+        let (_, body) = server.get("/?name=xoyo&opt=get").await;
+        assert_eq!(body, data);
+    }
+    server.get("/?name=xoyo&opt=put&data=d").await;
+    let (_, body) = server.get("/?name=xoyo&opt=maxqueue&num=4").await;
+    assert_eq!(body, "HTTPMQ_MAXQUEUE_RESET_FIRST");
+    for data in ["c", "d"] {
+        let (_, body) = server.get("/?name=xoyo&opt=get").await;
+        assert_eq!(body, data);
+    }
+    let (_, body) = server.get("/?name=xoyo&opt=maxqueue&num=4").await;
+    assert_eq!(body, "HTTPMQ_MAXQUEUE_OK\nmaxqueue: 4\n");
+    server.get("/?name=xoyo&opt=put&data=e").await;
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "e");
+}
@@ -0,0 +1,164 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+};
+use httpmq_rs::state::Config;
+
+async fn send(server: &common::TestServer, method: Method, uri: &str, body: &str) -> String {
+    let req = Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let (code, _, body) = server.request(req).await;
+    assert_eq!(code, StatusCode::OK);
+    String::from_utf8(body).unwrap()
+}
+
+#[tokio::test]
+async fn test_put_method() {
+    let server = common::server();
+
+    assert_eq!(
+        send(&server, Method::PUT, "/?name=xoyo", "hello").await,
+        "HTTPMQ_PUT_OK"
+    );
+    // the body wins over the data parameter, which is the fallback
+    assert_eq!(
+        send(&server, Method::PUT, "/?name=xoyo&data=x", "world").await,
+        "HTTPMQ_PUT_OK"
+    );
+    assert_eq!(
+        send(&server, Method::PUT, "/?name=xoyo&data=again", "").await,
+        "HTTPMQ_PUT_OK"
+    );
+    assert_eq!(
+        send(&server, Method::PUT, "/?name=xoyo", "").await,
+        "HTTPMQ_PUT_NO_DATA"
+    );
+
+    for want in ["hello", "world", "again"] {
+        let (_, body) = server.get("/?name=xoyo&opt=get").await;
+        assert_eq!(body, want);
+    }
+}
+
+#[tokio::test]
+async fn test_post_method() {
+    let server = common::server();
+
+    // stored verbatim, whatever it holds
+    let data = "a&b=c\nd%20e";
+    assert_eq!(
+        send(&server, Method::POST, "/?opt=put&name=xoyo", data).await,
+        "HTTPMQ_PUT_OK"
+    );
+    assert_eq!(
+        send(&server, Method::POST, "/?name=xoyo&data=x", "body").await,
+        "HTTPMQ_PUT_OK"
+    );
+    assert_eq!(
+        send(&server, Method::POST, "/?opt=put&name=xoyo", "").await,
+        "HTTPMQ_PUT_NO_DATA"
+    );
+    // other opts work as with GET
+    assert_eq!(
+        send(&server, Method::POST, "/?opt=get&name=xoyo", "").await,
+        data
+    );
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "body");
+}
+
+#[tokio::test]
+async fn test_max_body_size() {
+    let server = common::server_with(Config {
+        max_body_size: 4,
+        ..Default::default()
+    });
+    for method in [Method::POST, Method::PUT] {
+        let req = Request::builder()
+            .method(method.clone())
+            .uri("/?opt=put&name=xoyo")
+            .body(Body::from("hello"))
+            .unwrap();
+        let (code, _, body) = server.request(req).await;
+        assert_eq!(code, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body, b"HTTPMQ_BODY_TOO_LARGE");
+
+        // without a declared length too
+        let (mut tx, body) = Body::channel();
+        tokio::spawn(async move {
+            tx.send_data("hel".into()).await.unwrap();
+            tx.send_data("lo".into()).await.unwrap();
+        });
+        let req = Request::builder()
+            .method(method)
+            .uri("/?opt=put&name=xoyo")
+            .body(body)
+            .unwrap();
+        let (code, _, _) = server.request(req).await;
+        assert_eq!(code, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    assert_eq!(
+        send(&server, Method::POST, "/?opt=put&name=xoyo", "hell").await,
+        "HTTPMQ_PUT_OK"
+    );
+}
+
+#[tokio::test]
+async fn test_delete_method() {
+    let server = common::server();
+    for _ in 0..3 {
+        server.get("/?name=xoyo&opt=put&data=a").await;
+    }
+    server.get("/?name=xoyo1&opt=put&data=b").await;
+
+    assert_eq!(
+        send(&server, Method::DELETE, "/?name=xoyo", "").await,
+        "HTTPMQ_CONFIRM_REQUIRED"
+    );
+    assert_eq!(
+        send(&server, Method::DELETE, "/?name=xoyo&confirm=xoyo", "").await,
+        "HTTPMQ_REMOVE_OK"
+    );
+
+    let db = server.state.db.raw();
+    for key in ["xoyo:1", "xoyo:2", "xoyo:3", "xoyo.putpos", "xoyo.maxqueue"] {
+        assert_eq!(db.get(key).unwrap(), None, "{}", key);
+    }
+    // neighbouring queues are left alone
+    let (_, body) = server.get("/?name=xoyo1&opt=get").await;
+    assert_eq!(body, "b");
+}
+
+#[tokio::test]
+async fn test_delete_as_reset() {
+    let server = common::server_with(Config {
+        delete_opt: String::from("reset"),
+        ..Default::default()
+    });
+    server.get("/?name=xoyo&opt=put&data=a").await;
+
+    assert_eq!(
+        send(&server, Method::DELETE, "/?name=xoyo&confirm=xoyo", "").await,
+        "HTTPMQ_RESET_OK"
+    );
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+}
+
+#[tokio::test]
+async fn test_method_not_allowed() {
+    let server = common::server();
+    let req = Request::builder()
+        .method(Method::PATCH)
+        .uri("/?name=xoyo")
+        .body(Body::empty())
+        .unwrap();
+    let (code, headers, _) = server.request(req).await;
+    assert_eq!(code, StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(headers[header::ALLOW], "GET, HEAD, POST, PUT, DELETE");
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use axum::{body::Body, http::Request};
+
+use httpmq_rs::state::Config;
+
+async fn scrape(server: &common::TestServer) -> String {
+    let (_, headers, body) = server
+        .request(Request::get("/metrics").body(Body::empty()).unwrap())
+        .await;
+    assert!(headers["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain; version=0.0.4"));
+    String::from_utf8(body).unwrap()
+}
+
+#[tokio::test]
+async fn test_metrics() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=maxqueue&num=2").await;
+    for data in ["a", "b", "c"] {
+        server
+            .get(&format!("/?name=xoyo&opt=put&data={}", data))
+            .await;
+    }
+    server.get("/?name=xoyo&opt=get").await;
+    server.get("/?name=other&opt=get").await;
+    server.get("/?name=looked-at&opt=status").await;
+
+    let body = scrape(&server).await;
+    for line in [
+        "# TYPE httpmq_puts_total counter",
+        "httpmq_puts_total{queue=\"xoyo\"} 2",
+        "httpmq_put_end_total{queue=\"xoyo\"} 1",
+        "httpmq_gets_total{queue=\"xoyo\"} 1",
+        "httpmq_get_end_total{queue=\"other\"} 1",
+        "httpmq_errors_total{queue=\"xoyo\"} 0",
+        "# TYPE httpmq_unread_messages gauge",
+        "httpmq_unread_messages{queue=\"xoyo\"} 1",
+        "httpmq_unread_messages{queue=\"other\"} 0",
+        "# TYPE httpmq_request_duration_seconds histogram",
+        "httpmq_request_duration_seconds_count{opt=\"put\"} 3",
+        "httpmq_request_duration_seconds_bucket{opt=\"get\",le=\"+Inf\"} 2",
+    ] {
+        assert!(body.lines().any(|l| l == line), "{} not in\n{}", line, body);
+    }
+    assert!(!body.contains("looked-at"), "{}", body);
+
+    // removed queues lose their series
+    server.get("/?name=xoyo&opt=remove&confirm=xoyo").await;
+    assert!(!scrape(&server).await.contains("\"xoyo\""));
+}
+
+#[tokio::test]
+async fn test_metrics_queue_limit() {
+    let server = common::server_with(Config {
+        metrics_max_queues: 1,
+        ..Default::default()
+    });
+    for name in ["a", "b", "c"] {
+        server.get(&format!("/?name={}&opt=put&data=x", name)).await;
+    }
+    let body = scrape(&server).await;
+    assert!(
+        body.contains("httpmq_puts_total{queue=\"a\"} 1\n"),
+        "{}",
+        body
+    );
+    assert!(
+        body.contains("httpmq_puts_total{queue=\"_other\"} 2\n"),
+        "{}",
+        body
+    );
+    assert!(!body.contains("queue=\"b\""), "{}", body);
+}
@@ -0,0 +1,76 @@
+mod common;
+
+#[tokio::test]
+async fn test_mirror() {
+    let server = common::server();
+    let (_, body) = server.get("/?name=payments&opt=mirror").await;
+    assert_eq!(body, "HTTPMQ_MIRROR_INVALID");
+    let (_, body) = server.get("/?name=payments&opt=mirror&dest=payments").await;
+    assert_eq!(body, "HTTPMQ_MIRROR_INVALID");
+    let (_, body) = server.get("/?name=payments&opt=mirror&dest=shadow").await;
+    assert_eq!(body, "HTTPMQ_MIRROR_OK");
+
+    for data in ["a", "b"] {
+        let (_, body) = server
+            .get(&format!("/?name=payments&opt=put&data={}", data))
+            .await;
+        assert_eq!(body, "HTTPMQ_PUT_OK");
+    }
+    let (_, body) = server.get("/?name=payments&opt=get").await;
+    assert_eq!(body, "a");
+    // the mirror has its own cursor
+    for data in ["a", "b"] {
+        let (_, body) = server.get("/?name=shadow&opt=get").await;
+        assert_eq!(body, data);
+    }
+    let (_, body) = server.get("/?name=payments&opt=status_json").await;
+    assert!(
+        body.contains(r#""mirror":{"target":"shadow","strict":false,"mirrored":2,"full":0}"#),
+        "{}",
+        body
+    );
+
+    let (_, body) = server.get("/?name=payments&opt=unmirror").await;
+    assert_eq!(body, "HTTPMQ_UNMIRROR_OK");
+    let (_, body) = server.get("/?name=payments&opt=unmirror").await;
+    assert_eq!(body, "HTTPMQ_UNMIRROR_NONE");
+    server.get("/?name=payments&opt=put&data=c").await;
+    let (_, body) = server.get("/?name=shadow&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+    let (_, body) = server.get("/?name=payments&opt=status_json").await;
+    assert!(!body.contains("mirror"), "{}", body);
+}
+
+#[tokio::test]
+async fn test_mirror_full() {
+    let server = common::server();
+    server.get("/?name=shadow&opt=maxqueue&num=1").await;
+    server.get("/?name=shadow&opt=put&data=x").await;
+    let (_, body) = server.get("/?name=shadow&opt=put&data=x").await;
+    assert_eq!(body, "HTTPMQ_PUT_END");
+
+    // the put goes through without the copy
+    server.get("/?name=payments&opt=mirror&dest=shadow").await;
+    let (_, body) = server.get("/?name=payments&opt=put&data=a").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+    let (_, body) = server.get("/?name=payments&opt=status").await;
+    assert!(
+        body.contains("Mirror: shadow, 0 mirrored, 1 full\n"),
+        "{}",
+        body
+    );
+
+    // unless the mirror is strict
+    server
+        .get("/?name=payments&opt=mirror&dest=shadow&strict=1")
+        .await;
+    let (_, body) = server.get("/?name=payments&opt=put&data=b").await;
+    assert_eq!(body, "HTTPMQ_MIRROR_FULL");
+    let (_, body) = server.get("/?name=payments&opt=status").await;
+    assert!(body.contains("Number of unread queue: 1\n"), "{}", body);
+    assert!(
+        body.contains("Mirror: shadow (strict), 0 mirrored, 1 full\n"),
+        "{}",
+        body
+    );
+}
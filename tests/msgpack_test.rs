@@ -0,0 +1,140 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+};
+use serde::Deserialize;
+use serde_bytes::ByteBuf;
+
+#[derive(Deserialize, Debug)]
+struct Get {
+    name: String,
+    result: String,
+    pos: u64,
+    data: Option<ByteBuf>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Put {
+    result: String,
+    count: usize,
+}
+
+#[derive(Deserialize, Debug)]
+struct Status {
+    putpos: u64,
+    getpos: u64,
+    unread: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct QueueOps {
+    name: String,
+    ops: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Stats {
+    total_ops: u64,
+    top: Vec<QueueOps>,
+}
+
+async fn put_batch(server: &common::TestServer, uri: &str, messages: &[&[u8]]) -> Put {
+    let messages: Vec<ByteBuf> = messages.iter().map(|m| ByteBuf::from(m.to_vec())).collect();
+    let req = Request::builder()
+        .method(Method::PUT)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/msgpack")
+        .header(header::ACCEPT, "application/msgpack")
+        .body(Body::from(rmp_serde::to_vec(&messages).unwrap()))
+        .unwrap();
+    let (code, headers, body) = server.request(req).await;
+    assert_eq!(code, StatusCode::OK);
+    assert_eq!(headers[header::CONTENT_TYPE], "application/msgpack");
+    rmp_serde::from_slice(&body).unwrap()
+}
+
+async fn get_msgpack<T: serde::de::DeserializeOwned>(server: &common::TestServer, uri: &str) -> T {
+    let (code, body) = server.get_bytes(uri).await;
+    assert_eq!(code, StatusCode::OK);
+    rmp_serde::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn test_msgpack_roundtrip() {
+    let server = common::server();
+    let messages: [&[u8]; 3] = [b"\xff\xfe\x00binary", b"plain", b"\xc3\x28"];
+
+    let put = put_batch(&server, "/?name=xoyo", &messages).await;
+    assert_eq!(put.result, "HTTPMQ_PUT_OK");
+    assert_eq!(put.count, 3);
+
+    let status: Status = get_msgpack(&server, "/?name=xoyo&opt=status&format=msgpack").await;
+    assert_eq!((status.putpos, status.getpos, status.unread), (3, 0, 3));
+
+    for (i, want) in messages.iter().enumerate() {
+        let get: Get = get_msgpack(&server, "/?name=xoyo&opt=get&format=msgpack").await;
+        assert_eq!(get.name, "xoyo");
+        assert_eq!(get.result, "HTTPMQ_GET_OK");
+        assert_eq!(get.pos, i as u64 + 1);
+        assert_eq!(get.data.unwrap().into_vec(), want.to_vec());
+    }
+
+    let get: Get = get_msgpack(&server, "/?name=xoyo&opt=get&format=msgpack").await;
+    assert_eq!(get.result, "HTTPMQ_GET_END");
+    assert!(get.data.is_none());
+}
+
+#[tokio::test]
+async fn test_msgpack_accept_header() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=put&data=hello").await;
+
+    let req = Request::get("/?name=xoyo&opt=get")
+        .header(header::ACCEPT, "application/x-msgpack")
+        .body(Body::empty())
+        .unwrap();
+    let (_, _, body) = server.request(req).await;
+    let get: Get = rmp_serde::from_slice(&body).unwrap();
+    assert_eq!(get.data.unwrap().into_vec(), b"hello");
+
+    // format= wins over Accept
+    let req = Request::get("/?name=xoyo&opt=status&format=text")
+        .header(header::ACCEPT, "application/msgpack")
+        .body(Body::empty())
+        .unwrap();
+    let (_, _, body) = server.request(req).await;
+    assert!(String::from_utf8(body)
+        .unwrap()
+        .contains("Queue Name: xoyo"));
+
+    let stats: Stats = get_msgpack(&server, "/stats?format=msgpack").await;
+    assert_eq!(stats.total_ops, 3);
+    assert_eq!(stats.top[0].name, "xoyo");
+    assert_eq!(stats.top[0].ops, 3);
+}
+
+#[tokio::test]
+async fn test_msgpack_batch_is_atomic() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=maxqueue&num=2").await;
+
+    let put = put_batch(&server, "/?name=xoyo", &[b"a", b"b", b"c"]).await;
+    assert_eq!(put.result, "HTTPMQ_PUT_END");
+    assert_eq!(put.count, 0);
+    let put = put_batch(&server, "/?name=xoyo", &[b"a", b""]).await;
+    assert_eq!(put.result, "HTTPMQ_PUT_NO_DATA");
+
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+
+    let req = Request::builder()
+        .method(Method::PUT)
+        .uri("/?name=xoyo")
+        .header(header::CONTENT_TYPE, "application/msgpack")
+        .body(Body::from(&b"\xc1"[..]))
+        .unwrap();
+    let (_, _, body) = server.request(req).await;
+    assert_eq!(body, b"HTTPMQ_PUT_INVALID_BODY");
+}
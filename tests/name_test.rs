@@ -0,0 +1,110 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use httpmq_rs::state::Config;
+
+#[tokio::test]
+async fn test_name_policy() {
+    let server = common::server();
+
+    for name in ["xoyo", "a-b_c.d", "Q1"] {
+        let (code, _) = server.get(&format!("/?name={}&opt=status", name)).await;
+        assert_eq!(code, StatusCode::OK, "{}", name);
+    }
+
+    let long = "a".repeat(257);
+    for name in ["", "a%0Ab", "a%20b", "a/b", "%C3%A9", &long] {
+        let (code, body) = server.get(&format!("/?name={}&opt=put&data=a", name)).await;
+        assert_eq!(code, StatusCode::BAD_REQUEST, "{}", name);
+        assert_eq!(body, "HTTPMQ_NAME_INVALID", "{}", name);
+    }
+
+    let (code, _) = server
+        .get(&format!("/?name={}&opt=status", "a".repeat(256)))
+        .await;
+    assert_eq!(code, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_permissive_names() {
+    let server = common::server_with(Config {
+        name_max_len: 8,
+        permissive_names: true,
+        ..Default::default()
+    });
+
+    let (_, body) = server.get("/?name=a%20b%2F%C3%A9&opt=put&data=a").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+    let (_, body) = server.get("/?name=a%20b%2F%C3%A9&opt=get").await;
+    assert_eq!(body, "a");
+
+    // the length limit and the empty name check still apply
+    let (code, _) = server.get("/?name=abcdefghi&opt=status").await;
+    assert_eq!(code, StatusCode::BAD_REQUEST);
+    let (code, _) = server.get("/?name=&opt=status").await;
+    assert_eq!(code, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_rejection_reasons() {
+    let server = common::server();
+    let long = "a".repeat(257);
+    for (query, reason) in [
+        ("opt=put&data=a", "name_missing"),
+        ("name=&opt=put&data=a", "name_missing"),
+        (&format!("name={}&opt=status", long)[..], "name_too_long"),
+        ("name=a%2Fb&opt=get", "name_characters"),
+    ] {
+        let (code, headers, body) = server
+            .request(
+                Request::get(format!("/?{}", query))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(code, StatusCode::BAD_REQUEST, "{}", query);
+        assert_eq!(body, b"HTTPMQ_NAME_INVALID", "{}", query);
+        assert_eq!(headers["x-httpmq-reason"], reason, "{}", query);
+    }
+
+    for query in ["name=xoyo&opt=frobnicate", "name=xoyo"] {
+        let (code, body) = server.get(&format!("/?{}", query)).await;
+        assert_eq!(code, StatusCode::BAD_REQUEST, "{}", query);
+        assert_eq!(body, "HTTPMQ_OPT_INVALID", "{}", query);
+    }
+    let (code, _) = server.get("/?name=xoyo&opt=put&num=-1").await;
+    assert_eq!(code, StatusCode::BAD_REQUEST);
+}
+
+// queue "job1" position 2 and queue "job" position 12 used to share the key
+// "job12"
+#[tokio::test]
+async fn test_names_ending_in_digits_keep_apart() {
+    let server = common::server();
+    server.get("/?name=job1&opt=put&data=job1-1").await;
+    server.get("/?name=job1&opt=put&data=job1-2").await;
+    for i in 1..=12 {
+        server
+            .get(&format!("/?name=job&opt=put&data=job-{}", i))
+            .await;
+    }
+
+    for i in 1..=2 {
+        let (_, body) = server.get("/?name=job1&opt=get").await;
+        assert_eq!(body, format!("job1-{}", i));
+    }
+    for i in 1..=12 {
+        let (_, body) = server.get("/?name=job&opt=get").await;
+        assert_eq!(body, format!("job-{}", i));
+    }
+
+    // removing one leaves the other's messages alone
+    server.get("/?name=job1&opt=put&data=again").await;
+    server.get("/?name=job&opt=put&data=kept").await;
+    server.get("/?name=job1&opt=remove&confirm=job1").await;
+    let (_, body) = server.get("/?name=job&opt=get").await;
+    assert_eq!(body, "kept");
+}
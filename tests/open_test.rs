@@ -0,0 +1,98 @@
+mod common;
+
+use httpmq_rs::{app, error::OpenError, schema, state::Config, state::State};
+use rocksdb::DB;
+use std::sync::Arc;
+
+#[test]
+fn test_open_error_kinds() {
+    let e = OpenError::from_message(
+        "/data/",
+        String::from("IO error: While lock file: /data/LOCK: Resource temporarily unavailable"),
+    );
+    assert!(matches!(&e, OpenError::Locked(lock) if lock == "/data/LOCK"));
+    assert!(e.to_string().contains("/data/LOCK"), "{}", e);
+    assert_eq!(e.exit_code(), 75);
+
+    let e = OpenError::from_message(
+        "/data",
+        String::from("Corruption: bad record length in MANIFEST-000004"),
+    );
+    assert!(matches!(e, OpenError::Corrupt(_)));
+    assert_eq!(e.exit_code(), 65);
+
+    let e = OpenError::from_message("/data", String::from("IO error: No space left on device"));
+    assert!(matches!(e, OpenError::Failed(_)));
+    assert_eq!(e.exit_code(), 74);
+}
+
+#[test]
+fn test_open_creates_database() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = State::open(Config {
+        dbpath: dir.path().to_str().unwrap().to_string(),
+        wait_for_lock: 1,
+        ..Default::default()
+    });
+    assert!(state.is_ok());
+}
+
+#[test]
+fn test_open_creates_parent_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    let dbpath = dir.path().join("a/b/db");
+    let state = State::open(Config {
+        dbpath: dbpath.to_str().unwrap().to_string(),
+        ..Default::default()
+    });
+    assert!(state.is_ok());
+
+    // a file in the way
+    let file = dir.path().join("file");
+    std::fs::write(&file, b"x").unwrap();
+    let e = State::open(Config {
+        dbpath: file.join("db").to_str().unwrap().to_string(),
+        ..Default::default()
+    })
+    .err()
+    .unwrap();
+    assert!(matches!(e, OpenError::Failed(_)));
+    assert!(e.to_string().contains("can't create"), "{}", e);
+}
+
+// a database opened with RocksDB's defaults, as before the tuning options,
+// opens with them and keeps its messages
+#[tokio::test]
+async fn test_open_default_database_with_tuning() {
+    let dir = tempfile::tempdir().unwrap();
+    let dbpath = dir.path().to_str().unwrap().to_string();
+    {
+        let db = DB::open_default(&dbpath).unwrap();
+        schema::migrate(&db).unwrap();
+        let state = Arc::new(State::with_storage(Config::default(), Box::new(db)));
+        let server = common::TestServer::new(app(state.clone()), state);
+        for data in ["a", "b"] {
+            let (_, body) = server
+                .get(&format!("/?name=xoyo&opt=put&data={}", data))
+                .await;
+            assert_eq!(body, "HTTPMQ_PUT_OK");
+        }
+    }
+
+    let state = Arc::new(
+        State::open(Config {
+            dbpath,
+            write_buffer_size: 1 << 20,
+            max_open_files: 64,
+            block_cache_size: 1 << 20,
+            bloom_bits: 0,
+            ..Default::default()
+        })
+        .unwrap(),
+    );
+    let server = common::TestServer::new(app(state.clone()), state);
+    for want in ["a", "b", "HTTPMQ_GET_END"] {
+        let (_, body) = server.get("/?name=xoyo&opt=get").await;
+        assert_eq!(body, want);
+    }
+}
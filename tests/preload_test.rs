@@ -0,0 +1,23 @@
+mod common;
+
+use std::time::Duration;
+
+use httpmq_rs::service::preload_metadata;
+
+#[tokio::test]
+async fn test_preload_metadata() {
+    let server = common::server();
+    for name in ["a", "a.n", "b", "c"] {
+        server.get(&format!("/?name={}&opt=put&data=x", name)).await;
+    }
+    server.get("/?name=c&opt=maxqueue&num=10").await;
+    // a.getpos and a.putpos sort either side of a.n.putpos
+    server.get("/?name=a&opt=put&data=y").await;
+    server.get("/?name=a&opt=get").await;
+
+    assert_eq!(
+        preload_metadata(&server.state, Duration::from_secs(10)),
+        (4, true)
+    );
+    assert_eq!(preload_metadata(&server.state, Duration::ZERO), (0, false));
+}
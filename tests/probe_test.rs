@@ -0,0 +1,66 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use std::{sync::atomic::Ordering, time::Duration};
+use tower::ServiceExt;
+
+use httpmq_rs::{limited_app, state::Config};
+
+#[tokio::test]
+async fn test_probes() {
+    let server = common::server_with(Config {
+        auth: Some(String::from("sesame")),
+        auth_read: Some(String::from("sesame")),
+        admin_auth: Some(String::from("sesame")),
+        ..Default::default()
+    });
+    let (code, body) = server.get("/healthz").await;
+    assert_eq!((code, &body[..]), (StatusCode::OK, "HTTPMQ_OK"));
+    let (code, body) = server.get("/readyz").await;
+    assert_eq!((code, &body[..]), (StatusCode::OK, "HTTPMQ_READY"));
+
+    // no queue comes out of it, and no request is counted
+    let (_, body) = server.get("/?opt=list&auth=sesame").await;
+    assert_eq!(body, "HTTPMQ_LIST_OK\nqueues: 0\n");
+    assert_eq!(server.state.hot.total_ops(), 0);
+}
+
+#[tokio::test]
+async fn test_not_ready_while_shutting_down() {
+    let server = common::server();
+    server.state.shutting_down.store(true, Ordering::Relaxed);
+    let (code, body) = server.get("/readyz").await;
+    assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body, "HTTPMQ_SHUTTING_DOWN");
+    let (code, _) = server.get("/healthz").await;
+    assert_eq!(code, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_probes_skip_load_shedding() {
+    let base = common::server_with(Config {
+        concurrency_limit: 1,
+        ..Default::default()
+    });
+    let server = common::TestServer::new(limited_app(base.state.clone()), base.state.clone());
+    // a get waiting for a put holds the only slot
+    let app = server.app.clone();
+    let waiting = tokio::spawn(async move {
+        let req = Request::get("/?name=xoyo&opt=get&wait=1")
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(req).await.unwrap().status()
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let (code, _) = server.get("/?name=xoyo&opt=status").await;
+    assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+    let (code, _) = server.get("/healthz").await;
+    assert_eq!(code, StatusCode::OK);
+    let (code, _) = server.get("/readyz").await;
+    assert_eq!(code, StatusCode::OK);
+    assert_eq!(waiting.await.unwrap(), StatusCode::OK);
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{header, Request},
+};
+use httpmq_rs::bucket::PutLimits;
+use httpmq_rs::clock::MockClock;
+use httpmq_rs::state::Config;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_bucket() {
+    let limits = PutLimits::new();
+    let now = Instant::now();
+    assert_eq!(limits.try_take("q", 10, 8, now), Ok(()));
+    assert_eq!(limits.status("q", 10, now).utilization, 0.8);
+    assert_eq!(
+        limits.try_take("q", 10, 4, now),
+        Err(Duration::from_millis(200))
+    );
+    assert_eq!(
+        limits.try_take("q", 10, 4, now + Duration::from_millis(200)),
+        Ok(())
+    );
+
+    // a batch larger than the bucket waits for it to be full
+    assert!(limits
+        .try_take("q", 10, 20, now + Duration::from_secs(1))
+        .is_err());
+    assert_eq!(
+        limits.try_take("q", 10, 20, now + Duration::from_secs(2)),
+        Ok(())
+    );
+    assert_eq!(
+        limits
+            .status("q", 10, now + Duration::from_secs(2))
+            .utilization,
+        1.0
+    );
+
+    // a new limit starts with a full bucket
+    assert_eq!(
+        limits.try_take("q", 100, 100, now + Duration::from_secs(2)),
+        Ok(())
+    );
+    assert_eq!(limits.status("other", 5, now).utilization, 0.0);
+}
+
+#[tokio::test]
+async fn test_put_rate_limit() {
+    let clock = Arc::new(MockClock::new());
+    let server = common::server_with_clock(Config::default(), clock.clone());
+    let (_, body) = server
+        .get("/?name=webhooks-in&opt=config&data=%7B%22max_put_rate%22%3A2%7D")
+        .await;
+    assert_eq!(body, "HTTPMQ_CONFIG_OK");
+
+    for _ in 0..2 {
+        let (_, body) = server.get("/?name=webhooks-in&opt=put&data=a").await;
+        assert_eq!(body, "HTTPMQ_PUT_OK");
+    }
+    let req = Request::get("/?name=webhooks-in&opt=put&data=a")
+        .body(Body::empty())
+        .unwrap();
+    let (_, headers, body) = server.request(req).await;
+    assert_eq!(body, b"HTTPMQ_PUT_RATELIMITED");
+    assert_eq!(headers[header::RETRY_AFTER], "1");
+    let (_, body) = server.get("/?name=webhooks-in&opt=status_json").await;
+    assert!(
+        body.contains(r#""put_rate":{"limit":2,"utilization":1.0}"#),
+        "{}",
+        body
+    );
+
+    // other queues don't share the limit
+    let (_, body) = server.get("/?name=other&opt=put&data=a").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+    let (_, body) = server.get("/?name=other&opt=status_json").await;
+    assert!(!body.contains("put_rate"), "{}", body);
+
+    clock.advance(Duration::from_millis(500));
+    let (_, body) = server.get("/?name=webhooks-in&opt=put&data=a").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+    let (_, body) = server.get("/?name=webhooks-in&opt=status").await;
+    assert!(
+        body.contains("Put rate limit: 2/s, 100.0% used\n"),
+        "{}",
+        body
+    );
+
+    let (_, body) = server
+        .get("/?name=webhooks-in&opt=config&data=%7B%22max_put_rate%22%3A0%7D")
+        .await;
+    assert!(body.contains("max_put_rate"), "{}", body);
+}
+
+#[tokio::test]
+async fn test_refused_put_keeps_rate() {
+    let clock = Arc::new(MockClock::new());
+    let server = common::server_with_clock(Config::default(), clock);
+    let (_, body) = server.get("/?name=webhooks-in&opt=maxqueue&num=2").await;
+    assert!(body.starts_with("HTTPMQ_MAXQUEUE_OK"), "{}", body);
+    let (_, body) = server
+        .get("/?name=webhooks-in&opt=config&data=%7B%22max_put_rate%22%3A3%7D")
+        .await;
+    assert_eq!(body, "HTTPMQ_CONFIG_OK");
+
+    for data in ["a", "b"] {
+        let uri = format!("/?name=webhooks-in&opt=put&data={}", data);
+        let (_, body) = server.get(&uri).await;
+        assert_eq!(body, "HTTPMQ_PUT_OK");
+    }
+    let (_, body) = server.get("/?name=webhooks-in&opt=put&data=c").await;
+    assert_eq!(body, "HTTPMQ_PUT_END");
+    for data in ["a", "b"] {
+        let (_, body) = server.get("/?name=webhooks-in&opt=get").await;
+        assert_eq!(body, data);
+    }
+    // the refused put took nothing from the limit
+    let (_, body) = server.get("/?name=webhooks-in&opt=put&data=d").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use httpmq_rs::error::HttpmqError;
+use httpmq_rs::queue::{QueueError, QueueStore};
+use httpmq_rs::state::Config;
+
+fn store(maxqueue: u64) -> (QueueStore, tempfile::TempDir) {
+    let dir = tempfile::tempdir().unwrap();
+    let store = QueueStore::open(Config {
+        dbpath: dir.path().to_str().unwrap().to_string(),
+        maxqueue,
+        ..Config::default()
+    })
+    .unwrap();
+    (store, dir)
+}
+
+#[tokio::test]
+async fn test_queue_put_get() {
+    let (store, _dir) = store(10);
+    assert_eq!(store.put("xoyo", b"a").await.unwrap(), 1);
+    assert_eq!(store.put("xoyo", b"b").await.unwrap(), 2);
+
+    let message = store.get("xoyo").await.unwrap().unwrap();
+    assert_eq!((message.pos, message.seq), (1, Some(1)));
+    assert_eq!(message.data, b"a");
+    assert_eq!(store.get("xoyo").await.unwrap().unwrap().data, b"b");
+    assert_eq!(store.get("xoyo").await.unwrap(), None);
+
+    let status = store.status("xoyo").unwrap();
+    assert_eq!(
+        (status.putpos, status.getpos, status.unread, status.seq),
+        (2, 2, 0, 2)
+    );
+}
+
+#[tokio::test]
+async fn test_queue_refusals() {
+    let (store, _dir) = store(1);
+    assert!(matches!(
+        store.put("xoyo", b"").await,
+        Err(QueueError::Refused("HTTPMQ_PUT_NO_DATA"))
+    ));
+    store.put("xoyo", b"a").await.unwrap();
+    assert!(matches!(
+        store.put("xoyo", b"b").await,
+        Err(QueueError::Refused("HTTPMQ_PUT_END"))
+    ));
+    assert!(matches!(
+        store.put("a/b", b"a").await,
+        Err(QueueError::Failed(HttpmqError::NameInvalid))
+    ));
+    assert!(matches!(
+        store.set_maxqueue("xoyo", 0).await,
+        Err(QueueError::Refused("HTTPMQ_MAXQUEUE_CANCLE"))
+    ));
+}
+
+#[tokio::test]
+async fn test_queue_maxqueue_and_reset() {
+    let (store, _dir) = store(1);
+    store.set_maxqueue("xoyo", 3).await.unwrap();
+    for data in [b"a", b"b", b"c"] {
+        store.put("xoyo", data).await.unwrap();
+    }
+    assert_eq!(store.status("xoyo").unwrap().maxqueue, 3);
+
+    store.reset("xoyo").await.unwrap();
+    let status = store.status("xoyo").unwrap();
+    assert_eq!((status.maxqueue, status.putpos, status.unread), (1, 0, 0));
+    assert_eq!(store.get("xoyo").await.unwrap(), None);
+}
+
+// each store goes by its own config, not a process-wide default
+#[tokio::test]
+async fn test_queue_stores_keep_their_config() {
+    let (small, _small_dir) = store(2);
+    let (large, _large_dir) = store(1000);
+    assert_eq!(small.status("xoyo").unwrap().maxqueue, 2);
+    assert_eq!(large.status("xoyo").unwrap().maxqueue, 1000);
+
+    small.reset("xoyo").await.unwrap();
+    large.reset("xoyo").await.unwrap();
+    assert_eq!(small.status("xoyo").unwrap().maxqueue, 2);
+    assert_eq!(large.status("xoyo").unwrap().maxqueue, 1000);
+}
+
+// a store made from a server's state shares its queues with the HTTP API
+#[tokio::test]
+async fn test_queue_shares_server_state() {
+    let server = common::server();
+    let store = QueueStore::from_state(server.state.clone());
+
+    store.put("xoyo", b"from the library").await.unwrap();
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "from the library");
+
+    server.get("/?name=xoyo&opt=put&data=over%20http").await;
+    assert_eq!(store.get("xoyo").await.unwrap().unwrap().data, b"over http");
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""total_get":2"#), "{}", body);
+}
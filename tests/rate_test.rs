@@ -0,0 +1,83 @@
+mod common;
+
+use httpmq_rs::rate::{Event, Rates};
+use httpmq_rs::state::Config;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_rates_slide() {
+    let rates = Rates::new();
+    let start = Instant::now();
+    for _ in 0..20 {
+        rates.record_at(Some("a"), &[Event::Put], start);
+    }
+    rates.record_at(Some("a"), &[Event::Put, Event::Full], start);
+    rates.record_at(Some("a"), &[Event::Get, Event::Error], start);
+
+    let rates_now = rates.queue_at("a", start).unwrap();
+    assert_eq!(rates_now[0].window_secs, 10);
+    assert_eq!(rates_now[0].puts, 2.1);
+    assert_eq!(rates_now[0].full, 0.1);
+    assert_eq!(rates_now[1].gets, 1.0 / 60.0);
+    assert_eq!(rates_now[0].error_rate, 1.0 / 22.0);
+
+    // out of the short window, still in the long one
+    let later = rates
+        .queue_at("a", start + Duration::from_secs(30))
+        .unwrap();
+    assert_eq!(later[0].puts, 0.0);
+    assert_eq!(later[1].puts, 21.0 / 60.0);
+
+    // a slot reused a minute later starts over
+    rates.record_at(Some("a"), &[Event::Put], start + Duration::from_secs(60));
+    let wrapped = rates
+        .queue_at("a", start + Duration::from_secs(60))
+        .unwrap();
+    assert_eq!(wrapped[0].puts, 0.1);
+    assert_eq!(wrapped[1].puts, 0.1 / 6.0);
+}
+
+#[test]
+fn test_rates_bounded() {
+    let rates = Rates::new();
+    let start = Instant::now();
+    for i in 0..100 {
+        rates.record_at(Some(&format!("q{}", i)), &[Event::Put], start);
+    }
+    assert!(rates.queue_at("q0", start).is_some());
+    assert!(rates.queue_at("q99", start).is_none());
+    assert!(rates.other()[1].puts > 0.0);
+
+    // once the first queues went idle they make room
+    let later = start + Duration::from_secs(120);
+    rates.record_at(Some("new"), &[Event::Put], later);
+    assert!(rates.queue_at("new", later).is_some());
+    assert!(rates.queue_at("q0", later).is_none());
+}
+
+#[tokio::test]
+async fn test_throughput_in_stats_and_status() {
+    let server = common::server_with(Config {
+        maxqueue: 1,
+        ..Default::default()
+    });
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    let (_, body) = server.get("/?name=xoyo&opt=put&data=b").await;
+    assert_eq!(body, "HTTPMQ_PUT_END");
+    server.get("/?name=xoyo&opt=get").await;
+
+    let (_, body) = server.get("/stats").await;
+    assert!(
+        body.contains("Throughput (last 10s): 0.2 puts/s, 0.1 gets/s, 0.1 full/s, 0.0% errors"),
+        "{}",
+        body
+    );
+    assert!(body.contains("Throughput (last 60s): "), "{}", body);
+
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(
+        body.contains(r#""rates":[{"window_secs":10,"puts":0.2,"gets":0.1,"full":0.1,"#),
+        "{}",
+        body
+    );
+}
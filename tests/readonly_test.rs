@@ -0,0 +1,85 @@
+mod common;
+
+use httpmq_rs::state::Config;
+
+#[tokio::test]
+async fn test_readonly_peek() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    server.get("/?name=xoyo&opt=put&data=b").await;
+
+    let (_, body) = server.get("/?name=xoyo&opt=readonly").await;
+    assert_eq!(body, "HTTPMQ_READONLY_OK");
+
+    for uri in [
+        "/?name=xoyo&opt=put&data=c",
+        "/?name=xoyo&opt=maxqueue&num=10",
+        "/?name=xoyo&opt=reset&confirm=xoyo",
+        "/?name=xoyo&opt=remove&confirm=xoyo",
+        "/?name=xoyo&opt=replayall",
+    ] {
+        let (_, body) = server.get(uri).await;
+        assert_eq!(body, "HTTPMQ_QUEUE_READONLY", "{}", uri);
+    }
+
+    // gets peek at the head of the queue
+    for _ in 0..2 {
+        let (_, body) = server.get("/?name=xoyo&opt=get").await;
+        assert_eq!(body, "a");
+    }
+    let (_, body) = server.get("/?name=xoyo&opt=status").await;
+    assert!(body.contains("Read-only: peek"), "{}", body);
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""readonly":"peek""#), "{}", body);
+
+    let (_, body) = server.get("/?name=xoyo&opt=unlock").await;
+    assert_eq!(body, "HTTPMQ_UNLOCK_OK");
+    let (_, body) = server.get("/?name=xoyo&opt=unlock").await;
+    assert_eq!(body, "HTTPMQ_UNLOCK_NONE");
+    let (_, body) = server.get("/?name=xoyo&opt=put&data=c").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(!body.contains("readonly"), "{}", body);
+}
+
+#[tokio::test]
+async fn test_readonly_advance() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    server.get("/?name=xoyo&opt=put&data=b").await;
+
+    let (_, body) = server.get("/?name=xoyo&opt=readonly&mode=later").await;
+    assert_eq!(body, "HTTPMQ_READONLY_INVALID_MODE");
+    let (_, body) = server.get("/?name=xoyo&opt=readonly&mode=advance").await;
+    assert_eq!(body, "HTTPMQ_READONLY_OK");
+
+    let (_, body) = server.get("/?name=xoyo&opt=put&data=c").await;
+    assert_eq!(body, "HTTPMQ_QUEUE_READONLY");
+    for want in ["a", "b", "HTTPMQ_GET_END"] {
+        let (_, body) = server.get("/?name=xoyo&opt=get").await;
+        assert_eq!(body, want);
+    }
+    let (_, body) = server.get("/?name=xoyo&opt=status").await;
+    assert!(body.contains("Read-only: advance"), "{}", body);
+}
+
+#[tokio::test]
+async fn test_readonly_admin_ops() {
+    let server = common::server_with(Config {
+        admin_auth: Some(String::from("secret")),
+        ..Default::default()
+    });
+    let (_, body) = server.get("/?name=xoyo&opt=readonly").await;
+    assert_eq!(body, "HTTPMQ_AUTH_FAILED");
+    let (_, body) = server.get("/?name=xoyo&opt=unlock").await;
+    assert_eq!(body, "HTTPMQ_AUTH_FAILED");
+}
+
+#[tokio::test]
+async fn test_readonly_topic_subscriber() {
+    let server = common::server();
+    server.get("/?name=news&opt=subscribe&queue=a").await;
+    server.get("/?name=a&opt=readonly").await;
+    let (_, body) = server.get("/?name=news&opt=put&data=x").await;
+    assert_eq!(body, "HTTPMQ_QUEUE_READONLY");
+}
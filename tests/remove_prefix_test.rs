@@ -0,0 +1,64 @@
+mod common;
+
+use httpmq_rs::state::Config;
+
+#[tokio::test]
+async fn test_remove_prefix() {
+    let server = common::server_with(Config {
+        admin_auth: Some(String::from("sesame")),
+        ..Default::default()
+    });
+    for name in [
+        "loadtest-1",
+        "loadtest-2",
+        "loadtest-3",
+        "loadtest-4",
+        "load",
+        "keep",
+    ] {
+        server.get(&format!("/?name={}&opt=put&data=a", name)).await;
+    }
+    server
+        .get("/?name=loadtest-3&opt=readonly&auth=sesame")
+        .await;
+    // it may have been read-only, so it stays
+    server
+        .state
+        .db
+        .put(b"loadtest-4.readonly", b"garbage")
+        .unwrap();
+
+    let (_, body) = server.get("/?opt=remove_prefix&prefix=loadtest-").await;
+    assert_eq!(body, "HTTPMQ_AUTH_FAILED");
+    let (_, body) = server
+        .get("/?opt=remove_prefix&prefix=lo&auth=sesame")
+        .await;
+    assert_eq!(body, "HTTPMQ_PREFIX_TOO_SHORT");
+    let (_, body) = server.get("/?opt=remove_prefix&auth=sesame").await;
+    assert_eq!(body, "HTTPMQ_PREFIX_TOO_SHORT");
+
+    let (_, body) = server
+        .get("/?opt=remove_prefix&prefix=loadtest-&dry_run=1&auth=sesame")
+        .await;
+    assert_eq!(
+        body,
+        "HTTPMQ_REMOVE_PREFIX_DRY_RUN\nremoved: 2\nloadtest-1\nloadtest-2\nreadonly, kept: loadtest-3\ncorrupt, kept: loadtest-4\n"
+    );
+    let (_, body) = server.get("/?name=loadtest-1&opt=count").await;
+    assert_eq!(body, "1");
+
+    let (_, body) = server
+        .get("/?opt=remove_prefix&prefix=loadtest-&auth=sesame")
+        .await;
+    assert_eq!(
+        body,
+        "HTTPMQ_REMOVE_PREFIX_OK\nremoved: 2\nloadtest-1\nloadtest-2\nreadonly, kept: loadtest-3\ncorrupt, kept: loadtest-4\n"
+    );
+    assert!(server.state.db.get(b"loadtest-1:1").unwrap().is_none());
+    assert!(server.state.db.get(b"loadtest-2.putpos").unwrap().is_none());
+    assert!(server.state.db.get(b"loadtest-4:1").unwrap().is_some());
+    for name in ["loadtest-3", "load", "keep"] {
+        let (_, body) = server.get(&format!("/?name={}&opt=count", name)).await;
+        assert_eq!(body, "1", "{}", name);
+    }
+}
@@ -0,0 +1,203 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use httpmq_rs::state::Config;
+
+async fn drain(server: &common::TestServer, name: &str) -> Vec<String> {
+    let mut got = Vec::new();
+    loop {
+        let (_, body) = server.get(&format!("/?name={}&opt=get", name)).await;
+        if body == "HTTPMQ_GET_END" {
+            return got;
+        }
+        got.push(body);
+    }
+}
+
+#[tokio::test]
+async fn test_replayall() {
+    let server = common::server();
+    for i in 1..=3 {
+        server.get(&format!("/?name=xoyo&opt=put&data={}", i)).await;
+    }
+    assert_eq!(drain(&server, "xoyo").await, ["1", "2", "3"]);
+
+    let (_, body) = server.get("/?name=xoyo&opt=replayall").await;
+    assert_eq!(body, "HTTPMQ_REPLAYALL_OK\nreplayed: 3\n");
+    assert_eq!(drain(&server, "xoyo").await, ["1", "2", "3"]);
+
+    // leading slots that lost their message are skipped
+    server.state.db.raw().delete("xoyo:1").unwrap();
+    let (_, body) = server.get("/?name=xoyo&opt=replayall").await;
+    assert_eq!(body, "HTTPMQ_REPLAYALL_OK\nreplayed: 2\n");
+    assert_eq!(drain(&server, "xoyo").await, ["2", "3"]);
+}
+
+#[tokio::test]
+async fn test_replayall_with_deliveries_in_flight() {
+    let server = common::server();
+    for i in 1..=2 {
+        server.get(&format!("/?name=xoyo&opt=put&data={}", i)).await;
+    }
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "1");
+    let req = Request::get("/?name=xoyo&opt=get&ack=manual")
+        .body(Body::empty())
+        .unwrap();
+    let (_, headers, body) = server.request(req).await;
+    assert_eq!(body, b"2");
+    let lease = headers["x-httpmq-lease"].to_str().unwrap().to_string();
+
+    let (code, body) = server.get("/?name=xoyo&opt=replayall").await;
+    assert_eq!(
+        (code, &body[..]),
+        (StatusCode::CONFLICT, "HTTPMQ_REPLAYALL_IN_FLIGHT")
+    );
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""getpos":1"#), "{}", body);
+
+    // forced, the lease is void and everything goes out again
+    let (_, body) = server.get("/?name=xoyo&opt=replayall&force=1").await;
+    assert_eq!(body, "HTTPMQ_REPLAYALL_OK\nreplayed: 1\n");
+    let (code, _) = server
+        .get(&format!("/?name=xoyo&opt=ack&id={}", lease))
+        .await;
+    assert_eq!(code, StatusCode::CONFLICT);
+    assert_eq!(drain(&server, "xoyo").await, ["1", "2"]);
+}
+
+#[tokio::test]
+async fn test_replayall_after_wrap() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=maxqueue&num=5").await;
+    for i in 1..=4 {
+        server.get(&format!("/?name=xoyo&opt=put&data={}", i)).await;
+    }
+    drain(&server, "xoyo").await;
+    // slots 5, 1 and 2 take the next lap
+    for i in 5..=7 {
+        server.get(&format!("/?name=xoyo&opt=put&data={}", i)).await;
+    }
+    drain(&server, "xoyo").await;
+
+    // slot 3 holds message 3 but sits right after putpos, so only 4 of
+    // the 5 stored messages come back
+    let (_, body) = server.get("/?name=xoyo&opt=replayall").await;
+    assert_eq!(body, "HTTPMQ_REPLAYALL_OK\nreplayed: 4\n");
+    assert_eq!(drain(&server, "xoyo").await, ["4", "5", "6", "7"]);
+}
+
+#[tokio::test]
+async fn test_replayall_requires_admin_auth() {
+    let server = common::server_with(Config {
+        admin_auth: Some(String::from("secret")),
+        ..Default::default()
+    });
+    let (_, body) = server.get("/?name=xoyo&opt=replayall").await;
+    assert_eq!(body, "HTTPMQ_AUTH_FAILED");
+    let (_, body) = server.get("/?name=xoyo&opt=replayall&auth=secret").await;
+    assert_eq!(body, "HTTPMQ_REPLAYALL_OK\nreplayed: 0\n");
+}
+
+#[tokio::test]
+async fn test_replay_range() {
+    let server = common::server();
+    for i in 1..=5 {
+        server.get(&format!("/?name=xoyo&opt=put&data={}", i)).await;
+    }
+    server.get("/?name=xoyo&opt=get").await;
+    server.state.db.raw().delete("xoyo:3").unwrap();
+
+    let (_, body) = server
+        .get("/?name=xoyo&opt=replay&from=2&to=4&dest=xoyo-replay")
+        .await;
+    assert_eq!(
+        body,
+        "HTTPMQ_REPLAY_OK\ncopied: 2\nmissing: 1\noverwritten: 0\n"
+    );
+    assert_eq!(drain(&server, "xoyo-replay").await, ["2", "4"]);
+    // the source keeps its cursors
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""putpos":5"#), "{}", body);
+    assert!(body.contains(r#""getpos":1"#), "{}", body);
+}
+
+#[tokio::test]
+async fn test_replay_wraps_and_skips_overwritten() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=maxqueue&num=5").await;
+    for i in 1..=4 {
+        server.get(&format!("/?name=xoyo&opt=put&data={}", i)).await;
+    }
+    drain(&server, "xoyo").await;
+    // slots 5, 1 and 2 take the next lap
+    for i in 5..=7 {
+        server.get(&format!("/?name=xoyo&opt=put&data={}", i)).await;
+    }
+
+    let (_, body) = server
+        .get("/?name=xoyo&opt=replay&from=4&to=1&dest=xoyo-replay")
+        .await;
+    assert_eq!(
+        body,
+        "HTTPMQ_REPLAY_OK\ncopied: 3\nmissing: 0\noverwritten: 0\n"
+    );
+    assert_eq!(drain(&server, "xoyo-replay").await, ["4", "5", "6"]);
+
+    // slots 1 and 2 no longer hold the lap that 3 and 4 belong to
+    let (_, body) = server
+        .get("/?name=xoyo&opt=replay&from=1&to=4&dest=xoyo-replay")
+        .await;
+    assert_eq!(
+        body,
+        "HTTPMQ_REPLAY_OK\ncopied: 2\nmissing: 0\noverwritten: 2\n"
+    );
+    assert_eq!(drain(&server, "xoyo-replay").await, ["3", "4"]);
+}
+
+#[tokio::test]
+async fn test_replay_stops_when_dest_fills() {
+    let server = common::server();
+    server.get("/?name=dest&opt=maxqueue&num=3").await;
+    for i in 1..=5 {
+        server.get(&format!("/?name=xoyo&opt=put&data={}", i)).await;
+    }
+
+    let (_, body) = server
+        .get("/?name=xoyo&opt=replay&from=1&to=5&dest=dest")
+        .await;
+    assert_eq!(
+        body,
+        "HTTPMQ_REPLAY_STOPPED\ncopied: 3\nmissing: 0\noverwritten: 0\n\
+         reason: HTTPMQ_PUT_END\nnext: 4\n"
+    );
+    assert_eq!(drain(&server, "dest").await, ["1", "2", "3"]);
+    let (_, body) = server
+        .get("/?name=xoyo&opt=replay&from=4&to=5&dest=dest")
+        .await;
+    assert_eq!(
+        body,
+        "HTTPMQ_REPLAY_OK\ncopied: 2\nmissing: 0\noverwritten: 0\n"
+    );
+    assert_eq!(drain(&server, "dest").await, ["4", "5"]);
+}
+
+#[tokio::test]
+async fn test_replay_invalid() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    for query in [
+        "from=1&to=1",
+        "from=1&to=1&dest=xoyo",
+        "from=0&to=1&dest=other",
+        "from=1&to=100000001&dest=other",
+    ] {
+        let (_, body) = server
+            .get(&format!("/?name=xoyo&opt=replay&{}", query))
+            .await;
+        assert_eq!(body, "HTTPMQ_REPLAY_INVALID", "{}", query);
+    }
+}
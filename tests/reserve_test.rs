@@ -0,0 +1,462 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use httpmq_rs::{
+    clock::{Clock, MockClock},
+    reserve::Reservations,
+    state::Config,
+};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+fn test_reservation_times_out() {
+    let clock = MockClock::new();
+    let reservations = Reservations::new(Duration::from_secs(30), &clock);
+    let now = clock.instant();
+    assert!(reservations.try_reserve_at("xoyo", now));
+    assert!(!reservations.try_reserve_at("xoyo", now + Duration::from_secs(29)));
+    assert!(reservations.try_reserve_at("other", now));
+    assert!(!reservations.is_held_at("xoyo", now + Duration::from_secs(31)));
+    assert!(reservations.try_reserve_at("xoyo", now + Duration::from_secs(31)));
+    reservations.release("xoyo");
+    assert!(!reservations.is_held_at("xoyo", now));
+}
+
+#[tokio::test]
+async fn test_reserve_then_commit() {
+    let server = common::server();
+    server
+        .get("/?name=xoyo&opt=config&data=%7B%22delivery%22%3A%22at-least-once%22%7D")
+        .await;
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    server.get("/?name=xoyo&opt=put&data=b").await;
+
+    let (_, body) = server.get("/?name=xoyo&opt=get&mode=reserve").await;
+    assert_eq!(body, "a");
+    // one reservation at a time, and gets wait for it
+    let (_, body) = server.get("/?name=xoyo&opt=get&mode=reserve").await;
+    assert_eq!(body, "HTTPMQ_GET_RESERVED");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_RESERVED");
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""getpos":0"#), "{}", body);
+
+    let (code, body) = server.get("/?name=xoyo&opt=commit&pos=2").await;
+    assert_eq!(
+        (code, &body[..]),
+        (StatusCode::CONFLICT, "HTTPMQ_COMMIT_CONFLICT")
+    );
+    let (_, body) = server.get("/?name=xoyo&opt=commit&pos=1").await;
+    assert_eq!(body, "HTTPMQ_COMMIT_OK");
+    // committing twice is a conflict, the cursor has moved on
+    let (code, _) = server.get("/?name=xoyo&opt=commit&pos=1").await;
+    assert_eq!(code, StatusCode::CONFLICT);
+
+    // at least once, a plain get reserves too
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "b");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_RESERVED");
+    let (_, body) = server.get("/?name=xoyo&opt=commit&pos=2").await;
+    assert_eq!(body, "HTTPMQ_COMMIT_OK");
+    // nothing to reserve holds nothing
+    let (_, body) = server.get("/?name=xoyo&opt=get&mode=reserve").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+}
+
+#[tokio::test]
+async fn test_reservation_expires() {
+    let server = common::server_with(Config {
+        reserve_timeout: 0,
+        ..Default::default()
+    });
+    server
+        .get("/?name=xoyo&opt=config&data=%7B%22delivery%22%3A%22at-least-once%22%7D")
+        .await;
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    let (_, body) = server.get("/?name=xoyo&opt=get&mode=reserve").await;
+    assert_eq!(body, "a");
+    // the consumer died, the message goes out again
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "a");
+    let (_, body) = server.get("/?name=xoyo&opt=commit&pos=1").await;
+    assert_eq!(body, "HTTPMQ_COMMIT_OK");
+    // whoever commits first wins, the late one conflicts
+    let (code, _) = server.get("/?name=xoyo&opt=commit&pos=1").await;
+    assert_eq!(code, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn test_reservation_expires_on_clock() {
+    let clock = Arc::new(MockClock::new());
+    let server = common::server_with_clock(Config::default(), clock.clone());
+    server
+        .get("/?name=xoyo&opt=config&data=%7B%22delivery%22%3A%22at-least-once%22%7D")
+        .await;
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "a");
+
+    clock.advance(Duration::from_secs(29));
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_RESERVED");
+    clock.advance(Duration::from_secs(2));
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "a");
+}
+
+// the lease of an ack=manual get, from its X-Httpmq-Lease header
+async fn get_manual(server: &common::TestServer) -> (String, Option<String>) {
+    let req = Request::get("/?name=xoyo&opt=get&ack=manual")
+        .body(Body::empty())
+        .unwrap();
+    let (_, headers, body) = server.request(req).await;
+    let lease = headers
+        .get("x-httpmq-lease")
+        .map(|lease| lease.to_str().unwrap().to_string());
+    (String::from_utf8(body).unwrap(), lease)
+}
+
+#[test]
+fn test_reservation_leases() {
+    let clock = MockClock::new();
+    let reservations = Reservations::new(Duration::from_secs(30), &clock);
+    let now = clock.instant();
+    let lease = reservations.lease_at("xoyo", 1, now);
+    // ids start off the clock's wall time
+    assert_eq!(lease, (clock.unix_millis() * 1000) as u64);
+    let second = reservations.lease_at("xoyo", 2, now + Duration::from_secs(10));
+    assert_ne!(second, lease);
+    assert_eq!(reservations.leased_pos("xoyo", lease, now), Some(1));
+    assert_eq!(reservations.leased_pos("xoyo", second, now), Some(2));
+    assert_eq!(reservations.leased_pos("other", lease, now), None);
+    assert_eq!(reservations.in_flight_at("xoyo", now), 2);
+
+    // each lease times out on its own, and is reclaimed by the next look
+    let later = now + Duration::from_secs(31);
+    assert_eq!(reservations.leased_pos("xoyo", lease, later), None);
+    assert_eq!(reservations.in_flight_at("xoyo", later), 1);
+    assert_eq!(
+        reservations
+            .leased_at("xoyo", later)
+            .into_iter()
+            .collect::<Vec<_>>(),
+        [2]
+    );
+    reservations.unlease("xoyo", 2);
+    assert!(reservations.leased_at("xoyo", later).is_empty());
+}
+
+#[tokio::test]
+async fn test_manual_ack() {
+    let server = common::server();
+    for data in ["a", "b", "c"] {
+        server
+            .get(&format!("/?name=xoyo&opt=put&data={}", data))
+            .await;
+    }
+
+    let (body, lease) = get_manual(&server).await;
+    assert_eq!(body, "a");
+    let lease = lease.unwrap();
+    // a plain get goes on past the leased message
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "b");
+    let (_, body) = server.get("/?name=xoyo&opt=status").await;
+    assert!(body.contains("In flight: 1\n"), "{}", body);
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""in_flight":1"#), "{}", body);
+
+    let (code, body) = server.get("/?name=xoyo&opt=ack&id=1").await;
+    assert_eq!(
+        (code, &body[..]),
+        (StatusCode::CONFLICT, "HTTPMQ_ACK_CONFLICT")
+    );
+    let uri = format!("/?name=xoyo&opt=ack&id={}", lease);
+    let (_, body) = server.get(&uri).await;
+    assert_eq!(body, "HTTPMQ_ACK_OK");
+    let (code, _) = server.get(&uri).await;
+    assert_eq!(code, StatusCode::CONFLICT);
+
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""in_flight":0"#), "{}", body);
+    assert!(body.contains(r#""getpos":1"#), "{}", body);
+    // without ack=manual a get is as it always was
+    let req = Request::get("/?name=xoyo&opt=get")
+        .body(Body::empty())
+        .unwrap();
+    let (_, headers, body) = server.request(req).await;
+    assert_eq!(body, b"c");
+    assert!(headers.get("x-httpmq-lease").is_none());
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+}
+
+// consumers each lease a message of their own and settle them in any order
+#[tokio::test]
+async fn test_manual_leases_side_by_side() {
+    let server = common::server();
+    for data in ["a", "b", "c"] {
+        server
+            .get(&format!("/?name=xoyo&opt=put&data={}", data))
+            .await;
+    }
+    let (first, a) = get_manual(&server).await;
+    let (second, b) = get_manual(&server).await;
+    assert_eq!((&first[..], &second[..]), ("a", "b"));
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""in_flight":2"#), "{}", body);
+
+    let (_, body) = server
+        .get(&format!("/?name=xoyo&opt=ack&id={}", b.unwrap()))
+        .await;
+    assert_eq!(body, "HTTPMQ_ACK_OK");
+    let (_, body) = server
+        .get(&format!("/?name=xoyo&opt=nack&id={}", a.unwrap()))
+        .await;
+    assert_eq!(body, "HTTPMQ_NACK_OK");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "a");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "c");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+}
+
+#[tokio::test]
+async fn test_manual_nack() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=put&data=a").await;
+
+    let (_, lease) = get_manual(&server).await;
+    let (code, body) = server.get("/?name=xoyo&opt=nack").await;
+    assert_eq!(
+        (code, &body[..]),
+        (StatusCode::CONFLICT, "HTTPMQ_NACK_CONFLICT")
+    );
+    let uri = format!("/?name=xoyo&opt=nack&id={}", lease.unwrap());
+    let (_, body) = server.get(&uri).await;
+    assert_eq!(body, "HTTPMQ_NACK_OK");
+
+    // the message is there for the next get
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "a");
+    let (_, body) = server.get("/?name=xoyo&opt=get&ack=eventually").await;
+    assert_eq!(body, "HTTPMQ_GET_MODE_INVALID");
+}
+
+#[tokio::test]
+async fn test_manual_lease_expires() {
+    let clock = Arc::new(MockClock::new());
+    let server = common::server_with_clock(Config::default(), clock.clone());
+    server.get("/?name=xoyo&opt=put&data=a").await;
+
+    let (_, first) = get_manual(&server).await;
+    clock.advance(Duration::from_secs(31));
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""in_flight":0"#), "{}", body);
+    // reclaimed by the next get, the late ack loses
+    let (body, second) = get_manual(&server).await;
+    assert_eq!(body, "a");
+    let (code, _) = server
+        .get(&format!("/?name=xoyo&opt=ack&id={}", first.unwrap()))
+        .await;
+    assert_eq!(code, StatusCode::CONFLICT);
+    let (_, body) = server
+        .get(&format!("/?name=xoyo&opt=ack&id={}", second.unwrap()))
+        .await;
+    assert_eq!(body, "HTTPMQ_ACK_OK");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+}
+
+#[tokio::test]
+async fn test_nack_delay() {
+    let clock = Arc::new(MockClock::new());
+    let server = common::server_with_clock(Config::default(), clock.clone());
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    server.get("/?name=xoyo&opt=put&data=b").await;
+
+    let (_, lease) = get_manual(&server).await;
+    let lease = lease.unwrap();
+    let uri = format!("/?name=xoyo&opt=nack&id={}&delay=10", lease);
+    let (_, body) = server.get(&uri).await;
+    assert_eq!(body, "HTTPMQ_NACK_OK");
+    // held back, but not in flight, and the old lease settles nothing
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""in_flight":0"#), "{}", body);
+    let (code, _) = server
+        .get(&format!("/?name=xoyo&opt=ack&id={}", lease))
+        .await;
+    assert_eq!(code, StatusCode::CONFLICT);
+    // only that message waits, the rest of the queue goes out
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "b");
+
+    clock.advance(Duration::from_secs(9));
+    let (body, _) = get_manual(&server).await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+    clock.advance(Duration::from_secs(2));
+    let (body, lease) = get_manual(&server).await;
+    assert_eq!(body, "a");
+    let (_, body) = server
+        .get(&format!("/?name=xoyo&opt=ack&id={}", lease.unwrap()))
+        .await;
+    assert_eq!(body, "HTTPMQ_ACK_OK");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+}
+
+// nacking a lease that isn't one of the queue's changes nothing
+#[tokio::test]
+async fn test_nack_unknown_lease() {
+    let server = common::server();
+    for name in ["xoyo", "other"] {
+        server
+            .get(&format!("/?name={}&opt=put&data={}", name, name))
+            .await;
+    }
+    let (_, lease) = get_manual(&server).await;
+    let lease = lease.unwrap();
+    let req = Request::get("/?name=other&opt=get&ack=manual")
+        .body(Body::empty())
+        .unwrap();
+    let (_, headers, _) = server.request(req).await;
+    let other = headers["x-httpmq-lease"].to_str().unwrap().to_string();
+
+    for id in [String::from("1"), other.clone()] {
+        let (code, body) = server.get(&format!("/?name=xoyo&opt=nack&id={}", id)).await;
+        assert_eq!(
+            (code, &body[..]),
+            (StatusCode::CONFLICT, "HTTPMQ_NACK_CONFLICT")
+        );
+    }
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""in_flight":1"#), "{}", body);
+
+    let uri = format!("/?name=xoyo&opt=ack&id={}", lease);
+    let (_, body) = server.get(&uri).await;
+    assert_eq!(body, "HTTPMQ_ACK_OK");
+    // already acked
+    let (code, _) = server
+        .get(&format!("/?name=xoyo&opt=nack&id={}", lease))
+        .await;
+    assert_eq!(code, StatusCode::CONFLICT);
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""getpos":1"#), "{}", body);
+
+    // the other queue's lease was never touched
+    let (_, body) = server.get("/?name=other&opt=status_json").await;
+    assert!(body.contains(r#""in_flight":1"#), "{}", body);
+    let (_, body) = server
+        .get(&format!("/?name=other&opt=ack&id={}", other))
+        .await;
+    assert_eq!(body, "HTTPMQ_ACK_OK");
+}
+
+#[tokio::test]
+async fn test_dead_letter() {
+    let clock = Arc::new(MockClock::new());
+    let server = common::server_with_clock(Config::default(), clock.clone());
+    let (_, body) = server
+        .get("/?name=xoyo&opt=config&data=%7B%22max_deliveries%22%3A2%2C%22dead_letter%22%3A%22dlq%22%7D")
+        .await;
+    assert_eq!(body, "HTTPMQ_CONFIG_OK");
+    let req = Request::get("/?name=xoyo&opt=put&data=a")
+        .header("x-httpmq-attr-kind", "poison")
+        .body(Body::empty())
+        .unwrap();
+    server.request(req).await;
+    server.get("/?name=xoyo&opt=put&data=b").await;
+
+    // a nack and a lease that times out both count
+    let (_, lease) = get_manual(&server).await;
+    server
+        .get(&format!("/?name=xoyo&opt=nack&id={}", lease.unwrap()))
+        .await;
+    let req = Request::get("/?name=xoyo&opt=get&ack=manual")
+        .body(Body::empty())
+        .unwrap();
+    let (_, headers, body) = server.request(req).await;
+    assert_eq!(body, b"a");
+    assert_eq!(headers["x-httpmq-deliveries"], "2");
+    clock.advance(Duration::from_secs(31));
+
+    let (body, _) = get_manual(&server).await;
+    assert_eq!(body, "b");
+    let req = Request::get("/?name=dlq&opt=get")
+        .body(Body::empty())
+        .unwrap();
+    let (_, headers, body) = server.request(req).await;
+    assert_eq!(body, b"a");
+    assert_eq!(headers["x-httpmq-attr-kind"], "poison");
+}
+
+#[tokio::test]
+async fn test_dead_letter_config() {
+    let server = common::server();
+    server.get("/?name=back&opt=alias&queue=xoyo").await;
+    for (data, expected) in [
+        ("%7B%22max_deliveries%22%3A0%7D", "HTTPMQ_CONFIG_INVALID"),
+        (
+            "%7B%22dead_letter%22%3A%22xoyo%22%7D",
+            "HTTPMQ_CONFIG_INVALID",
+        ),
+        (
+            "%7B%22dead_letter%22%3A%22a%2Fb%22%7D",
+            "HTTPMQ_CONFIG_INVALID",
+        ),
+        // an alias of the queue itself
+        (
+            "%7B%22dead_letter%22%3A%22back%22%7D",
+            "HTTPMQ_CONFIG_INVALID",
+        ),
+        ("%7B%22max_deliveries%22%3A1%7D", "HTTPMQ_CONFIG_OK"),
+    ] {
+        let (_, body) = server
+            .get(&format!("/?name=xoyo&opt=config&data={}", data))
+            .await;
+        assert!(body.starts_with(expected), "{}: {}", data, body);
+    }
+
+    // without a dead letter queue the message is dropped
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    let (_, lease) = get_manual(&server).await;
+    server
+        .get(&format!("/?name=xoyo&opt=nack&id={}", lease.unwrap()))
+        .await;
+    let (body, _) = get_manual(&server).await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+}
+
+// a dead letter queue that became an alias of the queue after it was
+// configured: the message goes round to the back of the queue, and the
+// get doesn't wait on the lock it holds itself
+#[tokio::test]
+async fn test_dead_letter_alias_back_to_queue() {
+    let server = common::server();
+    let (_, body) = server
+        .get("/?name=xoyo&opt=config&data=%7B%22max_deliveries%22%3A1%2C%22dead_letter%22%3A%22dlq%22%7D")
+        .await;
+    assert_eq!(body, "HTTPMQ_CONFIG_OK");
+    let (_, body) = server.get("/?name=dlq&opt=alias&queue=xoyo").await;
+    assert_eq!(body, "HTTPMQ_ALIAS_OK");
+    server.get("/?name=xoyo&opt=put&data=a").await;
+
+    let (_, lease) = get_manual(&server).await;
+    server
+        .get(&format!("/?name=xoyo&opt=nack&id={}", lease.unwrap()))
+        .await;
+    let (body, _) = tokio::time::timeout(Duration::from_secs(5), get_manual(&server))
+        .await
+        .expect("the get hangs");
+    assert_eq!(body, "a");
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""putpos":2"#), "{}", body);
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use axum::http::StatusCode;
+use httpmq_rs::state::Config;
+
+#[tokio::test]
+async fn test_reset_requires_confirm() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=put&data=a").await;
+
+    let (code, body) = server.get("/?name=xoyo&opt=reset").await;
+    assert_eq!(
+        (code, &body[..]),
+        (StatusCode::OK, "HTTPMQ_CONFIRM_REQUIRED")
+    );
+    let (_, body) = server.get("/?name=xoyo&opt=reset&confirm=xoy").await;
+    assert_eq!(body, "HTTPMQ_CONFIRM_REQUIRED");
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""putpos":1"#), "{}", body);
+
+    let (_, body) = server.get("/?name=xoyo&opt=reset&confirm=xoyo").await;
+    assert_eq!(body, "HTTPMQ_RESET_OK");
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""putpos":0"#), "{}", body);
+}
+
+#[tokio::test]
+async fn test_reset_requires_admin_auth() {
+    let server = common::server_with(Config {
+        admin_auth: Some(String::from("secret")),
+        ..Default::default()
+    });
+
+    let (code, body) = server.get("/?name=xoyo&opt=reset&confirm=xoyo").await;
+    assert_eq!(
+        (code, &body[..]),
+        (StatusCode::UNAUTHORIZED, "HTTPMQ_AUTH_FAILED")
+    );
+    let (code, _) = server
+        .get("/?name=xoyo&opt=reset&confirm=xoyo&auth=secre")
+        .await;
+    assert_eq!(code, StatusCode::UNAUTHORIZED);
+
+    let (code, body) = server
+        .get("/?name=xoyo&opt=reset&confirm=xoyo&auth=secret")
+        .await;
+    assert_eq!((code, &body[..]), (StatusCode::OK, "HTTPMQ_RESET_OK"));
+
+    // other operations are unaffected
+    let (_, body) = server.get("/?name=xoyo&opt=put&data=a").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+}
+
+#[tokio::test]
+async fn test_allow_unprotected_reset() {
+    let server = common::server_with(Config {
+        allow_unprotected_reset: true,
+        ..Default::default()
+    });
+
+    let (_, body) = server.get("/?name=xoyo&opt=reset").await;
+    assert_eq!(body, "HTTPMQ_RESET_OK");
+}
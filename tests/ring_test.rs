@@ -0,0 +1,92 @@
+use std::collections::{BTreeSet, VecDeque};
+
+use httpmq_rs::ring::{next_getpos, next_putpos, putlap, unread};
+
+#[test]
+fn test_ring_empty() {
+    assert_eq!(unread(5, 0, 0), 0);
+    assert_eq!(putlap(0, 0), 1);
+    assert_eq!(next_getpos(5, 0, 0), 0);
+    assert_eq!(next_putpos(5, 0, 0), 1);
+}
+
+#[test]
+fn test_ring_partially_consumed() {
+    assert_eq!(unread(5, 4, 2), 2);
+    assert_eq!(putlap(4, 2), 1);
+    assert_eq!(next_getpos(5, 4, 2), 3);
+}
+
+#[test]
+fn test_ring_fully_consumed() {
+    // equal positions are a drained queue, on either lap
+    assert_eq!(unread(5, 5, 5), 0);
+    assert_eq!(next_getpos(5, 5, 5), 0);
+    assert_eq!(next_putpos(5, 5, 5), 1);
+    assert_eq!(unread(5, 2, 2), 0);
+    assert_eq!(putlap(2, 2), 1);
+}
+
+#[test]
+fn test_ring_wrapped_producer() {
+    // 4 and 5 unread on the first lap, 1 and 2 on the next
+    assert_eq!(unread(5, 2, 3), 4);
+    assert_eq!(putlap(2, 3), 2);
+    assert_eq!(next_getpos(5, 2, 3), 4);
+    assert_eq!(next_getpos(5, 2, 5), 1);
+    assert_eq!(unread(5, 2, 5), 2);
+}
+
+#[test]
+fn test_ring_full() {
+    // nothing read yet, every slot holds a message
+    assert_eq!(unread(5, 5, 0), 5);
+    assert_eq!(next_putpos(5, 5, 0), 0);
+    // after a wrap putpos stops one short of getpos
+    assert_eq!(unread(5, 2, 3), 4);
+    assert_eq!(next_putpos(5, 2, 3), 0);
+    assert_eq!(unread(5, 5, 1), 4);
+    assert_eq!(next_putpos(5, 5, 1), 0);
+}
+
+#[test]
+fn test_ring_getpos_past_the_end() {
+    // e.g. set by fsck; gets stop there, so nothing counts as unread
+    assert_eq!(next_getpos(5, 2, 7), 0);
+    assert_eq!(unread(5, 2, 7), 0);
+}
+
+// every state puts and gets can reach from an empty queue: a put adds one
+// unread message, a get takes one, and unread is what gets can take
+#[test]
+fn test_ring_reachable_states() {
+    for maxqueue in 1..=6 {
+        let mut seen = BTreeSet::new();
+        let mut todo = VecDeque::from([(0, 0)]);
+        while let Some((putpos, getpos)) = todo.pop_front() {
+            if !seen.insert((putpos, getpos)) {
+                continue;
+            }
+            let n = unread(maxqueue, putpos, getpos);
+            assert!(n <= maxqueue, "{:?}", (maxqueue, putpos, getpos));
+
+            let p = next_putpos(maxqueue, putpos, getpos);
+            if p != 0 {
+                assert_eq!(unread(maxqueue, p, getpos), n + 1);
+                todo.push_back((p, getpos));
+            }
+            let g = next_getpos(maxqueue, putpos, getpos);
+            assert_eq!(g == 0, n == 0, "{:?}", (maxqueue, putpos, getpos));
+            if g != 0 {
+                assert_eq!(unread(maxqueue, putpos, g), n - 1);
+                todo.push_back((putpos, g));
+            }
+        }
+        // the producer got round to the start again
+        assert!(
+            maxqueue < 2 || seen.contains(&(1, maxqueue)),
+            "{}",
+            maxqueue
+        );
+    }
+}
@@ -0,0 +1,21 @@
+mod common;
+
+use httpmq_rs::state::Config;
+
+#[tokio::test]
+async fn test_runtime_metrics_in_stats() {
+    let (_, body) = common::server().get("/stats").await;
+    assert!(!body.contains("Runtime:"), "{}", body);
+
+    let server = common::server_with(Config {
+        runtime_metrics: true,
+        ..Default::default()
+    });
+    let (_, body) = server.get("/stats").await;
+    assert!(body.contains("Runtime: 1 workers busy "), "{}", body);
+    assert!(
+        body.contains(", injection queue 0, blocking queue "),
+        "{}",
+        body
+    );
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use httpmq_rs::{
+    chunk,
+    error::OpenError,
+    schema::{SCHEMA_VERSION, VERSION_KEY},
+    state::{Config, State},
+};
+use rocksdb::{WriteBatch, DB};
+use std::sync::Arc;
+
+fn config(dir: &tempfile::TempDir) -> Config {
+    Config {
+        dbpath: dir.path().to_str().unwrap().to_string(),
+        ..Default::default()
+    }
+}
+
+// a database as written before the version key existed
+fn legacy_fixture(dir: &tempfile::TempDir) {
+    let db = DB::open_default(dir.path()).unwrap();
+    db.put("xoyo.putpos", "3").unwrap();
+    db.put("xoyo1", "a").unwrap();
+    db.put("xoyo2", "b").unwrap();
+    let mut batch = WriteBatch::default();
+    chunk::put(&mut batch, "xoyo3", b"0123456789", 4, &Default::default());
+    db.write(batch).unwrap();
+    // queue "q1" position 1, which version 0 couldn't tell from "q" 11
+    db.put("q.putpos", "1").unwrap();
+    db.put("q1", "x").unwrap();
+    db.put("q1.putpos", "1").unwrap();
+    db.put("q11", "y").unwrap();
+}
+
+#[tokio::test]
+async fn test_migrate_legacy_keys() {
+    let dir = tempfile::tempdir().unwrap();
+    legacy_fixture(&dir);
+
+    let state = Arc::new(State::new(config(&dir)));
+    let db = state.db.raw();
+    assert_eq!(
+        db.get(VERSION_KEY).unwrap().unwrap(),
+        SCHEMA_VERSION.to_string().as_bytes()
+    );
+    for key in ["xoyo1", "xoyo2", "xoyo3", "xoyo3#0", "q1", "q11"] {
+        assert_eq!(db.get(key).unwrap(), None, "{}", key);
+    }
+    assert_eq!(db.get("xoyo:3#2").unwrap().unwrap(), b"89");
+
+    let server = common::TestServer::new(httpmq_rs::app(state.clone()), state);
+    for want in ["a", "b", "0123456789", "HTTPMQ_GET_END"] {
+        let (_, body) = server.get("/?name=xoyo&opt=get").await;
+        assert_eq!(body, want);
+    }
+    let (_, body) = server.get("/?name=q&opt=get").await;
+    assert_eq!(body, "x");
+    let (_, body) = server.get("/?name=q1&opt=get").await;
+    assert_eq!(body, "y");
+}
+
+#[test]
+fn test_migrate_is_idempotent() {
+    let dir = tempfile::tempdir().unwrap();
+    legacy_fixture(&dir);
+    drop(State::new(config(&dir)));
+
+    // a rerun, as after a crash before the version was recorded
+    {
+        let db = DB::open_default(dir.path()).unwrap();
+        db.delete(VERSION_KEY).unwrap();
+    }
+    let state = State::new(config(&dir));
+    let db = state.db.raw();
+    assert_eq!(db.get("xoyo:1").unwrap().unwrap(), b"a");
+    assert_eq!(db.get("q1:1").unwrap().unwrap(), b"y");
+}
+
+#[test]
+fn test_new_database_gets_version() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = State::new(config(&dir));
+    assert_eq!(
+        state.db.raw().get(VERSION_KEY).unwrap().unwrap(),
+        SCHEMA_VERSION.to_string().as_bytes()
+    );
+}
+
+#[test]
+fn test_refuse_newer_schema() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db = DB::open_default(dir.path()).unwrap();
+        db.put(VERSION_KEY, (SCHEMA_VERSION + 1).to_string())
+            .unwrap();
+    }
+    match State::open(config(&dir)) {
+        Err(e @ OpenError::SchemaTooNew { .. }) => assert_eq!(e.exit_code(), 78),
+        Err(e) => panic!("{}", e),
+        Ok(_) => panic!("opened a newer database"),
+    }
+}
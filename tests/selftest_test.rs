@@ -0,0 +1,82 @@
+mod common;
+
+use axum::http::StatusCode;
+use httpmq_rs::state::Config;
+use rocksdb::{Direction, IteratorMode};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn selftest_keys(server: &common::TestServer) -> Vec<String> {
+    let mode = IteratorMode::From(b"httpmq-selftest-", Direction::Forward);
+    server
+        .state
+        .db
+        .raw()
+        .iterator(mode)
+        .map(|(key, _)| String::from_utf8_lossy(&key).to_string())
+        .take_while(|key| key.starts_with("httpmq-selftest-"))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_selftest() {
+    let server = common::server();
+
+    let (code, body) = server.get("/?opt=selftest").await;
+    assert_eq!(code, StatusCode::OK);
+    let lines: Vec<_> = body.lines().collect();
+    assert_eq!(lines[0], "HTTPMQ_SELFTEST_OK", "{}", body);
+    for step in ["put", "get", "ring", "reset", "remove"] {
+        assert!(
+            lines
+                .iter()
+                .any(|l| l.starts_with(&format!("{}: ok", step))),
+            "{}",
+            body
+        );
+    }
+    assert!(selftest_keys(&server).is_empty());
+}
+
+#[tokio::test]
+async fn test_selftest_requires_admin_auth() {
+    let server = common::server_with(Config {
+        admin_auth: Some(String::from("secret")),
+        ..Default::default()
+    });
+
+    let (code, body) = server.get("/?opt=selftest").await;
+    assert_eq!(
+        (code, &body[..]),
+        (StatusCode::UNAUTHORIZED, "HTTPMQ_AUTH_FAILED")
+    );
+    let (code, _) = server.get("/?opt=selftest&auth=secret").await;
+    assert_eq!(code, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_selftest_removes_leftovers() {
+    let server = common::server();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+
+    // one crashed run long ago, one that may still be running
+    server
+        .get("/?name=httpmq-selftest-1000-0&opt=put&data=a")
+        .await;
+    server
+        .get(&format!("/?name=httpmq-selftest-{}-0&opt=put&data=a", now))
+        .await;
+
+    let (_, body) = server.get("/?opt=selftest").await;
+    assert!(body.contains("leftover queues removed: 1"), "{}", body);
+    let keys = selftest_keys(&server);
+    assert!(
+        keys.iter()
+            .all(|k| k.starts_with(&format!("httpmq-selftest-{}-0", now))),
+        "{:?}",
+        keys
+    );
+    assert!(!keys.is_empty());
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use axum::{body::Body, http::Request};
+use httpmq_rs::state::Config;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct Put {
+    pos: u64,
+    seq: u64,
+}
+
+#[derive(Deserialize)]
+struct Get {
+    pos: u64,
+    seq: Option<u64>,
+}
+
+#[tokio::test]
+async fn test_seq_keeps_counting_across_laps() {
+    let server = common::server_with(Config {
+        maxqueue: 2,
+        ..Default::default()
+    });
+    for i in 1..=5u64 {
+        let req = Request::get(format!("/?name=xoyo&opt=put&data={}", i))
+            .body(Body::empty())
+            .unwrap();
+        let (_, headers, body) = server.request(req).await;
+        assert_eq!(body, b"HTTPMQ_PUT_OK");
+        assert_eq!(headers["x-httpmq-seq"], i.to_string().as_str());
+
+        let req = Request::get("/?name=xoyo&opt=get")
+            .body(Body::empty())
+            .unwrap();
+        let (_, headers, body) = server.request(req).await;
+        assert_eq!(body, i.to_string().as_bytes());
+        assert_eq!(headers["x-httpmq-seq"], i.to_string().as_str());
+        // the position wraps, the sequence number doesn't
+        let pos = if i % 2 == 1 { "1" } else { "2" };
+        assert_eq!(headers["x-httpmq-pos"], pos);
+    }
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""seq":5"#), "{}", body);
+
+    // reset keeps counting, so consumers can still dedup
+    server.get("/?name=xoyo&opt=reset&confirm=xoyo").await;
+    let req = Request::get("/?name=xoyo&opt=put&data=x&format=msgpack")
+        .body(Body::empty())
+        .unwrap();
+    let (_, _, body) = server.request(req).await;
+    let put: Put = rmp_serde::from_slice(&body).unwrap();
+    assert_eq!((put.pos, put.seq), (1, 6));
+    let req = Request::get("/?name=xoyo&opt=get&format=msgpack")
+        .body(Body::empty())
+        .unwrap();
+    let (_, _, body) = server.request(req).await;
+    let get: Get = rmp_serde::from_slice(&body).unwrap();
+    assert_eq!((get.pos, get.seq), (1, Some(6)));
+}
+
+#[tokio::test]
+async fn test_seq_of_batch_and_old_messages() {
+    let server = common::server();
+    // a message from before sequence numbers
+    server.state.db.put(b"xoyo:1", b"old").unwrap();
+    server.state.db.put(b"xoyo.putpos", b"1").unwrap();
+    let req = Request::put("/?name=xoyo&opt=put")
+        .header("content-type", "application/msgpack")
+        .body(Body::from(
+            rmp_serde::to_vec(&vec![
+                serde_bytes::ByteBuf::from("a"),
+                serde_bytes::ByteBuf::from("b"),
+            ])
+            .unwrap(),
+        ))
+        .unwrap();
+    let (_, headers, body) = server.request(req).await;
+    assert_eq!(body, b"HTTPMQ_PUT_OK");
+    // the last of the batch
+    assert_eq!(headers["x-httpmq-pos"], "3");
+    assert_eq!(headers["x-httpmq-seq"], "2");
+
+    let req = Request::get("/?name=xoyo&opt=get")
+        .body(Body::empty())
+        .unwrap();
+    let (_, headers, body) = server.request(req).await;
+    assert_eq!(body, b"old");
+    assert!(headers.get("x-httpmq-seq").is_none());
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use httpmq_rs::{
+    app, shutdown,
+    state::{Config, State},
+};
+use std::sync::Arc;
+
+fn open(dir: &tempfile::TempDir, config: Config) -> common::TestServer {
+    let state = Arc::new(State::new(Config {
+        dbpath: dir.path().to_str().unwrap().to_string(),
+        ..config
+    }));
+    common::TestServer::new(app(state.clone()), state)
+}
+
+#[tokio::test]
+async fn test_close_writes_totals() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let server = open(&dir, Config::default());
+        server.get("/?name=xoyo&opt=put&data=abc").await;
+        shutdown::close(&server.state);
+        // nothing left for the periodic flush
+        assert_eq!(server.state.totals.flush(&*server.state.db).unwrap(), 0);
+    }
+
+    let server = open(&dir, Config::default());
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""lifetime":{"total_put":1,"#), "{}", body);
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "abc");
+}
+
+#[tokio::test]
+async fn test_sync_writes() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = || Config {
+        sync_writes: true,
+        ..Default::default()
+    };
+    {
+        let server = open(&dir, config());
+        for data in ["a", "b"] {
+            let (_, body) = server
+                .get(&format!("/?name=xoyo&opt=put&data={}", data))
+                .await;
+            assert_eq!(body, "HTTPMQ_PUT_OK");
+        }
+        let (_, body) = server.get("/?name=xoyo&opt=get").await;
+        assert_eq!(body, "a");
+    }
+
+    let server = open(&dir, config());
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "b");
+    let (_, body) = server.get("/?name=xoyo&opt=remove&confirm=xoyo").await;
+    assert_eq!(body, "HTTPMQ_REMOVE_OK");
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use axum::http::StatusCode;
+use httpmq_rs::stall::WriteStall;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_stall_episodes() {
+    let stall = WriteStall::new(Duration::from_millis(500));
+    let now = Instant::now();
+    stall.observe_write_at(Duration::from_millis(10), now);
+    assert!(!stall.check_at(now));
+
+    stall.observe_write_at(Duration::from_millis(800), now);
+    assert!(stall.check_at(now + Duration::from_millis(500)));
+    assert!(!stall.check_at(now + Duration::from_secs(2)));
+    let stats = stall.stats();
+    assert_eq!(stats.episodes, 1);
+    assert_eq!(stats.stalled_ms, 2000);
+    assert!(!stats.stalled);
+
+    // stopped by the database until it says otherwise
+    stall.set_stopped(true);
+    assert!(stall.check());
+    assert!(stall.stats().stalled);
+    stall.set_stopped(false);
+    assert_eq!(stall.stats().episodes, 2);
+
+    // a zero threshold only goes by the database
+    let stall = WriteStall::new(Duration::ZERO);
+    stall.observe_write_at(Duration::from_secs(30), now);
+    assert!(!stall.check_at(now));
+}
+
+#[tokio::test]
+async fn test_puts_fail_fast_while_stalled() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=put&data=a").await;
+
+    server.state.stall.set_stopped(true);
+    let start = Instant::now();
+    let (code, body) = server.get("/?name=xoyo&opt=put&data=b").await;
+    assert_eq!(
+        (code, &body[..]),
+        (StatusCode::SERVICE_UNAVAILABLE, "HTTPMQ_WRITE_STALLED")
+    );
+    assert!(start.elapsed() < Duration::from_secs(1));
+    // reads keep working
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "a");
+    let (_, body) = server.get("/stats").await;
+    assert!(
+        body.contains("Write stalls: 1, 0.0s stalled, stalled now"),
+        "{}",
+        body
+    );
+
+    server.state.stall.set_stopped(false);
+    let (_, body) = server.get("/?name=xoyo&opt=put&data=b").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+}
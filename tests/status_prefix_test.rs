@@ -0,0 +1,47 @@
+mod common;
+
+use httpmq_rs::storage::View;
+
+#[tokio::test]
+async fn test_status_prefix() {
+    let server = common::server();
+    for (name, n) in [("jobs-a", 2), ("jobs-b", 3), ("other", 1)] {
+        for _ in 0..n {
+            server.get(&format!("/?name={}&opt=put&data=x", name)).await;
+        }
+    }
+    server.get("/?name=jobs-b&opt=get").await;
+    // inside jobs-a's messages, which are skipped rather than read
+    server.state.db.put(b"jobs-a:9.putpos", b"1").unwrap();
+
+    let (_, body) = server.get("/?opt=status_prefix&prefix=jobs-").await;
+    assert!(
+        body.contains("Prefix: jobs-\nNumber of queues: 2\nNumber of unread queue: 4\n"),
+        "{}",
+        body
+    );
+    assert!(body.contains("Snapshot time: "), "{}", body);
+    assert!(body.contains("Queue jobs-a: 2 unread\nQueue jobs-b: 2 unread\n"));
+
+    let (_, body) = server.get("/?opt=status_prefix_json&prefix=jobs-").await;
+    let status: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(status["unread"], 4);
+    assert!(status["snapshot_ms"].as_u64().unwrap() > 0);
+    assert_eq!(status["queues"].as_array().unwrap().len(), 2);
+    assert_eq!(status["queues"][1]["getpos"], 1);
+
+    let (_, body) = server.get("/?opt=status_prefix_json&prefix=none").await;
+    assert!(body.contains(r#""unread":0"#), "{}", body);
+}
+
+#[tokio::test]
+async fn test_snapshot_view_ignores_later_writes() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    let view = View::Snapshot(server.state.db.raw().snapshot());
+    server.get("/?name=xoyo&opt=put&data=b").await;
+
+    assert_eq!(view.get(b"xoyo.putpos").unwrap().unwrap(), b"1");
+    let live = View::Live(&*server.state.db);
+    assert_eq!(live.get(b"xoyo.putpos").unwrap().unwrap(), b"2");
+}
@@ -0,0 +1,187 @@
+mod common;
+
+use axum::http::StatusCode;
+use rocksdb::{WriteBatch, DB};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use httpmq_rs::{
+    app,
+    error::HttpmqError,
+    state::{Config, State},
+    storage::Storage,
+};
+
+// fails the next `fail_reads` metadata reads, `fail_gets` single reads,
+// `fail_puts` single puts and `fail_writes` batch writes
+struct FlakyStorage {
+    db: DB,
+    fail_reads: Arc<AtomicUsize>,
+    fail_gets: Arc<AtomicUsize>,
+    fail_puts: Arc<AtomicUsize>,
+    fail_writes: Arc<AtomicUsize>,
+}
+
+fn take(n: &AtomicUsize) -> bool {
+    n.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+        .is_ok()
+}
+
+impl FlakyStorage {
+    fn open(path: &std::path::Path) -> FlakyStorage {
+        FlakyStorage {
+            db: DB::open_default(path).unwrap(),
+            fail_reads: Arc::new(AtomicUsize::new(0)),
+            fail_gets: Arc::new(AtomicUsize::new(0)),
+            fail_puts: Arc::new(AtomicUsize::new(0)),
+            fail_writes: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn fail(&self) -> bool {
+        take(&self.fail_reads)
+    }
+}
+
+impl Storage for FlakyStorage {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, HttpmqError> {
+        if take(&self.fail_gets) {
+            return Err(HttpmqError::Db(String::from("injected read failure")));
+        }
+        Storage::get(&self.db, key)
+    }
+
+    fn multi_get(&self, keys: Vec<Vec<u8>>) -> Vec<Result<Option<Vec<u8>>, HttpmqError>> {
+        if self.fail() {
+            return keys
+                .iter()
+                .map(|_| Err(HttpmqError::Db(String::from("injected read failure"))))
+                .collect();
+        }
+        Storage::multi_get(&self.db, keys)
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<(), HttpmqError> {
+        if take(&self.fail_puts) {
+            return Err(HttpmqError::Db(String::from("injected write failure")));
+        }
+        Storage::put(&self.db, key, value)
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<(), HttpmqError> {
+        Storage::delete(&self.db, key)
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<(), HttpmqError> {
+        if take(&self.fail_writes) {
+            return Err(HttpmqError::Db(String::from("injected write failure")));
+        }
+        Storage::write(&self.db, batch)
+    }
+
+    fn raw(&self) -> &DB {
+        &self.db
+    }
+}
+
+#[tokio::test]
+async fn test_flaky_metadata_read_does_not_reset_cursors() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = FlakyStorage::open(dir.path());
+    let fail_reads = storage.fail_reads.clone();
+    let state = Arc::new(State::with_storage(Config::default(), Box::new(storage)));
+    let server = common::TestServer::new(app(state.clone()), state);
+
+    for data in ["a", "b"] {
+        server
+            .get(&format!("/?name=xoyo&opt=put&data={}", data))
+            .await;
+    }
+
+    for opt in ["put&data=c", "get", "status"] {
+        fail_reads.store(1, Ordering::SeqCst);
+        let (code, body) = server.get(&format!("/?name=xoyo&opt={}", opt)).await;
+        assert_eq!(code, StatusCode::INTERNAL_SERVER_ERROR, "{}", opt);
+        assert_eq!(body, "HTTPMQ_DB_ERROR", "{}", opt);
+    }
+
+    // the failed put didn't start over at position 1
+    let (_, body) = server.get("/?name=xoyo&opt=put&data=c").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+    for data in ["a", "b", "c"] {
+        let (_, body) = server.get("/?name=xoyo&opt=get").await;
+        assert_eq!(body, data);
+    }
+}
+
+#[tokio::test]
+async fn test_failed_getpos_write_is_an_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = FlakyStorage::open(dir.path());
+    let fail_puts = storage.fail_puts.clone();
+    let state = Arc::new(State::with_storage(Config::default(), Box::new(storage)));
+    let server = common::TestServer::new(app(state.clone()), state);
+    server.get("/?name=xoyo&opt=put&data=a").await;
+
+    // not mistaken for an empty queue, and the message isn't lost
+    fail_puts.store(1, Ordering::SeqCst);
+    let (code, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(code, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body, "HTTPMQ_DB_ERROR");
+    let (code, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(code, StatusCode::OK);
+    assert_eq!(body, "a");
+}
+
+#[tokio::test]
+async fn test_failed_put_or_get_leaves_metadata_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = FlakyStorage::open(dir.path());
+    let (fail_gets, fail_writes) = (storage.fail_gets.clone(), storage.fail_writes.clone());
+    let state = Arc::new(State::with_storage(Config::default(), Box::new(storage)));
+    let server = common::TestServer::new(app(state.clone()), state);
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    let (_, before) = server.get("/?name=xoyo&opt=status").await;
+
+    // the position was worked out, but the write carrying it and the
+    // message failed as one
+    fail_writes.store(1, Ordering::SeqCst);
+    let (code, body) = server.get("/?name=xoyo&opt=put&data=b").await;
+    assert_eq!(code, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body, "HTTPMQ_DB_ERROR");
+    let (_, after) = server.get("/?name=xoyo&opt=status").await;
+    assert_eq!(after, before);
+
+    // a slot that can't be read doesn't move the cursor
+    fail_gets.store(1, Ordering::SeqCst);
+    let (code, _) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(code, StatusCode::INTERNAL_SERVER_ERROR);
+    let (_, after) = server.get("/?name=xoyo&opt=status").await;
+    assert_eq!(after, before);
+
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "a");
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+}
+
+#[tokio::test]
+async fn test_not_ready_when_the_database_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = FlakyStorage::open(dir.path());
+    let fail_gets = storage.fail_gets.clone();
+    let state = Arc::new(State::with_storage(Config::default(), Box::new(storage)));
+    let server = common::TestServer::new(app(state.clone()), state);
+
+    fail_gets.store(1, Ordering::SeqCst);
+    let (code, body) = server.get("/readyz").await;
+    assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body, "HTTPMQ_DB_ERROR");
+    let (code, _) = server.get("/healthz").await;
+    assert_eq!(code, StatusCode::OK);
+    let (code, body) = server.get("/readyz").await;
+    assert_eq!(code, StatusCode::OK);
+    assert_eq!(body, "HTTPMQ_READY");
+}
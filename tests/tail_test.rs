@@ -0,0 +1,96 @@
+mod common;
+
+use axum::{body::Body, http::Request};
+use serde::Deserialize;
+use serde_bytes::ByteBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use httpmq_rs::{
+    clock::{Clock, MockClock},
+    state::Config,
+};
+
+#[derive(Deserialize, Debug)]
+struct Tail {
+    result: String,
+    messages: Vec<Message>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Message {
+    pos: u64,
+    seq: u64,
+    data: ByteBuf,
+}
+
+#[tokio::test]
+async fn test_tail() {
+    let clock = Arc::new(MockClock::new());
+    let server = common::server_with_clock(Config::default(), clock.clone());
+    let (_, body) = server.get("/?name=xoyo&opt=tail").await;
+    assert_eq!(body, "HTTPMQ_TAIL_NONE");
+
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    clock.advance(Duration::from_secs(1));
+    server.get("/?name=xoyo&opt=put&data=b").await;
+    let (_, headers, body) = server
+        .request(
+            Request::get("/?name=xoyo&opt=tail")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(body, b"b");
+    assert_eq!(headers["x-httpmq-pos"], "2");
+    assert_eq!(headers["x-httpmq-seq"], "2");
+    assert_eq!(
+        headers["x-httpmq-put-at"],
+        clock.unix_millis().to_string().as_str()
+    );
+
+    // no cursor moved, and consumed messages still show
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "a");
+    let (_, body) = server.get("/?name=xoyo&opt=tail&num=5").await;
+    let put_at = clock.unix_millis() as u64;
+    assert_eq!(
+        body,
+        format!(
+            "HTTPMQ_TAIL_OK\n2 2 {} b\n1 1 {} a\n",
+            put_at,
+            put_at - 1000
+        )
+    );
+}
+
+#[tokio::test]
+async fn test_tail_wraps() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=maxqueue&num=3").await;
+    for data in ["a", "b", "c"] {
+        server
+            .get(&format!("/?name=xoyo&opt=put&data={}", data))
+            .await;
+        server.get("/?name=xoyo&opt=get").await;
+    }
+    // d goes to slot 1 on the next lap
+    server.get("/?name=xoyo&opt=put&data=d").await;
+
+    let (_, _, body) = server
+        .request(
+            Request::get("/?name=xoyo&opt=tail&num=10&format=msgpack")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    let tail: Tail = rmp_serde::from_slice(&body).unwrap();
+    assert_eq!(tail.result, "HTTPMQ_TAIL_OK");
+    let got: Vec<_> = tail
+        .messages
+        .iter()
+        .map(|m| (m.pos, m.seq, &m.data[..]))
+        .collect();
+    // bounded by the ring, newest first
+    assert_eq!(got, [(1, 4, &b"d"[..]), (3, 3, b"c"), (2, 2, b"b")]);
+}
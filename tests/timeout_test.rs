@@ -0,0 +1,32 @@
+mod common;
+
+use httpmq_rs::{service::request_timeout, state::Config};
+use std::time::Duration;
+
+#[test]
+fn test_request_timeout_clamped() {
+    let config = Config {
+        request_timeout: 10,
+        max_request_timeout: 60,
+        ..Default::default()
+    };
+    assert_eq!(request_timeout(&config, None), Duration::from_secs(10));
+    assert_eq!(request_timeout(&config, Some(30)), Duration::from_secs(30));
+    assert_eq!(
+        request_timeout(&config, Some(3600)),
+        Duration::from_secs(60)
+    );
+    assert_eq!(request_timeout(&config, Some(0)), Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn test_timeout_over_cap_is_not_rejected() {
+    let server = common::server_with(Config {
+        max_request_timeout: 1,
+        ..Default::default()
+    });
+    let (_, body) = server.get("/?name=xoyo&opt=put&data=a&timeout=3600").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+    let (_, body) = server.get("/?name=xoyo&opt=get&timeout=3600").await;
+    assert_eq!(body, "a");
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use httpmq_rs::state::Config;
+
+#[tokio::test]
+async fn test_topic_fanout() {
+    let server = common::server();
+
+    let (_, body) = server.get("/?name=news&opt=put&data=a&topic=1").await;
+    assert_eq!(body, "HTTPMQ_TOPIC_NOT_FOUND");
+
+    for queue in ["a", "b"] {
+        let (_, body) = server
+            .get(&format!("/?name=news&opt=subscribe&queue={}", queue))
+            .await;
+        assert_eq!(body, "HTTPMQ_SUBSCRIBE_OK");
+    }
+    let (_, body) = server.get("/?name=news&opt=subscribe&queue=news").await;
+    assert_eq!(body, "HTTPMQ_TOPIC_INVALID");
+    let (_, body) = server.get("/?name=other&opt=subscribe&queue=news").await;
+    assert_eq!(body, "HTTPMQ_TOPIC_INVALID");
+
+    let (_, body) = server.get("/?name=news&opt=put&data=hello&topic=1").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+    let (_, body) = server.get("/?name=news&opt=put&data=world").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK");
+
+    let (_, body) = server.get("/?name=news&opt=status_json").await;
+    assert!(
+        body.contains(r#""name":"news","unread":4,"snapshot_ms":"#),
+        "{}",
+        body
+    );
+    let (_, body) = server.get("/?name=news&opt=status").await;
+    assert!(body.contains("Subscriber a: 2 unread"), "{}", body);
+
+    for queue in ["a", "b"] {
+        for want in ["hello", "world"] {
+            let (_, body) = server.get(&format!("/?name={}&opt=get", queue)).await;
+            assert_eq!(body, want);
+        }
+    }
+
+    let (_, body) = server.get("/?name=news&opt=unsubscribe&queue=b").await;
+    assert_eq!(body, "HTTPMQ_UNSUBSCRIBE_OK");
+    let (_, body) = server.get("/?name=news&opt=unsubscribe&queue=b").await;
+    assert_eq!(body, "HTTPMQ_UNSUBSCRIBE_NONE");
+    server.get("/?name=news&opt=put&data=again").await;
+    let (_, body) = server.get("/?name=b&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+
+    // subscriptions survive a restart
+    let topics = httpmq_rs::topic::Topics::load(&*server.state.db);
+    assert_eq!(topics.subscribers("news"), Some(vec![String::from("a")]));
+}
+
+#[tokio::test]
+async fn test_topic_full_subscriber() {
+    let server = common::server();
+    server.get("/?name=news&opt=subscribe&queue=a").await;
+    server.get("/?name=news&opt=subscribe&queue=b").await;
+    server.get("/?name=b&opt=maxqueue&num=1").await;
+    server.get("/?name=news&opt=put&data=1").await;
+
+    // b is full, so nobody gets the message
+    let (_, body) = server.get("/?name=news&opt=put&data=2").await;
+    assert_eq!(body, "HTTPMQ_PUT_END");
+    let (_, body) = server.get("/?name=a&opt=status_json").await;
+    assert!(body.contains(r#""unread":1"#), "{}", body);
+}
+
+#[tokio::test]
+async fn test_topic_skip_full_subscriber() {
+    let server = common::server_with(Config {
+        topic_skip_full: true,
+        ..Default::default()
+    });
+    server.get("/?name=news&opt=subscribe&queue=a").await;
+    server.get("/?name=news&opt=subscribe&queue=b").await;
+    server.get("/?name=b&opt=maxqueue&num=1").await;
+    server.get("/?name=news&opt=put&data=1").await;
+
+    let (_, body) = server.get("/?name=news&opt=put&data=2").await;
+    assert_eq!(body, "HTTPMQ_PUT_OK\nskipped: b\n");
+    let (_, body) = server.get("/?name=a&opt=status_json").await;
+    assert!(body.contains(r#""unread":2"#), "{}", body);
+}
+
+#[tokio::test]
+async fn test_topic_admin_auth() {
+    let server = common::server_with(Config {
+        admin_auth: Some(String::from("secret")),
+        ..Default::default()
+    });
+    let (_, body) = server.get("/?name=news&opt=subscribe&queue=a").await;
+    assert_eq!(body, "HTTPMQ_AUTH_FAILED");
+    let (_, body) = server
+        .get("/?name=news&opt=subscribe&queue=a&auth=secret")
+        .await;
+    assert_eq!(body, "HTTPMQ_SUBSCRIBE_OK");
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use axum::Router;
+use httpmq_rs::{
+    app,
+    state::{Config, State},
+};
+use std::sync::Arc;
+
+fn open(dir: &tempfile::TempDir) -> common::TestServer {
+    let state = Arc::new(State::new(Config {
+        dbpath: dir.path().to_str().unwrap().to_string(),
+        ..Default::default()
+    }));
+    let app: Router = app(state.clone());
+    common::TestServer::new(app, state)
+}
+
+#[tokio::test]
+async fn test_totals_survive_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let server = open(&dir);
+        server.get("/?name=xoyo&opt=put&data=abc").await;
+        server.get("/?name=xoyo&opt=put&data=de").await;
+        server.get("/?name=xoyo&opt=get").await;
+        let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+        let lifetime =
+            r#""lifetime":{"total_put":2,"total_get":1,"total_bytes_in":5,"total_bytes_out":3}"#;
+        assert!(body.contains(lifetime), "{}", body);
+        assert_eq!(server.state.totals.flush(&*server.state.db).unwrap(), 1);
+        // nothing new to write
+        assert_eq!(server.state.totals.flush(&*server.state.db).unwrap(), 0);
+    }
+
+    let server = open(&dir);
+    server.get("/?name=xoyo&opt=get").await;
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    let since_start =
+        r#""since_start":{"total_put":0,"total_get":1,"total_bytes_in":0,"total_bytes_out":2}"#;
+    let lifetime =
+        r#""lifetime":{"total_put":2,"total_get":2,"total_bytes_in":5,"total_bytes_out":5}"#;
+    assert!(body.contains(since_start), "{}", body);
+    assert!(body.contains(lifetime), "{}", body);
+    let (_, body) = server.get("/?name=xoyo&opt=status").await;
+    assert!(
+        body.contains("Lifetime: 2 puts, 2 gets, 5 bytes in, 5 bytes out"),
+        "{}",
+        body
+    );
+
+    // removing the queue drops its totals
+    server.state.totals.flush(&*server.state.db).unwrap();
+    server.get("/?name=xoyo&opt=remove&confirm=xoyo").await;
+    assert!(server.state.db.get(b"xoyo.totals").unwrap().is_none());
+    let (_, body) = server.get("/?name=xoyo&opt=status_json").await;
+    assert!(body.contains(r#""lifetime":{"total_put":0"#), "{}", body);
+}
+
+#[tokio::test]
+async fn test_topic_put_counts_subscribers() {
+    let server = common::server();
+    server.get("/?name=news&opt=subscribe&queue=a").await;
+    server.get("/?name=news&opt=subscribe&queue=b").await;
+    server.get("/?name=news&opt=put&data=xy").await;
+    for queue in ["a", "b"] {
+        let (_, body) = server
+            .get(&format!("/?name={}&opt=status_json", queue))
+            .await;
+        assert!(
+            body.contains(r#""since_start":{"total_put":1,"total_get":0,"total_bytes_in":2"#),
+            "{}",
+            body
+        );
+    }
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+
+#[tokio::test]
+async fn test_view() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=put&data=a").await;
+    server.get("/?name=xoyo&opt=put&data=b").await;
+    let (_, status) = server.get("/?name=xoyo&opt=status").await;
+
+    let (_, headers, body) = server
+        .request(
+            Request::get("/?name=xoyo&opt=view&pos=2")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(body, b"b");
+    assert_eq!(headers["x-httpmq-pos"], "2");
+    assert_eq!(headers["x-httpmq-seq"], "2");
+    let (_, body) = server.get("/?name=xoyo&opt=view&pos=3").await;
+    assert_eq!(body, "HTTPMQ_GET_NONE");
+
+    // no cursor moved
+    let (_, after) = server.get("/?name=xoyo&opt=status").await;
+    assert_eq!(after, status);
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "a");
+    // consumed messages stay viewable until overwritten
+    let (_, body) = server.get("/?name=xoyo&opt=view&pos=1").await;
+    assert_eq!(body, "a");
+}
+
+#[tokio::test]
+async fn test_view_invalid_pos() {
+    let server = common::server();
+    server.get("/?name=xoyo&opt=maxqueue&num=10").await;
+    for uri in [
+        "/?name=xoyo&opt=view",
+        "/?name=xoyo&opt=view&pos=0",
+        "/?name=xoyo&opt=view&pos=11",
+    ] {
+        let (_, body) = server.get(uri).await;
+        assert_eq!(body, "HTTPMQ_VIEW_INVALID", "{}", uri);
+    }
+    let (_, body) = server.get("/?name=xoyo&opt=view&pos=10").await;
+    assert_eq!(body, "HTTPMQ_GET_NONE");
+    let (code, body) = server.get("/?name=xoyo&opt=view&pos=-1").await;
+    assert_eq!(code, StatusCode::BAD_REQUEST);
+    assert_eq!(body, "HTTPMQ_ARGS_INVALID");
+}
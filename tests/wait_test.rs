@@ -0,0 +1,76 @@
+mod common;
+
+use axum::http::StatusCode;
+use httpmq_rs::clock::{Clock, MockClock};
+use httpmq_rs::state::Config;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[tokio::test]
+async fn test_get_waits_for_put() {
+    let server = common::server();
+    let start = Instant::now();
+    let ((_, body), _) = tokio::join!(server.get("/?name=xoyo&opt=get&wait=5"), async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        server.get("/?name=xoyo&opt=put&data=a").await
+    });
+    assert_eq!(body, "a");
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn test_get_wait_expires() {
+    let clock = Arc::new(MockClock::new());
+    let server = common::server_with_clock(Config::default(), clock.clone());
+    let start = clock.instant();
+    let get = server.get("/?name=xoyo&opt=get&wait=5");
+    tokio::pin!(get);
+    // the wait runs on the server's clock, it ends once that has moved on
+    let (_, body) = loop {
+        tokio::select! {
+            res = &mut get => break res,
+            _ = tokio::time::sleep(Duration::from_millis(10)) => {
+                clock.advance(Duration::from_secs(1));
+            }
+        }
+    };
+    assert_eq!(body, "HTTPMQ_GET_END");
+    assert!(clock.instant() - start >= Duration::from_secs(5));
+
+    // without wait= an empty queue answers at once
+    let start = Instant::now();
+    let (_, body) = server.get("/?name=xoyo&opt=get").await;
+    assert_eq!(body, "HTTPMQ_GET_END");
+    assert!(start.elapsed() < Duration::from_millis(500));
+}
+
+#[tokio::test]
+async fn test_get_wait_within_request_timeout() {
+    let server = common::server();
+    let start = Instant::now();
+    let (code, body) = server.get("/?name=xoyo&opt=get&wait=30&timeout=1").await;
+    assert_eq!(code, StatusCode::OK);
+    assert_eq!(body, "HTTPMQ_GET_END");
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn test_waiting_gets_share_puts() {
+    let server = common::server();
+    let gets = async {
+        tokio::join!(
+            server.get("/?name=xoyo&opt=get&wait=5"),
+            server.get("/?name=xoyo&opt=get&wait=5")
+        )
+    };
+    let puts = async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        server.get("/?name=xoyo&opt=put&data=a").await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        server.get("/?name=xoyo&opt=put&data=b").await;
+    };
+    let (((_, x), (_, y)), _) = tokio::join!(gets, puts);
+    let mut got = [x, y];
+    got.sort();
+    assert_eq!(got, ["a", "b"]);
+}